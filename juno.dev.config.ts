@@ -45,6 +45,53 @@ export default defineDevConfig(() => ({
 					memory: "stable" as MemoryText,
 					mutablePermissions: true
 				},
+				{
+					// Handle -> user index, written by the satellite only
+					collection: "users_handles",
+					read: "public" as PermissionText,
+					write: "controllers" as PermissionText,
+					memory: "stable" as MemoryText,
+					mutablePermissions: true
+				},
+				{
+					// Handles released by renames and deletions, written by the satellite only
+					collection: "users_handles_history",
+					read: "public" as PermissionText,
+					write: "controllers" as PermissionText,
+					memory: "stable" as MemoryText,
+					mutablePermissions: true
+				},
+				{
+					// Renames of each user, for the rename rate limit, written by the satellite only
+					collection: "users_handles_renames",
+					read: "controllers" as PermissionText,
+					write: "controllers" as PermissionText,
+					memory: "stable" as MemoryText,
+					mutablePermissions: true
+				},
+				{
+					collection: "reserved_handles",
+					read: "controllers" as PermissionText,
+					write: "controllers" as PermissionText,
+					memory: "stable" as MemoryText,
+					mutablePermissions: true
+				},
+				{
+					// Content moderation terms, written by the satellite only
+					collection: "moderation_terms",
+					read: "controllers" as PermissionText,
+					write: "controllers" as PermissionText,
+					memory: "stable" as MemoryText,
+					mutablePermissions: true
+				},
+				{
+					// User and tag documents flagged for review, written by the satellite only
+					collection: "moderation_queue",
+					read: "controllers" as PermissionText,
+					write: "controllers" as PermissionText,
+					memory: "stable" as MemoryText,
+					mutablePermissions: true
+				},
 				{
 					collection: "tags",
 					read: "public" as PermissionText,
@@ -65,9 +112,61 @@ export default defineDevConfig(() => ({
 					write: "controllers" as PermissionText,
 					memory: "stable" as MemoryText,
 					mutablePermissions: true
+				},
+				{
+					collection: "vote_credits",
+					read: "controllers" as PermissionText,
+					write: "controllers" as PermissionText,
+					memory: "stable" as MemoryText,
+					mutablePermissions: true
+				},
+				{
+					collection: "delegations",
+					read: "public" as PermissionText,
+					write: "private" as PermissionText,
+					memory: "stable" as MemoryText,
+					mutablePermissions: true
+				},
+				{
+					collection: "events",
+					read: "controllers" as PermissionText,
+					write: "controllers" as PermissionText,
+					memory: "stable" as MemoryText,
+					mutablePermissions: true
+				},
+				{
+					collection: "logs",
+					read: "controllers" as PermissionText,
+					write: "controllers" as PermissionText,
+					memory: "stable" as MemoryText,
+					mutablePermissions: true
+				},
+				{
+					// Settings changed by controllers, written by the satellite only
+					collection: "settings",
+					read: "controllers" as PermissionText,
+					write: "controllers" as PermissionText,
+					memory: "stable" as MemoryText,
+					mutablePermissions: true
+				},
+				{
+					collection: "jobs",
+					read: "controllers" as PermissionText,
+					write: "controllers" as PermissionText,
+					memory: "stable" as MemoryText,
+					mutablePermissions: true
 				}
 			],
-			storage: []  // No storage collections needed yet
+			storage: [
+				{
+					// Static JSON reputation snapshots, uploaded by the satellite
+					collection: "snapshots",
+					read: "public" as PermissionText,
+					write: "controllers" as PermissionText,
+					memory: "heap" as MemoryText,
+					mutablePermissions: true
+				}
+			]
 		}
 	}
 }));
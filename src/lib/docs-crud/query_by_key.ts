@@ -1,4 +1,6 @@
-import { listDocs } from '@junobuild/core';
+import { listDocs, type Doc } from '@junobuild/core';
+import type { HandleHistoryData, UserData, UserHandleData } from '$lib/types';
+import { canonicalHandle } from '$lib/keys/canonical_handle';
 
 /**
  * Query documents by key pattern (filtering happens in the backend).
@@ -41,4 +43,53 @@ export async function queryDocsByKey<T>(collection: string, keyPattern: string)
     collection,
     filter: { matcher: { key: keyPattern } }
   });
-} 
\ No newline at end of file
+} 
+
+/**
+ * Looks a user up by handle through the users_handles index.
+ *
+ * The index key (hdl_{handle}_usr_{ulid}_) gives the user's ULID, which is then used to
+ * fetch the user document (_prn_{principal}_usr_{ulid}_). Users that haven't been
+ * migrated to the index yet still carry the handle in their legacy key, so they are
+ * looked up there as a fallback.
+ *
+ * A handle nobody holds anymore resolves through users_handles_history to the user who
+ * released it last. The returned document then has a different `user_handle`, which
+ * callers should redirect to.
+ *
+ * @param handle - The user's handle (canonicalized like in keys)
+ * @returns {Promise<import('@junobuild/core').Doc<UserData> | undefined>} - The user document, if any
+ */
+export async function queryUserByHandle(handle: string): Promise<Doc<UserData> | undefined> {
+  const normalized = canonicalHandle(handle);
+
+  const index = await queryDocsByKey<UserHandleData>('users_handles', `^hdl_${normalized}_usr_`);
+  if (index.items.length) {
+    const user = await queryUserByUlid(index.items[0].data.user_ulid);
+    if (user) {
+      return user;
+    }
+  }
+
+  // Legacy user keys: _prn_{principal}_usr_{ulid}_hdl_{handle}_
+  const legacy = await queryDocsByKey<UserData>('users', `_hdl_${normalized}_$`);
+  if (legacy.items.length) {
+    return legacy.items[0];
+  }
+
+  // Old handle: follow the most recent release to the user's current document
+  const history = await queryDocsByKey<HandleHistoryData>('users_handles_history', `^hdl_${normalized}_usr_`);
+  const latest = [...history.items].sort((a, b) => Number(b.data.released_at - a.data.released_at))[0];
+  return latest ? queryUserByUlid(latest.data.user_ulid) : undefined;
+}
+
+/**
+ * Fetches a user document by ULID, whatever its key format.
+ *
+ * @param userUlid - The user's ULID
+ * @returns {Promise<import('@junobuild/core').Doc<UserData> | undefined>} - The user document, if any
+ */
+async function queryUserByUlid(userUlid: string): Promise<Doc<UserData> | undefined> {
+  const users = await queryDocsByKey<UserData>('users', `_usr_${userUlid}_`);
+  return users.items[0];
+}
@@ -40,8 +40,8 @@ export async function createUserDoc({
   // Generate a new unique user ID (ULID) for this user document.
   const userUlid = createUlid();
 
-  // Build the document key using the principal, ULID, and user handle.
-  const userDocKey = formatUserKey(principal, userUlid, user_handle);
+  // Build the document key using the principal and ULID.
+  const userDocKey = formatUserKey(principal, userUlid);
 
   // Prepare the user data object to be stored in the database.
   const userDocData = {
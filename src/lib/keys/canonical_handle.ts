@@ -0,0 +1,18 @@
+/**
+ * Canonical form of a user or tag handle, as written in document keys
+ *
+ * Mirrors `CanonicalHandle` in src/satellite/src/utils/normalize.rs: trims, applies
+ * Unicode NFKC normalization, lowercases and keeps only letters, digits and hyphens.
+ * Two handles collide if and only if their canonical forms are equal, so every key
+ * pattern built from a handle must use this form.
+ *
+ * @param handle - The handle as entered or displayed (string, any case)
+ * @returns {string} The canonical handle
+ */
+export function canonicalHandle(handle: string): string {
+    return handle
+        .trim()
+        .normalize('NFKC')
+        .toLowerCase()
+        .replace(/[^\p{Alphabetic}\p{N}-]/gu, '');
+}
@@ -1,4 +1,5 @@
 import { isValid } from 'ulid';
+import { canonicalHandle } from './canonical_handle';
 
 /**
  * Formats a tag document key
@@ -6,7 +7,7 @@ import { isValid } from 'ulid';
  *
  * @param userUlid - The creator's user ULID (string type)
  * @param tagUlid - The tag's ULID (string type)
- * @param tagHandle - The tag's handle (string, original case)
+ * @param tagHandle - The tag's handle (string, original case, canonicalized for the key)
  * @returns {string} The formatted tag document key
  */
 export function formatTagKey(userUlid: string, tagUlid: string, tagHandle: string): string {
@@ -16,8 +17,8 @@ export function formatTagKey(userUlid: string, tagUlid: string, tagHandle: strin
     if (!isValid(tagUlid)) {
         throw new Error('Invalid tag ULID provided for tag key formatting');
     }
-    // Normalize tag handle for key (lowercase, no spaces)
-    const normalizedTagHandle = tagHandle.toLowerCase();
+    // Canonicalize tag handle for key, like the satellite does
+    const normalizedTagHandle = canonicalHandle(tagHandle);
     // Compose the key in the required format
     return `usr_${userUlid}_tag_${tagUlid}_hdl_${normalizedTagHandle}_`;
 } 
\ No newline at end of file
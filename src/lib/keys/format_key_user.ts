@@ -2,19 +2,19 @@ import { isValid } from 'ulid';
 
 /**
  * Formats a user document key
- * Format: _prn_{principal}_usr_{userUlid}_hdl_{username}_
+ * Format: _prn_{principal}_usr_{userUlid}_
+ *
+ * The handle is no longer part of the key: handles are looked up through the
+ * users_handles index collection (hdl_{handle}_usr_{userUlid}_), which the
+ * satellite maintains whenever the user document changes.
  *
  * @param principal - The user's principal (string)
  * @param userUlid - The user's ULID (string, must be uppercase, 26 chars)
- * @param username - The user's handle (string)
  * @returns {string} The formatted user document key
  */
-export function formatUserKey(principal: string, userUlid: string, username: string): string {
+export function formatUserKey(principal: string, userUlid: string): string {
     if (!isValid(userUlid)) {
         throw new Error('Invalid ULID provided for user key formatting');
     }
-    // Normalize username for key (lowercase, no spaces)
-    const normalizedUsername = username.toLowerCase();
-    // Compose the key in the new format
-    return `_prn_${principal}_usr_${userUlid}_hdl_${normalizedUsername}_`;
-} 
\ No newline at end of file
+    return `_prn_${principal}_usr_${userUlid}_`;
+}
\ No newline at end of file
@@ -41,6 +41,9 @@
 // ULID creation
 export { createUlid } from './create_ulid';
 
+// Canonical handle, as written in keys
+export { canonicalHandle } from './canonical_handle';
+
 // Document key formatting functions
 export { formatUserKey } from './format_key_user';
 export { formatTagKey } from './format_key_tag';
@@ -22,6 +22,83 @@ export interface UserData {
     description?: string;   // User profile description (optional)
 }
 
+/**
+ * Users handles index data interface
+ *
+ * Key format: hdl_{handle}_usr_{user_ulid}_
+ * Maintained by the satellite whenever a user document changes.
+ *
+ * - user_ulid: ULID of the user holding the handle (required, string)
+ */
+export interface UserHandleData {
+    user_ulid: string;      // ULID of the user holding the handle (required, string)
+}
+
+/**
+ * Handle history data interface
+ *
+ * Key format: hdl_{handle}_usr_{user_ulid}_
+ * Written by the satellite when a user renames or deletes their account.
+ *
+ * - user_ulid: ULID of the user who released the handle (required, string)
+ * - handle: The released handle, lowercase (required, string)
+ * - released_at: When the handle was released, in nanoseconds (required)
+ * - renamed_to: The user's new handle, absent if the user was deleted (optional)
+ */
+export interface HandleHistoryData {
+    user_ulid: string;      // ULID of the user who released the handle (required, string)
+    handle: string;         // The released handle, lowercase (required, string)
+    released_at: bigint;    // When the handle was released, in nanoseconds (required)
+    renamed_to?: string;    // The user's new handle, absent if the user was deleted (optional)
+}
+
+/**
+ * Rename log of a user, stored in the users_handles_renames collection (controllers only)
+ * Key format: usr_{user_ulid}_
+ */
+export interface HandleRenamesData {
+    user_ulid: string;      // ULID of the user (required, string)
+    renamed_at: bigint[];   // When the user renamed, in nanoseconds, oldest first (required)
+}
+
+/**
+ * Reserved or blocked handle, stored in the reserved_handles collection (controllers only)
+ * Key format: skl_{skeleton}_, so look-alikes of the handle share the entry
+ */
+export interface ReservedHandleData {
+    handle: string;         // The reserved handle, as entered (required, string)
+    reserved_for?: string;  // Principal allowed to take it, absent if blocked for everyone (optional)
+    reason: string;         // Why the handle is reserved (required, string)
+}
+
+/**
+ * Content moderation term, stored in the moderation_terms collection (controllers only)
+ * Key format: trm_{folded term}_, so spellings that fold the same share the entry
+ */
+export type ModerationSeverity = 'flag' | 'reject';
+
+export interface ModerationTermData {
+    term: string;                   // The term, as entered (required, string)
+    severity: ModerationSeverity;   // 'reject' blocks the write, 'flag' queues it for review (required)
+    match_inside_words: boolean;    // Also match inside longer words (required)
+}
+
+/**
+ * Document flagged for review, stored in the moderation_queue collection (controllers only)
+ * Key format: {collection}_{documentKey}
+ */
+export interface FlaggedTerm {
+    field: string;          // The field the term was found in, e.g. "display_name" (required, string)
+    term: string;           // The matching moderation term (required, string)
+}
+
+export interface ModerationQueueData {
+    collection: string;     // Collection of the flagged document, "users" or "tags" (required, string)
+    doc_key: string;        // Key of the flagged document (required, string)
+    matches: FlaggedTerm[]; // The terms found in the document (required)
+    flagged_at: bigint;     // When the document was flagged, in nanoseconds (required)
+}
+
 /**
  * Tag data interface
  *
@@ -39,6 +116,12 @@ export interface UserData {
  * - reputation_threshold: Minimum reputation needed for voting power
  * - vote_reward: Reputation points given for casting votes
  * - min_users_for_threshold: Minimum users needed before vote rewards are restricted
+ * - vote_cooldown: Minutes an author must wait before voting on the same target again (0-44640, 0 = none)
+ * - duplicate_vote_policy: How repeated votes on the same target count (default 'cumulative')
+ * - duplicate_vote_cap: Max votes per target with the cumulative policy (0 = no cap)
+ * - reciprocal_vote_dampening: Reduction of positive votes between flagged reciprocal voters (0.0-1.0, 0 = off)
+ * - vote_credits: Optional voting credits budget (absent = votes are free)
+ * - vote_mode: 'standard' (default) or 'quadratic' (requires vote_credits)
  */
 export interface TagData {
     owner_ulid?: string;         // ULID key of the creator (references Users collection)
@@ -55,6 +138,38 @@ export interface TagData {
     reputation_threshold?: number;    // Minimum reputation needed for voting power
     vote_reward?: number;             // Reputation points given for casting votes
     min_users_for_threshold?: number; // Minimum users needed before vote rewards are restricted
+    vote_cooldown?: number;           // Cooldown in minutes between votes on the same target (0 = none)
+    duplicate_vote_policy?: DuplicateVotePolicy; // How repeated votes on the same target count
+    duplicate_vote_cap?: number;      // Max votes per target with the cumulative policy (0 = none)
+    reciprocal_vote_dampening?: number; // Reduction of flagged reciprocal upvotes (0.0 = off, 1.0 = ignored)
+    vote_credits?: VoteCreditsConfig;   // Voting credits budget (absent = votes are free)
+    vote_mode?: VoteMode;               // 'quadratic' lets votes carry an intensity paid for in credits
+}
+
+/**
+ * Policy for repeated votes by the same author on the same target within a tag
+ * - 'cumulative': every vote counts (up to duplicate_vote_cap if set)
+ * - 'latest_wins': only the most recent vote counts
+ * - 'one_vote': a single vote per target; it can be edited but not repeated
+ */
+export type DuplicateVotePolicy = 'cumulative' | 'latest_wins' | 'one_vote';
+
+/**
+ * Voting mode of a tag
+ * - 'standard': every vote counts once
+ * - 'quadratic': a vote with intensity n counts n times and costs n² times its credit cost
+ */
+export type VoteMode = 'standard' | 'quadratic';
+
+/**
+ * Voting credits configuration for a tag
+ * Each user starts with max_credits and regains one credit every regen_minutes.
+ */
+export interface VoteCreditsConfig {
+    max_credits: number;         // Maximum (and starting) balance (1-10000)
+    regen_minutes: number;       // Minutes to regenerate one credit (0 = never)
+    positive_vote_cost: number;  // Credits spent by a positive vote
+    negative_vote_cost: number;  // Credits spent by a negative vote
 }
 
 /**
@@ -74,6 +189,89 @@ export interface VoteData {
     vote_ulid?: string;      // ULID for this specific vote (generated internally)
     value?: number;         // Vote value (+1 for upvote, -1 for downvote)
     weight?: number;        // Vote weight (default: 1.0)
+    intensity?: number;     // Vote intensity in quadratic voting tags (cost = intensity², default: 1)
+}
+
+/**
+ * Delegation Document
+ * 
+ * Delegates the owner's voting power in a tag to another user.
+ * Deleting the document revokes the delegation.
+ * 
+ * Key format: usr_{delegator_ulid}_tag_{tag_ulid}_del_{delegate_ulid}_
+ */
+export interface DelegationData {
+    delegator_ulid: string;  // User giving away their voting power (references Users collection)
+    delegate_ulid: string;   // User receiving the voting power (references Users collection)
+    tag_ulid: string;        // Tag the delegation applies to (references Tags collection)
+}
+
+/**
+ * Event Document
+ * 
+ * Append-only reputation event outbox, written by the satellite.
+ * Consumers read it with the `get_events_since(cursor, limit)` query.
+ * 
+ * Key format: evt_{sequence}_ (sequence zero-padded to 20 digits)
+ * Description format: tag_{tag_ulid}_type_{event_type}_
+ */
+export type EventType =
+    | 'VoteCast'
+    | 'VoteChanged'
+    | 'VoteRemoved'
+    | 'ReputationChanged'
+    | 'VotingPowerGained'
+    | 'VotingPowerLost'
+    | 'TagPhaseChanged';
+
+export interface EventData {
+    sequence: number;                // Position in the log, strictly increasing
+    event_type: EventType;           // Kind of event
+    tag_ulid: string;                // Tag the event happened in
+    user_ulid?: string;              // User the event is about (vote author for vote events)
+    target_ulid?: string;            // Vote target (vote events only)
+    vote_ulid?: string;              // Vote ULID (vote events only)
+    value?: number;                  // Vote value or new effective reputation
+    previous_value?: number;         // Previous value, for changes
+    in_bootstrap_phase?: boolean;    // New phase (TagPhaseChanged only)
+    created_at: number;              // When the event was recorded (nanoseconds)
+}
+
+/**
+ * Log Document
+ * 
+ * Persistent log entries, written by the satellite's logger.
+ * Controllers read them with the `get_logs(filter, cursor, limit)` query.
+ * 
+ * Key format: log_{timestamp}_{sequence}_ (zero-padded to 20 and 10 digits)
+ * Description format: lvl_{level}_mod_{module}_
+ */
+export type LogLevel = 'Debug' | 'Info' | 'Warn' | 'Error';
+
+export interface LogEntry {
+    level: LogLevel;                 // Severity
+    module: string;                  // Module path of the call site
+    message: string;                 // Formatted message, including the [file:line] prefix
+    correlation_id: string;          // Shared by every entry of the same invocation
+    created_at: number;              // When the entry was written (nanoseconds)
+}
+
+export type JobStep =
+    | { VoteWeight: { user_ulid: string; tag_ulid: string } }
+    | { Reputation: { user_ulid: string; tag_ulid: string } };
+
+export type JobStatus = 'Pending' | 'Failed';
+
+export interface JobData {
+    job_id: string;                  // Key of the job document
+    origin: string;                  // What created the job, e.g. "process_vote"
+    steps: JobStep[];                // Every step of the job, in order
+    next_step: number;               // Index of the next step to run
+    status: JobStatus;               // Pending, or Failed after 3 failed runs
+    attempts: number;                // Runs that ended with a failed step
+    last_error?: string;             // Error of the last failed step
+    created_at: number;              // When the job was checkpointed (nanoseconds)
+    updated_at: number;              // When the job last progressed or failed (nanoseconds)
 }
 
 /**
@@ -100,16 +298,63 @@ export interface ReputationData {
     has_voting_power: boolean;              // Whether user has sufficient reputation
 }
 
+/**
+ * Error returned by the satellite's endpoints (`Err : KarmieError` in Candid).
+ * 
+ * Document writes rejected by `assert_set_doc` can only carry a string, so they
+ * use the form "KARMIE_<CODE>: <message>" instead, e.g.
+ * "KARMIE_HANDLE_TAKEN: Username 'alice' is already taken. Please choose a different username."
+ * Match on the variant (or the code prefix), never on the English message.
+ */
+export type KarmieError =
+    | { InvalidData: { collection: string; reason: string } }
+    | { InvalidField: { field: string; reason: string } }
+    | { InvalidUlid: { ulid: string; reason: string } }
+    | { InvalidKey: { key: string; reason: string } }
+    | { KeyMismatch: { expected: string; actual: string } }
+    | { ImmutableField: { field: string } }
+    | { HandleTaken: { handle: string } }
+    | { HandleQuarantined: { handle: string; available_in_seconds: bigint } }
+    | { HandleConfusable: { handle: string; similar_to: string } }
+    | { HandleReserved: { handle: string } }
+    | { TagNameTaken: { name: string } }
+    | { ContentRejected: { field: string } }
+    | { AccountExists: { principal: string } }
+    | { SelfVote: null }
+    | { SelfDelegation: null }
+    | { NotFound: { entity: string; id: string } }
+    | { CallerMismatch: { field: string; claimed: string; actual: string } }
+    | { Unauthorized: { reason: string } }
+    | { VoteCooldown: { remaining_minutes: number } }
+    | { DuplicateVote: { reason: string } }
+    | { InsufficientCredits: { balance: number; cost: number } }
+    | { DelegationRejected: { reason: string } }
+    | { RateLimited: { collection: string; retry_after_seconds: bigint } }
+    | { LimitExceeded: { what: string; max: bigint; actual: bigint } }
+    | { Storage: { collection: string; reason: string } }
+    | { Internal: { message: string } };
+
 // --- Canonical document types using the SDK's Doc<T> ---
 
 /** A user document as returned by Juno SDK */
 export type UserDocument = Doc<UserData>;
+/** A users_handles index document as returned by Juno SDK */
+export type UserHandleDocument = Doc<UserHandleData>;
+/** A users_handles_history document as returned by Juno SDK */
+export type HandleHistoryDocument = Doc<HandleHistoryData>;
 /** A tag document as returned by Juno SDK */
 export type TagDocument = Doc<TagData>;
 /** A vote document as returned by Juno SDK */
 export type VoteDocument = Doc<VoteData>;
 /** A reputation document as returned by Juno SDK */
 export type ReputationDocument = Doc<ReputationData>;
+/** A delegation document as returned by Juno SDK */
+export type DelegationDocument = Doc<DelegationData>;
+/** An event document as returned by Juno SDK */
+export type EventDocument = Doc<EventData>;
+/** A log document as returned by Juno SDK */
+export type LogDocument = Doc<LogEntry>;
+export type JobDocument = Doc<JobData>;
 
 // Optionally, you can define a generic alias for all your documents:
 export type AppDocument<T> = Doc<T>; 
\ No newline at end of file
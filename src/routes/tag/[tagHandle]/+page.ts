@@ -5,6 +5,7 @@ import { authUserDoc } from '$lib/stores/authUserDoc';
 import { listDocs } from '@junobuild/core';
 import type { TagDocument } from '$lib/types';
 import { queryDocsByKey } from '$lib/docs-crud/query_by_key';
+import { canonicalHandle } from '$lib/keys/mod.js';
 import { dummyData } from '$lib/data/dummyProfileData';
 
 // Disable prerendering for this dynamic route
@@ -32,8 +33,8 @@ export const load: PageLoad = async ({ params }) => {
       tagHandle,
       fetchTagData: async () => {
         try {
-          // Normalize handle to lowercase to match database storage format
-          const normalizedHandle = tagHandle.toLowerCase();
+          // Canonicalize handle to match database storage format
+          const normalizedHandle = canonicalHandle(tagHandle);
           
           // Query using the normalized handle pattern in the key (tags are stored as: usr_{userUlid}_tag_{tagUlid}_hdl_{tagHandle}_)
           const results = await queryDocsByKey<TagDocument>('tags', `hdl_${normalizedHandle}_`);
@@ -1,6 +1,6 @@
 import type { PageLoad } from './$types';
 import { error } from '@sveltejs/kit';
-import { queryDocsByKey } from '$lib/docs-crud/query_by_key';
+import { queryUserByHandle } from '$lib/docs-crud/query_by_key';
 import { get } from 'svelte/store';
 import { authUserDoc } from '$lib/stores/authUserDoc';
 import { dummyProfileData } from '$lib/data/dummyProfileData';
@@ -44,14 +44,12 @@ export const load: PageLoad = async ({ params }) => {
     handle,
     fetchUserData: async () => {
       try {
-        // Query using the hdl_ prefix pattern as per database schema
-        const results = await queryDocsByKey<UserDocument>('users', `hdl_${handle}_`);
-        
-        if (!results.items.length) {
+        // Resolve the handle through the users_handles index
+        const userDoc: UserDocument | undefined = await queryUserByHandle(handle);
+
+        if (!userDoc) {
           throw error(404, 'User not found');
         }
-
-        const userDoc = results.items[0];
         
         return {
           user: userDoc,
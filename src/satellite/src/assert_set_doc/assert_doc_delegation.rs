@@ -0,0 +1,136 @@
+use crate::logger;
+use junobuild_satellite::AssertSetDocContext;
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::{DelegationData, UserData};
+use junobuild_utils::decode_doc_data;
+use crate::processors::document_keys::DocKey;
+use crate::processors::document_queries::query_doc_by_key;
+use crate::processors::key_pattern::{KeyPattern, KeySegment};
+use crate::core::{check_delegation_cycle, get_incoming_delegations, get_outgoing_delegation};
+
+/// Validates a delegation document before creation or update
+///
+/// This function performs the following validation steps:
+/// 1. Decodes the delegation data
+/// 2. Verifies the document key matches the delegator, tag and delegate in the data
+/// 3. Prevents self-delegation
+/// 4. Verifies the caller is the delegator
+/// 5. Verifies the tag exists
+/// 6. Verifies the delegate exists
+/// 7. Ensures the delegator has no other delegation in the tag
+/// 8. Rejects delegations that would create a cycle or an over-long chain
+///
+/// Revoking a delegation is done by deleting the document.
+///
+/// # Arguments
+/// * `context` - The validation context containing the document data
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if validation passes, the reason it failed otherwise
+pub fn validate_delegation_document(context: &AssertSetDocContext) -> Result<(), KarmieError> {
+    logger!("debug", "[validate_delegation_document] Starting delegation validation: key={}", context.data.key);
+
+    // Step 1: Decode the delegation data
+    let delegation: DelegationData = decode_doc_data(&context.data.data.proposed.data)
+        .map_err(|e| {
+            logger!("error", "[validate_delegation_document] Invalid delegation data format: {}", e);
+            KarmieError::invalid_data("delegations", e)
+        })?;
+
+    // Step 2: Verify the key matches the data
+    // The parties are encoded in the key, which is how delegations are queried
+    let expected_key = DocKey::delegation(&delegation.delegator_ulid, &delegation.tag_ulid, &delegation.delegate_ulid)
+        .map_err(|e| {
+            logger!("error", "[validate_delegation_document] Invalid delegation data: {}", e);
+            e
+        })?;
+    if context.data.key != expected_key.to_string() {
+        logger!("error", "[validate_delegation_document] Document key does not match delegation data (expected: {}, got: {})",
+            expected_key, context.data.key);
+        return Err(KarmieError::KeyMismatch {
+            expected: expected_key.to_string(),
+            actual: context.data.key.clone(),
+        });
+    }
+
+    // Step 3: Prevent self-delegation
+    if delegation.delegator_ulid == delegation.delegate_ulid {
+        logger!("error", "[validate_delegation_document] Users cannot delegate to themselves");
+        return Err(KarmieError::SelfDelegation);
+    }
+
+    // Step 4: Verify the caller is the delegator
+    let caller_principal = context.caller.to_string();
+    let user_results = query_doc_by_key("users", &KeyPattern::new().anchor_start().prn(&caller_principal).build())
+        .map_err(|e| KarmieError::storage("users", e))?;
+    let (_, user_doc) = user_results.items.first().ok_or_else(|| {
+        logger!("error", "[validate_delegation_document] No user document found for caller: {}", caller_principal);
+        KarmieError::not_found("User", &caller_principal)
+    })?;
+    let caller_user_data: UserData = decode_doc_data(&user_doc.data)
+        .map_err(|e| KarmieError::invalid_data("users", e))?;
+
+    if caller_user_data.user_ulid.as_deref() != Some(delegation.delegator_ulid.as_str()) {
+        let actual = caller_user_data.user_ulid.unwrap_or_default();
+        logger!("error", "[validate_delegation_document] delegator_ulid mismatch: delegation claims to be from user '{}' but caller's user_ulid is '{}'",
+            delegation.delegator_ulid, actual);
+        return Err(KarmieError::CallerMismatch {
+            field: "delegator_ulid".to_string(),
+            claimed: delegation.delegator_ulid.clone(),
+            actual,
+        });
+    }
+
+    // Step 5: Verify the tag exists
+    let tag_results = query_doc_by_key("tags", &KeyPattern::new().tag(&delegation.tag_ulid).build())
+        .map_err(|e| KarmieError::storage("tags", e))?;
+    if tag_results.items.is_empty() {
+        logger!("error", "[validate_delegation_document] Tag not found: {}", delegation.tag_ulid);
+        return Err(KarmieError::not_found("Tag", &delegation.tag_ulid));
+    }
+
+    // Step 6: Verify the delegate exists
+    let delegate_results = query_doc_by_key("users", &KeyPattern::new().usr(&delegation.delegate_ulid).build())
+        .map_err(|e| KarmieError::storage("users", e))?;
+    if delegate_results.items.is_empty() {
+        logger!("error", "[validate_delegation_document] Delegate not found: {}", delegation.delegate_ulid);
+        return Err(KarmieError::not_found("Delegate", &delegation.delegate_ulid));
+    }
+
+    // Step 7: Only one delegation per delegator and tag
+    let existing = query_doc_by_key(
+        "delegations",
+        &KeyPattern::new().anchor_start().usr(&delegation.delegator_ulid).tag(&delegation.tag_ulid).any(KeySegment::Del).build(),
+    )
+    .map_err(|e| KarmieError::storage("delegations", e))?;
+    if let Some((other_key, _)) = existing.items.iter().find(|(key, _)| *key != context.data.key) {
+        let reason = format!(
+            "User {} already delegates in this tag ({}). Revoke it before delegating to someone else.",
+            delegation.delegator_ulid, other_key
+        );
+        logger!("error", "[validate_delegation_document] {}", reason);
+        return Err(KarmieError::DelegationRejected { reason });
+    }
+
+    // Step 8: Reject cycles and over-long chains
+    check_delegation_cycle(
+        &delegation.delegator_ulid,
+        &delegation.delegate_ulid,
+        |user| Ok(get_outgoing_delegation(user, &delegation.tag_ulid)?.map(|d| d.delegate_ulid)),
+        |user| {
+            Ok(get_incoming_delegations(user, &delegation.tag_ulid)?
+                .into_iter()
+                .map(|d| d.delegator_ulid)
+                .collect())
+        },
+    )
+    .map_err(|e| {
+        logger!("error", "[validate_delegation_document] {}", e);
+        e
+    })?;
+
+    logger!("info", "[validate_delegation_document] Delegation validation passed: {} -> {} in tag={}",
+        delegation.delegator_ulid, delegation.delegate_ulid, delegation.tag_ulid);
+
+    Ok(())
+}
@@ -1,8 +1,9 @@
 use crate::logger;
 use junobuild_satellite::AssertSetDocContext;
 use junobuild_utils::decode_doc_data;
+use crate::utils::errors::KarmieError;
 use crate::utils::structs::ReputationData;
-use crate::processors::document_keys::{validate_reputation_key, format_reputation_key};
+use crate::processors::document_keys::DocKey;
 
 /// Validates a reputation document before creation or update
 /// 
@@ -20,17 +21,16 @@ use crate::processors::document_keys::{validate_reputation_key, format_reputatio
 ///   - data.data.proposed.data: The binary data of the proposed document
 /// 
 /// # Returns
-/// * `Result<(), String>` - Ok if validation passes, Err with detailed message if it fails
-pub fn validate_reputation_document(context: &AssertSetDocContext) -> Result<(), String> {
+/// * `Result<(), KarmieError>` - Ok if validation passes, the reason it failed otherwise
+pub fn validate_reputation_document(context: &AssertSetDocContext) -> Result<(), KarmieError> {
     // Step 1: Verify collection name
     // This ensures we're only validating documents in the correct collection
     if context.data.collection != "reputations" {
-        let err_msg = format!(
-            "[validate_reputation_document] Invalid collection: expected 'reputations', got '{}'",
-            context.data.collection
-        );
-        logger!("error", "{}", err_msg);
-        return Err(err_msg);
+        logger!("error", "[validate_reputation_document] Invalid collection: expected 'reputations', got '{}'",
+            context.data.collection);
+        return Err(KarmieError::Internal {
+            message: format!("Expected the reputations collection, got '{}'", context.data.collection),
+        });
     }
 
     logger!("debug", "[validate_reputation_document] Validating reputation document: key={}", context.data.key );
@@ -41,32 +41,32 @@ pub fn validate_reputation_document(context: &AssertSetDocContext) -> Result<(),
     let rep_data: ReputationData = decode_doc_data(&context.data.data.proposed.data)
         .map_err(|e| {
             logger!("error", "[validate_reputation_document] Failed to decode reputation data: {}", e);
-            format!("Failed to decode reputation data: {}", e)
+            KarmieError::invalid_data("reputations", e)
         })?;
 
-    // Step 3: Validate the key format using document_keys validation
+    // Step 3: Parse the key with the typed document key model
     // This ensures the key follows our standardized format: usr_{user.data.key}_tag_{tag.data.ulid}_
-    validate_reputation_key(&context.data.key)
+    let key = DocKey::parse_kind(&context.data.key, "reputation")
         .map_err(|e| {
             logger!("error", "[validate_reputation_document] Invalid reputation key format: {}", e);
-            format!("Invalid reputation key format: {}", e)
+            e
         })?;
     
     // Step 3.1: Verify the key matches the data
     // Generate the expected key from the data and compare with actual key
-    let expected_key = format_reputation_key(&rep_data.owner_ulid, &rep_data.tag_ulid)
+    let expected_key = DocKey::reputation(&rep_data.owner_ulid, &rep_data.tag_ulid)
         .map_err(|e| {
             logger!("error", "[validate_reputation_document] Failed to format reputation key: {}", e);
-            format!("Failed to format reputation key: {}", e)
+            e
         })?;
     
-    if context.data.key != expected_key {
-        let err_msg = format!(
-            "[validate_reputation_document] Key does not match data. Expected: {}, Got: {}",
-            expected_key, context.data.key
-        );
-        logger!("error", "{}", err_msg);
-        return Err(err_msg);
+    if key != expected_key {
+        logger!("error", "[validate_reputation_document] Key does not match data. Expected: {}, Got: {}",
+            expected_key, context.data.key);
+        return Err(KarmieError::KeyMismatch {
+            expected: expected_key.to_string(),
+            actual: context.data.key.clone(),
+        });
     }
 
     // Step 4: Validate field constraints
@@ -78,11 +78,11 @@ pub fn validate_reputation_document(context: &AssertSetDocContext) -> Result<(),
     // 4.2: Validate voting rewards (must be non-negative)
     if rep_data.reputation_rewards < 0.0 {
         let err_msg = format!(
-            "[validate_reputation_document] Total voting rewards reputation cannot be negative (got: {})",
+            "Total voting rewards reputation cannot be negative (got: {})",
             rep_data.reputation_rewards
         );
-        logger!("error", "{}", err_msg);
-        return Err(err_msg);
+        logger!("error", "[validate_reputation_document] {}", err_msg);
+        return Err(KarmieError::invalid_field("reputation_rewards", err_msg));
     }
 
     // 4.3: Validate vote weight (must be between 0.0 and 1.0)
@@ -90,11 +90,11 @@ pub fn validate_reputation_document(context: &AssertSetDocContext) -> Result<(),
     let weight_value = rep_data.vote_weight.value();
     if weight_value < 0.0 || weight_value > 1.0 {
         let err_msg = format!(
-            "[validate_reputation_document] Vote weight must be between 0.0 and 1.0 (got: {})",
+            "Vote weight must be between 0.0 and 1.0 (got: {})",
             weight_value
         );
-        logger!("error", "{}", err_msg);
-        return Err(err_msg);
+        logger!("error", "[validate_reputation_document] {}", err_msg);
+        return Err(KarmieError::invalid_field("vote_weight", err_msg));
     }
 
     logger!("info", "[validate_reputation_document] Successfully validated reputation document: key={}", context.data.key );
@@ -1,98 +1,251 @@
 use junobuild_satellite::AssertSetDocContext;
 use junobuild_utils::decode_doc_data;
 use crate::{
-    validation::{validate_handle, validate_tag_date_struct, validate_description},
+    validation::{validate_handle, validate_tag_date_struct, validate_description, validate_vote_cooldown, validate_duplicate_vote_policy,
+        validate_reciprocal_vote_dampening, validate_vote_credits, validate_vote_mode},
     utils::structs::TagData,
+    utils::errors::KarmieError,
     logger,
-    utils::normalize::normalize_handle,
-    processors::document_queries::query_doc_by_key,
+    utils::normalize::CanonicalHandle,
+    processors::document_keys::DocKey,
+    processors::document_queries::DocQuery,
+    processors::key_pattern::{KeyPattern, KeySegment},
+    core::{moderate_content, assert_handle_not_reserved, is_rekeying_tag, tag_moderated_fields},
 };
 
 /// Validates a tag document before creation or update
 /// 
 /// This function performs comprehensive validation of tag documents:
-/// 1. Decodes and validates the basic tag data structure
+/// 1. Decodes and validates the basic tag data structure, and checks it matches the document key
 /// 2. Validates tag name format and restrictions (using username validation)
+///    - New names must not be reserved, taken, or look like a taken name (see `validate_new_tag_name`)
 /// 3. Validates description format and length
+///    - New or changed names and descriptions must not contain disallowed terms (see core/moderation.rs)
 /// 4. Validates time period configuration
 /// 5. Validates reputation and voting settings
+/// 6. Validates the vote cooldown (0 to 44640 minutes)
+/// 7. Validates the duplicate vote policy and cap
+/// 8. Validates the reciprocal vote dampening (0.0 to 1.0)
+/// 9. Validates the voting credits configuration, if enabled
+/// 10. Validates the voting mode (quadratic voting requires credits)
 /// 
 /// # Arguments
 /// * `context` - The validation context containing:
 ///   - caller: The Principal ID of the user making the request
 ///   - collection: Must be "tags"
-///   - key: The document key (nanoid-generated)
+///   - key: The document key, `usr_{owner}_tag_{tag}_hdl_{tagName}_`
 ///   - data: The proposed document data
 /// 
 /// # Returns
-/// * `Result<(), String>` - Ok if validation passes, Err with detailed message if it fails
-pub fn validate_tag_document(context: &AssertSetDocContext) -> Result<(), String> {
+/// * `Result<(), KarmieError>` - Ok if validation passes, the reason it failed otherwise
+pub fn validate_tag_document(context: &AssertSetDocContext) -> Result<(), KarmieError> {
     // Step 1: Decode and validate the basic tag data structure
     let tag_data: TagData = decode_doc_data(&context.data.data.proposed.data)
         .map_err(|e| {
             logger!("error", "[assert_set_doc] Failed to decode tag data: key={}, error={}", context.data.key, e);
-            format!("Invalid tag data format: {}", e)
+            KarmieError::invalid_data("tags", e)
         })?;
     
+    // Step 1.1: Verify the key matches the data
+    // Name checks read the names of other tags from their keys, so a key that doesn't
+    // carry the tag's own name would escape them (and renaming in place would leave it stale)
+    let expected_key = DocKey::tag(&tag_data.owner_ulid, &tag_data.tag_ulid, &tag_data.tag_handle)
+        .map_err(|e| {
+            logger!("error", "[validate_tag_document] Invalid tag data: {}", e);
+            e
+        })?;
+    if !tag_key_matches(&context.data.key, &expected_key, context.data.data.current.is_some()) {
+        logger!("error", "[validate_tag_document] Key does not match data. Expected: {}, Got: {}",
+            expected_key, context.data.key);
+        return Err(KarmieError::KeyMismatch {
+            expected: expected_key.to_string(),
+            actual: context.data.key.clone(),
+        });
+    }
+
     // Step 2: Validate tag name format using username validation patterns
     // This treats the tag's short name like a username with same constraints
     validate_handle(&tag_data.tag_handle)
         .map_err(|e| {
-            let err_msg = format!("[assert_set_doc] Tag name validation failed: {}", e);
-            logger!("error", "{}", err_msg);
-            err_msg
+            logger!("error", "[assert_set_doc] Tag name validation failed: {}", e);
+            KarmieError::invalid_field("tag_handle", e.message())
         })?;
 
-    // Check for tag name uniqueness using normalized handle (normalized so that names like john123 and JOHN123)
-    let normalized_name = normalize_handle(&tag_data.tag_handle);
-    
-    // Query for existing tags with this handle
-    let existing_tags = query_doc_by_key("tags", &format!("hdl_{}_", normalized_name))?;
-
-    // Check if any tags were found (excluding the current document if it's an update)
-    if !existing_tags.items.is_empty() {
-        for (doc_key, doc) in existing_tags.items {
-            // Check if this is an update by looking at current data
-            let is_update = context.data.data.current.is_some();
-    
-            // For updates, skip if we're looking at the current document
-            if is_update && doc_key == context.data.key {
-                logger!("debug", "[validate_tag_document] Skipping current document during update check");
-                continue;
-            }
-        
-            // If we get here, we found a duplicate tag
-            let err_msg = format!("Tag with name '{}' already exists", tag_data.tag_handle);
-            logger!("error", "[validate_tag_document] {}", err_msg);
-            return Err(err_msg);
-        }
+    // Step 2.1: Check the name against reserved and taken names, and their look-alikes
+    // Tags keep the name they already have, so only new names are checked
+    // (tags moved to a canonical key by core/handle_keys.rs keep theirs too)
+    let current_data: Option<TagData> = match &context.data.data.current {
+        Some(current) => Some(decode_doc_data(&current.data)
+            .map_err(|e| {
+                logger!("error", "[validate_tag_document] Failed to decode current tag data: {}", e);
+                KarmieError::invalid_data("tags", e)
+            })?),
+        None => None,
+    };
+    let current_name = current_data.as_ref().map(|current| CanonicalHandle::new(&current.tag_handle));
+    let name = CanonicalHandle::new(&tag_data.tag_handle);
+    if current_name.as_ref() != Some(&name) && !is_rekeying_tag(&context.data.key) {
+        validate_new_tag_name(context, &tag_data, &name)?;
     }
 
     // Step 3: Validate description format and length
     validate_description(&tag_data.description)?;
 
+    // Step 3.1: Reject new or changed content containing disallowed terms
+    // Flagged content is accepted and queued for review, rolled back with the write if it fails
+    if !is_rekeying_tag(&context.data.key) {
+        moderate_content("tags", &context.data.key, &tag_moderated_fields(&tag_data, current_data.as_ref()))
+            .map_err(|e| {
+                logger!("error", "[validate_tag_document] Content rejected for tag {}: {}", context.data.key, e);
+                e
+            })?;
+    }
+
     // Step 4: Validate time periods using the struct validation function
     validate_tag_date_struct(&tag_data.time_periods)?;
 
     // Step 5: Validate vote reward (0.0 to 1.0)
     if tag_data.vote_reward < 0.0 || tag_data.vote_reward > 1.0 {
         let err_msg = format!(
-            "[validate_tag_document] Vote reward must be between 0.0 and 1.0 (got: {})",
+            "Vote reward must be between 0.0 and 1.0 (got: {})",
             tag_data.vote_reward
         );
-        logger!("error", "{}", err_msg);
-        return Err(err_msg);
+        logger!("error", "[validate_tag_document] {}", err_msg);
+        return Err(KarmieError::invalid_field("vote_reward", err_msg));
     }
 
     // Step 6: Validate minimum users (must be greater than 0)
     if tag_data.min_users_for_threshold == 0 {
         let err_msg = format!(
-            "[validate_tag_document] Minimum users must be greater than 0 (got: {})",
+            "Minimum users must be greater than 0 (got: {})",
             tag_data.min_users_for_threshold
         );
-        logger!("error", "{}", err_msg);
-        return Err(err_msg);
+        logger!("error", "[validate_tag_document] {}", err_msg);
+        return Err(KarmieError::invalid_field("min_users_for_threshold", err_msg));
+    }
+
+    // Step 7: Validate vote cooldown (0 to 44640 minutes)
+    validate_vote_cooldown(tag_data.vote_cooldown)?;
+
+    // Step 8: Validate duplicate vote policy (cap only allowed with cumulative votes)
+    validate_duplicate_vote_policy(tag_data.duplicate_vote_policy, tag_data.duplicate_vote_cap)?;
+
+    // Step 9: Validate reciprocal vote dampening (0.0 to 1.0)
+    validate_reciprocal_vote_dampening(tag_data.reciprocal_vote_dampening)?;
+
+    // Step 10: Validate voting credits configuration
+    validate_vote_credits(tag_data.vote_credits.as_ref())?;
+
+    // Step 11: Validate voting mode
+    validate_vote_mode(tag_data.vote_mode, tag_data.vote_credits.as_ref())?;
+
+    Ok(())
+}
+
+/// Whether a tag's key is the one built from its data
+///
+/// New tags must use the exact key. Existing tags may still have a key written with an
+/// earlier handle normalization (see core/handle_keys.rs), as long as it reads as the
+/// same owner, tag and name.
+///
+/// # Arguments
+/// * `key` - The document key
+/// * `expected` - The key built from the tag data
+/// * `is_update` - Whether the tag is already stored
+///
+/// # Returns
+/// * `bool` - True if the key matches the data
+fn tag_key_matches(key: &str, expected: &DocKey, is_update: bool) -> bool {
+    if is_update {
+        DocKey::parse_noncanonical(key).is_ok_and(|parsed| parsed == *expected)
+    } else {
+        key == expected.to_string()
+    }
+}
+
+/// Checks a name a tag is about to take
+///
+/// 1. The name must not be reserved (see core/reserved_handles.rs)
+/// 2. The name must not be taken, or look like a taken name (same confusable skeleton),
+///    so that names like john123 and JOHN123, or "paypal" and "раypal" (Cyrillic "р"), collide
+///
+/// # Arguments
+/// * `context` - The validation context of the tag write
+/// * `tag_data` - The proposed tag data
+/// * `name` - The canonical tag name, as it appears in keys
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if the tag can take the name, the reason it can't otherwise
+fn validate_new_tag_name(context: &AssertSetDocContext, tag_data: &TagData, name: &CanonicalHandle) -> Result<(), KarmieError> {
+    // Step 1: Reject reserved and blocked names, and their look-alikes
+    assert_handle_not_reserved(&tag_data.tag_handle, context.caller)
+        .map_err(|e| {
+            logger!("error", "[validate_new_tag_name] Tag name '{}' is reserved: {}", tag_data.tag_handle, e);
+            e
+        })?;
+
+    // Step 2: Compare against the skeleton of every tag name
+    // Tag keys carry the canonical name; tag writes are rare enough for a key scan
+    let skeleton = name.skeleton();
+    let all_tags = KeyPattern::new().any(KeySegment::Hdl).anchor_end().build();
+    for item in DocQuery::new("tags", &all_tags).iter() {
+        let (doc_key, _) = item?;
+
+        // For updates, skip the current document
+        if doc_key == context.data.key {
+            continue;
+        }
+
+        // Keys written with an earlier normalization still count, with their canonical name
+        let existing_name = match DocKey::parse_noncanonical(&doc_key) {
+            Ok(DocKey::Tag { handle, .. }) => handle,
+            _ => continue,
+        };
+        if existing_name == name.as_str() {
+            logger!("error", "[validate_new_tag_name] Tag with name '{}' already exists", tag_data.tag_handle);
+            return Err(KarmieError::TagNameTaken {
+                name: tag_data.tag_handle.clone(),
+            });
+        }
+        if CanonicalHandle::new(&existing_name).skeleton() == skeleton {
+            logger!("error", "[validate_new_tag_name] Tag name '{}' looks like existing tag '{}'", tag_data.tag_handle, existing_name);
+            return Err(KarmieError::HandleConfusable {
+                handle: tag_data.tag_handle.clone(),
+                similar_to: existing_name,
+            });
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAV";
+    const TAG: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAW";
+
+    #[test]
+    fn test_tag_key_matches_data() {
+        let expected = DocKey::tag(OWNER, TAG, "Rust").unwrap();
+        let key = format!("usr_{}_tag_{}_hdl_rust_", OWNER, TAG);
+        assert!(tag_key_matches(&key, &expected, false));
+        assert!(tag_key_matches(&key, &expected, true));
+
+        // A key carrying another name, e.g. after renaming in place
+        let other_name = format!("usr_{}_tag_{}_hdl_python_", OWNER, TAG);
+        assert!(!tag_key_matches(&other_name, &expected, false));
+        assert!(!tag_key_matches(&other_name, &expected, true));
+
+        // Another owner or tag in the key
+        let other_owner = format!("usr_{}_tag_{}_hdl_rust_", TAG, TAG);
+        assert!(!tag_key_matches(&other_owner, &expected, false));
+        assert!(!tag_key_matches("usr_rust_", &expected, true));
+
+        // Existing tags may keep a key from an earlier normalization, new ones can't
+        let legacy = format!("usr_{}_tag_{}_hdl_Rust_", OWNER, TAG);
+        assert!(tag_key_matches(&legacy, &expected, true));
+        assert!(!tag_key_matches(&legacy, &expected, false));
+    }
+}
@@ -2,10 +2,12 @@ use junobuild_satellite::AssertSetDocContext;
 use junobuild_utils::decode_doc_data;
 use junobuild_shared::types::list::{ListMatcher, ListParams};
 use crate::{
+    utils::errors::KarmieError,
     validation::{validate_handle, validate_display_name, validate_ulid_timestamp, CheckULIDisNew},
     utils::structs::UserData,
-    processors::document_keys::format_user_key,
-    processors::document_queries::query_doc_by_key,
+    processors::document_keys::DocKey,
+    utils::normalize::CanonicalHandle,
+    core::{assert_handle_available, claim_user_handle, assert_handle_not_reserved, assert_handle_unheld, assert_rename_allowed, is_migrating_user, moderate_content, user_moderated_fields},
 };
 use crate::list_docs;
 use crate::logger;
@@ -15,12 +17,20 @@ use crate::IS_PLAYGROUND;
 /// 
 /// This function performs comprehensive validation of user documents:
 /// 1. Validates the document key field format by comparing with a freshly generated comparisson key
-/// 2. Validates username format and restrictions
+/// 2. Validates username format and restrictions, and rejects reserved handles
 /// 3. Validates display name format and restrictions
-/// 4. Ensures username uniqueness across the system
-/// 5. Enforces one-document-per-identity rule in production mode
+/// 4. Ensures username uniqueness against the users_handles index (see core/users_handles.rs),
+///    on the confusable skeleton, including handles other users released recently
+/// 5. Rate limits handle changes (see core/handle_renames.rs)
+/// 6. Rejects display names and handles containing disallowed terms (see core/moderation.rs)
+/// 7. Enforces one-document-per-identity rule in production mode
+/// 8. Claims the handle in the users_handles index, in the same call as the user write
 ///
-/// User key format: _prn_{principal}_usr_{ulid}_hdl_{username}_
+/// User key format: _prn_{principal}_usr_{ulid}_
+///
+/// Users created before the index keep their legacy key (`_prn_{principal}_usr_{ulid}_hdl_{username}_`)
+/// until they are migrated. They can still update their document, but not their handle.
+/// Users with a new key rename by changing `user_handle`.
 /// 
 /// # Arguments
 /// * `context` - The validation context containing:
@@ -30,14 +40,13 @@ use crate::IS_PLAYGROUND;
 ///   - data: The proposed document data
 /// 
 /// # Returns
-/// * `Result<(), String>` - Ok if validation passes, Err with detailed message if it fails
-pub fn assert_doc_user(context: &AssertSetDocContext) -> Result<(), String> {
+/// * `Result<(), KarmieError>` - Ok if validation passes, the reason it failed otherwise
+pub fn assert_doc_user(context: &AssertSetDocContext) -> Result<(), KarmieError> {
     // Step 1: Decode and validate user data
     let user_data: UserData = decode_doc_data(&context.data.data.proposed.data)
         .map_err(|e| {
-            let err_msg = format!("[assert_doc_user] Failed to decode user data: {}", e);
-            logger!("error", "{}", err_msg);
-            err_msg
+            logger!("error", "[assert_doc_user] Failed to decode user data: {}", e);
+            KarmieError::invalid_data("users", e)
         })?;
     
     // Check if we're updating an existing document
@@ -48,47 +57,50 @@ pub fn assert_doc_user(context: &AssertSetDocContext) -> Result<(), String> {
     if let Some(ref user_key) = user_data.user_ulid {
         // Step 2.1: Validate ULID format
         if let Err(e) = crate::processors::ulid_generator::validate_ulid(user_key) {
-            let err_msg = format!("[assert_doc_user] Invalid ULID format: {}", e);
-            logger!("error", "{}", err_msg);
-            return Err(err_msg);
+            logger!("error", "[assert_doc_user] Invalid ULID format: {}", e);
+            return Err(e);
         }
 
         // Step 2.2: Validate ULID timestamp (strict for new documents, lenient for updates)
-        let check_is_new = if is_update { 
+        // Migrated users keep their ULID, so their new document isn't new either
+        let check_is_new = if is_update || is_migrating_user(&context.data.key) { 
             CheckULIDisNew::no()  // For updates: validate format but not timestamp freshness
         } else { 
             CheckULIDisNew::yes() // For new docs: validate format AND require recent timestamp
         };
         
         if let Err(e) = validate_ulid_timestamp(user_key, check_is_new) {
-            let err_msg = format!("[assert_doc_user] Invalid ULID timestamp: {}", e);
-            logger!("error", "{}", err_msg);
-            return Err(err_msg);
+            logger!("error", "[assert_doc_user] Invalid ULID timestamp: {}", e);
+            return Err(e);
         }
         
         // Step 2.3: Then check if the formatted key matches what was provided
-        match format_user_key(&context.caller.to_string(), user_key, &user_data.user_handle) {
-            Ok(expected_key) => {
-                if expected_key != context.data.key {
-                    let err_msg = format!(
-                        "[assert_doc_user] Invalid document key format. Expected: {}, Got: {}", 
-                        expected_key, 
-                        context.data.key
-                    );
-                    logger!("error", "{}", err_msg);
-                    return Err(err_msg);
-                }
-            },
-            Err(e) => {
-                let err_msg = format!("[assert_doc_user] Failed to format user key: {}", e);
-                logger!("error", "{}", err_msg);
-                return Err(err_msg);
+        let expected_key = DocKey::user(&context.caller.to_string(), user_key)
+            .map_err(|e| {
+                logger!("error", "[assert_doc_user] Failed to format user key: {}", e);
+                e
+            })?;
+        let matches_key = match context.data.key.parse::<DocKey>() {
+            Ok(key @ DocKey::User { .. }) => key == expected_key,
+            // Legacy keys can be updated until migrated, as long as the handle in the key is unchanged
+            Ok(key @ DocKey::LegacyUser { .. }) if is_update => {
+                DocKey::legacy_user(&context.caller.to_string(), user_key, &user_data.user_handle).is_ok_and(|legacy| legacy == key)
             }
+            _ => false,
+        };
+        if !matches_key {
+            logger!("error", "[assert_doc_user] Invalid document key format. Expected: {}, Got: {}", 
+                expected_key, 
+                context.data.key
+            );
+            return Err(KarmieError::KeyMismatch {
+                expected: expected_key.to_string(),
+                actual: context.data.key.clone(),
+            });
         }
     } else {
-        let err_msg = "[assert_doc_user] Missing user_ulid field in user data".to_string();
-        logger!("error", "{}", err_msg);
-        return Err(err_msg);
+        logger!("error", "[assert_doc_user] Missing user_ulid field in user data");
+        return Err(KarmieError::invalid_field("user_ulid", "missing"));
     }
     
     // Step 3: Validate username format and restrictions
@@ -97,45 +109,73 @@ pub fn assert_doc_user(context: &AssertSetDocContext) -> Result<(), String> {
         return Err(err);
     }
 
+    // Step 3.1: Reject reserved and blocked handles, and their look-alikes
+    // Only handles being claimed are checked: users keep the handle they already have,
+    // and migrated users the handle they had before reservations existed
+    let current_data: Option<UserData> = match &context.data.data.current {
+        Some(current) => Some(decode_doc_data(&current.data)
+            .map_err(|e| {
+                logger!("error", "[assert_doc_user] Failed to decode current user data: {}", e);
+                KarmieError::invalid_data("users", e)
+            })?),
+        None => None,
+    };
+    let current_handle = current_data.as_ref().map(|current| current.user_handle.clone());
+    let is_rename = current_handle.as_deref().is_some_and(|current| CanonicalHandle::new(current) != CanonicalHandle::new(&user_data.user_handle));
+    let claims_handle = (current_handle.is_none() || is_rename) && !is_migrating_user(&context.data.key);
+    if claims_handle {
+        assert_handle_not_reserved(&user_data.user_handle, context.caller)
+            .map_err(|e| {
+                logger!("error", "[assert_doc_user] Handle '{}' is reserved: {}", user_data.user_handle, e);
+                e
+            })?;
+    }
+
     // Step 4: Validate display name format and restrictions
     validate_display_name(&user_data.display_name)
         .map_err(|e| {
-            let err_msg = format!("[assert_doc_user] Display name validation failed: {}", e);
-            logger!("error", "{}", err_msg);
-            err_msg
+            logger!("error", "[assert_doc_user] Display name validation failed: {}", e);
+            e
         })?;
 
-    // Step 5: Ensure username uniqueness using direct key-based lookup
-    // This is more efficient than loading the entire table into memory first
-    let normalized_username = crate::processors::document_keys::sanitize_for_key(&user_data.user_handle);
-    logger!("debug", "[assert_doc_user] Checking username uniqueness for handle: {}", normalized_username);
+    // Step 5: Ensure username uniqueness against the users_handles index
+    // The index is keyed by handle and described by skeleton, so these are direct lookups
+    if let Some(ref user_key) = user_data.user_ulid {
+        logger!("debug", "[assert_doc_user] Checking username uniqueness for handle: {}", user_data.user_handle);
+        let available = if claims_handle {
+            assert_handle_available(&user_data.user_handle, user_key)
+        } else {
+            // Look-alikes and quarantines don't apply to a handle the user already has
+            assert_handle_unheld(&user_data.user_handle, user_key)
+        };
+        available
+            .map_err(|e| {
+                logger!("error", "[assert_doc_user] Username '{}' is not available: {}", user_data.user_handle, e);
+                e
+            })?;
+    }
 
-    // Use query_doc_by_key with a more semantic query
-    let results = query_doc_by_key("users", &format!("hdl_{}_", normalized_username))
-        .map_err(|e| {
-            let err_msg = format!("[assert_doc_user] Failed to query existing usernames: {}", e);
-            logger!("error", "{}", err_msg);
-            err_msg
-        })?;
+    // Step 5.1: Renames are rate limited per user (see core/handle_renames.rs)
+    if let (true, Some(ref user_key)) = (is_rename, &user_data.user_ulid) {
+        logger!("debug", "[assert_doc_user] Rename from {:?} to {}", current_handle, user_data.user_handle);
+        assert_rename_allowed(user_key)
+            .map_err(|e| {
+                logger!("error", "[assert_doc_user] Rename rejected for user {}: {}", user_key, e);
+                e
+            })?;
+    }
 
-    // Check if we found any documents with this username
-    if results.items_length > 0 {
-        // If this is an update, it's ok if we found our own document
-        for (existing_key, _) in results.items {
-            if !is_update || existing_key != context.data.key {
-                let err_msg = format!(
-                    "[assert_doc_user] Username '{}' is already taken. Please choose a different username.",
-                    user_data.user_handle
-                );
-                logger!("error", "{}", err_msg);
-                return Err(err_msg);
-            }
-        }
-    } else {
-        logger!("debug", "[assert_doc_user] Username '{}' is available", user_data.user_handle);
+    // Step 6: Reject new or changed content containing disallowed terms
+    // Flagged content is accepted and queued for review, rolled back with the write if it fails
+    if !is_migrating_user(&context.data.key) {
+        moderate_content("users", &context.data.key, &user_moderated_fields(&user_data, current_data.as_ref()))
+            .map_err(|e| {
+                logger!("error", "[assert_doc_user] Content rejected for user {}: {}", context.data.key, e);
+                e
+            })?;
     }
 
-    // Step 6: In production mode, enforce one-document-per-identity rule
+    // Step 7: In production mode, enforce one-document-per-identity rule
     if !IS_PLAYGROUND {
         // In production mode, we can search by owner field (Principal ID)
         // This is a built-in Juno field, no need for description
@@ -155,13 +195,27 @@ pub fn assert_doc_user(context: &AssertSetDocContext) -> Result<(), String> {
 
         // Check if any existing documents are owned by this principal (excluding this document if it's an update)
         for (doc_key, doc) in existing_docs.items {
-            if doc_key != context.data.key && doc.owner.to_string() == principal_string {
-                let err_msg = format!("[assert_doc_user] Users can only have one account in production mode. key={}", context.data.key);
-                logger!("error", "{}", err_msg);
-                return Err(err_msg);
+            // A migrated user has both documents until the legacy one is deleted
+            if doc_key != context.data.key && doc.owner.to_string() == principal_string && !is_migrating_user(&doc_key) {
+                logger!("error", "[assert_doc_user] Users can only have one account in production mode. key={}", context.data.key);
+                return Err(KarmieError::AccountExists {
+                    principal: principal_string,
+                });
             }
         }
     }
 
+    // Step 8: Claim the handle in the users_handles index, once every check has passed
+    // Written now rather than in on_set_doc, so that it's rolled back if the user write fails,
+    // and a second user claiming the same handle right after is rejected
+    // (migrations index the user themselves, once the legacy document is gone)
+    if let (false, Some(ref user_key)) = (is_migrating_user(&context.data.key), &user_data.user_ulid) {
+        claim_user_handle(user_key, &user_data.user_handle)
+            .map_err(|e| {
+                logger!("error", "[assert_doc_user] Failed to claim handle '{}' for user {}: {}", user_data.user_handle, user_key, e);
+                e
+            })?;
+    }
+
     Ok(())
 }
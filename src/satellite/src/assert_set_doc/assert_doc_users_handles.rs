@@ -0,0 +1,109 @@
+use crate::logger;
+use junobuild_satellite::AssertSetDocContext;
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::{UserData, UserHandleData};
+use junobuild_utils::decode_doc_data;
+use crate::processors::document_keys::DocKey;
+use crate::processors::document_queries::DocQuery;
+use crate::processors::key_pattern::KeyPattern;
+use crate::core::{assert_handle_unheld, claimed_handle, find_user_doc, handle_description, USERS_HANDLES_COLLECTION};
+
+/// Validates a users_handles index document before creation or update
+///
+/// This function performs the following validation steps:
+/// 1. Verifies the writer is the satellite, which maintains the index from the users hooks
+/// 2. Decodes the index data and verifies the document key matches it
+/// 3. Verifies the user exists and currently has this handle (or is claiming it, see
+///    `claim_user_handle`), and the description holds its skeleton
+/// 4. Ensures no other user holds the handle
+/// 5. Ensures the user has no entry for another handle (it must be deleted first)
+///
+/// Key format: hdl_{handle}_usr_{ulid}_, description: skl_{skeleton}_
+///
+/// # Arguments
+/// * `context` - The validation context containing the document data
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if validation passes, the reason it failed otherwise
+pub fn validate_users_handles_document(context: &AssertSetDocContext) -> Result<(), KarmieError> {
+    logger!("debug", "[validate_users_handles_document] Starting users_handles validation: key={}", context.data.key);
+
+    // Step 1: Only the satellite writes the index
+    if context.caller != ic_cdk::id() {
+        logger!("error", "[validate_users_handles_document] Rejected write by {}", context.caller);
+        return Err(KarmieError::Unauthorized {
+            reason: "The users_handles index is maintained by the satellite".to_string(),
+        });
+    }
+
+    // Step 2: Decode the data and verify the key matches it
+    let entry: UserHandleData = decode_doc_data(&context.data.data.proposed.data)
+        .map_err(|e| {
+            logger!("error", "[validate_users_handles_document] Invalid users_handles data format: {}", e);
+            KarmieError::invalid_data(USERS_HANDLES_COLLECTION, e)
+        })?;
+
+    let (handle, key_ulid) = match DocKey::parse_kind(&context.data.key, "user_handle")? {
+        DocKey::UserHandle { handle, user_ulid } => (handle, user_ulid),
+        other => return Err(other.kind_mismatch("user_handle")),
+    };
+    if key_ulid != entry.user_ulid {
+        let expected = DocKey::user_handle(&handle, &entry.user_ulid)?.to_string();
+        logger!("error", "[validate_users_handles_document] Key {} does not match user {}", context.data.key, entry.user_ulid);
+        return Err(KarmieError::KeyMismatch {
+            expected,
+            actual: context.data.key.clone(),
+        });
+    }
+
+    // Step 3: The user must exist and currently have this handle
+    // A user claiming a handle in assert_doc_user has it in the document being written
+    let user_handle = match claimed_handle(&entry.user_ulid) {
+        Some(claimed) => claimed,
+        None => {
+            let (user_key, user_doc) = find_user_doc(&entry.user_ulid)?
+                .ok_or_else(|| KarmieError::not_found("User", &entry.user_ulid))?;
+            let user: UserData = decode_doc_data(&user_doc.data)
+                .map_err(|e| KarmieError::invalid_data("users", format!("user {}: {}", user_key, e)))?;
+            user.user_handle
+        }
+    };
+    let expected_key = DocKey::user_handle(&user_handle, &entry.user_ulid)?;
+    if expected_key.to_string() != context.data.key {
+        logger!("error", "[validate_users_handles_document] User {} now has handle {}, not {}",
+            entry.user_ulid, user_handle, handle);
+        return Err(KarmieError::KeyMismatch {
+            expected: expected_key.to_string(),
+            actual: context.data.key.clone(),
+        });
+    }
+
+    // Step 3.1: The description holds the handle's skeleton, used to find look-alike handles
+    let expected_description = handle_description(&user_handle);
+    if context.data.data.proposed.description.as_deref() != Some(expected_description.as_str()) {
+        logger!("error", "[validate_users_handles_document] Invalid description for {}: {:?}",
+            context.data.key, context.data.data.proposed.description);
+        return Err(KarmieError::invalid_field("description", format!("must be {}", expected_description)));
+    }
+
+    // Step 4: No other user may hold the handle
+    // Look-alike handles and quarantines were checked on the user write (assert_doc_user),
+    // where handles grandfathered by the migration are exempt from them
+    assert_handle_unheld(&handle, &entry.user_ulid)
+        .map_err(|e| {
+            logger!("error", "[validate_users_handles_document] Handle {} is already held: {}", handle, e);
+            e
+        })?;
+
+    // Step 5: One entry per user
+    let own_entries = KeyPattern::new().usr(&entry.user_ulid).anchor_end().build();
+    for item in DocQuery::new(USERS_HANDLES_COLLECTION, &own_entries).iter() {
+        let (key, _) = item?;
+        if key != context.data.key {
+            logger!("error", "[validate_users_handles_document] User {} still has handle entry {}", entry.user_ulid, key);
+            return Err(KarmieError::invalid_field("user_handle", format!("user {} is still indexed under {}", entry.user_ulid, key)));
+        }
+    }
+
+    Ok(())
+}
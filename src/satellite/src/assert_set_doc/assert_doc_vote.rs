@@ -1,47 +1,87 @@
 use crate::logger;
 use junobuild_satellite::AssertSetDocContext;
-use crate::utils::structs::{VoteData, UserData};
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::{VoteData, UserData, TagData, DuplicateVotePolicy};
 use junobuild_utils::decode_doc_data;
+use crate::processors::document_keys::DocKey;
 use crate::processors::document_queries::query_doc_by_key;
+use crate::processors::key_pattern::KeyPattern;
 use crate::validation::{validate_ulid_timestamp, CheckULIDisNew};
+use crate::core::{
+    base_vote_cost, check_new_vote_allowed, cooldown_status_from_votes,
+    query_author_votes_on_target, spend_vote_credits, validate_vote_intensity,
+};
 use ic_cdk;
 
 /// Validates a vote document before creation or update
 /// 
 /// This function performs comprehensive validation of vote documents:
-/// 1. Decodes and validates the basic vote data structure
+/// 1. Decodes and validates the basic vote data structure, and checks it matches the document key
 /// 2. Validates description format using DocumentDescription helper
 /// 3. Validates vote value constraints (+1 or -1)
 /// 4. Validates vote weight constraints (0.0 to 1.0)
 /// 5. Prevents self-voting
 /// 6. Verifies tag exists using ListMatcher by key
 /// 7. Ensures vote timestamp is not backdated or in the future using IC blockchain time
-/// 
+/// 8. Enforces the tag's vote cooldown between the same author and target (new votes only)
+/// 9. Enforces the tag's duplicate vote policy between the same author and target (new votes only)
+/// 10. Validates the vote intensity for the tag's voting mode (quadratic voting)
+/// 11. Spends the cost of the vote from the author's voting credits if the tag uses them
+///
+/// For updates, only `value`, `weight` and `intensity` may change. The author, tag, target and
+/// vote ULID are encoded in the document key, so changing any of them in place
+/// would leave the key pointing at the wrong users; see [`validate_vote_update`].
+///
 /// # Arguments
 /// * `context` - The validation context containing the document data
-/// 
+///
 /// # Returns
-/// * `Result<(), String>` - Ok if validation passes, Err with detailed message if it fails
-pub fn validate_vote_document(context: &AssertSetDocContext) -> Result<(), String> {
+/// * `Result<(), KarmieError>` - Ok if validation passes, the reason it failed otherwise
+pub fn validate_vote_document(context: &AssertSetDocContext) -> Result<(), KarmieError> {
     logger!("debug", "[validate_vote_document] Starting vote validation: key={}", context.data.key);
 
     // Step 1: Access the full document structure and prepare it
-    
+
     // Decode and validate the basic vote data structure
     let vote_doc = &context.data.data.proposed;
     let vote_data: VoteData = decode_doc_data(&vote_doc.data)
         .map_err(|e| {
             logger!("error", "[validate_vote_document] Failed to decode vote data: key={}, error={}", context.data.key, e);
-            format!("Invalid vote data format: {}", e)
+            KarmieError::invalid_data("votes", e)
         })?;
 
+    // Step 1.1: Verify the key matches the data
+    // Earlier votes are found by key (cooldown, duplicates, reputation), so a vote stored
+    // under another key would escape those checks
+    validate_vote_key(&context.data.key, &vote_data)?;
+
+    // Step 1.2: For updates, make sure only mutable fields have changed
+    let is_update = context.data.data.current.is_some();
+    let current_vote: Option<VoteData> = match &context.data.data.current {
+        Some(current_doc) => {
+            let current_vote: VoteData = decode_doc_data(&current_doc.data)
+                .map_err(|e| {
+                    logger!("error", "[validate_vote_document] Failed to decode current vote data: key={}, error={}", context.data.key, e);
+                    KarmieError::invalid_data("votes", e)
+                })?;
+
+            validate_vote_update(&current_vote, &vote_data)?;
+            Some(current_vote)
+        }
+        None => None,
+    };
+
     // Step 2: Validate vote timestamp is not backdated or in the future
-    // Pass the vote_key to validate_ulid_timestamp with CheckULIDisNew::yes() to ensure
-    // the timestamp is recent (within last 5 minutes) and not in the future
-    if let Err(e) = validate_ulid_timestamp(&vote_data.vote_ulid, CheckULIDisNew::yes()) {
-        let err_msg = format!("[validate_vote_document] Invalid vote timestamp: {}", e);
-        logger!("error", "{}", err_msg);
-        return Err(err_msg);
+    // New votes use CheckULIDisNew::yes() to ensure the timestamp is recent (within
+    // the last 2 minutes). Edits keep their original ULID, so only the general checks apply.
+    let check_is_new = if is_update {
+        CheckULIDisNew::no()
+    } else {
+        CheckULIDisNew::yes()
+    };
+    if let Err(e) = validate_ulid_timestamp(&vote_data.vote_ulid, check_is_new) {
+        logger!("error", "[validate_vote_document] Invalid vote timestamp: {}", e);
+        return Err(e);
     }
 
     // Step 3: Validate vote value constraints
@@ -54,11 +94,11 @@ pub fn validate_vote_document(context: &AssertSetDocContext) -> Result<(), Strin
     // - No neutral votes (0) allowed to encourage clear stance
     if vote_data.value != -1.0 && vote_data.value != 1.0 {
         let err_msg = format!(
-            "[validate_vote_document] Vote value must be either -1 or 1 (got: {}). Neutral votes (0) are not allowed.",
+            "Vote value must be either -1 or 1 (got: {}). Neutral votes (0) are not allowed.",
             vote_data.value
         );
-        logger!("error", "{}", err_msg);
-        return Err(err_msg);
+        logger!("error", "[validate_vote_document] {}", err_msg);
+        return Err(KarmieError::invalid_field("value", err_msg));
     }
 
     // Step 4: Validate vote weight constraints
@@ -74,11 +114,11 @@ pub fn validate_vote_document(context: &AssertSetDocContext) -> Result<(), Strin
     // - Age of previous votes
     if vote_data.weight < 0.0 || vote_data.weight > 1.0 {
         let err_msg = format!(
-            "[validate_vote_document] Vote weight must be between 0.0 and 1.0 (got: {})",
+            "Vote weight must be between 0.0 and 1.0 (got: {})",
             vote_data.weight
         );
-        logger!("error", "{}", err_msg);
-        return Err(err_msg);
+        logger!("error", "[validate_vote_document] {}", err_msg);
+        return Err(KarmieError::invalid_field("weight", err_msg));
     }
 
     // Step 5: Validate tag exists
@@ -86,37 +126,43 @@ pub fn validate_vote_document(context: &AssertSetDocContext) -> Result<(), Strin
     
     // First validate that tag_key is not empty
     if vote_data.tag_ulid.trim().is_empty() {
-        let err_msg = "[validate_vote_document] Tag key cannot be empty";
-        logger!("error", "{}", err_msg);
-        return Err(err_msg.to_string());
+        logger!("error", "[validate_vote_document] Tag key cannot be empty");
+        return Err(KarmieError::invalid_field("tag_ulid", "cannot be empty"));
     }
     
     // Construct the key pattern to find the tag document
     // Tag keys follow the pattern: tag_{ulid}_
     // This pattern will match any document that contains this tag ULID segment
     // For example: usr_123_tag_456_hdl_example_ would match with pattern "tag_456_"
-    let tag_key_pattern = format!(".*tag_{}_.*", vote_data.tag_ulid);
+    let tag_key_pattern = KeyPattern::new().tag(&vote_data.tag_ulid).build();
     
     // Query for the tag using the constructed key pattern
     let tag_results = query_doc_by_key(
         "tags",
         &tag_key_pattern
-    )?;
+    )
+    .map_err(|e| KarmieError::storage("tags", e))?;
     
     // Check if we found any matching tags
     if tag_results.items.is_empty() {
-        let err_msg = format!("[validate_vote_document] Tag not found: {}", &tag_key_pattern);
-        logger!("error", "{}", err_msg);
-        return Err(err_msg);
+        logger!("error", "[validate_vote_document] Tag not found: {}", &tag_key_pattern);
+        return Err(KarmieError::not_found("Tag", &vote_data.tag_ulid));
     }
     
     logger!("debug", "[validate_vote_document] Found tag: {}", vote_data.tag_ulid);
 
+    // Decode the tag so its settings can be enforced below
+    let (_, tag_doc) = &tag_results.items[0];
+    let tag_data: TagData = decode_doc_data(&tag_doc.data)
+        .map_err(|e| {
+            logger!("error", "[validate_vote_document] Failed to decode tag data: {}", e);
+            KarmieError::invalid_data("tags", e)
+        })?;
+
     // Step 6: Validate no self-voting
     if vote_data.owner_ulid == vote_data.target_ulid {
-        let err_msg = "[validate_vote_document] Users cannot vote on themselves";
-        logger!("error", "{}", err_msg);
-        return Err(err_msg.to_string());
+        logger!("error", "[validate_vote_document] Users cannot vote on themselves");
+        return Err(KarmieError::SelfVote);
     }
 
     // Step 7: Validate that owner_ulid matches the actual user creating the vote
@@ -124,43 +170,97 @@ pub fn validate_vote_document(context: &AssertSetDocContext) -> Result<(), Strin
     
     // Find the user document for the Principal creating this vote
     let caller_principal = context.caller.to_string();
-    let user_key_pattern = format!("_prn_{}_", caller_principal);
-    let user_results = query_doc_by_key("users", &user_key_pattern)?;
+    let user_key_pattern = KeyPattern::new().anchor_start().prn(&caller_principal).build();
+    let user_results = query_doc_by_key("users", &user_key_pattern)
+        .map_err(|e| KarmieError::storage("users", e))?;
 
     if user_results.items.is_empty() {
-        let err_msg = format!("[validate_vote_document] No user document found for caller: {}", caller_principal);
-        logger!("error", "{}", err_msg);
-        return Err(err_msg);
+        logger!("error", "[validate_vote_document] No user document found for caller: {}", caller_principal);
+        return Err(KarmieError::not_found("User", &caller_principal));
     }
 
     // Get the caller's user document and extract their user_ulid
     let (_, user_doc) = &user_results.items[0];
     let caller_user_data: UserData = decode_doc_data(&user_doc.data)
         .map_err(|e| {
-            let err_msg = format!("[validate_vote_document] Failed to decode caller's user data: {}", e);
-            logger!("error", "{}", err_msg);
-            err_msg
+            logger!("error", "[validate_vote_document] Failed to decode caller's user data: {}", e);
+            KarmieError::invalid_data("users", e)
         })?;
 
     // Verify the vote's owner_ulid matches the caller's user_ulid
     if let Some(caller_user_ulid) = &caller_user_data.user_ulid {
         if *caller_user_ulid != vote_data.owner_ulid {
-            let err_msg = format!(
-                "[validate_vote_document] owner_ulid mismatch: vote claims to be from user '{}' but caller's user_ulid is '{}'",
+            logger!("error", "[validate_vote_document] owner_ulid mismatch: vote claims to be from user '{}' but caller's user_ulid is '{}'",
                 vote_data.owner_ulid,
                 caller_user_ulid
             );
-            logger!("error", "{}", err_msg);
-            return Err(err_msg);
+            return Err(KarmieError::CallerMismatch {
+                field: "owner_ulid".to_string(),
+                claimed: vote_data.owner_ulid.clone(),
+                actual: caller_user_ulid.clone(),
+            });
         }
     } else {
-        let err_msg = "[validate_vote_document] Caller's user document missing user_ulid field";
-        logger!("error", "{}", err_msg);
-        return Err(err_msg.to_string());
+        logger!("error", "[validate_vote_document] Caller's user document missing user_ulid field");
+        return Err(KarmieError::invalid_data("users", "caller's user document is missing user_ulid"));
     }
 
     logger!("debug", "[validate_vote_document] owner_ulid validation passed: {} matches caller's user_ulid", vote_data.owner_ulid);
 
+    // Steps 8-9: Enforce the tag's cooldown and duplicate vote policy between this author and target
+    // Edits don't create a new vote, so both only apply to new documents
+    let restricts_duplicates = tag_data.duplicate_vote_policy != DuplicateVotePolicy::Cumulative
+        || tag_data.duplicate_vote_cap > 0;
+
+    if !is_update && (tag_data.vote_cooldown > 0 || restricts_duplicates) {
+        let previous_votes = query_author_votes_on_target(
+            &vote_data.owner_ulid,
+            &vote_data.target_ulid,
+            &vote_data.tag_ulid,
+        )
+        .map_err(|e| KarmieError::storage("votes", e))?;
+
+        // Step 8: Vote cooldown
+        let cooldown = cooldown_status_from_votes(&previous_votes, tag_data.vote_cooldown);
+        if cooldown.remaining_minutes > 0 {
+            logger!("error", "[validate_vote_document] Must wait {} more minutes before voting on this user again in tag {}",
+                cooldown.remaining_minutes,
+                tag_data.tag_handle
+            );
+            return Err(KarmieError::VoteCooldown {
+                remaining_minutes: cooldown.remaining_minutes,
+            });
+        }
+
+        // Step 9: Duplicate vote policy
+        if let Err(e) = check_new_vote_allowed(
+            tag_data.duplicate_vote_policy,
+            tag_data.duplicate_vote_cap,
+            previous_votes.len(),
+        ) {
+            logger!("error", "[validate_vote_document] {} (tag: {})", e, tag_data.tag_handle);
+            return Err(e);
+        }
+    }
+
+    // Step 10: Validate the vote intensity for the tag's voting mode
+    let base_cost = tag_data.vote_credits
+        .map(|config| base_vote_cost(&config, &vote_data))
+        .unwrap_or(1);
+    if let Err(e) = validate_vote_intensity(tag_data.vote_mode, tag_data.vote_credits.as_ref(), base_cost, &vote_data) {
+        logger!("error", "[validate_vote_document] {} (tag: {})", e, tag_data.tag_handle);
+        return Err(e);
+    }
+
+    // Step 11: Spend the author's voting credits, last so that only accepted votes are charged
+    // Edits are charged too, since making a vote more expensive costs the difference
+    if let Some(credits_config) = &tag_data.vote_credits {
+        if let Err(e) = spend_vote_credits(credits_config, tag_data.vote_mode, &vote_data, current_vote.as_ref()) {
+            logger!("error", "[validate_vote_document] {} (tag: {})", e, tag_data.tag_handle);
+            return Err(e);
+        }
+    }
+
     logger!("info", "[validate_vote_document] Vote validation passed: author={} voted {} on target={} in tag={}",
         vote_data.owner_ulid,
         vote_data.value,
@@ -170,3 +270,145 @@ pub fn validate_vote_document(context: &AssertSetDocContext) -> Result<(), Strin
 
     Ok(())
 }
+
+/// Checks that a vote's key is the one built from its data
+///
+/// # Arguments
+/// * `key` - The document key
+/// * `vote` - The vote data being written
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if the key matches, `InvalidKey` if it isn't a vote key,
+///   `KeyMismatch` if it is another vote's key
+fn validate_vote_key(key: &str, vote: &VoteData) -> Result<(), KarmieError> {
+    let key_parsed = DocKey::parse_kind(key, "vote")
+        .map_err(|e| {
+            logger!("error", "[validate_vote_key] Invalid vote key format: {}", e);
+            e
+        })?;
+
+    let expected_key = DocKey::vote(&vote.owner_ulid, &vote.tag_ulid, &vote.target_ulid, &vote.vote_ulid)
+        .map_err(|e| {
+            logger!("error", "[validate_vote_key] Invalid vote data: {}", e);
+            e
+        })?;
+
+    if key_parsed != expected_key {
+        logger!("error", "[validate_vote_key] Key does not match data. Expected: {}, Got: {}", expected_key, key);
+        return Err(KarmieError::KeyMismatch {
+            expected: expected_key.to_string(),
+            actual: key.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Validates an update to an existing vote document
+/// 
+/// Mutable fields:
+/// - `value`: a vote can be flipped between +1 and -1
+/// - `weight`: recalculated client-side from the author's current vote weight
+/// - `intensity`: can be raised or lowered in quadratic voting tags
+/// 
+/// Immutable fields (all of them are part of the document key):
+/// - `owner_ulid`
+/// - `tag_ulid`
+/// - `target_ulid`
+/// - `vote_ulid`
+/// 
+/// To vote on another user or in another tag, the vote must be deleted and a new one created.
+/// 
+/// # Arguments
+/// * `current` - The vote data currently stored
+/// * `proposed` - The vote data being written
+/// 
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if only mutable fields changed, `ImmutableField` naming the first one that changed
+fn validate_vote_update(current: &VoteData, proposed: &VoteData) -> Result<(), KarmieError> {
+    let immutable_fields = [
+        ("owner_ulid", &current.owner_ulid, &proposed.owner_ulid),
+        ("tag_ulid", &current.tag_ulid, &proposed.tag_ulid),
+        ("target_ulid", &current.target_ulid, &proposed.target_ulid),
+        ("vote_ulid", &current.vote_ulid, &proposed.vote_ulid),
+    ];
+
+    for (field, current_value, proposed_value) in immutable_fields {
+        if current_value != proposed_value {
+            logger!("error", "[validate_vote_update] Field '{}' cannot be changed on an existing vote (current: {}, proposed: {})",
+                field, current_value, proposed_value
+            );
+            return Err(KarmieError::ImmutableField {
+                field: field.to_string(),
+            });
+        }
+    }
+
+    logger!("debug", "[validate_vote_update] Vote update allowed: value {} -> {}, weight {} -> {}",
+        current.value, proposed.value, current.weight, proposed.weight);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote() -> VoteData {
+        VoteData {
+            owner_ulid: "01ARZ3NDEKTSV4RRFFQ69G5FAV".to_string(),
+            target_ulid: "01ARZ3NDEKTSV4RRFFQ69G5FAW".to_string(),
+            tag_ulid: "01ARZ3NDEKTSV4RRFFQ69G5FAX".to_string(),
+            vote_ulid: "01ARZ3NDEKTSV4RRFFQ69G5FAY".to_string(),
+            value: 1.0,
+            weight: 1.0,
+            intensity: 0,
+        }
+    }
+
+    fn vote_key(vote: &VoteData) -> String {
+        format!("usr_{}_tag_{}_tar_{}_key_{}_", vote.owner_ulid, vote.tag_ulid, vote.target_ulid, vote.vote_ulid)
+    }
+
+    #[test]
+    fn test_vote_key_matches_data() {
+        let current = vote();
+        assert!(validate_vote_key(&vote_key(&current), &current).is_ok());
+
+        // Another target in the key than in the data
+        let other = VoteData { target_ulid: "01ARZ3NDEKTSV4RRFFQ69G5FAZ".to_string(), ..vote() };
+        assert!(matches!(validate_vote_key(&vote_key(&other), &current), Err(KarmieError::KeyMismatch { .. })));
+
+        // Not a vote key at all
+        let reputation_key = format!("usr_{}_tag_{}_", current.owner_ulid, current.tag_ulid);
+        assert!(matches!(validate_vote_key(&reputation_key, &current), Err(KarmieError::InvalidKey { .. })));
+        assert!(matches!(validate_vote_key("my_vote", &current), Err(KarmieError::InvalidKey { .. })));
+    }
+
+    #[test]
+    fn test_vote_update_allows_value_and_intensity() {
+        let current = vote();
+        let proposed = VoteData { value: -1.0, weight: 0.5, intensity: 3, ..vote() };
+        assert!(validate_vote_update(&current, &proposed).is_ok());
+        assert!(validate_vote_update(&current, &current).is_ok());
+    }
+
+    #[test]
+    fn test_vote_update_rejects_immutable_fields() {
+        let current = vote();
+        let other = "01ARZ3NDEKTSV4RRFFQ69G5FAZ".to_string();
+        let changes = [
+            ("owner_ulid", VoteData { owner_ulid: other.clone(), ..vote() }),
+            ("tag_ulid", VoteData { tag_ulid: other.clone(), ..vote() }),
+            ("target_ulid", VoteData { target_ulid: other.clone(), ..vote() }),
+            ("vote_ulid", VoteData { vote_ulid: other.clone(), ..vote() }),
+        ];
+
+        for (field, proposed) in changes {
+            match validate_vote_update(&current, &proposed) {
+                Err(KarmieError::ImmutableField { field: rejected }) => assert_eq!(rejected, field),
+                other => panic!("expected ImmutableField for {}, got {:?}", field, other),
+            }
+        }
+    }
+}
@@ -6,6 +6,8 @@
  * - Votes
  * - Tags
  * - Reputations
+ * - Delegations
+ * - Users handles (index maintained by the satellite)
  * 
  * Each function is called by the main assert_set_doc hook to validate
  * documents before they are created or updated in the database.
@@ -15,8 +17,12 @@ mod assert_doc_user;
 mod assert_doc_vote;
 mod assert_doc_tag;
 mod assert_doc_reputation;
+mod assert_doc_delegation;
+mod assert_doc_users_handles;
 
 pub use assert_doc_user::assert_doc_user;
 pub use assert_doc_vote::validate_vote_document;
 pub use assert_doc_tag::validate_tag_document;
 pub use assert_doc_reputation::validate_reputation_document;
+pub use assert_doc_delegation::validate_delegation_document;
+pub use assert_doc_users_handles::validate_users_handles_document;
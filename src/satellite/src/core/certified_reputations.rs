@@ -0,0 +1,667 @@
+/*!
+ * Certified reputations
+ *
+ * Query responses are answered by a single replica, so a malicious replica could lie
+ * about a user's reputation. To let web clients and integrating canisters detect this,
+ * the satellite keeps a Merkle tree over every stored reputation:
+ * ```text
+ * "reputations" -> usr_{user}_tag_{tag}_ -> sha256(candid(ReputationData))
+ * ```
+ *
+ * # Certified data
+ * A canister has a single certified data slot, and Juno sets it to the root of its
+ * certified asset tree on every asset write. Rather than competing for the slot, the
+ * reputation tree's root hash is published as an asset of the snapshots collection:
+ * ```text
+ * /snapshots/certification/reputations.json   -> { "root_hash": hex, "updated_at": ns }
+ * ```
+ * Juno certifies that asset like any other, so one certified root covers both the hosted
+ * assets and the reputations. `get_user_reputation_certified` returns the reputation with
+ * its witness in the reputation tree, plus the root asset with the IC certificate and
+ * Juno's witness for it (as served by `http_request`). Clients check the whole chain with
+ * `verify_certified_reputation`:
+ * ```text
+ * certificate -> Juno asset tree -> root asset -> reputation tree -> reputation
+ * ```
+ * The root asset is rewritten after every reputation write, in the same message, so the
+ * published root always matches the tree once the write is committed.
+ *
+ * # State
+ * The tree lives in heap memory. `on_post_upgrade` rebuilds it from the reputations
+ * collection and republishes the root; controllers can do the same with
+ * `rebuild_reputation_certification`.
+ */
+
+use std::cell::RefCell;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use candid::{CandidType, Principal};
+use ic_certification::{labeled, labeled_hash, AsHashTree, Certificate, Hash, HashTree, LookupResult, RbTree};
+use junobuild_satellite::{get_controllers, Doc};
+use junobuild_shared::controllers::is_controller;
+use junobuild_storage::http::types::HttpRequest;
+use junobuild_utils::decode_doc_data;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::core::reputation_snapshots::{upload_json, SNAPSHOTS_COLLECTION};
+use crate::logger;
+use crate::processors::document_keys::DocKey;
+use crate::processors::document_queries::query_doc_by_key;
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::ReputationData;
+
+/// Label of the reputations subtree
+pub const REPUTATIONS_LABEL: &[u8] = b"reputations";
+
+/// Label of Juno's certified asset tree (v1, path -> sha256 of the content)
+pub const JUNO_ASSETS_LABEL: &[u8] = b"http_assets";
+
+/// Header in which Juno returns the certificate and witness of an asset
+const IC_CERTIFICATE_HEADER: &str = "IC-Certificate";
+
+/// Path of the asset publishing the reputation tree's root hash
+pub fn certified_root_path() -> String {
+    format!("/{}/certification/reputations.json", SNAPSHOTS_COLLECTION)
+}
+
+/// Content of the root asset
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CertifiedRoot {
+    /// Hex-encoded root hash of the reputation tree
+    pub root_hash: String,
+
+    /// When the root was published (nanoseconds since epoch)
+    pub updated_at: u64,
+}
+
+/// A reputation with everything needed to verify it
+#[derive(Serialize, Deserialize, Clone, Debug, CandidType)]
+pub struct CertifiedReputation {
+    /// Key of the reputation (`usr_{user}_tag_{tag}_`)
+    pub key: String,
+
+    /// The reputation, or None if the user has no reputation in the tag
+    pub reputation: Option<ReputationData>,
+
+    /// CBOR-encoded hash tree proving the reputation's hash (or its absence) under the published root
+    pub witness: Vec<u8>,
+
+    /// Path of the root asset
+    pub root_path: String,
+
+    /// Content of the root asset (JSON `CertifiedRoot`)
+    pub root_asset: Vec<u8>,
+
+    /// The IC certificate (CBOR) covering the satellite's certified data
+    pub certificate: Vec<u8>,
+
+    /// CBOR-encoded hash tree proving the root asset in Juno's certified asset tree
+    pub asset_witness: Vec<u8>,
+}
+
+/// Merkle tree of reputation hashes
+#[derive(Default)]
+pub struct ReputationTree {
+    /// Reputation key -> hash of the reputation data
+    tree: RbTree<Vec<u8>, Hash>,
+
+    /// Whether the tree has been loaded from the reputations collection
+    initialized: bool,
+
+    /// Root hash of the last published root asset
+    published_root: Option<Hash>,
+}
+
+impl ReputationTree {
+    /// Inserts or replaces the hash of a reputation
+    pub fn insert(&mut self, key: &str, reputation: &ReputationData) -> Result<(), String> {
+        let hash = reputation_hash(reputation)?;
+        self.tree.insert(key.as_bytes().to_vec(), hash);
+        Ok(())
+    }
+
+    /// The root hash to publish
+    pub fn root_hash(&self) -> Hash {
+        labeled_hash(REPUTATIONS_LABEL, &self.tree.root_hash())
+    }
+
+    /// Witness proving the value stored under a key, or its absence
+    pub fn witness(&self, key: &str) -> HashTree {
+        labeled(REPUTATIONS_LABEL, self.tree.witness(key.as_bytes()))
+    }
+}
+
+thread_local! {
+    static REPUTATION_TREE: RefCell<ReputationTree> = RefCell::new(ReputationTree::default());
+}
+
+/// Hashes a reputation (sha256 of its candid encoding)
+///
+/// # Arguments
+/// * `reputation` - The reputation data
+///
+/// # Returns
+/// * `Result<Hash, String>` - The hash or an encoding error
+pub fn reputation_hash(reputation: &ReputationData) -> Result<Hash, String> {
+    let encoded = candid::encode_one(reputation)
+        .map_err(|e| format!("Failed to encode reputation for hashing: {}", e))?;
+    Ok(Sha256::digest(&encoded).into())
+}
+
+/// Publishes the tree's root hash as the root asset, which Juno certifies
+fn publish_root_hash(tree: &mut ReputationTree) -> Result<(), String> {
+    let root_hash = tree.root_hash();
+    logger!("debug", "[publish_root_hash] Reputation tree root={}", hex::encode(root_hash));
+
+    let root = CertifiedRoot {
+        root_hash: hex::encode(root_hash),
+        updated_at: ic_cdk::api::time(),
+    };
+    upload_json(certified_root_path(), &root, 0)?;
+
+    tree.published_root = Some(root_hash);
+    Ok(())
+}
+
+/// Loads every stored reputation into the tree
+fn load_tree(tree: &mut ReputationTree) -> Result<usize, String> {
+    let results = query_doc_by_key("reputations", "")?;
+
+    *tree = ReputationTree::default();
+    for (key, doc) in &results.items {
+        match decode_doc_data::<ReputationData>(&doc.data) {
+            Ok(reputation) => tree.insert(key, &reputation)?,
+            Err(e) => {
+                logger!("warn", "[load_tree] Skipping undecodable reputation: key={}, error={}", key, e);
+            }
+        }
+    }
+    tree.initialized = true;
+
+    Ok(results.items.len())
+}
+
+/// Updates the tree after a reputation document was written
+///
+/// Called after every `set_doc_store` on the reputations collection. Failures are
+/// logged rather than returned, since the reputation itself was stored successfully;
+/// certified queries fail until the root is published again.
+///
+/// # Arguments
+/// * `key` - Key of the reputation document
+/// * `doc` - The stored document
+pub fn certify_reputation_doc(key: &str, doc: &Doc) {
+    let result = REPUTATION_TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+
+        // The stored document is already in the collection, so loading also covers it
+        if !tree.initialized {
+            load_tree(&mut tree)?;
+        } else {
+            let reputation: ReputationData = decode_doc_data(&doc.data)
+                .map_err(|e| format!("Failed to decode reputation data: {}", e))?;
+            tree.insert(key, &reputation)?;
+        }
+
+        publish_root_hash(&mut tree)
+    });
+
+    if let Err(e) = result {
+        logger!("error", "[certify_reputation_doc] Failed to certify reputation: key={}, error={}", key, e);
+    }
+}
+
+/// Loads the tree and publishes its root
+fn reload_tree() -> Result<usize, String> {
+    REPUTATION_TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        let count = load_tree(&mut tree)?;
+        publish_root_hash(&mut tree)?;
+        Ok(count)
+    })
+}
+
+/// Rebuilds the tree after an upgrade, called from `on_post_upgrade`
+pub fn restore_reputation_certification() {
+    match reload_tree() {
+        Ok(count) => logger!("info", "[restore_reputation_certification] Certified {} reputations", count),
+        Err(e) => logger!("error", "[restore_reputation_certification] Failed to rebuild the reputation tree: {}", e),
+    }
+}
+
+/// Rebuilds the tree from the reputations collection (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal requesting the rebuild
+///
+/// # Returns
+/// * `Result<usize, KarmieError>` - Number of reputations loaded
+pub fn rebuild_reputation_tree(caller: Principal) -> Result<usize, KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        logger!("error", "[rebuild_reputation_tree] Only controllers can rebuild reputation certification caller={}", caller);
+        return Err(KarmieError::Unauthorized {
+            reason: "Only controllers can rebuild reputation certification".to_string(),
+        });
+    }
+
+    let count = reload_tree().map_err(|e| KarmieError::storage("reputations", e))?;
+    logger!("info", "[rebuild_reputation_tree] Certified {} reputations", count);
+    Ok(count)
+}
+
+/// Splits Juno's `IC-Certificate` header into the certificate and the witness
+///
+/// The header has the form `certificate=:{base64}:, tree=:{base64}:` (plus more fields
+/// for version 2, which are ignored).
+///
+/// # Returns
+/// * `Result<(Vec<u8>, Vec<u8>), String>` - The CBOR certificate and witness
+fn parse_certificate_header(value: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let field = |name: &str| -> Result<Vec<u8>, String> {
+        let encoded = value
+            .split(", ")
+            .find_map(|part| part.strip_prefix(name)?.strip_prefix("=:")?.strip_suffix(':'))
+            .ok_or_else(|| format!("The IC-Certificate header has no {}", name))?;
+        BASE64.decode(encoded).map_err(|e| format!("Invalid {} in the IC-Certificate header: {}", name, e))
+    };
+
+    Ok((field("certificate")?, field("tree")?))
+}
+
+/// The root asset as Juno serves it over HTTP
+struct RootAsset {
+    /// Content of the asset
+    content: Vec<u8>,
+
+    /// The IC certificate (CBOR)
+    certificate: Vec<u8>,
+
+    /// Juno's witness for the asset (CBOR)
+    witness: Vec<u8>,
+}
+
+/// Reads the root asset with its certificate and witness
+///
+/// # Returns
+/// * `Result<RootAsset, KarmieError>` - The asset, or Err if it isn't served with a certificate
+fn certified_root_asset() -> Result<RootAsset, KarmieError> {
+    let response = junobuild_satellite::http_request(HttpRequest {
+        url: certified_root_path(),
+        method: "GET".to_string(),
+        headers: vec![],
+        body: vec![],
+        certificate_version: None,
+    });
+
+    if response.status_code != 200 {
+        return Err(KarmieError::from(format!(
+            "[get_certified_reputation] The root asset could not be read (status {})", response.status_code
+        )));
+    }
+
+    let header = response
+        .headers
+        .iter()
+        .find(|header| header.0.eq_ignore_ascii_case(IC_CERTIFICATE_HEADER))
+        .ok_or_else(|| KarmieError::from("[get_certified_reputation] No certificate available, use a query call"))?;
+    let (certificate, witness) = parse_certificate_header(&header.1).map_err(KarmieError::from)?;
+
+    Ok(RootAsset {
+        content: response.body,
+        certificate,
+        witness,
+    })
+}
+
+/// Gets a user's reputation in a tag with its certificate and witnesses
+///
+/// Must be called from a query, the only context where the IC provides a certificate.
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+/// * `tag_ulid` - ULID of the tag
+///
+/// # Returns
+/// * `Result<CertifiedReputation, KarmieError>` - The reputation and its proof, or the reason it couldn't be certified
+pub fn get_certified_reputation(user_ulid: &str, tag_ulid: &str) -> Result<CertifiedReputation, KarmieError> {
+    let key = DocKey::reputation(user_ulid, tag_ulid)?.to_string();
+
+    let reputation = match junobuild_satellite::get_doc(String::from("reputations"), key.clone()) {
+        Some(doc) => Some(decode_doc_data::<ReputationData>(&doc.data)
+            .map_err(|e| KarmieError::invalid_data("reputations", e))?),
+        None => None,
+    };
+
+    let witness = REPUTATION_TREE.with(|tree| {
+        let tree = tree.borrow();
+        if !tree.initialized {
+            return Err(KarmieError::from("[get_certified_reputation] Reputation certification is not initialized yet"));
+        }
+        if tree.published_root != Some(tree.root_hash()) {
+            return Err(KarmieError::from("[get_certified_reputation] The reputation root hash is not published yet"));
+        }
+        Ok(tree.witness(&key))
+    })?;
+
+    let root_asset = certified_root_asset()?;
+
+    Ok(CertifiedReputation {
+        key,
+        reputation,
+        witness: serde_cbor::to_vec(&witness)
+            .map_err(|e| KarmieError::from(format!("Failed to encode witness: {}", e)))?,
+        root_path: certified_root_path(),
+        root_asset: root_asset.content,
+        certificate: root_asset.certificate,
+        asset_witness: root_asset.witness,
+    })
+}
+
+/// Verifies a witness against the published root hash
+///
+/// Verification helpers are meant for integrating canisters and Rust clients,
+/// the satellite itself never calls them.
+///
+/// # Arguments
+/// * `witness` - The hash tree returned with the reputation
+/// * `key` - Key of the reputation
+/// * `reputation` - The returned reputation (None if it should be absent)
+/// * `root_hash` - The root hash of the reputation tree
+///
+/// # Returns
+/// * `Result<(), String>` - Ok if the witness proves exactly this reputation
+#[allow(dead_code)]
+pub fn verify_reputation_witness(
+    witness: &HashTree,
+    key: &str,
+    reputation: Option<&ReputationData>,
+    root_hash: &[u8],
+) -> Result<(), String> {
+    if witness.digest().as_slice() != root_hash {
+        return Err("Witness root hash does not match the published root".to_string());
+    }
+
+    match (witness.lookup_path([REPUTATIONS_LABEL, key.as_bytes()]), reputation) {
+        (LookupResult::Found(hash), Some(reputation)) => {
+            if hash != reputation_hash(reputation)?.as_slice() {
+                return Err(format!("Reputation does not match its certified hash: key={}", key));
+            }
+            Ok(())
+        }
+        (LookupResult::Absent, None) => Ok(()),
+        (LookupResult::Found(_), None) => Err(format!("Reputation is certified but missing from the response: key={}", key)),
+        (LookupResult::Absent, Some(_)) => Err(format!("Reputation is certified as absent: key={}", key)),
+        (LookupResult::Unknown, _) | (LookupResult::Error, _) => {
+            Err(format!("Witness does not cover key={}", key))
+        }
+    }
+}
+
+/// Verifies the root asset against the certified data and returns the root hash it publishes
+///
+/// # Arguments
+/// * `asset_witness` - Juno's witness for the root asset
+/// * `root_path` - Path of the root asset
+/// * `root_asset` - Content of the root asset
+/// * `certified_data` - The satellite's certified data
+///
+/// # Returns
+/// * `Result<Vec<u8>, String>` - The certified root hash of the reputation tree
+#[allow(dead_code)]
+pub fn verify_root_asset(
+    asset_witness: &HashTree,
+    root_path: &str,
+    root_asset: &[u8],
+    certified_data: &[u8],
+) -> Result<Vec<u8>, String> {
+    if asset_witness.digest().as_slice() != certified_data {
+        return Err("Asset witness does not match the certified data".to_string());
+    }
+
+    match asset_witness.lookup_path([JUNO_ASSETS_LABEL, root_path.as_bytes()]) {
+        LookupResult::Found(hash) if hash == Sha256::digest(root_asset).as_slice() => {}
+        LookupResult::Found(_) => return Err(format!("Root asset does not match its certified hash: path={}", root_path)),
+        _ => return Err(format!("Asset witness does not cover path={}", root_path)),
+    }
+
+    let root: CertifiedRoot = serde_json::from_slice(root_asset)
+        .map_err(|e| format!("Invalid root asset: {}", e))?;
+    hex::decode(&root.root_hash).map_err(|e| format!("Invalid root hash in the root asset: {}", e))
+}
+
+/// Verifies a certified reputation response
+///
+/// Checks that the certificate's certified data for `canister_id` is the root of Juno's
+/// asset tree, that the root asset is certified in it, and that the witness proves the
+/// returned reputation under the root hash the asset publishes.
+///
+/// The certificate's BLS signature is NOT checked here: it must be verified against the
+/// IC root key by the caller's agent (agent-js and ic-agent do this when reading
+/// certificates) before trusting the result.
+///
+/// # Arguments
+/// * `response` - The response of `get_user_reputation_certified`
+/// * `canister_id` - The satellite's canister id
+///
+/// # Returns
+/// * `Result<Option<ReputationData>, String>` - The verified reputation, or an error
+#[allow(dead_code)]
+pub fn verify_certified_reputation(
+    response: &CertifiedReputation,
+    canister_id: Principal,
+) -> Result<Option<ReputationData>, String> {
+    let certificate: Certificate = serde_cbor::from_slice(&response.certificate)
+        .map_err(|e| format!("Invalid certificate: {}", e))?;
+
+    let certified_data = match certificate.tree.lookup_path([
+        b"canister".as_slice(),
+        canister_id.as_slice(),
+        b"certified_data".as_slice(),
+    ]) {
+        LookupResult::Found(data) => data.to_vec(),
+        _ => return Err("Certificate does not contain the satellite's certified data".to_string()),
+    };
+
+    let asset_witness: HashTree = serde_cbor::from_slice(&response.asset_witness)
+        .map_err(|e| format!("Invalid asset witness: {}", e))?;
+    let root_hash = verify_root_asset(&asset_witness, &response.root_path, &response.root_asset, &certified_data)?;
+
+    let witness: HashTree = serde_cbor::from_slice(&response.witness)
+        .map_err(|e| format!("Invalid witness: {}", e))?;
+
+    verify_reputation_witness(&witness, &response.key, response.reputation.as_ref(), &root_hash)?;
+
+    Ok(response.reputation.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::structs::VoteWeight;
+    use ic_certification::{fork, fork_hash, pruned};
+    use serde_cbor::ser::Serializer;
+
+    const KEY_A: &str = "usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_tag_01ARZ3NDEKTSV4RRFFQ69G5FAW_";
+    const KEY_B: &str = "usr_01ARZ3NDEKTSV4RRFFQ69G5FAX_tag_01ARZ3NDEKTSV4RRFFQ69G5FAW_";
+    const KEY_MISSING: &str = "usr_01ARZ3NDEKTSV4RRFFQ69G5FAY_tag_01ARZ3NDEKTSV4RRFFQ69G5FAW_";
+
+    fn reputation(owner: &str, total: f64) -> ReputationData {
+        ReputationData {
+            owner_ulid: owner.to_string(),
+            tag_ulid: "01ARZ3NDEKTSV4RRFFQ69G5FAW".to_string(),
+            reputation_basis: total,
+            reputation_rewards: 0.0,
+            reputation_total_effective: total,
+            last_calculation: 1,
+            vote_weight: VoteWeight::new(0.5).unwrap(),
+            has_voting_power: true,
+        }
+    }
+
+    fn tree_with_two() -> (ReputationTree, ReputationData, ReputationData) {
+        let a = reputation("01ARZ3NDEKTSV4RRFFQ69G5FAV", 42.0);
+        let b = reputation("01ARZ3NDEKTSV4RRFFQ69G5FAX", 7.0);
+        let mut tree = ReputationTree::default();
+        tree.insert(KEY_A, &a).unwrap();
+        tree.insert(KEY_B, &b).unwrap();
+        (tree, a, b)
+    }
+
+    /// CBOR with the self-describe tag, the way Juno encodes its witnesses
+    fn juno_cbor(tree: &HashTree) -> Vec<u8> {
+        let mut serializer = Serializer::new(vec![]);
+        serializer.self_describe().unwrap();
+        tree.serialize(&mut serializer).unwrap();
+        serializer.into_inner()
+    }
+
+    /// Juno's asset tree holding the root asset and another asset, with its root and the root asset's witness
+    fn juno_assets(root_asset: &[u8]) -> (Hash, HashTree) {
+        let mut assets: RbTree<String, Hash> = RbTree::new();
+        assets.insert(certified_root_path(), Sha256::digest(root_asset).into());
+        assets.insert("/index.html".to_string(), Sha256::digest(b"<html></html>").into());
+        let expr_hash = labeled_hash(b"http_expr", &[7u8; 32]);
+
+        let root = fork_hash(&labeled_hash(JUNO_ASSETS_LABEL, &assets.root_hash()), &expr_hash);
+        let witness = fork(labeled(JUNO_ASSETS_LABEL, assets.witness(certified_root_path().as_bytes())), pruned(expr_hash));
+        (root, witness)
+    }
+
+    fn root_asset(tree: &ReputationTree) -> Vec<u8> {
+        serde_json::to_vec(&CertifiedRoot { root_hash: hex::encode(tree.root_hash()), updated_at: 1 }).unwrap()
+    }
+
+    #[test]
+    fn test_witness_proves_value() {
+        let (tree, a, b) = tree_with_two();
+        let root = tree.root_hash();
+
+        let witness = tree.witness(KEY_A);
+        assert!(verify_reputation_witness(&witness, KEY_A, Some(&a), &root).is_ok());
+
+        // The witness for A doesn't prove B's value
+        assert!(verify_reputation_witness(&witness, KEY_B, Some(&b), &root).is_err());
+    }
+
+    #[test]
+    fn test_witness_detects_tampered_value() {
+        let (tree, a, _) = tree_with_two();
+        let root = tree.root_hash();
+
+        let mut forged = a.clone();
+        forged.reputation_total_effective = 1_000.0;
+        assert!(verify_reputation_witness(&tree.witness(KEY_A), KEY_A, Some(&forged), &root).is_err());
+
+        // Claiming the reputation doesn't exist is detected too
+        assert!(verify_reputation_witness(&tree.witness(KEY_A), KEY_A, None, &root).is_err());
+    }
+
+    #[test]
+    fn test_witness_proves_absence() {
+        let (tree, a, _) = tree_with_two();
+        let root = tree.root_hash();
+
+        let witness = tree.witness(KEY_MISSING);
+        assert!(verify_reputation_witness(&witness, KEY_MISSING, None, &root).is_ok());
+        assert!(verify_reputation_witness(&witness, KEY_MISSING, Some(&a), &root).is_err());
+    }
+
+    #[test]
+    fn test_witness_rejected_after_update() {
+        let (mut tree, a, _) = tree_with_two();
+        let witness = tree.witness(KEY_A);
+
+        let mut updated = a.clone();
+        updated.reputation_total_effective = 43.0;
+        tree.insert(KEY_A, &updated).unwrap();
+
+        // An old witness no longer matches the new root
+        assert!(verify_reputation_witness(&witness, KEY_A, Some(&a), &tree.root_hash()).is_err());
+        assert!(verify_reputation_witness(&tree.witness(KEY_A), KEY_A, Some(&updated), &tree.root_hash()).is_ok());
+    }
+
+    #[test]
+    fn test_witness_survives_cbor_round_trip() {
+        let (tree, a, _) = tree_with_two();
+        let encoded = serde_cbor::to_vec(&tree.witness(KEY_A)).unwrap();
+        let decoded: HashTree = serde_cbor::from_slice(&encoded).unwrap();
+
+        assert!(verify_reputation_witness(&decoded, KEY_A, Some(&a), &tree.root_hash()).is_ok());
+    }
+
+    #[test]
+    fn test_parse_certificate_header() {
+        let header = format!(
+            "certificate=:{}:, tree=:{}:, expr_path=:{}:, version=2",
+            BASE64.encode(b"cert"),
+            BASE64.encode(b"tree"),
+            BASE64.encode(b"path")
+        );
+        assert_eq!(parse_certificate_header(&header).unwrap(), (b"cert".to_vec(), b"tree".to_vec()));
+
+        assert!(parse_certificate_header("certificate=:AAAA:").is_err());
+        assert!(parse_certificate_header("certificate=:!!:, tree=:AAAA:").is_err());
+    }
+
+    #[test]
+    fn test_verify_root_asset() {
+        let (tree, _, _) = tree_with_two();
+        let content = root_asset(&tree);
+        let (certified_data, witness) = juno_assets(&content);
+
+        assert_eq!(
+            verify_root_asset(&witness, &certified_root_path(), &content, &certified_data).unwrap(),
+            tree.root_hash().to_vec()
+        );
+
+        // A root asset that isn't the certified one is rejected
+        let forged = serde_json::to_vec(&CertifiedRoot { root_hash: hex::encode([1u8; 32]), updated_at: 1 }).unwrap();
+        assert!(verify_root_asset(&witness, &certified_root_path(), &forged, &certified_data).is_err());
+
+        // So is a witness for another certified data
+        assert!(verify_root_asset(&witness, &certified_root_path(), &content, &[0u8; 32]).is_err());
+        assert!(verify_root_asset(&witness, "/index.html", &content, &certified_data).is_err());
+    }
+
+    #[test]
+    fn test_verify_full_response() {
+        let (tree, a, _) = tree_with_two();
+        let canister_id = Principal::from_text("rigfr-siaaa-aaaal-ab4fa-cai").unwrap();
+        let content = root_asset(&tree);
+        let (certified_data, asset_witness) = juno_assets(&content);
+
+        // Certificate state tree as the IC would build it (signature is checked by the agent)
+        let certificate = Certificate {
+            tree: labeled(
+                "canister",
+                labeled(
+                    canister_id.as_slice(),
+                    labeled("certified_data", ic_certification::leaf(certified_data.to_vec())),
+                ),
+            ),
+            signature: vec![],
+            delegation: None,
+        };
+
+        let mut response = CertifiedReputation {
+            key: KEY_A.to_string(),
+            reputation: Some(a.clone()),
+            witness: serde_cbor::to_vec(&tree.witness(KEY_A)).unwrap(),
+            root_path: certified_root_path(),
+            root_asset: content,
+            certificate: serde_cbor::to_vec(&certificate).unwrap(),
+            asset_witness: juno_cbor(&asset_witness),
+        };
+        let verified = verify_certified_reputation(&response, canister_id).unwrap();
+        assert_eq!(verified.unwrap().reputation_total_effective, 42.0);
+
+        // Another canister's certificate doesn't vouch for this one
+        let other = Principal::from_text("vjyvo-kyaaa-aaaal-asc5a-cai").unwrap();
+        assert!(verify_certified_reputation(&response, other).is_err());
+
+        // A tampered value is rejected
+        response.reputation.as_mut().unwrap().reputation_total_effective = 99.0;
+        assert!(verify_certified_reputation(&response, canister_id).is_err());
+    }
+}
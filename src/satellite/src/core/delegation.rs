@@ -0,0 +1,534 @@
+/*!
+ * Liquid delegation
+ *
+ * A user can delegate their voting power in a tag to another user. The delegate's
+ * votes then count with the delegator's reputation on top of their own, and the
+ * delegator's own votes in that tag stop counting.
+ *
+ * # Storage
+ * Delegations live in the `delegations` collection, one document per delegator and tag:
+ * ```text
+ * usr_{delegator}_tag_{tag}_del_{delegate}_
+ * ```
+ * Deleting the document revokes the delegation.
+ *
+ * # Chains
+ * Delegations are transitive: if A delegates to B and B delegates to C, both A's and
+ * B's reputation flow to C. Chains are limited to `MAX_DELEGATION_DEPTH` users and
+ * can never form a cycle (enforced in assert_doc_delegation.rs).
+ *
+ * # Folding into the engine
+ * When a target's basis reputation is calculated, each author's `AuthorInfo` is
+ * replaced by `fold_delegated_power`:
+ * - `effective_reputation`: the author's own reputation plus the effective reputation
+ *   of every active (transitive) delegator
+ * - `vote_weight`: the author's own vote weight. It spreads the combined reputation
+ *   across the author's votes, so each delegator's influence stays at 100% just like
+ *   it would if they voted themselves
+ * - `votes_active`: true if the author or any of their delegators has voting power
+ */
+
+use std::collections::HashSet;
+
+use candid::CandidType;
+use junobuild_utils::decode_doc_data;
+use serde::{Deserialize, Serialize};
+
+use crate::core::jobs::{run_steps_within_budget, JobStep};
+use crate::core::reputation_calculations::get_user_reputation_slim;
+use crate::core::reputation_snapshots::refresh_stale_snapshots;
+use crate::logger;
+use crate::processors::document_queries::query_doc_by_key;
+use crate::processors::key_pattern::{KeyPattern, KeySegment};
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::{AuthorInfo, DelegationData, VoteData};
+
+/// Collection storing delegations
+pub const DELEGATIONS_COLLECTION: &str = "delegations";
+
+/// Maximum number of users in a delegation chain (delegator to final delegate)
+pub const MAX_DELEGATION_DEPTH: usize = 10;
+
+/// Delegations involving a user
+#[derive(Serialize, Deserialize, Clone, Debug, CandidType)]
+pub struct DelegationList {
+    /// Delegations other users have given to this user
+    pub incoming: Vec<DelegationData>,
+
+    /// Delegations this user has given to other users (at most one per tag)
+    pub outgoing: Vec<DelegationData>,
+}
+
+/// Queries the delegations collection and decodes every matching document
+fn query_delegations(key_pattern: &str) -> Result<Vec<DelegationData>, String> {
+    let results = query_doc_by_key(DELEGATIONS_COLLECTION, key_pattern)?;
+
+    Ok(results
+        .items
+        .iter()
+        .filter_map(|(key, doc)| match decode_doc_data::<DelegationData>(&doc.data) {
+            Ok(delegation) => Some(delegation),
+            Err(e) => {
+                logger!("warn", "[query_delegations] Skipping undecodable delegation: key={}, error={}", key, e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Gets the delegation a user has given in a tag, if any
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the delegator
+/// * `tag_ulid` - ULID of the tag
+///
+/// # Returns
+/// * `Result<Option<DelegationData>, String>` - The delegation or None if the user votes themselves
+pub fn get_outgoing_delegation(user_ulid: &str, tag_ulid: &str) -> Result<Option<DelegationData>, String> {
+    let key_pattern = KeyPattern::new().anchor_start().usr(user_ulid).tag(tag_ulid).any(KeySegment::Del).build();
+    Ok(query_delegations(&key_pattern)?.into_iter().next())
+}
+
+/// Gets the delegations a user has received in a tag
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the delegate
+/// * `tag_ulid` - ULID of the tag
+///
+/// # Returns
+/// * `Result<Vec<DelegationData>, String>` - The direct (non-transitive) incoming delegations
+pub fn get_incoming_delegations(user_ulid: &str, tag_ulid: &str) -> Result<Vec<DelegationData>, String> {
+    let key_pattern = KeyPattern::new().tag(tag_ulid).del(user_ulid).anchor_end().build();
+    query_delegations(&key_pattern)
+}
+
+/// Lists the incoming and outgoing delegations of a user
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+/// * `tag_ulid` - Optional ULID of a tag to restrict the list to
+///
+/// # Returns
+/// * `Result<DelegationList, KarmieError>` - The delegations or the reason they couldn't be listed
+pub fn list_delegations(user_ulid: &str, tag_ulid: Option<&str>) -> Result<DelegationList, KarmieError> {
+    if user_ulid.trim().is_empty() {
+        return Err(KarmieError::invalid_field("user_ulid", "cannot be empty"));
+    }
+
+    let (outgoing_pattern, incoming_pattern) = match tag_ulid {
+        Some(tag) => (
+            KeyPattern::new().anchor_start().usr(user_ulid).tag(tag).any(KeySegment::Del).build(),
+            KeyPattern::new().tag(tag).del(user_ulid).anchor_end().build(),
+        ),
+        None => (
+            KeyPattern::new().anchor_start().usr(user_ulid).any(KeySegment::Tag).any(KeySegment::Del).build(),
+            KeyPattern::new().del(user_ulid).anchor_end().build(),
+        ),
+    };
+
+    Ok(DelegationList {
+        incoming: query_delegations(&incoming_pattern).map_err(|e| KarmieError::storage(DELEGATIONS_COLLECTION, e))?,
+        outgoing: query_delegations(&outgoing_pattern).map_err(|e| KarmieError::storage(DELEGATIONS_COLLECTION, e))?,
+    })
+}
+
+/// Follows a delegation chain from a user to the final delegate
+///
+/// # Arguments
+/// * `start` - ULID of the first user in the chain
+/// * `next` - Looks up the delegate of a user (None if the user doesn't delegate)
+///
+/// # Returns
+/// * `Result<Vec<String>, String>` - The chain starting with `start`, or an error if it
+///   loops or is longer than `MAX_DELEGATION_DEPTH`
+pub fn follow_delegation_chain<F>(start: &str, mut next: F) -> Result<Vec<String>, String>
+where
+    F: FnMut(&str) -> Result<Option<String>, String>,
+{
+    let mut chain = vec![start.to_string()];
+
+    while let Some(delegate) = next(chain.last().unwrap())? {
+        if chain.contains(&delegate) {
+            return Err(format!("Delegation cycle detected: {} -> {}", chain.join(" -> "), delegate));
+        }
+        chain.push(delegate);
+        if chain.len() > MAX_DELEGATION_DEPTH {
+            return Err(format!(
+                "Delegation chain is longer than {} users: {}",
+                MAX_DELEGATION_DEPTH,
+                chain.join(" -> ")
+            ));
+        }
+    }
+
+    Ok(chain)
+}
+
+/// Measures the longest chain of delegators ending at a user
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+/// * `incoming` - Looks up the direct delegators of a user
+///
+/// # Returns
+/// * `Result<usize, String>` - Number of users in the longest chain before `user_ulid`
+///   (0 if nobody delegates to them). The walk stops at `MAX_DELEGATION_DEPTH`, any
+///   longer chain is reported as that length.
+pub fn longest_incoming_chain<F>(user_ulid: &str, mut incoming: F) -> Result<usize, String>
+where
+    F: FnMut(&str) -> Result<Vec<String>, String>,
+{
+    let mut seen: HashSet<String> = HashSet::from([user_ulid.to_string()]);
+    let mut level: Vec<String> = vec![user_ulid.to_string()];
+    let mut depth = 0;
+
+    while depth < MAX_DELEGATION_DEPTH {
+        let mut next_level = Vec::new();
+        for user in &level {
+            for delegator in incoming(user)? {
+                if seen.insert(delegator.clone()) {
+                    next_level.push(delegator);
+                }
+            }
+        }
+        if next_level.is_empty() {
+            break;
+        }
+        depth += 1;
+        level = next_level;
+    }
+
+    Ok(depth)
+}
+
+/// Checks that a new delegation doesn't create a cycle or an over-long chain
+///
+/// The delegation joins the longest chain of delegators ending at the delegator to the
+/// delegate's own chain, so both count toward `MAX_DELEGATION_DEPTH`.
+///
+/// # Arguments
+/// * `delegator_ulid` - ULID of the user delegating
+/// * `delegate_ulid` - ULID of the user receiving the delegation
+/// * `next` - Looks up the existing delegate of a user
+/// * `incoming` - Looks up the direct delegators of a user
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if the delegation is allowed, `DelegationRejected` otherwise
+pub fn check_delegation_cycle<F, G>(delegator_ulid: &str, delegate_ulid: &str, next: F, incoming: G) -> Result<(), KarmieError>
+where
+    F: FnMut(&str) -> Result<Option<String>, String>,
+    G: FnMut(&str) -> Result<Vec<String>, String>,
+{
+    let chain = follow_delegation_chain(delegate_ulid, next)
+        .map_err(|reason| KarmieError::DelegationRejected { reason })?;
+
+    if chain.iter().any(|user| user == delegator_ulid) {
+        return Err(KarmieError::DelegationRejected {
+            reason: format!(
+                "Delegation would create a cycle: {} -> {}",
+                delegator_ulid,
+                chain.join(" -> ")
+            ),
+        });
+    }
+
+    // The delegator, and every chain already ending at them, join the front of the chain
+    let upstream = longest_incoming_chain(delegator_ulid, incoming)
+        .map_err(|reason| KarmieError::DelegationRejected { reason })?;
+    if upstream + 1 + chain.len() > MAX_DELEGATION_DEPTH {
+        return Err(KarmieError::DelegationRejected {
+            reason: format!("Delegation chain would be longer than {} users", MAX_DELEGATION_DEPTH),
+        });
+    }
+
+    Ok(())
+}
+
+/// Collects every user whose voting power flows to a delegate, directly or transitively
+///
+/// # Arguments
+/// * `delegate_ulid` - ULID of the delegate
+/// * `incoming` - Looks up the direct delegators of a user
+///
+/// # Returns
+/// * `Result<Vec<String>, String>` - The delegators in breadth-first order, without duplicates
+pub fn collect_delegators<F>(delegate_ulid: &str, mut incoming: F) -> Result<Vec<String>, String>
+where
+    F: FnMut(&str) -> Result<Vec<String>, String>,
+{
+    let mut seen: HashSet<String> = HashSet::from([delegate_ulid.to_string()]);
+    let mut delegators: Vec<String> = Vec::new();
+    let mut level: Vec<String> = vec![delegate_ulid.to_string()];
+
+    // A chain holds at most MAX_DELEGATION_DEPTH users, so the delegators are at most
+    // MAX_DELEGATION_DEPTH - 1 levels away
+    for _ in 1..MAX_DELEGATION_DEPTH {
+        let mut next_level = Vec::new();
+        for user in &level {
+            for delegator in incoming(user)? {
+                if seen.insert(delegator.clone()) {
+                    delegators.push(delegator.clone());
+                    next_level.push(delegator);
+                }
+            }
+        }
+        if next_level.is_empty() {
+            break;
+        }
+        level = next_level;
+    }
+
+    Ok(delegators)
+}
+
+/// Folds delegated voting power into a delegate's author information
+///
+/// # Arguments
+/// * `own` - The delegate's own author information
+/// * `delegated` - Author information of every transitive delegator
+///
+/// # Returns
+/// * `AuthorInfo` - The combined author information (see module docs)
+pub fn fold_delegated_power(own: &AuthorInfo, delegated: &[AuthorInfo]) -> AuthorInfo {
+    let own_reputation = if own.votes_active { own.effective_reputation } else { 0.0 };
+
+    let active_delegators: Vec<&AuthorInfo> = delegated.iter().filter(|info| info.votes_active).collect();
+    let delegated_reputation: f64 = active_delegators.iter().map(|info| info.effective_reputation).sum();
+
+    AuthorInfo {
+        effective_reputation: own_reputation + delegated_reputation,
+        vote_weight: own.vote_weight.clone(),
+        votes_active: own.votes_active || !active_delegators.is_empty(),
+    }
+}
+
+/// Gets an author's information in a tag with delegated power folded in
+///
+/// # Arguments
+/// * `author_ulid` - ULID of the author
+/// * `tag_ulid` - ULID of the tag
+///
+/// # Returns
+/// * `Result<Option<AuthorInfo>, String>` - The folded information, or None if the author has no reputation document
+pub async fn get_author_info_with_delegations(author_ulid: &str, tag_ulid: &str) -> Result<Option<AuthorInfo>, String> {
+    let own = match get_user_reputation_slim(author_ulid, tag_ulid).await? {
+        Some(info) => info,
+        None => return Ok(None),
+    };
+
+    let delegators = collect_delegators(author_ulid, |user| {
+        Ok(get_incoming_delegations(user, tag_ulid)?
+            .into_iter()
+            .map(|delegation| delegation.delegator_ulid)
+            .collect())
+    })?;
+
+    if delegators.is_empty() {
+        return Ok(Some(own));
+    }
+
+    let mut delegated: Vec<AuthorInfo> = Vec::new();
+    for delegator in &delegators {
+        match get_user_reputation_slim(delegator, tag_ulid).await {
+            Ok(Some(info)) => delegated.push(info),
+            Ok(None) => {}
+            Err(e) => {
+                logger!("warn", "[get_author_info_with_delegations] Skipping delegator={} of author={} in tag={}: {}",
+                    delegator, author_ulid, tag_ulid, e);
+            }
+        }
+    }
+
+    let folded = fold_delegated_power(&own, &delegated);
+    logger!("info", "[get_author_info_with_delegations] Author={} in tag={} holds power of {} delegators: reputation {} -> {}",
+        author_ulid, tag_ulid, delegators.len(), own.effective_reputation, folded.effective_reputation);
+
+    Ok(Some(folded))
+}
+
+/// Recalculates the reputation affected by a delegation being created or revoked
+///
+/// The delegator's votes start (or stop) counting on their own, and the power of the
+/// delegator moves to (or away from) every user up the delegate's chain, so the targets
+/// of all their votes in the tag are recalculated.
+///
+/// # Arguments
+/// * `delegation` - The delegation that was created or revoked
+///
+/// # Returns
+/// * `Result<(), String>` - Ok once every affected target is recalculated or checkpointed into a job
+pub async fn recalculate_delegation_targets(delegation: &DelegationData) -> Result<(), String> {
+    let tag_ulid = &delegation.tag_ulid;
+
+    let mut users = vec![delegation.delegator_ulid.clone()];
+    users.extend(follow_delegation_chain(&delegation.delegate_ulid, |user| {
+        Ok(get_outgoing_delegation(user, tag_ulid)?.map(|d| d.delegate_ulid))
+    })?);
+
+    let mut targets: Vec<String> = Vec::new();
+    for user in &users {
+        let votes = query_doc_by_key("votes", &KeyPattern::new().anchor_start().usr(user).tag(tag_ulid).any(KeySegment::Tar).build())?;
+        for (key, doc) in &votes.items {
+            match decode_doc_data::<VoteData>(&doc.data) {
+                Ok(vote) => {
+                    if !targets.contains(&vote.target_ulid) {
+                        targets.push(vote.target_ulid);
+                    }
+                }
+                Err(e) => {
+                    logger!("warn", "[recalculate_delegation_targets] Skipping undecodable vote: key={}, error={}", key, e);
+                }
+            }
+        }
+    }
+
+    logger!("info", "[recalculate_delegation_targets] Delegation {} -> {} in tag={} affects {} users and {} targets",
+        delegation.delegator_ulid, delegation.delegate_ulid, tag_ulid, users.len(), targets.len());
+
+    let steps = targets
+        .iter()
+        .map(|target| JobStep::Reputation { user_ulid: target.clone(), tag_ulid: tag_ulid.clone() })
+        .collect();
+    run_steps_within_budget("delegation", steps).await?;
+
+    refresh_stale_snapshots().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::structs::VoteWeight;
+    use std::collections::HashMap;
+
+    fn edges(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(from, to)| (from.to_string(), to.to_string())).collect()
+    }
+
+    fn next_in(map: &HashMap<String, String>) -> impl FnMut(&str) -> Result<Option<String>, String> + '_ {
+        move |user| Ok(map.get(user).cloned())
+    }
+
+    fn incoming_in(map: &HashMap<String, String>) -> impl FnMut(&str) -> Result<Vec<String>, String> + '_ {
+        move |user| {
+            let mut delegators: Vec<String> = map
+                .iter()
+                .filter(|(_, to)| to.as_str() == user)
+                .map(|(from, _)| from.clone())
+                .collect();
+            delegators.sort();
+            Ok(delegators)
+        }
+    }
+
+    fn info(reputation: f64, weight: f64, active: bool) -> AuthorInfo {
+        AuthorInfo {
+            effective_reputation: reputation,
+            vote_weight: VoteWeight::new(weight).unwrap(),
+            votes_active: active,
+        }
+    }
+
+    #[test]
+    fn test_follow_chain() {
+        let map = edges(&[("A", "B"), ("B", "C")]);
+        assert_eq!(follow_delegation_chain("A", next_in(&map)).unwrap(), vec!["A", "B", "C"]);
+        assert_eq!(follow_delegation_chain("C", next_in(&map)).unwrap(), vec!["C"]);
+    }
+
+    #[test]
+    fn test_rejects_cycles() {
+        // C -> A would close A -> B -> C
+        let map = edges(&[("A", "B"), ("B", "C")]);
+        assert!(check_delegation_cycle("C", "A", next_in(&map), incoming_in(&map)).is_err());
+        assert!(check_delegation_cycle("B", "A", next_in(&map), incoming_in(&map)).is_err());
+
+        // D -> A is fine
+        assert!(check_delegation_cycle("D", "A", next_in(&map), incoming_in(&map)).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_long_chains() {
+        let users: Vec<String> = (0..MAX_DELEGATION_DEPTH).map(|i| format!("U{}", i)).collect();
+        let pairs: Vec<(&str, &str)> = users.windows(2).map(|w| (w[0].as_str(), w[1].as_str())).collect();
+        let map = edges(&pairs);
+
+        // The existing chain is exactly at the limit, so nobody can join its front
+        assert_eq!(follow_delegation_chain("U0", next_in(&map)).unwrap().len(), MAX_DELEGATION_DEPTH);
+        assert!(check_delegation_cycle("X", "U0", next_in(&map), incoming_in(&map)).is_err());
+        assert!(check_delegation_cycle("X", "U1", next_in(&map), incoming_in(&map)).is_ok());
+
+        // Nobody can join its tail either
+        let last = users.last().unwrap();
+        assert_eq!(longest_incoming_chain(last, incoming_in(&map)).unwrap(), MAX_DELEGATION_DEPTH - 1);
+        assert!(check_delegation_cycle(last, "X", next_in(&map), incoming_in(&map)).is_err());
+    }
+
+    #[test]
+    fn test_rejects_long_chains_built_from_both_ends() {
+        // Two chains of half the limit each: H0 -> .. -> H4 and T0 -> .. -> T4
+        let half = MAX_DELEGATION_DEPTH / 2;
+        let heads: Vec<String> = (0..half).map(|i| format!("H{}", i)).collect();
+        let tails: Vec<String> = (0..half).map(|i| format!("T{}", i)).collect();
+        let pairs: Vec<(&str, &str)> = heads.windows(2).chain(tails.windows(2))
+            .map(|w| (w[0].as_str(), w[1].as_str()))
+            .collect();
+        let mut map = edges(&pairs);
+
+        // Linking them gives a chain exactly at the limit
+        let (head_end, tail_start) = (heads.last().unwrap().clone(), tails[0].clone());
+        assert!(check_delegation_cycle(&head_end, &tail_start, next_in(&map), incoming_in(&map)).is_ok());
+        map.insert(head_end, tail_start);
+        assert_eq!(follow_delegation_chain("H0", next_in(&map)).unwrap().len(), MAX_DELEGATION_DEPTH);
+
+        // Growing it at either end is rejected
+        assert!(check_delegation_cycle("X", "H0", next_in(&map), incoming_in(&map)).is_err());
+        assert!(check_delegation_cycle(tails.last().unwrap(), "X", next_in(&map), incoming_in(&map)).is_err());
+
+        // A branch that stays within the limit is fine
+        assert!(check_delegation_cycle("X", "H1", next_in(&map), incoming_in(&map)).is_ok());
+    }
+
+    #[test]
+    fn test_collect_transitive_delegators() {
+        // A -> C, B -> C, D -> A, E -> D
+        let map = edges(&[("A", "C"), ("B", "C"), ("D", "A"), ("E", "D")]);
+
+        assert_eq!(collect_delegators("C", incoming_in(&map)).unwrap(), vec!["A", "B", "D", "E"]);
+        assert_eq!(collect_delegators("A", incoming_in(&map)).unwrap(), vec!["D", "E"]);
+        assert!(collect_delegators("E", incoming_in(&map)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_collect_delegators_survives_cycles() {
+        // Stored data should never contain a cycle, but it must not loop if it does
+        let map = edges(&[("A", "B"), ("B", "A")]);
+        assert_eq!(collect_delegators("A", incoming_in(&map)).unwrap(), vec!["B"]);
+    }
+
+    #[test]
+    fn test_fold_adds_active_delegators() {
+        let own = info(40.0, 0.25, true);
+        let delegated = vec![info(10.0, 0.5, true), info(50.0, 1.0, false), info(5.0, 0.1, true)];
+
+        let folded = fold_delegated_power(&own, &delegated);
+        assert_eq!(folded.effective_reputation, 55.0);
+        assert_eq!(folded.vote_weight.value(), 0.25);
+        assert!(folded.votes_active);
+    }
+
+    #[test]
+    fn test_fold_gives_inactive_delegate_voting_power() {
+        // The delegate's own reputation doesn't count without voting power, but delegated power does
+        let own = info(3.0, 0.5, false);
+
+        let folded = fold_delegated_power(&own, &[info(20.0, 1.0, true)]);
+        assert_eq!(folded.effective_reputation, 20.0);
+        assert!(folded.votes_active);
+
+        let folded = fold_delegated_power(&own, &[info(20.0, 1.0, false)]);
+        assert_eq!(folded.effective_reputation, 0.0);
+        assert!(!folded.votes_active);
+    }
+}
@@ -0,0 +1,262 @@
+/*!
+ * Duplicate vote handling
+ *
+ * An author can cast several votes on the same target in the same tag, because
+ * every vote key ends with its own ULID:
+ * ```text
+ * usr_{author}_tag_{tag}_tar_{target}_key_{vote}_
+ * ```
+ *
+ * Each tag defines a `DuplicateVotePolicy` that decides which of these votes count:
+ * - Cumulative: all votes count, optionally capped at `duplicate_vote_cap`
+ * - LatestWins: only the most recent vote counts
+ * - OneVote: only one vote is allowed (the first one)
+ *
+ * The policy is enforced when votes are created (see assert_doc_vote.rs) and applied
+ * again whenever votes are read for calculations, so that votes stored before the
+ * policy was set (or changed) are counted consistently everywhere.
+ */
+
+use std::collections::HashMap;
+
+use junobuild_satellite::Doc;
+use junobuild_utils::decode_doc_data;
+
+use crate::processors::document_queries::query_doc_by_key;
+use crate::processors::key_pattern::{KeyPattern, KeySegment};
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::{DuplicateVotePolicy, VoteData};
+
+/// The fields that identify a vote for duplicate detection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoteIdentity {
+    pub tag_ulid: String,
+    pub owner_ulid: String,
+    pub target_ulid: String,
+
+    /// ULIDs sort chronologically, so this orders votes by creation time
+    pub vote_ulid: String,
+}
+
+impl VoteIdentity {
+    /// Builds the identity of a decoded vote
+    pub fn from_vote(vote: &VoteData) -> Self {
+        Self {
+            tag_ulid: vote.tag_ulid.clone(),
+            owner_ulid: vote.owner_ulid.clone(),
+            target_ulid: vote.target_ulid.clone(),
+            vote_ulid: vote.vote_ulid.clone(),
+        }
+    }
+
+    /// Builds the identity of a vote document, None if the document can't be decoded
+    pub fn from_doc(doc: &Doc) -> Option<Self> {
+        decode_doc_data::<VoteData>(&doc.data)
+            .ok()
+            .map(|vote| Self::from_vote(&vote))
+    }
+}
+
+/// Gets all votes an author has cast on a target in a tag
+///
+/// # Arguments
+/// * `author_ulid` - ULID of the user who cast the votes
+/// * `target_ulid` - ULID of the user who received the votes
+/// * `tag_ulid` - ULID of the tag
+///
+/// # Returns
+/// * `Result<Vec<(String, Doc)>, String>` - The vote documents (key, doc) or an error message
+pub fn query_author_votes_on_target(
+    author_ulid: &str,
+    target_ulid: &str,
+    tag_ulid: &str,
+) -> Result<Vec<(String, Doc)>, String> {
+    let key_pattern = KeyPattern::new().anchor_start().usr(author_ulid).tag(tag_ulid).tar(target_ulid).any(KeySegment::Key).anchor_end().build();
+    Ok(query_doc_by_key("votes", &key_pattern)?.items)
+}
+
+/// Filters a list of votes down to the ones that count under a tag's policy
+///
+/// Votes are grouped by (tag, author, target). Within each group, votes are ordered by
+/// their ULID (creation time) and the policy decides which ones are kept:
+/// - Cumulative with cap 0: all votes
+/// - Cumulative with cap N: the first N votes
+/// - LatestWins: the most recent vote
+/// - OneVote: the first vote
+///
+/// Items for which `identify` returns None (e.g. undecodable documents) are kept
+/// so that callers can keep handling them the way they already do.
+///
+/// # Arguments
+/// * `items` - The votes to filter (any type, e.g. VoteData or (key, Doc))
+/// * `policy` - The tag's duplicate vote policy
+/// * `cap` - The tag's duplicate vote cap (only used for Cumulative)
+/// * `identify` - Extracts the vote identity from an item
+///
+/// # Returns
+/// * `Vec<T>` - The votes that count, in their original order
+pub fn apply_duplicate_vote_policy<T, F>(
+    items: Vec<T>,
+    policy: DuplicateVotePolicy,
+    cap: u32,
+    identify: F,
+) -> Vec<T>
+where
+    F: Fn(&T) -> Option<VoteIdentity>,
+{
+    // Nothing to filter for uncapped cumulative votes
+    if policy == DuplicateVotePolicy::Cumulative && cap == 0 {
+        return items;
+    }
+
+    let identities: Vec<Option<VoteIdentity>> = items.iter().map(&identify).collect();
+
+    // Group item indexes by (tag, author, target)
+    let mut groups: HashMap<(&str, &str, &str), Vec<usize>> = HashMap::new();
+    for (index, identity) in identities.iter().enumerate() {
+        if let Some(id) = identity {
+            groups
+                .entry((id.tag_ulid.as_str(), id.owner_ulid.as_str(), id.target_ulid.as_str()))
+                .or_default()
+                .push(index);
+        }
+    }
+
+    // Decide which indexes to keep in each group
+    let mut keep = vec![false; items.len()];
+    for (index, identity) in identities.iter().enumerate() {
+        if identity.is_none() {
+            keep[index] = true;
+        }
+    }
+
+    for indexes in groups.values_mut() {
+        indexes.sort_by(|a, b| {
+            let a_ulid = &identities[*a].as_ref().unwrap().vote_ulid;
+            let b_ulid = &identities[*b].as_ref().unwrap().vote_ulid;
+            a_ulid.cmp(b_ulid)
+        });
+
+        let kept: &[usize] = match policy {
+            DuplicateVotePolicy::Cumulative => &indexes[..indexes.len().min(cap as usize)],
+            DuplicateVotePolicy::LatestWins => &indexes[indexes.len() - 1..],
+            DuplicateVotePolicy::OneVote => &indexes[..1],
+        };
+
+        for index in kept {
+            keep[*index] = true;
+        }
+    }
+
+    items
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(item, keep)| if keep { Some(item) } else { None })
+        .collect()
+}
+
+/// Checks whether a new vote is allowed given the author's existing votes on the target
+///
+/// # Arguments
+/// * `policy` - The tag's duplicate vote policy
+/// * `cap` - The tag's duplicate vote cap (only used for Cumulative)
+/// * `existing_votes` - Number of votes the author already cast on the target in this tag
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if the vote is allowed, `DuplicateVote` with the reason otherwise
+pub fn check_new_vote_allowed(
+    policy: DuplicateVotePolicy,
+    cap: u32,
+    existing_votes: usize,
+) -> Result<(), KarmieError> {
+    match policy {
+        DuplicateVotePolicy::OneVote if existing_votes > 0 => Err(KarmieError::DuplicateVote {
+            reason: "Only one vote per user is allowed in this tag. Edit your existing vote instead.".to_string(),
+        }),
+        DuplicateVotePolicy::Cumulative if cap > 0 && existing_votes >= cap as usize => Err(KarmieError::DuplicateVote {
+            reason: format!("Vote limit reached: at most {} votes per user are allowed in this tag", cap),
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(owner: &str, target: &str, vote_ulid: &str) -> VoteIdentity {
+        VoteIdentity {
+            tag_ulid: "TAG".to_string(),
+            owner_ulid: owner.to_string(),
+            target_ulid: target.to_string(),
+            vote_ulid: vote_ulid.to_string(),
+        }
+    }
+
+    fn ulids(votes: &[VoteIdentity]) -> Vec<&str> {
+        votes.iter().map(|v| v.vote_ulid.as_str()).collect()
+    }
+
+    fn sample_votes() -> Vec<VoteIdentity> {
+        vec![
+            vote("A", "B", "03"),
+            vote("A", "B", "01"),
+            vote("A", "C", "05"),
+            vote("C", "B", "04"),
+            vote("A", "B", "02"),
+        ]
+    }
+
+    #[test]
+    fn test_cumulative_without_cap_keeps_everything() {
+        let kept = apply_duplicate_vote_policy(sample_votes(), DuplicateVotePolicy::Cumulative, 0, |v| Some(v.clone()));
+        assert_eq!(ulids(&kept), vec!["03", "01", "05", "04", "02"]);
+    }
+
+    #[test]
+    fn test_cumulative_with_cap_keeps_earliest() {
+        let kept = apply_duplicate_vote_policy(sample_votes(), DuplicateVotePolicy::Cumulative, 2, |v| Some(v.clone()));
+        assert_eq!(ulids(&kept), vec!["01", "05", "04", "02"]);
+    }
+
+    #[test]
+    fn test_latest_wins_keeps_most_recent() {
+        let kept = apply_duplicate_vote_policy(sample_votes(), DuplicateVotePolicy::LatestWins, 0, |v| Some(v.clone()));
+        assert_eq!(ulids(&kept), vec!["03", "05", "04"]);
+    }
+
+    #[test]
+    fn test_one_vote_keeps_first() {
+        let kept = apply_duplicate_vote_policy(sample_votes(), DuplicateVotePolicy::OneVote, 0, |v| Some(v.clone()));
+        assert_eq!(ulids(&kept), vec!["01", "05", "04"]);
+    }
+
+    #[test]
+    fn test_groups_are_per_tag() {
+        let mut other_tag = vote("A", "B", "09");
+        other_tag.tag_ulid = "OTHER".to_string();
+        let mut votes = sample_votes();
+        votes.push(other_tag);
+
+        let kept = apply_duplicate_vote_policy(votes, DuplicateVotePolicy::LatestWins, 0, |v| Some(v.clone()));
+        assert_eq!(ulids(&kept), vec!["03", "05", "04", "09"]);
+    }
+
+    #[test]
+    fn test_unidentified_items_are_kept() {
+        let kept = apply_duplicate_vote_policy(sample_votes(), DuplicateVotePolicy::OneVote, 0, |v| {
+            if v.vote_ulid == "02" { None } else { Some(v.clone()) }
+        });
+        assert_eq!(ulids(&kept), vec!["01", "05", "04", "02"]);
+    }
+
+    #[test]
+    fn test_check_new_vote_allowed() {
+        assert!(check_new_vote_allowed(DuplicateVotePolicy::Cumulative, 0, 100).is_ok());
+        assert!(check_new_vote_allowed(DuplicateVotePolicy::Cumulative, 3, 2).is_ok());
+        assert!(check_new_vote_allowed(DuplicateVotePolicy::Cumulative, 3, 3).is_err());
+        assert!(check_new_vote_allowed(DuplicateVotePolicy::LatestWins, 0, 5).is_ok());
+        assert!(check_new_vote_allowed(DuplicateVotePolicy::OneVote, 0, 0).is_ok());
+        assert!(check_new_vote_allowed(DuplicateVotePolicy::OneVote, 0, 1).is_err());
+    }
+}
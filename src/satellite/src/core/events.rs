@@ -0,0 +1,627 @@
+/*!
+ * Reputation event outbox
+ *
+ * Downstream systems (notification workers, partner canisters, ...) learn about changes
+ * by consuming an append-only log instead of polling every document. The satellite
+ * writes one document per event into the `events` collection:
+ * ```text
+ * key:         evt_{sequence}_                 (sequence zero-padded to 20 digits)
+ * description: tag_{tag}_type_{event_type}_
+ * ```
+ * Sequence numbers are strictly increasing and never reused, so consumers keep the last
+ * sequence they processed as a cursor and call `get_events_since(cursor, limit)`.
+ *
+ * Events emitted:
+ * - `VoteCast`, `VoteChanged`, `VoteRemoved` by the vote hooks
+ * - `ReputationChanged`, `VotingPowerGained`, `VotingPowerLost` whenever the engine stores a reputation
+ * - `TagPhaseChanged` when a tag enters or leaves its bootstrap phase
+ *
+ * # Retention
+ * Events older than `EVENT_RETENTION_NS` are pruned by a timer every
+ * `EVENT_PRUNE_INTERVAL_NS`, `MAX_EVENTS_PRUNED` per message: a full batch schedules
+ * the next one right away, until every expired event is gone. The timer is started
+ * after upgrades and by the first append of a fresh satellite. Consumers whose cursor is older than `oldest_sequence` missed events and
+ * should resynchronise from the reputation queries.
+ *
+ * # State
+ * The next sequence number is cached in heap memory and reloaded from the newest event
+ * after an upgrade. Emitting an event never fails the operation that triggered it.
+ */
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use candid::CandidType;
+use junobuild_satellite::{delete_doc_store, list_docs_store, set_doc_store, DelDoc, Doc, SetDoc};
+use junobuild_shared::types::list::{
+    ListMatcher, ListOrder, ListOrderField, ListPaginate, ListParams, TimestampMatcher,
+};
+use junobuild_utils::{decode_doc_data, encode_doc_data};
+use serde::{Deserialize, Serialize};
+
+use crate::logger;
+use crate::processors::document_queries::DocQuery;
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::{ReputationData, VoteData};
+
+/// Collection holding the events
+pub const EVENTS_COLLECTION: &str = "events";
+
+/// Maximum number of events returned by `get_events_since`
+pub const MAX_EVENTS_PAGE: usize = 100;
+
+/// How long events are kept (30 days)
+pub const EVENT_RETENTION_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Minimum time between two pruning runs (1 hour)
+pub const EVENT_PRUNE_INTERVAL_NS: u64 = 60 * 60 * 1_000_000_000;
+
+/// Maximum number of events deleted by a single pruning message
+pub const MAX_EVENTS_PRUNED: usize = 500;
+
+/// Kind of event
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, CandidType)]
+pub enum EventType {
+    /// A user voted on a target
+    VoteCast,
+
+    /// A vote's value, intensity, tag or target was edited
+    VoteChanged,
+
+    /// A vote was deleted
+    VoteRemoved,
+
+    /// A user's effective reputation changed
+    ReputationChanged,
+
+    /// A user reached the tag's reputation threshold
+    VotingPowerGained,
+
+    /// A user fell below the tag's reputation threshold
+    VotingPowerLost,
+
+    /// A tag entered or left its bootstrap phase
+    TagPhaseChanged,
+}
+
+/// An event stored in the `events` collection
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, CandidType)]
+pub struct EventData {
+    /// Position in the log, strictly increasing
+    pub sequence: u64,
+
+    /// Kind of event
+    pub event_type: EventType,
+
+    /// ULID of the tag the event happened in
+    pub tag_ulid: String,
+
+    /// The user the event is about (the vote's author for vote events)
+    pub user_ulid: Option<String>,
+
+    /// The vote's target (vote events only)
+    pub target_ulid: Option<String>,
+
+    /// The vote's ULID (vote events only)
+    pub vote_ulid: Option<String>,
+
+    /// New value: the vote's value, or the effective reputation
+    pub value: Option<f64>,
+
+    /// Previous value, for changes
+    pub previous_value: Option<f64>,
+
+    /// Whether the tag is now in its bootstrap phase (TagPhaseChanged only)
+    pub in_bootstrap_phase: Option<bool>,
+
+    /// When the event was recorded (nanoseconds since epoch)
+    pub created_at: u64,
+}
+
+/// A page of events returned to consumers
+#[derive(Serialize, Deserialize, Clone, Debug, CandidType)]
+pub struct EventPage {
+    /// Events with a sequence greater than the cursor, oldest first
+    pub events: Vec<EventData>,
+
+    /// Cursor to pass to the next call (the last returned sequence, or the given cursor)
+    pub next_cursor: u64,
+
+    /// Whether more events are already available after this page
+    pub has_more: bool,
+
+    /// Oldest sequence still retained (None if the log is empty)
+    pub oldest_sequence: Option<u64>,
+}
+
+/// An event before it is assigned a sequence number
+#[derive(Clone, Debug, PartialEq)]
+pub struct NewEvent {
+    pub event_type: EventType,
+    pub tag_ulid: String,
+    pub user_ulid: Option<String>,
+    pub target_ulid: Option<String>,
+    pub vote_ulid: Option<String>,
+    pub value: Option<f64>,
+    pub previous_value: Option<f64>,
+    pub in_bootstrap_phase: Option<bool>,
+}
+
+impl NewEvent {
+    fn new(event_type: EventType, tag_ulid: &str) -> Self {
+        NewEvent {
+            event_type,
+            tag_ulid: tag_ulid.to_string(),
+            user_ulid: None,
+            target_ulid: None,
+            vote_ulid: None,
+            value: None,
+            previous_value: None,
+            in_bootstrap_phase: None,
+        }
+    }
+}
+
+/// Heap state of the outbox
+#[derive(Default)]
+struct EventLog {
+    /// Next sequence number to assign (None until loaded from the collection)
+    next_sequence: Option<u64>,
+
+    /// Whether the pruning timer is running (timers don't survive upgrades)
+    pruning_started: bool,
+
+    /// Last known bootstrap phase of each tag
+    tag_phases: HashMap<String, bool>,
+}
+
+thread_local! {
+    static EVENT_LOG: RefCell<EventLog> = RefCell::new(EventLog::default());
+}
+
+/// Formats the key of an event
+///
+/// Sequences are zero-padded so that key order is sequence order.
+pub fn format_event_key(sequence: u64) -> String {
+    format!("evt_{:020}_", sequence)
+}
+
+/// Parses the sequence number out of an event key
+pub fn parse_event_key(key: &str) -> Result<u64, String> {
+    key.strip_prefix("evt_")
+        .and_then(|rest| rest.strip_suffix('_'))
+        .ok_or_else(|| format!("Invalid event key: {}", key))?
+        .parse::<u64>()
+        .map_err(|e| format!("Invalid event key {}: {}", key, e))
+}
+
+/// Formats the description of an event, used to filter events by tag and type
+pub fn format_event_description(tag_ulid: &str, event_type: EventType) -> String {
+    format!("tag_{}_type_{:?}_", tag_ulid, event_type)
+}
+
+/// Derives the events caused by storing a reputation
+///
+/// # Arguments
+/// * `before` - The reputation before the write (None if it was created)
+/// * `after` - The stored reputation
+///
+/// # Returns
+/// * `Vec<NewEvent>` - ReputationChanged and/or VotingPowerGained/Lost, possibly empty
+pub fn reputation_change_events(before: Option<&ReputationData>, after: &ReputationData) -> Vec<NewEvent> {
+    let mut events = Vec::new();
+
+    let previous_reputation = before.map(|data| data.reputation_total_effective).unwrap_or(0.0);
+    if previous_reputation != after.reputation_total_effective {
+        let mut event = NewEvent::new(EventType::ReputationChanged, &after.tag_ulid);
+        event.user_ulid = Some(after.owner_ulid.clone());
+        event.value = Some(after.reputation_total_effective);
+        event.previous_value = Some(previous_reputation);
+        events.push(event);
+    }
+
+    let had_voting_power = before.map(|data| data.has_voting_power).unwrap_or(false);
+    if had_voting_power != after.has_voting_power {
+        let event_type = if after.has_voting_power {
+            EventType::VotingPowerGained
+        } else {
+            EventType::VotingPowerLost
+        };
+        let mut event = NewEvent::new(event_type, &after.tag_ulid);
+        event.user_ulid = Some(after.owner_ulid.clone());
+        event.value = Some(after.reputation_total_effective);
+        events.push(event);
+    }
+
+    events
+}
+
+/// Builds a vote event
+///
+/// # Arguments
+/// * `event_type` - VoteCast, VoteChanged or VoteRemoved
+/// * `vote` - The vote (the previous version for VoteRemoved)
+/// * `previous` - The previous version of an edited vote
+pub fn vote_event(event_type: EventType, vote: &VoteData, previous: Option<&VoteData>) -> NewEvent {
+    let mut event = NewEvent::new(event_type, &vote.tag_ulid);
+    event.user_ulid = Some(vote.owner_ulid.clone());
+    event.target_ulid = Some(vote.target_ulid.clone());
+    event.vote_ulid = Some(vote.vote_ulid.clone());
+    event.value = Some(vote.value);
+    event.previous_value = previous.map(|previous| previous.value);
+    event
+}
+
+/// Drops events at or before the cursor and truncates to the limit
+///
+/// # Returns
+/// * `(Vec<EventData>, bool)` - The events to return and whether more were available
+pub fn select_events_after(events: Vec<EventData>, cursor: u64, limit: usize) -> (Vec<EventData>, bool) {
+    let mut selected: Vec<EventData> = events.into_iter().filter(|event| event.sequence > cursor).collect();
+    let has_more = selected.len() > limit;
+    selected.truncate(limit);
+    (selected, has_more)
+}
+
+/// Lists events in key order
+fn list_events(start_after: Option<String>, limit: usize, desc: bool) -> Result<Vec<(String, Doc)>, KarmieError> {
+    let mut query = DocQuery::new(EVENTS_COLLECTION, "^evt_")
+        .order_by(ListOrderField::Keys, desc)
+        .page_size(limit);
+    if let Some(key) = start_after {
+        query = query.start_after(&key);
+    }
+    query.iter().take(limit).collect()
+}
+
+/// Sequence of the oldest or newest retained event
+fn boundary_sequence(newest: bool) -> Result<Option<u64>, String> {
+    match list_events(None, 1, newest)?.first() {
+        Some((key, _)) => Ok(Some(parse_event_key(key)?)),
+        None => Ok(None),
+    }
+}
+
+/// Starts pruning expired events on a timer, unless it is already running
+///
+/// Called after upgrades and when events are appended.
+pub fn start_event_pruning() {
+    let started = EVENT_LOG.with(|log| std::mem::replace(&mut log.borrow_mut().pruning_started, true));
+    if started {
+        return;
+    }
+
+    ic_cdk_timers::set_timer_interval(Duration::from_nanos(EVENT_PRUNE_INTERVAL_NS), prune_expired_events);
+    // Catch up with anything that expired while the timer wasn't running
+    ic_cdk_timers::set_timer(Duration::ZERO, prune_expired_events);
+}
+
+/// Deletes a batch of expired events, and schedules the next batch if there may be more
+fn prune_expired_events() {
+    let (listed, pruned) = prune_events_batch(ic_cdk::api::time());
+
+    // A batch with failed deletions stops here, the next interval retries it
+    if listed == MAX_EVENTS_PRUNED && pruned == listed {
+        ic_cdk_timers::set_timer(Duration::ZERO, prune_expired_events);
+    }
+}
+
+/// Deletes up to `MAX_EVENTS_PRUNED` events older than the retention period
+///
+/// # Returns
+/// * `(usize, usize)` - Number of expired events listed and number deleted
+fn prune_events_batch(now: u64) -> (usize, usize) {
+    let expired = list_docs_store(
+        ic_cdk::id(),
+        EVENTS_COLLECTION.to_string(),
+        &ListParams {
+            matcher: Some(ListMatcher {
+                key: Some("^evt_".to_string()),
+                created_at: Some(TimestampMatcher::LessThan(now.saturating_sub(EVENT_RETENTION_NS))),
+                ..Default::default()
+            }),
+            paginate: Some(ListPaginate {
+                start_after: None,
+                limit: Some(MAX_EVENTS_PRUNED),
+            }),
+            order: Some(ListOrder {
+                desc: false,
+                field: ListOrderField::Keys,
+            }),
+            owner: None,
+        },
+    );
+
+    let expired = match expired {
+        Ok(results) => results.items,
+        Err(e) => {
+            logger!("error", "[prune_events_batch] Failed to list expired events: {}", e);
+            return (0, 0);
+        }
+    };
+
+    let listed = expired.len();
+    let mut pruned = 0;
+    for (key, doc) in expired {
+        match delete_doc_store(ic_cdk::id(), EVENTS_COLLECTION.to_string(), key.clone(), DelDoc { version: doc.version }) {
+            Ok(_) => pruned += 1,
+            Err(e) => logger!("error", "[prune_events_batch] Failed to delete event {}: {}", key, e),
+        }
+    }
+
+    if pruned > 0 {
+        logger!("info", "[prune_events_batch] Pruned {} expired events", pruned);
+    }
+
+    (listed, pruned)
+}
+
+/// Appends an event to the log
+fn append_event(event: NewEvent) -> Result<u64, String> {
+    let next_sequence = match EVENT_LOG.with(|log| log.borrow().next_sequence) {
+        Some(sequence) => sequence,
+        None => boundary_sequence(true)?.map(|newest| newest + 1).unwrap_or(1),
+    };
+
+    let now = ic_cdk::api::time();
+    let data = EventData {
+        sequence: next_sequence,
+        event_type: event.event_type,
+        tag_ulid: event.tag_ulid,
+        user_ulid: event.user_ulid,
+        target_ulid: event.target_ulid,
+        vote_ulid: event.vote_ulid,
+        value: event.value,
+        previous_value: event.previous_value,
+        in_bootstrap_phase: event.in_bootstrap_phase,
+        created_at: now,
+    };
+
+    // No version: keys are never reused, so writing over an existing event fails
+    set_doc_store(
+        ic_cdk::id(),
+        EVENTS_COLLECTION.to_string(),
+        format_event_key(next_sequence),
+        SetDoc {
+            data: encode_doc_data(&data)?,
+            description: Some(format_event_description(&data.tag_ulid, data.event_type)),
+            version: None,
+        },
+    )?;
+
+    EVENT_LOG.with(|log| log.borrow_mut().next_sequence = Some(next_sequence + 1));
+
+    start_event_pruning();
+
+    Ok(next_sequence)
+}
+
+/// Records events, logging failures
+///
+/// # Arguments
+/// * `events` - The events to append, in order
+pub fn emit_events(events: Vec<NewEvent>) {
+    for event in events {
+        let event_type = event.event_type;
+        let tag_ulid = event.tag_ulid.clone();
+        match append_event(event) {
+            Ok(sequence) => {
+                logger!("debug", "[emit_events] Recorded {:?} event #{} in tag={}", event_type, sequence, tag_ulid);
+            }
+            Err(e) => {
+                logger!("error", "[emit_events] Failed to record {:?} event in tag={}: {}", event_type, tag_ulid, e);
+            }
+        }
+    }
+}
+
+/// Records the events caused by storing a reputation document
+///
+/// Called after every `set_doc_store` on the reputations collection.
+///
+/// # Arguments
+/// * `before` - The document before the write
+/// * `after` - The stored document
+pub fn emit_reputation_events(before: Option<&Doc>, after: &Doc) {
+    let decoded = before
+        .map(|doc| decode_doc_data::<ReputationData>(&doc.data))
+        .transpose()
+        .and_then(|before| Ok((before, decode_doc_data::<ReputationData>(&after.data)?)));
+
+    match decoded {
+        Ok((before, after)) => emit_events(reputation_change_events(before.as_ref(), &after)),
+        Err(e) => logger!("error", "[emit_reputation_events] Failed to decode reputation data: {}", e),
+    }
+}
+
+/// Last recorded bootstrap phase of a tag (tags start in the bootstrap phase)
+fn recorded_tag_phase(tag_ulid: &str) -> Result<bool, String> {
+    let results = list_docs_store(
+        ic_cdk::id(),
+        EVENTS_COLLECTION.to_string(),
+        &ListParams {
+            matcher: Some(ListMatcher {
+                key: Some("^evt_".to_string()),
+                description: Some(format!("^{}$", format_event_description(tag_ulid, EventType::TagPhaseChanged))),
+                ..Default::default()
+            }),
+            paginate: Some(ListPaginate {
+                start_after: None,
+                limit: Some(1),
+            }),
+            order: Some(ListOrder {
+                desc: true,
+                field: ListOrderField::Keys,
+            }),
+            owner: None,
+        },
+    )?;
+
+    match results.items.first() {
+        Some((_, doc)) => {
+            let event: EventData = decode_doc_data(&doc.data)?;
+            Ok(event.in_bootstrap_phase.unwrap_or(true))
+        }
+        None => Ok(true),
+    }
+}
+
+/// Records a TagPhaseChanged event if a tag's bootstrap phase differs from the last one seen
+///
+/// # Arguments
+/// * `tag_ulid` - ULID of the tag
+/// * `in_bootstrap_phase` - Whether the tag is currently in its bootstrap phase
+pub fn observe_tag_phase(tag_ulid: &str, in_bootstrap_phase: bool) {
+    let known = EVENT_LOG.with(|log| log.borrow().tag_phases.get(tag_ulid).copied());
+    let previous = match known {
+        Some(phase) => phase,
+        None => match recorded_tag_phase(tag_ulid) {
+            Ok(phase) => phase,
+            Err(e) => {
+                logger!("error", "[observe_tag_phase] Failed to load the phase of tag={}: {}", tag_ulid, e);
+                return;
+            }
+        },
+    };
+
+    EVENT_LOG.with(|log| log.borrow_mut().tag_phases.insert(tag_ulid.to_string(), in_bootstrap_phase));
+
+    if previous != in_bootstrap_phase {
+        logger!("info", "[observe_tag_phase] Tag={} {} its bootstrap phase",
+            tag_ulid, if in_bootstrap_phase { "entered" } else { "left" });
+        let mut event = NewEvent::new(EventType::TagPhaseChanged, tag_ulid);
+        event.in_bootstrap_phase = Some(in_bootstrap_phase);
+        emit_events(vec![event]);
+    }
+}
+
+/// Returns the events recorded after a cursor
+///
+/// # Arguments
+/// * `cursor` - The last sequence the consumer processed (0 to start from the beginning)
+/// * `limit` - Maximum number of events to return (capped at `MAX_EVENTS_PAGE`)
+///
+/// # Returns
+/// * `Result<EventPage, KarmieError>` - The events, oldest first, and the next cursor
+pub fn get_event_page(cursor: u64, limit: u32) -> Result<EventPage, KarmieError> {
+    let limit = (limit as usize).clamp(1, MAX_EVENTS_PAGE);
+
+    // Juno paginates after an existing key only, so a cursor whose event was pruned
+    // starts from the oldest retained event instead
+    let oldest_sequence = boundary_sequence(false).map_err(|e| KarmieError::storage(EVENTS_COLLECTION, e))?;
+    let start_after = match oldest_sequence {
+        Some(oldest) if cursor >= oldest => Some(format_event_key(cursor)),
+        _ => None,
+    };
+    let results = list_events(start_after, limit + 1, false)?;
+
+    let events = results
+        .iter()
+        .map(|(key, doc)| {
+            decode_doc_data::<EventData>(&doc.data)
+                .map_err(|e| KarmieError::invalid_data(EVENTS_COLLECTION, format!("event {}: {}", key, e)))
+        })
+        .collect::<Result<Vec<_>, KarmieError>>()?;
+
+    let (events, has_more) = select_events_after(events, cursor, limit);
+    let next_cursor = events.last().map(|event| event.sequence).unwrap_or(cursor);
+
+    Ok(EventPage {
+        events,
+        next_cursor,
+        has_more,
+        oldest_sequence,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::structs::VoteWeight;
+
+    fn reputation(total: f64, has_voting_power: bool) -> ReputationData {
+        ReputationData {
+            owner_ulid: "U".to_string(),
+            tag_ulid: "T".to_string(),
+            reputation_basis: total,
+            reputation_rewards: 0.0,
+            reputation_total_effective: total,
+            last_calculation: 0,
+            vote_weight: VoteWeight::new(0.0).unwrap(),
+            has_voting_power,
+        }
+    }
+
+    fn event(sequence: u64) -> EventData {
+        EventData {
+            sequence,
+            event_type: EventType::VoteCast,
+            tag_ulid: "T".to_string(),
+            user_ulid: None,
+            target_ulid: None,
+            vote_ulid: None,
+            value: None,
+            previous_value: None,
+            in_bootstrap_phase: None,
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_event_keys_sort_by_sequence() {
+        assert_eq!(format_event_key(42), "evt_00000000000000000042_");
+        assert!(format_event_key(9) < format_event_key(10));
+        assert!(format_event_key(999) < format_event_key(1000));
+        assert_eq!(parse_event_key(&format_event_key(u64::MAX)), Ok(u64::MAX));
+        assert!(parse_event_key("usr_X_tag_Y_").is_err());
+        assert!(parse_event_key("evt_abc_").is_err());
+    }
+
+    #[test]
+    fn test_reputation_change_events() {
+        // Unchanged reputation and voting power: no event
+        assert!(reputation_change_events(Some(&reputation(5.0, false)), &reputation(5.0, false)).is_empty());
+
+        // Changed reputation
+        let events = reputation_change_events(Some(&reputation(5.0, false)), &reputation(7.5, false));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, EventType::ReputationChanged);
+        assert_eq!(events[0].previous_value, Some(5.0));
+        assert_eq!(events[0].value, Some(7.5));
+
+        // Crossing the threshold in both directions
+        let gained = reputation_change_events(Some(&reputation(5.0, false)), &reputation(15.0, true));
+        assert_eq!(gained.iter().map(|e| e.event_type).collect::<Vec<_>>(),
+            vec![EventType::ReputationChanged, EventType::VotingPowerGained]);
+        let lost = reputation_change_events(Some(&reputation(15.0, true)), &reputation(5.0, false));
+        assert_eq!(lost[1].event_type, EventType::VotingPowerLost);
+
+        // New documents are compared against zero reputation without voting power
+        assert!(reputation_change_events(None, &reputation(0.0, false)).is_empty());
+        assert_eq!(reputation_change_events(None, &reputation(3.0, false)).len(), 1);
+    }
+
+    #[test]
+    fn test_select_events_after_cursor() {
+        let events: Vec<EventData> = (1..=5).map(event).collect();
+
+        let (selected, has_more) = select_events_after(events.clone(), 2, 2);
+        assert_eq!(selected.iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![3, 4]);
+        assert!(has_more);
+
+        let (selected, has_more) = select_events_after(events.clone(), 3, 10);
+        assert_eq!(selected.len(), 2);
+        assert!(!has_more);
+
+        // A cursor past the newest event returns nothing
+        let (selected, has_more) = select_events_after(events, 9, 10);
+        assert!(selected.is_empty());
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn test_event_description() {
+        assert_eq!(format_event_description("T", EventType::TagPhaseChanged), "tag_T_type_TagPhaseChanged_");
+    }
+}
@@ -0,0 +1,380 @@
+/*!
+ * Audit and repair of the handles stored in keys
+ *
+ * User and tag handles are written in keys in their canonical form (see `CanonicalHandle`
+ * in utils/normalize.rs). Documents written before the canonical form was introduced can
+ * have a key with another form, e.g. `hdl_ｊｏｈｎ_` instead of `hdl_john_`: such keys don't
+ * parse as `DocKey`, so they are missed by the uniqueness checks and by handle lookups.
+ *
+ * `find_handle_key_mismatches` compares the key of every document carrying a handle with
+ * the key its data gives:
+ * ```text
+ * users (legacy keys):     _prn_{principal}_usr_{ulid}_hdl_{user_handle}_
+ * users_handles:           hdl_{user_handle of the user}_usr_{ulid}_
+ * users_handles_history:   hdl_{handle}_usr_{ulid}_
+ * tags:                    usr_{ulid}_tag_{ulid}_hdl_{tag_handle}_
+ * ```
+ * and `repair_handle_keys` fixes them in batches:
+ * - legacy users are moved to the new key format, like `migrate_users_handles` does
+ * - index entries are rebuilt from the user document by `sync_user_handle`
+ * - history entries and tags are written again under the expected key, then the old
+ *   document is deleted. Tags keep their owner, and skip the name checks meant for new
+ *   names while they are moved (see `is_rekeying_tag`)
+ */
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use candid::{CandidType, Principal};
+use junobuild_satellite::{delete_doc_store, get_controllers, get_doc_store, set_doc_store, DelDoc, Doc, SetDoc};
+use junobuild_shared::controllers::is_controller;
+use junobuild_utils::{decode_doc_data, encode_doc_data};
+use serde::{Deserialize, Serialize};
+
+use crate::core::handle_renames::HANDLE_HISTORY_COLLECTION;
+use crate::core::users_handles::{handle_description, migrate_legacy_user, sync_user_handle, USERS_HANDLES_COLLECTION};
+use crate::logger;
+use crate::processors::document_keys::DocKey;
+use crate::processors::document_queries::DocQuery;
+use crate::utils::errors::KarmieError;
+use crate::utils::normalize::CanonicalHandle;
+use crate::utils::structs::{HandleHistoryData, TagData, UserData};
+
+/// Maximum number of documents repaired by a `repair_handle_keys` call
+pub const MAX_HANDLE_KEYS_REPAIR_BATCH: usize = 50;
+
+thread_local! {
+    /// New key of the tag being moved, whose write skips the checks meant for new names
+    static REKEYING_TAG: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// A document whose key doesn't match the canonical form of its handle
+#[derive(Serialize, Deserialize, Clone, Debug, CandidType)]
+pub struct HandleKeyMismatch {
+    /// Collection of the document
+    pub collection: String,
+    /// The stored key
+    pub key: String,
+    /// The key the document should have, None if its data gives no valid key
+    pub expected_key: Option<String>,
+    /// What is wrong with the key
+    pub reason: String,
+}
+
+/// Outcome of a `repair_handle_keys` batch
+#[derive(Serialize, Deserialize, Clone, Debug, Default, CandidType)]
+pub struct HandleKeysReport {
+    /// Documents moved to their expected key
+    pub repaired: u32,
+    /// Documents that couldn't be repaired, with the reason
+    pub failed: Vec<String>,
+    /// Mismatches left, including the failed ones
+    pub remaining: u32,
+}
+
+/// Whether a tags key is the new key of the tag being moved by `repair_handle_keys`
+///
+/// # Arguments
+/// * `key` - Key of the tags document being written
+///
+/// # Returns
+/// * `bool` - True while the tag is moved to this key
+pub fn is_rekeying_tag(key: &str) -> bool {
+    REKEYING_TAG.with(|rekeying| rekeying.borrow().as_deref() == Some(key))
+}
+
+/// Fails with `Unauthorized` unless the caller is a controller
+fn assert_controller(caller: Principal) -> Result<(), KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        return Err(KarmieError::Unauthorized {
+            reason: "Only controllers can audit handle keys".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Compares a stored key with the key given by the document data
+fn compare_key(collection: &str, key: &str, expected: Result<DocKey, KarmieError>) -> Option<HandleKeyMismatch> {
+    match expected {
+        Ok(expected) if expected.to_string() == key => None,
+        Ok(expected) => Some(HandleKeyMismatch {
+            collection: collection.to_string(),
+            key: key.to_string(),
+            expected_key: Some(expected.to_string()),
+            reason: "Handle is not in canonical form".to_string(),
+        }),
+        Err(e) => Some(HandleKeyMismatch {
+            collection: collection.to_string(),
+            key: key.to_string(),
+            expected_key: None,
+            reason: e.message(),
+        }),
+    }
+}
+
+/// Decodes the data of a document of a collection
+fn decode<T: for<'de> Deserialize<'de>>(collection: &str, key: &str, doc: &Doc) -> Result<T, KarmieError> {
+    decode_doc_data(&doc.data).map_err(|e| KarmieError::invalid_data(collection, format!("document {}: {}", key, e)))
+}
+
+/// Finds the documents whose key doesn't match the canonical form of their handle
+///
+/// # Returns
+/// * `Result<Vec<HandleKeyMismatch>, KarmieError>` - The mismatches, by collection then key
+pub fn find_handle_key_mismatches() -> Result<Vec<HandleKeyMismatch>, KarmieError> {
+    let mut mismatches = Vec::new();
+
+    // Users: remember each user's handle for the index, and check the legacy keys
+    let mut user_handles: HashMap<String, String> = HashMap::new();
+    for item in DocQuery::new("users", "").iter() {
+        let (key, doc) = item?;
+        let user: UserData = match decode("users", &key, &doc) {
+            Ok(user) => user,
+            Err(e) => {
+                mismatches.extend(compare_key("users", &key, Err(e)));
+                continue;
+            }
+        };
+        let expected = match DocKey::parse_noncanonical(&key) {
+            Ok(DocKey::User { user_ulid, .. }) => {
+                user_handles.insert(user_ulid, user.user_handle);
+                continue;
+            }
+            Ok(DocKey::LegacyUser { principal, user_ulid, .. }) => {
+                let expected = DocKey::legacy_user(&principal, &user_ulid, &user.user_handle);
+                user_handles.insert(user_ulid, user.user_handle);
+                expected
+            }
+            Ok(other) => Err(other.kind_mismatch("user")),
+            Err(e) => Err(e),
+        };
+        mismatches.extend(compare_key("users", &key, expected));
+    }
+
+    // Index entries must match the handle of the user document
+    for item in DocQuery::new(USERS_HANDLES_COLLECTION, "").iter() {
+        let (key, _) = item?;
+        let expected = match DocKey::parse_noncanonical(&key) {
+            Ok(DocKey::UserHandle { user_ulid, .. }) => match user_handles.get(&user_ulid) {
+                Some(handle) => DocKey::user_handle(handle, &user_ulid),
+                None => Err(KarmieError::not_found("User", &user_ulid)),
+            },
+            Ok(other) => Err(other.kind_mismatch("user_handle")),
+            Err(e) => Err(e),
+        };
+        mismatches.extend(compare_key(USERS_HANDLES_COLLECTION, &key, expected));
+    }
+
+    // History entries must match their data, which must be canonical too
+    for item in DocQuery::new(HANDLE_HISTORY_COLLECTION, "").iter() {
+        let (key, doc) = item?;
+        let entry: HandleHistoryData = match decode(HANDLE_HISTORY_COLLECTION, &key, &doc) {
+            Ok(entry) => entry,
+            Err(e) => {
+                mismatches.extend(compare_key(HANDLE_HISTORY_COLLECTION, &key, Err(e)));
+                continue;
+            }
+        };
+        let canonical_data = CanonicalHandle::is_canonical(&entry.handle)
+            && entry.renamed_to.as_deref().is_none_or(CanonicalHandle::is_canonical);
+        let expected = DocKey::user_handle(&entry.handle, &entry.user_ulid);
+        match compare_key(HANDLE_HISTORY_COLLECTION, &key, expected) {
+            Some(mismatch) => mismatches.push(mismatch),
+            None if !canonical_data => mismatches.push(HandleKeyMismatch {
+                collection: HANDLE_HISTORY_COLLECTION.to_string(),
+                key: key.clone(),
+                expected_key: Some(key),
+                reason: "Handle in data is not in canonical form".to_string(),
+            }),
+            None => {}
+        }
+    }
+
+    // Tags must match their name
+    for item in DocQuery::new("tags", "").iter() {
+        let (key, doc) = item?;
+        let tag: TagData = match decode("tags", &key, &doc) {
+            Ok(tag) => tag,
+            Err(e) => {
+                mismatches.extend(compare_key("tags", &key, Err(e)));
+                continue;
+            }
+        };
+        let expected = match DocKey::parse_noncanonical(&key) {
+            Ok(DocKey::Tag { user_ulid, tag_ulid, .. }) => DocKey::tag(&user_ulid, &tag_ulid, &tag.tag_handle),
+            Ok(other) => Err(other.kind_mismatch("tag")),
+            Err(e) => Err(e),
+        };
+        mismatches.extend(compare_key("tags", &key, expected));
+    }
+
+    Ok(mismatches)
+}
+
+/// Lists the documents whose key doesn't match the canonical form of their handle (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal requesting the audit
+///
+/// # Returns
+/// * `Result<Vec<HandleKeyMismatch>, KarmieError>` - The mismatches, or Err if the caller is not a controller
+pub fn audit_handle_keys(caller: Principal) -> Result<Vec<HandleKeyMismatch>, KarmieError> {
+    assert_controller(caller)?;
+    find_handle_key_mismatches()
+}
+
+/// Writes a history entry again under its expected key, with canonical handles
+fn rekey_history_entry(key: &str, doc: &Doc) -> Result<(), KarmieError> {
+    let entry: HandleHistoryData = decode(HANDLE_HISTORY_COLLECTION, key, doc)?;
+    let canonical = HandleHistoryData {
+        handle: CanonicalHandle::new(&entry.handle).into_string(),
+        renamed_to: entry.renamed_to.as_deref().map(|handle| CanonicalHandle::new(handle).into_string()),
+        ..entry
+    };
+    let new_key = DocKey::user_handle(&canonical.handle, &canonical.user_ulid)?.to_string();
+
+    // The user may have released both forms, the most recent release is kept
+    let existing = if new_key == key {
+        Some(doc.clone())
+    } else {
+        get_doc_store(ic_cdk::id(), HANDLE_HISTORY_COLLECTION.to_string(), new_key.clone())
+            .map_err(|e| KarmieError::storage(HANDLE_HISTORY_COLLECTION, e))?
+    };
+    let keep_existing = match &existing {
+        Some(existing) if new_key != key => {
+            decode::<HandleHistoryData>(HANDLE_HISTORY_COLLECTION, &new_key, existing)?.released_at >= canonical.released_at
+        }
+        _ => false,
+    };
+
+    if !keep_existing {
+        let data = encode_doc_data(&canonical).map_err(|e| KarmieError::invalid_data(HANDLE_HISTORY_COLLECTION, e))?;
+        set_doc_store(
+            ic_cdk::id(),
+            HANDLE_HISTORY_COLLECTION.to_string(),
+            new_key.clone(),
+            SetDoc {
+                data,
+                description: Some(handle_description(&canonical.handle)),
+                version: existing.and_then(|doc| doc.version),
+            },
+        )
+        .map_err(|e| KarmieError::storage(HANDLE_HISTORY_COLLECTION, e))?;
+    }
+    if new_key != key {
+        delete_doc_store(ic_cdk::id(), HANDLE_HISTORY_COLLECTION.to_string(), key.to_string(), DelDoc { version: doc.version })
+            .map_err(|e| KarmieError::storage(HANDLE_HISTORY_COLLECTION, e))?;
+    }
+
+    logger!("info", "[rekey_history_entry] Moved history entry {} to {}", key, new_key);
+    Ok(())
+}
+
+/// Moves a tag to its expected key, keeping its owner
+///
+/// The new document is written first, with the owner of the tag as caller, then the old
+/// document is deleted.
+fn rekey_tag(key: &str, new_key: &str, doc: &Doc) -> Result<(), KarmieError> {
+    REKEYING_TAG.with(|rekeying| *rekeying.borrow_mut() = Some(new_key.to_string()));
+    let moved = set_doc_store(
+        doc.owner,
+        "tags".to_string(),
+        new_key.to_string(),
+        SetDoc { data: doc.data.clone(), description: doc.description.clone(), version: None },
+    );
+    REKEYING_TAG.with(|rekeying| *rekeying.borrow_mut() = None);
+    moved.map_err(|e| KarmieError::storage("tags", e))?;
+
+    if let Err(e) = delete_doc_store(doc.owner, "tags".to_string(), key.to_string(), DelDoc { version: doc.version }) {
+        // The tag now has two documents, roll the whole batch back
+        ic_cdk::trap(&format!("[rekey_tag] Failed to delete tag {}: {}", key, e));
+    }
+
+    logger!("info", "[rekey_tag] Moved tag {} to {}", key, new_key);
+    Ok(())
+}
+
+/// Repairs one mismatch
+fn repair_mismatch(mismatch: &HandleKeyMismatch) -> Result<(), KarmieError> {
+    let collection = mismatch.collection.as_str();
+    let doc = get_doc_store(ic_cdk::id(), collection.to_string(), mismatch.key.clone())
+        .map_err(|e| KarmieError::storage(collection, e))?
+        .ok_or_else(|| KarmieError::not_found("Document", &mismatch.key))?;
+
+    match (collection, DocKey::parse_noncanonical(&mismatch.key)?) {
+        ("users", DocKey::LegacyUser { .. }) => migrate_legacy_user(&mismatch.key, &doc),
+        (USERS_HANDLES_COLLECTION, DocKey::UserHandle { user_ulid, .. }) => sync_user_handle(&user_ulid),
+        (HANDLE_HISTORY_COLLECTION, DocKey::UserHandle { .. }) => rekey_history_entry(&mismatch.key, &doc),
+        ("tags", DocKey::Tag { .. }) => match &mismatch.expected_key {
+            Some(new_key) => rekey_tag(&mismatch.key, new_key, &doc),
+            None => Err(KarmieError::invalid_field("tag_handle", &mismatch.reason)),
+        },
+        (_, other) => Err(other.kind_mismatch(collection)),
+    }
+}
+
+/// Moves a batch of documents to the key matching the canonical form of their handle (controllers only)
+///
+/// Run it until `remaining` is 0. Documents that can't be repaired, e.g. because their
+/// handle is too short once canonicalized, are listed in `failed` and need a manual fix.
+///
+/// # Arguments
+/// * `caller` - The principal requesting the repair
+///
+/// # Returns
+/// * `Result<HandleKeysReport, KarmieError>` - The batch outcome, or Err if the caller is not a controller
+pub fn repair_handle_keys(caller: Principal) -> Result<HandleKeysReport, KarmieError> {
+    assert_controller(caller)?;
+
+    let mut report = HandleKeysReport::default();
+    for mismatch in find_handle_key_mismatches()?.iter().take(MAX_HANDLE_KEYS_REPAIR_BATCH) {
+        match repair_mismatch(mismatch) {
+            Ok(()) => report.repaired += 1,
+            Err(e) => {
+                logger!("error", "[repair_handle_keys] Failed to repair {} {}: {}", mismatch.collection, mismatch.key, e);
+                report.failed.push(format!("{} {}: {}", mismatch.collection, mismatch.key, e));
+            }
+        }
+    }
+    report.remaining = find_handle_key_mismatches()?.len() as u32;
+
+    logger!("info", "[repair_handle_keys] Repaired {} documents, {} failed, {} mismatches remaining",
+        report.repaired, report.failed.len(), report.remaining);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAV";
+    const TAG: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAW";
+
+    #[test]
+    fn test_compare_key() {
+        let canonical = format!("usr_{}_tag_{}_hdl_rust_", USER, TAG);
+        assert!(compare_key("tags", &canonical, DocKey::tag(USER, TAG, "Rust")).is_none());
+
+        // Written before NFKC normalization
+        let fullwidth = format!("usr_{}_tag_{}_hdl_ｒｕｓｔ_", USER, TAG);
+        let mismatch = compare_key("tags", &fullwidth, DocKey::tag(USER, TAG, "Ｒｕｓｔ")).unwrap();
+        assert_eq!(mismatch.expected_key.as_deref(), Some(canonical.as_str()));
+
+        // No valid key for the data
+        let mismatch = compare_key("tags", &fullwidth, DocKey::tag(USER, TAG, "py")).unwrap();
+        assert!(mismatch.expected_key.is_none());
+    }
+
+    #[test]
+    fn test_is_rekeying_tag() {
+        let key = format!("usr_{}_tag_{}_hdl_rust_", USER, TAG);
+        assert!(!is_rekeying_tag(&key));
+
+        REKEYING_TAG.with(|rekeying| *rekeying.borrow_mut() = Some(key.clone()));
+        assert!(is_rekeying_tag(&key));
+        assert!(!is_rekeying_tag(&format!("usr_{}_tag_{}_hdl_go_", USER, TAG)));
+
+        REKEYING_TAG.with(|rekeying| *rekeying.borrow_mut() = None);
+        assert!(!is_rekeying_tag(&key));
+    }
+}
@@ -0,0 +1,531 @@
+/*!
+ * Handle renames: history, quarantine and rate limit
+ *
+ * With the users_handles index (see core/users_handles.rs), users change their handle by
+ * updating `user_handle` in their document. Without safety rails anyone could claim a
+ * well-known user's old handle a minute after the rename, so every handle a user
+ * releases (by renaming or deleting their account) is recorded in a history collection,
+ * and every rename in a per-user log:
+ * ```text
+ * users_handles_history:  hdl_{handle}_usr_{ulid}_   data: { user_ulid, handle, released_at, renamed_to }
+ *                         description: skl_{skeleton}_
+ * users_handles_renames:  usr_{ulid}_                data: { user_ulid, renamed_at }
+ * ```
+ *
+ * The history is used to:
+ * - Quarantine released handles: only the user who released a handle can claim it, or a
+ *   handle that looks like it, until `quarantine_days` have passed (`HandleQuarantined`)
+ * - Rate limit renames: a user can rename at most `max_renames` times per
+ *   `rename_window_days` (`RateLimited`). Renames are counted from the rename log, since
+ *   history entries are per handle: a user going back and forth between two handles
+ *   would only ever have two
+ * - Resolve old handles: a lookup by a handle nobody holds anymore resolves to the user
+ *   who released it last, so profile links keep working after a rename
+ *
+ * Releases are recorded when the index entry of the old handle is dropped: for renames by
+ * `claim_user_handle`, in the same call as the user write, and for deleted users by
+ * `sync_user_handle` in the users delete hooks. Until then, the old index entry
+ * still holds the handle, so there is no window in which it is free.
+ *
+ * # Notes
+ * - The history is kept after the quarantine ends, only the active holder takes precedence
+ * - Releasing the same handle twice updates the existing history entry
+ * - The rename log keeps the last `MAX_RENAMES_LIMIT` renames; users without a log (renamed
+ *   before it existed) are counted from their history
+ * - The policy lives in heap memory; `set_handle_policy` also saves it to the `settings`
+ *   collection, and it is restored after upgrades (see utils/settings.rs)
+ */
+
+use std::cell::RefCell;
+
+use candid::{CandidType, Principal};
+use junobuild_satellite::{get_controllers, get_doc_store, set_doc_store, SetDoc};
+use junobuild_shared::controllers::is_controller;
+use junobuild_utils::{decode_doc_data, encode_doc_data};
+use serde::{Deserialize, Serialize};
+
+use crate::core::users_handles::{find_user_doc, handle_description, handle_holders};
+use crate::logger;
+use crate::processors::document_keys::DocKey;
+use crate::processors::document_queries::DocQuery;
+use crate::processors::key_pattern::{KeyPattern, KeySegment};
+use crate::utils::errors::KarmieError;
+use crate::utils::normalize::CanonicalHandle;
+use crate::utils::settings::{load_setting, save_setting};
+use crate::utils::structs::{HandleHistoryData, HandleRenamesData, UserData};
+
+/// Collection recording the handles released by users
+pub const HANDLE_HISTORY_COLLECTION: &str = "users_handles_history";
+
+/// Collection logging the renames of each user
+pub const HANDLE_RENAMES_COLLECTION: &str = "users_handles_renames";
+
+/// Upper bound of `max_renames`, and number of renames kept per user
+const MAX_RENAMES_LIMIT: u32 = 100;
+
+/// Nanoseconds in one day
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Nanoseconds in one second
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Name of the saved policy in the settings collection
+const HANDLE_POLICY_SETTING: &str = "handle_policy";
+
+/// Upper bound of `quarantine_days` and `rename_window_days`
+const MAX_POLICY_DAYS: u32 = 3_650;
+
+/// Rename rules, changed by controllers with `set_handle_policy`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, CandidType)]
+pub struct HandlePolicy {
+    /// How long a released handle is reserved for the user who released it, in days
+    pub quarantine_days: u32,
+
+    /// Maximum number of renames per user within the window
+    pub max_renames: u32,
+
+    /// Length of the rename window, in days
+    pub rename_window_days: u32,
+}
+
+impl Default for HandlePolicy {
+    fn default() -> Self {
+        HandlePolicy {
+            quarantine_days: 60,
+            max_renames: 2,
+            rename_window_days: 30,
+        }
+    }
+}
+
+impl HandlePolicy {
+    fn quarantine_ns(&self) -> u64 {
+        self.quarantine_days as u64 * NANOS_PER_DAY
+    }
+
+    fn rename_window_ns(&self) -> u64 {
+        self.rename_window_days as u64 * NANOS_PER_DAY
+    }
+}
+
+/// The user a handle resolves to
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, CandidType)]
+pub struct HandleResolution {
+    /// ULID of the user
+    pub user_ulid: String,
+
+    /// Key of the user document
+    pub user_key: String,
+
+    /// The user's current handle
+    pub current_handle: String,
+
+    /// True if the handle was released by the user, who now goes by `current_handle`
+    pub redirected: bool,
+}
+
+thread_local! {
+    static HANDLE_POLICY: RefCell<HandlePolicy> = RefCell::new(HandlePolicy::default());
+}
+
+/// Gets the rename rules
+pub fn get_handle_policy() -> HandlePolicy {
+    HANDLE_POLICY.with(|policy| *policy.borrow())
+}
+
+/// Replaces the rename rules (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal changing the rules
+/// * `policy` - The new rules
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if updated, Err if the caller is not a controller or the policy is invalid
+pub fn set_handle_policy(caller: Principal, policy: HandlePolicy) -> Result<(), KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        logger!("error", "[set_handle_policy] Only controllers can change the handle policy caller={}", caller);
+        return Err(KarmieError::Unauthorized {
+            reason: "Only controllers can change the handle policy".to_string(),
+        });
+    }
+
+    if policy.quarantine_days > MAX_POLICY_DAYS {
+        return Err(KarmieError::invalid_field("quarantine_days", format!(
+            "must be at most {} (got: {})", MAX_POLICY_DAYS, policy.quarantine_days
+        )));
+    }
+    if policy.rename_window_days == 0 || policy.rename_window_days > MAX_POLICY_DAYS {
+        return Err(KarmieError::invalid_field("rename_window_days", format!(
+            "must be between 1 and {} (got: {})", MAX_POLICY_DAYS, policy.rename_window_days
+        )));
+    }
+    if policy.max_renames == 0 || policy.max_renames > MAX_RENAMES_LIMIT {
+        return Err(KarmieError::invalid_field("max_renames", format!(
+            "must be between 1 and {} (got: {})", MAX_RENAMES_LIMIT, policy.max_renames
+        )));
+    }
+
+    save_setting(HANDLE_POLICY_SETTING, &policy)?;
+    HANDLE_POLICY.with(|current| *current.borrow_mut() = policy);
+    logger!("info", "[set_handle_policy] Handle policy set to {:?}", policy);
+    Ok(())
+}
+
+/// Restores the rules saved by controllers, called after upgrades
+pub fn restore_handle_policy() {
+    match load_setting::<HandlePolicy>(HANDLE_POLICY_SETTING) {
+        Ok(Some(policy)) => {
+            logger!("info", "[restore_handle_policy] Restored handle policy {:?}", policy);
+            HANDLE_POLICY.with(|current| *current.borrow_mut() = policy);
+        }
+        Ok(None) => {}
+        Err(e) => logger!("error", "[restore_handle_policy] Keeping the default policy: {}", e),
+    }
+}
+
+/// Time left (ns) before a handle released by other users can be claimed
+///
+/// # Arguments
+/// * `releases` - History entries of the handle
+/// * `user_ulid` - ULID of the user claiming the handle, whose own releases don't count
+/// * `now` - Current time in nanoseconds
+/// * `policy` - The rename rules
+///
+/// # Returns
+/// * `Option<u64>` - None if the handle isn't quarantined for this user
+pub fn quarantine_remaining_ns(
+    releases: &[HandleHistoryData],
+    user_ulid: &str,
+    now: u64,
+    policy: &HandlePolicy,
+) -> Option<u64> {
+    releases
+        .iter()
+        .filter(|release| release.user_ulid != user_ulid)
+        .map(|release| release.released_at.saturating_add(policy.quarantine_ns()).saturating_sub(now))
+        .filter(|remaining| *remaining > 0)
+        .max()
+}
+
+/// Time left (ns) before a user can rename again
+///
+/// # Arguments
+/// * `renamed_at` - When the user renamed, in nanoseconds
+/// * `now` - Current time in nanoseconds
+/// * `policy` - The rename rules
+///
+/// # Returns
+/// * `Option<u64>` - None if the user is within their rename budget
+pub fn rename_retry_after_ns(renamed_at: &[u64], now: u64, policy: &HandlePolicy) -> Option<u64> {
+    let window_ns = policy.rename_window_ns();
+    let mut recent: Vec<u64> = renamed_at
+        .iter()
+        .copied()
+        .filter(|renamed_at| now.saturating_sub(*renamed_at) < window_ns)
+        .collect();
+
+    if recent.len() < policy.max_renames as usize {
+        return None;
+    }
+
+    // A rename is possible again once enough renames have left the window
+    recent.sort_unstable();
+    let freeing = recent[recent.len() - policy.max_renames as usize];
+    Some(window_ns - now.saturating_sub(freeing))
+}
+
+/// Decodes the history entries matching a key pattern
+fn query_history(pattern: &str) -> Result<Vec<HandleHistoryData>, KarmieError> {
+    let mut entries = Vec::new();
+    for item in DocQuery::new(HANDLE_HISTORY_COLLECTION, pattern).iter() {
+        let (key, doc) = item?;
+        let entry: HandleHistoryData = decode_doc_data(&doc.data)
+            .map_err(|e| KarmieError::invalid_data(HANDLE_HISTORY_COLLECTION, format!("entry {}: {}", key, e)))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Handles released by a user, most recent first
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+///
+/// # Returns
+/// * `Result<Vec<HandleHistoryData>, KarmieError>` - The history entries of the user
+pub fn get_handle_history(user_ulid: &str) -> Result<Vec<HandleHistoryData>, KarmieError> {
+    let mut history = query_history(&KeyPattern::new().usr(user_ulid).anchor_end().build())?;
+    history.sort_by_key(|entry| std::cmp::Reverse(entry.released_at));
+    Ok(history)
+}
+
+/// Releases of a handle by any user, most recent first
+fn handle_releases(handle: &str) -> Result<Vec<HandleHistoryData>, KarmieError> {
+    let mut releases = query_history(&KeyPattern::new().anchor_start().hdl(&CanonicalHandle::new(handle).into_string()).build())?;
+    releases.sort_by_key(|release| std::cmp::Reverse(release.released_at));
+    Ok(releases)
+}
+
+/// Releases of a handle or of a handle that looks like it, by any user
+fn confusable_releases(handle: &str) -> Result<Vec<HandleHistoryData>, KarmieError> {
+    let skeleton = KeyPattern::new().anchor_start().skl(&CanonicalHandle::new(handle).skeleton()).anchor_end().build();
+    let mut releases = Vec::new();
+    for item in DocQuery::new(HANDLE_HISTORY_COLLECTION, "").description(&skeleton).iter() {
+        let (key, doc) = item?;
+        let entry: HandleHistoryData = decode_doc_data(&doc.data)
+            .map_err(|e| KarmieError::invalid_data(HANDLE_HISTORY_COLLECTION, format!("entry {}: {}", key, e)))?;
+        releases.push(entry);
+    }
+
+    // Entries recorded before skeletons were introduced are only found by their key
+    for release in handle_releases(handle)? {
+        if !releases.iter().any(|known| known.user_ulid == release.user_ulid && known.handle == release.handle) {
+            releases.push(release);
+        }
+    }
+    Ok(releases)
+}
+
+/// Records that a user released a handle
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+/// * `handle` - The released handle, as in the index key
+/// * `renamed_to` - The user's new handle, None if the user was deleted
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok once the release is stored
+pub fn record_handle_release(user_ulid: &str, handle: &str, renamed_to: Option<&str>) -> Result<(), KarmieError> {
+    let key = DocKey::user_handle(handle, user_ulid)?.to_string();
+    let entry = HandleHistoryData {
+        user_ulid: user_ulid.to_string(),
+        handle: CanonicalHandle::new(handle).into_string(),
+        released_at: ic_cdk::api::time(),
+        renamed_to: renamed_to.map(|handle| CanonicalHandle::new(handle).into_string()),
+    };
+
+    let version = get_doc_store(ic_cdk::id(), HANDLE_HISTORY_COLLECTION.to_string(), key.clone())
+        .map_err(|e| KarmieError::storage(HANDLE_HISTORY_COLLECTION, e))?
+        .and_then(|doc| doc.version);
+    let data = encode_doc_data(&entry).map_err(|e| KarmieError::invalid_data(HANDLE_HISTORY_COLLECTION, e))?;
+    set_doc_store(
+        ic_cdk::id(),
+        HANDLE_HISTORY_COLLECTION.to_string(),
+        key.clone(),
+        SetDoc { data, description: Some(handle_description(handle)), version },
+    )
+    .map_err(|e| KarmieError::storage(HANDLE_HISTORY_COLLECTION, e))?;
+
+    logger!("info", "[record_handle_release] User {} released handle {} (renamed to {:?})", user_ulid, entry.handle, entry.renamed_to);
+    Ok(())
+}
+
+/// Key of the rename log of a user: `usr_{ulid}_`
+fn renames_key(user_ulid: &str) -> String {
+    format!("{}_{}_", KeySegment::Usr.name(), user_ulid)
+}
+
+/// When a user renamed, oldest first, and the version of their rename log
+///
+/// Users who renamed before the log existed don't have one: their renames are read from
+/// the history, which undercounts renames back to a handle but is all there is.
+fn load_renames(user_ulid: &str) -> Result<(Vec<u64>, Option<u64>), KarmieError> {
+    let doc = get_doc_store(ic_cdk::id(), HANDLE_RENAMES_COLLECTION.to_string(), renames_key(user_ulid))
+        .map_err(|e| KarmieError::storage(HANDLE_RENAMES_COLLECTION, e))?;
+    if let Some(doc) = doc {
+        let renames: HandleRenamesData = decode_doc_data(&doc.data)
+            .map_err(|e| KarmieError::invalid_data(HANDLE_RENAMES_COLLECTION, format!("user {}: {}", user_ulid, e)))?;
+        return Ok((renames.renamed_at, doc.version));
+    }
+
+    let mut renamed_at: Vec<u64> = get_handle_history(user_ulid)?
+        .into_iter()
+        .filter(|entry| entry.renamed_to.is_some())
+        .map(|entry| entry.released_at)
+        .collect();
+    renamed_at.sort_unstable();
+    Ok((renamed_at, None))
+}
+
+/// Appends a rename to a rename log, dropping the oldest ones beyond `MAX_RENAMES_LIMIT`
+fn append_rename(renamed_at: &mut Vec<u64>, now: u64) {
+    renamed_at.push(now);
+    let excess = renamed_at.len().saturating_sub(MAX_RENAMES_LIMIT as usize);
+    renamed_at.drain(..excess);
+}
+
+/// Records that a user renamed
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok once the rename is logged
+pub fn record_rename(user_ulid: &str) -> Result<(), KarmieError> {
+    let (mut renamed_at, version) = load_renames(user_ulid)?;
+    append_rename(&mut renamed_at, ic_cdk::api::time());
+
+    let data = encode_doc_data(&HandleRenamesData { user_ulid: user_ulid.to_string(), renamed_at })
+        .map_err(|e| KarmieError::invalid_data(HANDLE_RENAMES_COLLECTION, e))?;
+    set_doc_store(
+        ic_cdk::id(),
+        HANDLE_RENAMES_COLLECTION.to_string(),
+        renames_key(user_ulid),
+        SetDoc { data, description: None, version },
+    )
+    .map_err(|e| KarmieError::storage(HANDLE_RENAMES_COLLECTION, e))?;
+
+    logger!("info", "[record_rename] Logged rename of user {}", user_ulid);
+    Ok(())
+}
+
+/// Fails with `HandleQuarantined` if another user released a handle, or a handle that looks
+/// like it, too recently
+///
+/// # Arguments
+/// * `handle` - The handle being claimed
+/// * `user_ulid` - ULID of the user claiming it
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if the handle isn't quarantined for this user
+pub fn assert_handle_not_quarantined(handle: &str, user_ulid: &str) -> Result<(), KarmieError> {
+    let releases = confusable_releases(handle)?;
+    match quarantine_remaining_ns(&releases, user_ulid, ic_cdk::api::time(), &get_handle_policy()) {
+        Some(remaining_ns) => Err(KarmieError::HandleQuarantined {
+            handle: CanonicalHandle::new(handle).into_string(),
+            available_in_seconds: remaining_ns.div_ceil(NANOS_PER_SECOND),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Whether a handle was released by any user too recently to be claimed by others
+///
+/// Used by the availability check, which doesn't know who is asking: the user who
+/// released the handle sees it as taken there, but can still claim it back.
+///
+/// # Arguments
+/// * `handle` - The handle to check
+///
+/// # Returns
+/// * `Result<bool, KarmieError>` - True if the handle is quarantined
+pub fn is_handle_quarantined(handle: &str) -> Result<bool, KarmieError> {
+    let releases = confusable_releases(handle)?;
+    Ok(quarantine_remaining_ns(&releases, "", ic_cdk::api::time(), &get_handle_policy()).is_some())
+}
+
+/// Fails with `RateLimited` if a user renamed too often within the window
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user renaming
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if the user can rename
+pub fn assert_rename_allowed(user_ulid: &str) -> Result<(), KarmieError> {
+    let (renamed_at, _) = load_renames(user_ulid)?;
+    match rename_retry_after_ns(&renamed_at, ic_cdk::api::time(), &get_handle_policy()) {
+        Some(retry_after_ns) => Err(KarmieError::RateLimited {
+            collection: HANDLE_HISTORY_COLLECTION.to_string(),
+            retry_after_seconds: retry_after_ns.div_ceil(NANOS_PER_SECOND),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Resolves a handle to the user holding it or, failing that, to the user who released it last
+///
+/// # Arguments
+/// * `handle` - The handle to resolve, current or old
+///
+/// # Returns
+/// * `Result<HandleResolution, KarmieError>` - The user, or `NotFound` if the handle was never used
+///   or its last holder was deleted
+pub fn resolve_handle(handle: &str) -> Result<HandleResolution, KarmieError> {
+    let handle = CanonicalHandle::new(handle).into_string();
+
+    let (user_ulid, redirected) = match handle_holders(&handle)?.into_iter().next() {
+        Some(holder) => (holder, false),
+        None => match handle_releases(&handle)?.into_iter().next() {
+            Some(release) => (release.user_ulid, true),
+            None => return Err(KarmieError::not_found("Handle", &handle)),
+        },
+    };
+
+    let (user_key, doc) = find_user_doc(&user_ulid)?
+        .ok_or_else(|| KarmieError::not_found("Handle", &handle))?;
+    let user: UserData = decode_doc_data(&doc.data)
+        .map_err(|e| KarmieError::invalid_data("users", format!("user {}: {}", user_key, e)))?;
+
+    Ok(HandleResolution {
+        user_ulid,
+        user_key,
+        current_handle: user.user_handle,
+        redirected,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_750_000_000_000_000_000;
+
+    fn release(user_ulid: &str, days_ago: u64, renamed: bool) -> HandleHistoryData {
+        HandleHistoryData {
+            user_ulid: user_ulid.to_string(),
+            handle: "alice".to_string(),
+            released_at: NOW - days_ago * NANOS_PER_DAY,
+            renamed_to: renamed.then(|| "alice2".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_quarantine_applies_to_other_users_only() {
+        let policy = HandlePolicy::default();
+        let releases = vec![release("A", 10, true)];
+
+        assert_eq!(quarantine_remaining_ns(&releases, "A", NOW, &policy), None);
+        assert_eq!(quarantine_remaining_ns(&releases, "B", NOW, &policy), Some(50 * NANOS_PER_DAY));
+
+        // Over once the quarantine has passed
+        let old = vec![release("A", 60, true)];
+        assert_eq!(quarantine_remaining_ns(&old, "B", NOW, &policy), None);
+    }
+
+    fn days_ago(days: u64) -> u64 {
+        NOW - days * NANOS_PER_DAY
+    }
+
+    #[test]
+    fn test_rename_rate_limit() {
+        let policy = HandlePolicy { max_renames: 2, rename_window_days: 30, ..HandlePolicy::default() };
+
+        assert_eq!(rename_retry_after_ns(&[days_ago(20)], NOW, &policy), None);
+
+        // Two renames in the window: the older one frees a slot after 30 - 20 days
+        let renamed_at = [days_ago(40), days_ago(20), days_ago(5)];
+        assert_eq!(rename_retry_after_ns(&renamed_at, NOW, &policy), Some(10 * NANOS_PER_DAY));
+    }
+
+    #[test]
+    fn test_renames_back_and_forth_are_all_counted() {
+        let policy = HandlePolicy { max_renames: 3, rename_window_days: 30, ..HandlePolicy::default() };
+
+        // alice -> bob -> alice -> bob: two history entries, but three renames
+        let mut renamed_at = Vec::new();
+        for days in [3, 2, 1] {
+            append_rename(&mut renamed_at, days_ago(days));
+        }
+        assert_eq!(rename_retry_after_ns(&renamed_at, NOW, &policy), Some(27 * NANOS_PER_DAY));
+    }
+
+    #[test]
+    fn test_rename_log_is_capped() {
+        let mut renamed_at = Vec::new();
+        for i in 0..(MAX_RENAMES_LIMIT as u64 + 5) {
+            append_rename(&mut renamed_at, NOW + i);
+        }
+        assert_eq!(renamed_at.len(), MAX_RENAMES_LIMIT as usize);
+        assert_eq!(renamed_at.first(), Some(&(NOW + 5)));
+    }
+}
@@ -0,0 +1,585 @@
+/*!
+ * Instruction-budgeted recalculation jobs
+ *
+ * A vote or a delegation change triggers a list of recalculation steps (vote weights and
+ * reputations of the affected users). On large tags those steps can exceed the
+ * per-message instruction limit, which would trap the hook and lose the write entirely.
+ *
+ * Steps are therefore run through `run_steps_within_budget`:
+ * - Before each step, the instructions already used by the message plus the estimated
+ *   cost of a step are compared with `JOB_INSTRUCTION_BUDGET`. The estimate is the most
+ *   expensive step seen so far, and at least `DEFAULT_STEP_INSTRUCTIONS`
+ * - When the budget runs low, the remaining steps are checkpointed into the `jobs`
+ *   collection and a timer is scheduled to continue them
+ * - The timer runs `run_pending_jobs`, which resumes jobs oldest first under the same
+ *   budget and reschedules itself while work remains
+ *
+ * A step is the unit of checkpointing: a single `calculate_user_reputation` (including
+ * its `get_active_users_count`) is never split across messages. The budget is half of
+ * `MESSAGE_INSTRUCTION_LIMIT`, so a step that starts within the budget can cost up to
+ * `MESSAGE_INSTRUCTION_LIMIT - JOB_INSTRUCTION_BUDGET` more than estimated and still
+ * complete. A single step costing more than that traps its message.
+ *
+ * Documents in the `jobs` collection:
+ * ```text
+ * key:         job_{created_at}_{sequence}_     (zero-padded, so key order is creation order)
+ * description: origin_{origin}_status_{status}_
+ * ```
+ *
+ * # Failures
+ * A step that fails in the runner increments the job's `attempts`. After
+ * `MAX_JOB_ATTEMPTS` the job is marked `Failed` and kept for controllers, who can list
+ * jobs with `get_jobs` and requeue failed ones with `run_jobs`.
+ *
+ * # State
+ * Whether a runner timer is pending and the largest step cost are kept in heap memory.
+ * Timers don't survive upgrades, so the first hook after an upgrade checks for pending
+ * jobs and reschedules the runner. Step costs are measured again after an upgrade, until
+ * then the estimate is `DEFAULT_STEP_INSTRUCTIONS`.
+ */
+
+use std::cell::RefCell;
+use std::fmt;
+use std::time::Duration;
+
+use candid::{CandidType, Principal};
+use junobuild_satellite::{get_controllers, list_docs_store, set_doc_store, delete_doc_store, DelDoc, Doc, SetDoc};
+use junobuild_shared::controllers::is_controller;
+use junobuild_shared::types::list::{ListMatcher, ListOrder, ListOrderField, ListPaginate, ListParams};
+use junobuild_utils::{decode_doc_data, encode_doc_data};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{calculate_and_store_vote_weight, calculate_user_reputation, refresh_stale_snapshots};
+use crate::logger;
+use crate::utils::errors::KarmieError;
+
+/// Collection holding the checkpointed jobs
+pub const JOBS_COLLECTION: &str = "jobs";
+
+/// Instructions an update call may use before it traps
+pub const MESSAGE_INSTRUCTION_LIMIT: u64 = 40_000_000_000;
+
+/// Instructions a message may use before remaining steps are checkpointed
+///
+/// Half of `MESSAGE_INSTRUCTION_LIMIT`, so that a step costing more than any step seen
+/// so far still has room to complete.
+pub const JOB_INSTRUCTION_BUDGET: u64 = MESSAGE_INSTRUCTION_LIMIT / 2;
+
+/// Smallest cost assumed for a step, used until larger steps are measured (e.g. after an upgrade)
+pub const DEFAULT_STEP_INSTRUCTIONS: u64 = 10_000_000_000;
+
+/// Number of failed runs after which a job is marked `Failed`
+pub const MAX_JOB_ATTEMPTS: u32 = 3;
+
+/// Maximum number of jobs loaded by a runner pass or returned by `get_jobs`
+pub const MAX_JOBS_PAGE: usize = 50;
+
+/// After this delay a scheduled runner that never cleared its flag is assumed to have trapped (1 minute)
+pub const JOB_RUNNER_STALE_NS: u64 = 60 * 1_000_000_000;
+
+/// A unit of recalculation work
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, CandidType)]
+pub enum JobStep {
+    /// `calculate_and_store_vote_weight` for a user in a tag
+    VoteWeight { user_ulid: String, tag_ulid: String },
+
+    /// `calculate_user_reputation` for a user in a tag
+    Reputation { user_ulid: String, tag_ulid: String },
+}
+
+impl fmt::Display for JobStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobStep::VoteWeight { user_ulid, tag_ulid } => {
+                write!(f, "vote weight of user={} in tag={}", user_ulid, tag_ulid)
+            }
+            JobStep::Reputation { user_ulid, tag_ulid } => {
+                write!(f, "reputation of user={} in tag={}", user_ulid, tag_ulid)
+            }
+        }
+    }
+}
+
+/// Whether a job is still being worked on
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, CandidType)]
+pub enum JobStatus {
+    /// Waiting for the runner
+    Pending,
+
+    /// Gave up after `MAX_JOB_ATTEMPTS` failed runs
+    Failed,
+}
+
+/// A checkpointed job stored in the `jobs` collection
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, CandidType)]
+pub struct JobData {
+    /// Key of the job document
+    pub job_id: String,
+
+    /// What created the job (e.g. "process_vote")
+    pub origin: String,
+
+    /// Every step of the job, in order
+    pub steps: Vec<JobStep>,
+
+    /// Index of the next step to run
+    pub next_step: u32,
+
+    /// Pending or Failed
+    pub status: JobStatus,
+
+    /// Number of runs that ended with a failed step
+    pub attempts: u32,
+
+    /// Error of the last failed step
+    pub last_error: Option<String>,
+
+    /// When the job was checkpointed (nanoseconds since epoch)
+    pub created_at: u64,
+
+    /// When the job last progressed or failed (nanoseconds since epoch)
+    pub updated_at: u64,
+}
+
+impl JobData {
+    /// Steps that haven't run yet
+    pub fn remaining_steps(&self) -> &[JobStep] {
+        self.steps.get(self.next_step as usize..).unwrap_or(&[])
+    }
+}
+
+/// Heap state of the job runner
+#[derive(Default)]
+struct JobRunner {
+    /// When the runner timer was scheduled (None if no runner is pending)
+    scheduled_at: Option<u64>,
+
+    /// Most instructions a single step used since the last upgrade
+    largest_step: u64,
+
+    /// Appended to job keys, so that jobs created in the same message get distinct keys
+    sequence: u32,
+
+    /// Whether pending jobs were looked for since the last upgrade
+    resumed_after_upgrade: bool,
+}
+
+thread_local! {
+    static JOB_RUNNER: RefCell<JobRunner> = RefCell::new(JobRunner::default());
+}
+
+/// Formats the key of a job
+pub fn format_job_key(created_at: u64, sequence: u32) -> String {
+    format!("job_{:020}_{:06}_", created_at, sequence)
+}
+
+/// Formats the description of a job, used to filter jobs by status
+pub fn format_job_description(origin: &str, status: JobStatus) -> String {
+    format!("origin_{}_status_{:?}_", origin, status)
+}
+
+/// Whether the next step could push the message past the budget
+///
+/// # Arguments
+/// * `used` - Instructions already used by the message
+/// * `largest_step` - Most instructions a single step is known to use
+/// * `budget` - Instructions the message may use
+pub fn should_checkpoint(used: u64, largest_step: u64, budget: u64) -> bool {
+    used.saturating_add(step_estimate(largest_step)) > budget
+}
+
+/// Instructions a step is assumed to cost, given the largest step measured so far
+fn step_estimate(largest_step: u64) -> u64 {
+    largest_step.max(DEFAULT_STEP_INSTRUCTIONS)
+}
+
+/// Instructions used by the current message
+///
+/// Unlike `instruction_counter` in utils/metrics.rs, this only counts the current
+/// message, which is what the per-message limit applies to.
+fn message_instructions() -> u64 {
+    ic_cdk::api::performance_counter(0)
+}
+
+/// Whether the remaining budget of the current message is too low for another step
+fn budget_exhausted() -> bool {
+    let largest_step = JOB_RUNNER.with(|runner| runner.borrow().largest_step);
+    should_checkpoint(message_instructions(), largest_step, JOB_INSTRUCTION_BUDGET)
+}
+
+/// Runs one step and records its cost
+async fn run_step(step: &JobStep) -> Result<(), KarmieError> {
+    let start = message_instructions();
+
+    logger!("info", "[run_step] Calculating {}", step);
+    match step {
+        JobStep::VoteWeight { user_ulid, tag_ulid } => {
+            calculate_and_store_vote_weight(user_ulid, tag_ulid).await?;
+        }
+        JobStep::Reputation { user_ulid, tag_ulid } => {
+            calculate_user_reputation(user_ulid, tag_ulid).await?;
+        }
+    }
+
+    let used = message_instructions().saturating_sub(start);
+    JOB_RUNNER.with(|runner| {
+        let mut runner = runner.borrow_mut();
+        runner.largest_step = runner.largest_step.max(used);
+    });
+    Ok(())
+}
+
+/// Writes a job document
+///
+/// # Returns
+/// * `Result<u64, KarmieError>` - The new version of the document
+fn store_job(job: &JobData, version: Option<u64>) -> Result<u64, KarmieError> {
+    let stored = set_doc_store(
+        ic_cdk::id(),
+        JOBS_COLLECTION.to_string(),
+        job.job_id.clone(),
+        SetDoc {
+            data: encode_doc_data(job).map_err(|e| KarmieError::invalid_data(JOBS_COLLECTION, e))?,
+            description: Some(format_job_description(&job.origin, job.status)),
+            version,
+        },
+    )
+    .map_err(|e| KarmieError::storage(JOBS_COLLECTION, e))?;
+
+    Ok(stored.data.after.version.unwrap_or_default())
+}
+
+/// Checkpoints steps into a new job and schedules the runner
+fn enqueue_job(origin: &str, steps: Vec<JobStep>) -> Result<(), KarmieError> {
+    let now = ic_cdk::api::time();
+    let sequence = JOB_RUNNER.with(|runner| {
+        let mut runner = runner.borrow_mut();
+        runner.sequence += 1;
+        runner.sequence
+    });
+
+    let job = JobData {
+        job_id: format_job_key(now, sequence),
+        origin: origin.to_string(),
+        steps,
+        next_step: 0,
+        status: JobStatus::Pending,
+        attempts: 0,
+        last_error: None,
+        created_at: now,
+        updated_at: now,
+    };
+    store_job(&job, None)?;
+
+    logger!("info", "[enqueue_job] Checkpointed {} steps of {} into job {}", job.steps.len(), origin, job.job_id);
+
+    schedule_job_runner();
+    Ok(())
+}
+
+/// Runs steps until they are done or the message's budget runs low
+///
+/// Steps that don't fit are checkpointed into a job and continued by the runner.
+///
+/// # Arguments
+/// * `origin` - What triggered the steps, recorded on the job
+/// * `steps` - The steps, in order
+///
+/// # Returns
+/// * `Result<usize, KarmieError>` - Number of steps deferred to a job (0 if all ran)
+pub async fn run_steps_within_budget(origin: &str, steps: Vec<JobStep>) -> Result<usize, KarmieError> {
+    resume_jobs_after_upgrade();
+
+    for (index, step) in steps.iter().enumerate() {
+        if budget_exhausted() {
+            let remaining = steps[index..].to_vec();
+            let deferred = remaining.len();
+            enqueue_job(origin, remaining)?;
+            return Ok(deferred);
+        }
+
+        run_step(step).await?;
+    }
+
+    Ok(0)
+}
+
+/// Lists jobs in key order
+fn list_jobs(status: Option<JobStatus>, limit: usize) -> Result<Vec<(String, Doc)>, KarmieError> {
+    let results = list_docs_store(
+        ic_cdk::id(),
+        JOBS_COLLECTION.to_string(),
+        &ListParams {
+            matcher: Some(ListMatcher {
+                key: Some("^job_".to_string()),
+                description: status.map(|status| format!("_status_{:?}_$", status)),
+                ..Default::default()
+            }),
+            paginate: Some(ListPaginate {
+                start_after: None,
+                limit: Some(limit),
+            }),
+            order: Some(ListOrder {
+                desc: false,
+                field: ListOrderField::Keys,
+            }),
+            owner: None,
+        },
+    )
+    .map_err(|e| KarmieError::storage(JOBS_COLLECTION, e))?;
+
+    Ok(results.items)
+}
+
+/// Schedules the runner on a timer, unless one is already pending
+pub fn schedule_job_runner() {
+    let now = ic_cdk::api::time();
+    let already_scheduled = JOB_RUNNER.with(|runner| {
+        let mut runner = runner.borrow_mut();
+        match runner.scheduled_at {
+            Some(scheduled_at) if now.saturating_sub(scheduled_at) < JOB_RUNNER_STALE_NS => true,
+            _ => {
+                runner.scheduled_at = Some(now);
+                false
+            }
+        }
+    });
+
+    if !already_scheduled {
+        ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(run_pending_jobs()));
+    }
+}
+
+/// Schedules the runner once after an upgrade if jobs were left pending
+fn resume_jobs_after_upgrade() {
+    let resumed = JOB_RUNNER.with(|runner| std::mem::replace(&mut runner.borrow_mut().resumed_after_upgrade, true));
+    if resumed {
+        return;
+    }
+
+    match list_jobs(Some(JobStatus::Pending), 1) {
+        Ok(jobs) if !jobs.is_empty() => {
+            logger!("info", "[resume_jobs_after_upgrade] Resuming pending jobs");
+            schedule_job_runner();
+        }
+        Ok(_) => {}
+        Err(e) => logger!("error", "[resume_jobs_after_upgrade] Failed to look for pending jobs: {}", e),
+    }
+}
+
+/// Continues one job until it is done, fails, or the budget runs low
+///
+/// # Returns
+/// * `Result<bool, KarmieError>` - Whether the job has finished (completed or failed)
+async fn resume_job(mut job: JobData, mut version: u64) -> Result<bool, KarmieError> {
+    while let Some(step) = job.remaining_steps().first().cloned() {
+        if budget_exhausted() {
+            return Ok(false);
+        }
+
+        job.updated_at = ic_cdk::api::time();
+
+        match run_step(&step).await {
+            Ok(()) => {
+                job.next_step += 1;
+            }
+            Err(e) => {
+                job.attempts += 1;
+                job.last_error = Some(e.to_string());
+                if job.attempts >= MAX_JOB_ATTEMPTS {
+                    job.status = JobStatus::Failed;
+                }
+                logger!("error", "[resume_job] Job {} failed on {} (attempt {}/{}): {}",
+                    job.job_id, step, job.attempts, MAX_JOB_ATTEMPTS, e);
+                store_job(&job, Some(version))?;
+                return Ok(job.status == JobStatus::Failed);
+            }
+        }
+
+        if !job.remaining_steps().is_empty() {
+            version = store_job(&job, Some(version))?;
+        }
+    }
+
+    delete_doc_store(ic_cdk::id(), JOBS_COLLECTION.to_string(), job.job_id.clone(), DelDoc { version: Some(version) })
+        .map_err(|e| KarmieError::storage(JOBS_COLLECTION, e))?;
+
+    logger!("info", "[resume_job] Job {} from {} completed {} steps", job.job_id, job.origin, job.steps.len());
+    Ok(true)
+}
+
+/// Timer entry point: continues pending jobs, oldest first
+///
+/// Reschedules itself while pending jobs remain.
+pub async fn run_pending_jobs() {
+    JOB_RUNNER.with(|runner| runner.borrow_mut().scheduled_at = None);
+
+    let jobs = match list_jobs(Some(JobStatus::Pending), MAX_JOBS_PAGE) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            logger!("error", "[run_pending_jobs] Failed to list pending jobs: {}", e);
+            return;
+        }
+    };
+
+    let mut progressed = false;
+    let mut unfinished = false;
+    for (key, doc) in jobs {
+        if budget_exhausted() {
+            unfinished = true;
+            break;
+        }
+
+        let job: JobData = match decode_doc_data(&doc.data) {
+            Ok(job) => job,
+            Err(e) => {
+                logger!("error", "[run_pending_jobs] Skipping undecodable job {}: {}", key, e);
+                continue;
+            }
+        };
+
+        progressed = true;
+        match resume_job(job, doc.version.unwrap_or_default()).await {
+            Ok(true) => {}
+            Ok(false) => unfinished = true,
+            Err(e) => {
+                logger!("error", "[run_pending_jobs] Failed to checkpoint job {}: {}", key, e);
+                unfinished = true;
+            }
+        }
+    }
+
+    if progressed {
+        refresh_stale_snapshots().await;
+    }
+
+    // Jobs beyond the loaded page, or left over by this pass
+    if unfinished || matches!(list_jobs(Some(JobStatus::Pending), 1), Ok(jobs) if !jobs.is_empty()) {
+        schedule_job_runner();
+    }
+}
+
+/// Lists checkpointed jobs, oldest first (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal requesting the jobs
+///
+/// # Returns
+/// * `Result<Vec<JobData>, KarmieError>` - Up to `MAX_JOBS_PAGE` jobs, or Err if the caller is not a controller
+pub fn get_job_list(caller: Principal) -> Result<Vec<JobData>, KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        return Err(KarmieError::Unauthorized {
+            reason: "Only controllers can list jobs".to_string(),
+        });
+    }
+
+    list_jobs(None, MAX_JOBS_PAGE)?
+        .iter()
+        .map(|(key, doc)| {
+            decode_doc_data::<JobData>(&doc.data)
+                .map_err(|e| KarmieError::invalid_data(JOBS_COLLECTION, format!("job {}: {}", key, e)))
+        })
+        .collect()
+}
+
+/// Requeues failed jobs and schedules the runner (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal requesting the run
+///
+/// # Returns
+/// * `Result<u32, KarmieError>` - Number of failed jobs requeued, or Err if the caller is not a controller
+pub fn requeue_jobs(caller: Principal) -> Result<u32, KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        return Err(KarmieError::Unauthorized {
+            reason: "Only controllers can run jobs".to_string(),
+        });
+    }
+
+    let mut requeued = 0;
+    for (key, doc) in list_jobs(Some(JobStatus::Failed), MAX_JOBS_PAGE)? {
+        let mut job: JobData = decode_doc_data(&doc.data)
+            .map_err(|e| KarmieError::invalid_data(JOBS_COLLECTION, format!("job {}: {}", key, e)))?;
+        job.status = JobStatus::Pending;
+        job.attempts = 0;
+        job.updated_at = ic_cdk::api::time();
+        store_job(&job, doc.version)?;
+        requeued += 1;
+    }
+
+    logger!("info", "[requeue_jobs] Requeued {} failed jobs", requeued);
+    schedule_job_runner();
+    Ok(requeued)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(user: &str) -> JobStep {
+        JobStep::Reputation { user_ulid: user.to_string(), tag_ulid: "TAG".to_string() }
+    }
+
+    #[test]
+    fn test_should_checkpoint() {
+        let budget = JOB_INSTRUCTION_BUDGET;
+        let largest = 12_000_000_000;
+        assert!(!should_checkpoint(0, largest, budget));
+        assert!(!should_checkpoint(budget - largest, largest, budget));
+        assert!(should_checkpoint(budget - largest + 1, largest, budget));
+        assert!(should_checkpoint(budget + 1, 0, budget));
+        assert!(should_checkpoint(u64::MAX, u64::MAX, budget));
+    }
+
+    #[test]
+    fn test_should_checkpoint_without_measured_steps() {
+        // After an upgrade no step was measured yet, the default cost is assumed
+        let budget = JOB_INSTRUCTION_BUDGET;
+        assert!(!should_checkpoint(budget - DEFAULT_STEP_INSTRUCTIONS, 0, budget));
+        assert!(should_checkpoint(budget - DEFAULT_STEP_INSTRUCTIONS + 1, 0, budget));
+        assert!(should_checkpoint(budget - DEFAULT_STEP_INSTRUCTIONS + 1, 1, budget));
+    }
+
+    #[test]
+    fn test_step_larger_than_any_seen_fits_in_message() {
+        // The latest a step can start is when the estimate just fits in the budget
+        for largest in [0, 1_000_000, DEFAULT_STEP_INSTRUCTIONS, 15_000_000_000] {
+            let used = JOB_INSTRUCTION_BUDGET - step_estimate(largest);
+            assert!(!should_checkpoint(used, largest, JOB_INSTRUCTION_BUDGET));
+
+            // A step costing the whole headroom above its estimate still completes
+            let step = step_estimate(largest) + (MESSAGE_INSTRUCTION_LIMIT - JOB_INSTRUCTION_BUDGET);
+            assert!(step > largest);
+            assert!(used + step <= MESSAGE_INSTRUCTION_LIMIT);
+        }
+    }
+
+    #[test]
+    fn test_job_keys_sort_by_creation() {
+        assert_eq!(format_job_key(42, 7), "job_00000000000000000042_000007_");
+        assert!(format_job_key(9, 2) < format_job_key(10, 1));
+        assert!(format_job_key(10, 1) < format_job_key(10, 2));
+        assert_eq!(format_job_description("process_vote", JobStatus::Pending), "origin_process_vote_status_Pending_");
+    }
+
+    #[test]
+    fn test_remaining_steps() {
+        let mut job = JobData {
+            job_id: format_job_key(1, 1),
+            origin: "process_vote".to_string(),
+            steps: vec![step("A"), step("B"), step("C")],
+            next_step: 1,
+            status: JobStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            created_at: 1,
+            updated_at: 1,
+        };
+        assert_eq!(job.remaining_steps(), &[step("B"), step("C")]);
+
+        job.next_step = 3;
+        assert!(job.remaining_steps().is_empty());
+        job.next_step = 10;
+        assert!(job.remaining_steps().is_empty());
+    }
+}
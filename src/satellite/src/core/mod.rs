@@ -1,5 +1,22 @@
+pub mod certified_reputations;
+pub mod delegation;
+pub mod duplicate_votes;
+pub mod events;
+pub mod handle_keys;
+pub mod handle_renames;
+pub mod jobs;
+pub mod moderation;
+pub mod quadratic_voting;
+pub mod rate_limiter;
 pub mod reputation_calculations;
+pub mod reputation_check;
+pub mod reputation_snapshots;
+pub mod reserved_handles;
+pub mod ring_detection;
 pub mod tag_calculations;
+pub mod users_handles;
+pub mod vote_cooldown;
+pub mod vote_credits;
 
 // Re-export commonly used functions for easier access
 pub use reputation_calculations::{
@@ -9,4 +26,137 @@ pub use reputation_calculations::{
     update_reputation_on_vote,
 };
 
+pub use reputation_check::{
+    check_reputation,
+    check_reputation_batch,
+    ReputationVerdict,
+    UserIdentifier,
+};
+
+pub use rate_limiter::{
+    enforce_rate_limit,
+    get_rate_limit_budgets,
+    restore_rate_limits,
+    set_rate_limit_budget,
+    RateLimitBudget,
+};
+
+pub use certified_reputations::{
+    get_certified_reputation,
+    rebuild_reputation_tree,
+    restore_reputation_certification,
+    CertifiedReputation,
+};
+
+pub use events::{
+    emit_events,
+    get_event_page,
+    start_event_pruning,
+    vote_event,
+    EventPage,
+    EventType,
+};
+
+pub use reputation_snapshots::{
+    assert_snapshot_write_allowed,
+    publish_reputation_snapshots,
+    refresh_stale_snapshots,
+};
+
+pub use delegation::{
+    check_delegation_cycle,
+    get_incoming_delegations,
+    get_outgoing_delegation,
+    list_delegations,
+    recalculate_delegation_targets,
+    DelegationList,
+};
+
+pub use jobs::{
+    get_job_list,
+    requeue_jobs,
+    run_steps_within_budget,
+    JobData,
+    JobStep,
+};
+
+pub use ring_detection::{analyze_voting_rings, RingAnalysis};
+
 pub use tag_calculations::get_active_users_count;
+
+pub use duplicate_votes::{
+    apply_duplicate_vote_policy,
+    check_new_vote_allowed,
+    query_author_votes_on_target,
+    VoteIdentity,
+};
+
+pub use vote_cooldown::{
+    assert_vote_delete_allowed,
+    cooldown_status_from_votes,
+    get_vote_cooldown_status_for_tag,
+    VoteCooldownStatus,
+};
+
+pub use quadratic_voting::validate_vote_intensity;
+
+pub use vote_credits::{
+    base_vote_cost,
+    get_vote_credits_status_for_tag,
+    spend_vote_credits,
+    VoteCreditsStatus,
+};
+
+pub use users_handles::{
+    assert_handle_available,
+    assert_handle_unheld,
+    claim_user_handle,
+    claimed_handle,
+    confusable_holders,
+    find_user_doc,
+    handle_description,
+    handle_holders,
+    is_migrating_user,
+    migrate_users_handles,
+    sync_user_handle,
+    HandlesMigrationReport,
+    USERS_HANDLES_COLLECTION,
+};
+
+pub use handle_renames::{
+    assert_rename_allowed,
+    get_handle_history,
+    get_handle_policy,
+    is_handle_quarantined,
+    resolve_handle,
+    restore_handle_policy,
+    set_handle_policy,
+    HandlePolicy,
+    HandleResolution,
+};
+
+pub use handle_keys::{
+    audit_handle_keys,
+    is_rekeying_tag,
+    repair_handle_keys,
+    HandleKeyMismatch,
+    HandleKeysReport,
+};
+
+pub use moderation::{
+    dismiss_flagged_content,
+    list_moderation_queue,
+    list_moderation_terms,
+    moderate_content,
+    remove_moderation_term,
+    set_moderation_term,
+    tag_moderated_fields,
+    user_moderated_fields,
+};
+
+pub use reserved_handles::{
+    assert_handle_not_reserved,
+    list_reserved_handles,
+    reserve_handle,
+    unreserve_handle,
+};
@@ -0,0 +1,604 @@
+/*!
+ * Content moderation
+ *
+ * User display names and handles, and tag names and descriptions, go through a
+ * moderation pipeline on every write that changes them. `assert_doc_user` and
+ * `validate_tag_document` call `moderate_content`, which:
+ * - rejects content matching a `Reject` term
+ * - adds documents matching a `Flag` term to the review queue, from the assert so that
+ *   batch writes (`set_many_docs`) are queued too and a failed write leaves no entry
+ *
+ * A pipeline is a list of `ModerationFilter`s. The default one holds a single filter, the
+ * controller-editable term list:
+ * ```text
+ * moderation_terms:  trm_{folded term}_        data: { term, severity, match_inside_words }
+ * moderation_queue:  {collection}_{doc key}   data: { collection, doc_key, matches, flagged_at }
+ * ```
+ *
+ * # Matching
+ * Content and terms are compared in a folded form (see `moderation_tokens`): NFKC
+ * normalization, lowercase, leetspeak undone ("h4t3" -> "hate", "$h!t" -> "shit"), and
+ * each word mapped to its confusable skeleton (see `CanonicalHandle::skeleton`), so that
+ * Cyrillic or fullwidth look-alikes match too. A "1" in a word reads as either "i" or "l"
+ * ("k1ll", "ki11"), so it is kept in the folded form and matches both. A term matches
+ * whole words, including words split by separators ("b.a.d", "b a d"); with
+ * `match_inside_words` it also matches inside longer words. Whole-word matching is the default to avoid flagging
+ * innocent words that contain a term.
+ */
+
+use candid::Principal;
+use junobuild_satellite::{delete_doc_store, get_controllers, get_doc_store, set_doc_store, DelDoc, SetDoc};
+use junobuild_shared::controllers::is_controller;
+use junobuild_utils::{decode_doc_data, encode_doc_data};
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::skeleton;
+
+use crate::logger;
+use crate::processors::document_queries::DocQuery;
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::{FlaggedTerm, ModerationQueueData, ModerationSeverity, ModerationTermData, TagData, UserData};
+
+/// Collection holding the moderation terms
+pub const MODERATION_TERMS_COLLECTION: &str = "moderation_terms";
+
+/// Collection holding the documents flagged for review
+pub const MODERATION_QUEUE_COLLECTION: &str = "moderation_queue";
+
+/// A moderation term found in a field
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModerationMatch {
+    /// The field the term was found in
+    pub field: String,
+    /// The term, as entered by the controller
+    pub term: String,
+    /// What to do with the content
+    pub severity: ModerationSeverity,
+}
+
+/// A step of the moderation pipeline
+pub trait ModerationFilter {
+    /// Name of the filter, for the logs
+    fn name(&self) -> &'static str;
+
+    /// Checks the value of a field
+    ///
+    /// # Arguments
+    /// * `field` - Name of the field, e.g. "display_name"
+    /// * `tokens` - The folded words of the value, see `moderation_tokens`
+    ///
+    /// # Returns
+    /// * `Vec<ModerationMatch>` - What the filter found, empty if the value is fine
+    fn check(&self, field: &str, tokens: &[String]) -> Vec<ModerationMatch>;
+}
+
+/// Ordered list of filters that content goes through
+#[derive(Default)]
+pub struct ModerationPipeline {
+    filters: Vec<Box<dyn ModerationFilter>>,
+}
+
+impl ModerationPipeline {
+    /// Creates a pipeline without filters
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a filter at the end of the pipeline
+    pub fn with(mut self, filter: impl ModerationFilter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// The default pipeline: the term list stored by controllers
+    ///
+    /// # Returns
+    /// * `Result<ModerationPipeline, KarmieError>` - The pipeline, or Err if the term list can't be read
+    pub fn load() -> Result<Self, KarmieError> {
+        Ok(Self::new().with(TermListFilter::new(load_terms()?)))
+    }
+
+    /// Runs every filter on every field
+    ///
+    /// # Arguments
+    /// * `fields` - Name and value of the fields to check
+    ///
+    /// # Returns
+    /// * `Vec<ModerationMatch>` - Everything the filters found, in field then filter order
+    pub fn check(&self, fields: &[(&str, &str)]) -> Vec<ModerationMatch> {
+        let mut matches = Vec::new();
+        for (field, value) in fields {
+            let tokens = moderation_tokens(value);
+            for filter in &self.filters {
+                let found = filter.check(field, &tokens);
+                if !found.is_empty() {
+                    logger!("debug", "[ModerationPipeline] Filter {} found {} terms in {}", filter.name(), found.len(), field);
+                }
+                matches.extend(found);
+            }
+        }
+        matches
+    }
+}
+
+/// Filter matching the controller-editable term list
+pub struct TermListFilter {
+    /// Each term with its folded form
+    terms: Vec<(String, ModerationTermData)>,
+}
+
+impl TermListFilter {
+    /// Creates the filter, folding each term once
+    ///
+    /// # Arguments
+    /// * `terms` - The terms to match
+    pub fn new(terms: Vec<ModerationTermData>) -> Self {
+        let terms = terms
+            .into_iter()
+            .map(|term| (fold_term(&term.term), term))
+            .filter(|(folded, _)| !folded.is_empty())
+            .collect();
+        TermListFilter { terms }
+    }
+}
+
+impl ModerationFilter for TermListFilter {
+    fn name(&self) -> &'static str {
+        "term_list"
+    }
+
+    fn check(&self, field: &str, tokens: &[String]) -> Vec<ModerationMatch> {
+        self.terms
+            .iter()
+            .filter(|(folded, term)| {
+                contains_words(tokens, folded) || (term.match_inside_words && contains_inside_words(tokens, folded))
+            })
+            .map(|(_, term)| ModerationMatch {
+                field: field.to_string(),
+                term: term.term.clone(),
+                severity: term.severity,
+            })
+            .collect()
+    }
+}
+
+/// Digit standing for either "i" or "l" in leetspeak, kept as is in folded words
+const AMBIGUOUS_DIGIT: char = '1';
+
+/// Undoes the leetspeak substitution of a digit
+///
+/// "1" is left alone, see `AMBIGUOUS_DIGIT`.
+fn unleet_digit(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '3' => 'e',
+        '4' => 'a',
+        '5' => 's',
+        '7' => 't',
+        '8' => 'b',
+        '9' => 'g',
+        other => other,
+    }
+}
+
+/// Splits content into folded words
+///
+/// Symbols used as letters ("@", "$", and "!" or "|" followed by a letter) are replaced
+/// first, the text is then split on anything that isn't alphanumeric. Digits in words that
+/// contain letters are read as leetspeak, and each word is mapped to its confusable skeleton.
+/// The skeleton would turn a "1" into an "l", so in those words it is kept as `AMBIGUOUS_DIGIT`.
+///
+/// # Arguments
+/// * `text` - The content to fold
+///
+/// # Returns
+/// * `Vec<String>` - The folded words, in order
+pub fn moderation_tokens(text: &str) -> Vec<String> {
+    let text = text.nfkc().collect::<String>().to_lowercase();
+    let chars: Vec<char> = text.chars().collect();
+
+    let mut unsymboled = String::with_capacity(text.len());
+    for (i, c) in chars.iter().enumerate() {
+        let next_is_letter = chars.get(i + 1).is_some_and(|next| next.is_alphanumeric());
+        unsymboled.push(match c {
+            '@' => 'a',
+            '$' => 's',
+            '!' if next_is_letter => 'i',
+            '|' if next_is_letter => 'l',
+            other => *other,
+        });
+    }
+
+    unsymboled
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let folded: String = if word.chars().any(char::is_alphabetic) {
+                let unleeted: String = word.chars().map(unleet_digit).collect();
+                unleeted
+                    .split(AMBIGUOUS_DIGIT)
+                    .map(|part| skeleton(part).collect::<String>())
+                    .collect::<Vec<_>>()
+                    .join(&AMBIGUOUS_DIGIT.to_string())
+            } else {
+                skeleton(word).collect()
+            };
+            folded
+                .to_lowercase()
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect()
+        })
+        .filter(|word: &String| !word.is_empty())
+        .collect()
+}
+
+/// Folded form of a term, its words joined
+fn fold_term(term: &str) -> String {
+    moderation_tokens(term).concat()
+}
+
+/// Whether two folded characters are the same, `AMBIGUOUS_DIGIT` standing for "i" or "l"
+fn folded_char_eq(a: char, b: char) -> bool {
+    let ambiguous = |c: char| c == AMBIGUOUS_DIGIT || c == 'i' || c == 'l';
+    a == b || ((a == AMBIGUOUS_DIGIT || b == AMBIGUOUS_DIGIT) && ambiguous(a) && ambiguous(b))
+}
+
+/// Whether two folded strings spell the same text (see `folded_char_eq`)
+fn folded_eq(a: &[char], b: &[char]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| folded_char_eq(*a, *b))
+}
+
+/// Whether consecutive words, joined, spell the folded term exactly
+fn contains_words(tokens: &[String], folded: &str) -> bool {
+    let folded: Vec<char> = folded.chars().collect();
+    (0..tokens.len()).any(|start| {
+        let mut joined: Vec<char> = Vec::new();
+        for token in &tokens[start..] {
+            joined.extend(token.chars());
+            if joined.len() >= folded.len() {
+                return folded_eq(&joined, &folded);
+            }
+        }
+        false
+    })
+}
+
+/// Whether the folded term appears anywhere in the words, joined
+fn contains_inside_words(tokens: &[String], folded: &str) -> bool {
+    let folded: Vec<char> = folded.chars().collect();
+    let joined: Vec<char> = tokens.concat().chars().collect();
+    !folded.is_empty() && joined.windows(folded.len()).any(|window| folded_eq(window, &folded))
+}
+
+/// The moderated fields of a user document that differ from the stored version
+///
+/// # Arguments
+/// * `proposed` - The user data being written
+/// * `current` - The stored user data, if any
+///
+/// # Returns
+/// * `Vec<(&str, &str)>` - Name and value of the fields to moderate
+pub fn user_moderated_fields<'a>(proposed: &'a UserData, current: Option<&UserData>) -> Vec<(&'static str, &'a str)> {
+    let mut fields = Vec::new();
+    if current.is_none_or(|current| current.display_name != proposed.display_name) {
+        fields.push(("display_name", proposed.display_name.as_str()));
+    }
+    if current.is_none_or(|current| current.user_handle != proposed.user_handle) {
+        fields.push(("user_handle", proposed.user_handle.as_str()));
+    }
+    fields
+}
+
+/// The moderated fields of a tag document that differ from the stored version
+///
+/// # Arguments
+/// * `proposed` - The tag data being written
+/// * `current` - The stored tag data, if any
+///
+/// # Returns
+/// * `Vec<(&str, &str)>` - Name and value of the fields to moderate
+pub fn tag_moderated_fields<'a>(proposed: &'a TagData, current: Option<&TagData>) -> Vec<(&'static str, &'a str)> {
+    let mut fields = Vec::new();
+    if current.is_none_or(|current| current.tag_handle != proposed.tag_handle) {
+        fields.push(("tag_handle", proposed.tag_handle.as_str()));
+    }
+    if current.is_none_or(|current| current.description != proposed.description) {
+        fields.push(("description", proposed.description.as_str()));
+    }
+    fields
+}
+
+/// Reads the moderation term list
+fn load_terms() -> Result<Vec<ModerationTermData>, KarmieError> {
+    let mut terms = Vec::new();
+    for item in DocQuery::new(MODERATION_TERMS_COLLECTION, "").iter() {
+        let (key, doc) = item?;
+        let term: ModerationTermData = decode_doc_data(&doc.data)
+            .map_err(|e| KarmieError::invalid_data(MODERATION_TERMS_COLLECTION, format!("entry {}: {}", key, e)))?;
+        terms.push(term);
+    }
+    Ok(terms)
+}
+
+/// Moderates the content of a document write
+///
+/// Called from the assert of the write: content with a `Reject` term fails the write, and
+/// content with a `Flag` term is added to the review queue. The queue entry is written in
+/// the same call as the document, so it is rolled back if the write fails.
+///
+/// # Arguments
+/// * `collection` - Collection of the document
+/// * `doc_key` - Key of the document
+/// * `fields` - Name and value of the fields being written, only the ones that changed
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if no field is rejected (flagged content is accepted
+///   and queued), `ContentRejected` otherwise
+pub fn moderate_content(collection: &str, doc_key: &str, fields: &[(&str, &str)]) -> Result<(), KarmieError> {
+    if fields.is_empty() {
+        return Ok(());
+    }
+
+    let matches = ModerationPipeline::load()?.check(fields);
+    if let Some(rejected) = matches.iter().find(|m| m.severity == ModerationSeverity::Reject) {
+        logger!("warn", "[moderate_content] Rejected {} matching moderation term {}", rejected.field, rejected.term);
+        return Err(KarmieError::ContentRejected { field: rejected.field.clone() });
+    }
+
+    let flagged: Vec<FlaggedTerm> = matches
+        .into_iter()
+        .filter(|m| m.severity == ModerationSeverity::Flag)
+        .map(|m| FlaggedTerm { field: m.field, term: m.term })
+        .collect();
+    if flagged.is_empty() {
+        return Ok(());
+    }
+    record_flagged_content(collection, doc_key, flagged)
+}
+
+/// Adds a document to the review queue
+///
+/// An existing entry for the document is replaced, so the queue shows what was flagged last.
+///
+/// # Arguments
+/// * `collection` - Collection of the document
+/// * `doc_key` - Key of the document
+/// * `matches` - The flagged terms
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok once the document is queued
+fn record_flagged_content(collection: &str, doc_key: &str, matches: Vec<FlaggedTerm>) -> Result<(), KarmieError> {
+
+    let key = format!("{}_{}", collection, doc_key);
+    let entry = ModerationQueueData {
+        collection: collection.to_string(),
+        doc_key: doc_key.to_string(),
+        matches,
+        flagged_at: ic_cdk::api::time(),
+    };
+    let version = get_doc_store(ic_cdk::id(), MODERATION_QUEUE_COLLECTION.to_string(), key.clone())
+        .map_err(|e| KarmieError::storage(MODERATION_QUEUE_COLLECTION, e))?
+        .and_then(|doc| doc.version);
+    let data = encode_doc_data(&entry).map_err(|e| KarmieError::invalid_data(MODERATION_QUEUE_COLLECTION, e))?;
+    set_doc_store(
+        ic_cdk::id(),
+        MODERATION_QUEUE_COLLECTION.to_string(),
+        key.clone(),
+        SetDoc { data, description: None, version },
+    )
+    .map_err(|e| KarmieError::storage(MODERATION_QUEUE_COLLECTION, e))?;
+
+    logger!("info", "[record_flagged_content] Flagged {} for review: {:?}", key, entry.matches);
+    Ok(())
+}
+
+/// Fails with `Unauthorized` unless the caller is a controller
+fn assert_controller(caller: Principal, action: &str) -> Result<(), KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        logger!("error", "[moderation] Only controllers can {} caller={}", action, caller);
+        return Err(KarmieError::Unauthorized {
+            reason: format!("Only controllers can {}", action),
+        });
+    }
+    Ok(())
+}
+
+/// Key of the moderation_terms document of a term: `trm_{folded term}_`
+fn term_key(term: &str) -> String {
+    format!("trm_{}_", fold_term(term))
+}
+
+/// Adds or replaces a moderation term (controllers only)
+///
+/// Terms with the same folded form share an entry, e.g. "Bad" replaces "b4d".
+///
+/// # Arguments
+/// * `caller` - The principal making the change
+/// * `term` - The term, its severity and how it matches
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if stored, Err if the caller is not a controller or the term is empty
+pub fn set_moderation_term(caller: Principal, term: ModerationTermData) -> Result<(), KarmieError> {
+    assert_controller(caller, "edit moderation terms")?;
+
+    if fold_term(&term.term).is_empty() {
+        return Err(KarmieError::invalid_field("term", "must contain letters or digits"));
+    }
+
+    let key = term_key(&term.term);
+    let version = get_doc_store(ic_cdk::id(), MODERATION_TERMS_COLLECTION.to_string(), key.clone())
+        .map_err(|e| KarmieError::storage(MODERATION_TERMS_COLLECTION, e))?
+        .and_then(|doc| doc.version);
+    let data = encode_doc_data(&term).map_err(|e| KarmieError::invalid_data(MODERATION_TERMS_COLLECTION, e))?;
+    set_doc_store(
+        ic_cdk::id(),
+        MODERATION_TERMS_COLLECTION.to_string(),
+        key.clone(),
+        SetDoc { data, description: None, version },
+    )
+    .map_err(|e| KarmieError::storage(MODERATION_TERMS_COLLECTION, e))?;
+
+    logger!("info", "[set_moderation_term] Stored moderation term {} ({:?})", key, term.severity);
+    Ok(())
+}
+
+/// Removes a moderation term (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal making the change
+/// * `term` - The term, or any spelling with the same folded form
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if removed, `NotFound` if there was no such term
+pub fn remove_moderation_term(caller: Principal, term: &str) -> Result<(), KarmieError> {
+    assert_controller(caller, "edit moderation terms")?;
+
+    let key = term_key(term);
+    let doc = get_doc_store(ic_cdk::id(), MODERATION_TERMS_COLLECTION.to_string(), key.clone())
+        .map_err(|e| KarmieError::storage(MODERATION_TERMS_COLLECTION, e))?
+        .ok_or_else(|| KarmieError::not_found("Moderation term", term))?;
+    delete_doc_store(ic_cdk::id(), MODERATION_TERMS_COLLECTION.to_string(), key.clone(), DelDoc { version: doc.version })
+        .map_err(|e| KarmieError::storage(MODERATION_TERMS_COLLECTION, e))?;
+
+    logger!("info", "[remove_moderation_term] Removed moderation term {}", key);
+    Ok(())
+}
+
+/// Lists the moderation terms (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal reading the list
+///
+/// # Returns
+/// * `Result<Vec<ModerationTermData>, KarmieError>` - The terms, in folded order
+pub fn list_moderation_terms(caller: Principal) -> Result<Vec<ModerationTermData>, KarmieError> {
+    assert_controller(caller, "list moderation terms")?;
+    load_terms()
+}
+
+/// Lists the documents waiting for review (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal reading the queue
+///
+/// # Returns
+/// * `Result<Vec<ModerationQueueData>, KarmieError>` - The flagged documents, oldest first
+pub fn list_moderation_queue(caller: Principal) -> Result<Vec<ModerationQueueData>, KarmieError> {
+    assert_controller(caller, "review flagged content")?;
+
+    let mut entries = Vec::new();
+    for item in DocQuery::new(MODERATION_QUEUE_COLLECTION, "").iter() {
+        let (key, doc) = item?;
+        let entry: ModerationQueueData = decode_doc_data(&doc.data)
+            .map_err(|e| KarmieError::invalid_data(MODERATION_QUEUE_COLLECTION, format!("entry {}: {}", key, e)))?;
+        entries.push(entry);
+    }
+    entries.sort_by_key(|entry| entry.flagged_at);
+    Ok(entries)
+}
+
+/// Removes a document from the review queue once reviewed (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal making the change
+/// * `collection` - Collection of the flagged document
+/// * `doc_key` - Key of the flagged document
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if removed, `NotFound` if the document wasn't queued
+pub fn dismiss_flagged_content(caller: Principal, collection: &str, doc_key: &str) -> Result<(), KarmieError> {
+    assert_controller(caller, "review flagged content")?;
+
+    let key = format!("{}_{}", collection, doc_key);
+    let doc = get_doc_store(ic_cdk::id(), MODERATION_QUEUE_COLLECTION.to_string(), key.clone())
+        .map_err(|e| KarmieError::storage(MODERATION_QUEUE_COLLECTION, e))?
+        .ok_or_else(|| KarmieError::not_found("Flagged document", &key))?;
+    delete_doc_store(ic_cdk::id(), MODERATION_QUEUE_COLLECTION.to_string(), key.clone(), DelDoc { version: doc.version })
+        .map_err(|e| KarmieError::storage(MODERATION_QUEUE_COLLECTION, e))?;
+
+    logger!("info", "[dismiss_flagged_content] Dismissed {} caller={}", key, caller);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(term: &str, severity: ModerationSeverity, match_inside_words: bool) -> ModerationTermData {
+        ModerationTermData { term: term.to_string(), severity, match_inside_words }
+    }
+
+    fn found(pipeline: &ModerationPipeline, text: &str) -> Vec<String> {
+        pipeline.check(&[("description", text)]).into_iter().map(|m| m.term).collect()
+    }
+
+    #[test]
+    fn test_moderation_tokens() {
+        assert_eq!(moderation_tokens("Hello, World!"), moderation_tokens("hello world"));
+        assert_eq!(moderation_tokens("h4t3"), moderation_tokens("hate"));
+        assert_eq!(moderation_tokens("$h!t"), moderation_tokens("shit"));
+        assert_eq!(moderation_tokens("ＢＡＤ"), moderation_tokens("bad"));
+        assert_eq!(moderation_tokens("bаd"), moderation_tokens("bad")); // Cyrillic "а"
+
+        // Numbers on their own aren't leetspeak
+        assert_ne!(moderation_tokens("2024"), moderation_tokens("zoza"));
+    }
+
+    #[test]
+    fn test_one_reads_as_i_or_l() {
+        let pipeline = ModerationPipeline::new().with(TermListFilter::new(vec![
+            term("kill", ModerationSeverity::Flag, false),
+            term("lie", ModerationSeverity::Flag, true),
+        ]));
+
+        assert_eq!(found(&pipeline, "k1ll"), vec!["kill"]);
+        assert_eq!(found(&pipeline, "ki11"), vec!["kill"]);
+        assert_eq!(found(&pipeline, "k111"), vec!["kill"]);
+        assert_eq!(found(&pipeline, "a11e1ie"), vec!["lie"]);
+
+        // Only "1" is ambiguous, "i" and "l" stay distinct
+        assert!(found(&pipeline, "klll kiii").is_empty());
+    }
+
+    #[test]
+    fn test_term_list_whole_words() {
+        let pipeline = ModerationPipeline::new().with(TermListFilter::new(vec![
+            term("bad", ModerationSeverity::Reject, false),
+            term("very mean", ModerationSeverity::Flag, false),
+        ]));
+
+        assert_eq!(found(&pipeline, "a b4d tag"), vec!["bad"]);
+        assert_eq!(found(&pipeline, "a b.a.d tag"), vec!["bad"]);
+        assert_eq!(found(&pipeline, "VERY-MEAN people"), vec!["very mean"]);
+
+        // Whole words only: "badge" and "abad" contain the term but are fine
+        assert!(found(&pipeline, "a badge for abad").is_empty());
+    }
+
+    #[test]
+    fn test_term_list_inside_words() {
+        let pipeline = ModerationPipeline::new().with(TermListFilter::new(vec![
+            term("scam", ModerationSeverity::Flag, true),
+        ]));
+
+        assert_eq!(found(&pipeline, "totallynotascam"), vec!["scam"]);
+        assert_eq!(found(&pipeline, "sc4m"), vec!["scam"]);
+        assert!(found(&pipeline, "scream").is_empty());
+    }
+
+    #[test]
+    fn test_pipeline_reports_fields_and_severities() {
+        let pipeline = ModerationPipeline::new().with(TermListFilter::new(vec![
+            term("bad", ModerationSeverity::Reject, false),
+            term("meh", ModerationSeverity::Flag, false),
+        ]));
+
+        let matches = pipeline.check(&[("display_name", "Meh"), ("user_handle", "bad-guy")]);
+        assert_eq!(matches.len(), 2);
+        assert_eq!((matches[0].field.as_str(), matches[0].severity), ("display_name", ModerationSeverity::Flag));
+        assert_eq!((matches[1].field.as_str(), matches[1].severity), ("user_handle", ModerationSeverity::Reject));
+
+        // Terms without letters or digits never match
+        assert!(TermListFilter::new(vec![term("!!!", ModerationSeverity::Reject, true)]).terms.is_empty());
+    }
+}
@@ -0,0 +1,222 @@
+/*!
+ * Quadratic voting
+ *
+ * Tags with `vote_mode: quadratic` let users express how strongly they feel about
+ * a vote through its `intensity`:
+ * - The vote counts `intensity` times in the target's basis reputation (linear)
+ * - The vote costs `intensity²` times its base credit cost (quadratic)
+ *
+ * Doubling a vote's impact therefore costs four times as much, so users with large
+ * budgets can't dominate a tag by pouring all their credits into a few targets.
+ * Quadratic mode requires voting credits (see vote_credits.rs).
+ *
+ * In standard mode, every vote has intensity 1 and `VoteData.intensity` must be 0 or 1.
+ */
+
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::{VoteCreditsConfig, VoteData, VoteMode};
+
+/// The intensity a vote counts with in a tag
+///
+/// Votes stored before intensities existed have intensity 0 and count as 1.
+pub fn effective_intensity(mode: VoteMode, vote: &VoteData) -> u32 {
+    match mode {
+        VoteMode::Standard => 1,
+        VoteMode::Quadratic => vote.intensity.max(1),
+    }
+}
+
+/// Multiplier applied to a vote's base credit cost (intensity²)
+pub fn intensity_cost_multiplier(mode: VoteMode, vote: &VoteData) -> u32 {
+    let intensity = effective_intensity(mode, vote);
+    intensity.saturating_mul(intensity)
+}
+
+/// Highest intensity affordable from a full balance for a given base cost
+///
+/// # Arguments
+/// * `config` - The tag's credits configuration
+/// * `base_cost` - Credit cost of the vote at intensity 1
+///
+/// # Returns
+/// * `u32` - The largest intensity whose cost (base_cost × intensity²) fits in max_credits
+pub fn max_intensity(config: &VoteCreditsConfig, base_cost: u32) -> u32 {
+    let max_units = config.max_credits / base_cost.max(1);
+    let mut intensity = (max_units as f64).sqrt() as u32;
+
+    // Correct any floating point rounding
+    while intensity > 0 && intensity.saturating_mul(intensity) > max_units {
+        intensity -= 1;
+    }
+    while (intensity + 1).saturating_mul(intensity + 1) <= max_units {
+        intensity += 1;
+    }
+
+    intensity
+}
+
+/// Validates a vote's intensity for the tag's voting mode
+///
+/// # Arguments
+/// * `mode` - The tag's voting mode
+/// * `credits` - The tag's credits configuration
+/// * `base_cost` - Credit cost of the vote at intensity 1
+/// * `vote` - The vote being written
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if the intensity is allowed, `InvalidField` with the allowed range otherwise
+pub fn validate_vote_intensity(
+    mode: VoteMode,
+    credits: Option<&VoteCreditsConfig>,
+    base_cost: u32,
+    vote: &VoteData,
+) -> Result<(), KarmieError> {
+    match (mode, credits) {
+        (VoteMode::Standard, _) => {
+            if vote.intensity > 1 {
+                return Err(KarmieError::invalid_field("intensity", format!(
+                    "Vote intensity is only allowed in quadratic voting tags (got: {})",
+                    vote.intensity
+                )));
+            }
+        }
+        (VoteMode::Quadratic, Some(config)) => {
+            let max = max_intensity(config, base_cost);
+            let intensity = effective_intensity(mode, vote);
+            if intensity > max {
+                return Err(KarmieError::invalid_field("intensity", format!(
+                    "Vote intensity must be between 1 and {} in this tag (got: {})",
+                    max, vote.intensity
+                )));
+            }
+        }
+        (VoteMode::Quadratic, None) => {
+            return Err(KarmieError::invalid_field("vote_mode", "Quadratic voting tag has no voting credits configured"));
+        }
+    }
+
+    Ok(())
+}
+
+/// A vote's contribution to the target's basis reputation
+///
+/// # Arguments
+/// * `value` - Vote direction (+1 or -1)
+/// * `intensity` - Effective intensity (see `effective_intensity`)
+/// * `effective_reputation` - The author's effective reputation in the tag
+/// * `vote_weight` - The author's vote weight in the tag
+/// * `time_multiplier` - The vote's time-based multiplier
+///
+/// # Returns
+/// * `f64` - value × intensity × reputation × weight × time multiplier
+pub fn basis_contribution(
+    value: f64,
+    intensity: u32,
+    effective_reputation: f64,
+    vote_weight: f64,
+    time_multiplier: f64,
+) -> f64 {
+    value * intensity as f64 * effective_reputation * vote_weight * time_multiplier
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::vote_credits::{vote_cost, vote_cost_delta};
+
+    fn credits(max_credits: u32) -> VoteCreditsConfig {
+        VoteCreditsConfig {
+            max_credits,
+            regen_minutes: 60,
+            positive_vote_cost: 1,
+            negative_vote_cost: 2,
+        }
+    }
+
+    fn vote(value: f64, intensity: u32) -> VoteData {
+        VoteData {
+            owner_ulid: "A".to_string(),
+            target_ulid: "B".to_string(),
+            tag_ulid: "T".to_string(),
+            vote_ulid: "V".to_string(),
+            value,
+            weight: 1.0,
+            intensity,
+        }
+    }
+
+    #[test]
+    fn test_cost_is_quadratic_in_intensity() {
+        let config = credits(100);
+        for intensity in 1..=5u32 {
+            assert_eq!(vote_cost(&config, VoteMode::Quadratic, &vote(1.0, intensity)), intensity * intensity);
+            assert_eq!(vote_cost(&config, VoteMode::Quadratic, &vote(-1.0, intensity)), 2 * intensity * intensity);
+        }
+
+        // Standard mode ignores intensity
+        assert_eq!(vote_cost(&config, VoteMode::Standard, &vote(1.0, 5)), 1);
+
+        // Intensity 0 (votes stored before intensities) costs like intensity 1
+        assert_eq!(vote_cost(&config, VoteMode::Quadratic, &vote(1.0, 0)), 1);
+    }
+
+    #[test]
+    fn test_raising_intensity_costs_the_difference() {
+        let config = credits(100);
+        let previous = vote(1.0, 2);
+
+        assert_eq!(vote_cost_delta(&config, VoteMode::Quadratic, &vote(1.0, 3), Some(&previous)), 5);
+        assert_eq!(vote_cost_delta(&config, VoteMode::Quadratic, &vote(1.0, 1), Some(&previous)), 0);
+    }
+
+    #[test]
+    fn test_contribution_is_linear_in_intensity() {
+        let single = basis_contribution(1.0, 1, 50.0, 0.25, 0.5);
+        assert_eq!(single, 6.25);
+
+        for intensity in 1..=5u32 {
+            let contribution = basis_contribution(1.0, intensity, 50.0, 0.25, 0.5);
+            assert_eq!(contribution, single * intensity as f64);
+            assert_eq!(basis_contribution(-1.0, intensity, 50.0, 0.25, 0.5), -contribution);
+        }
+    }
+
+    #[test]
+    fn test_spreading_credits_beats_concentrating() {
+        // With 16 credits, one vote at intensity 4 has the same impact as four at intensity 1,
+        // but the four single votes only cost 4 credits
+        let config = credits(16);
+        let concentrated = vote(1.0, 4);
+        let spread = vote(1.0, 1);
+
+        assert_eq!(vote_cost(&config, VoteMode::Quadratic, &concentrated), 16);
+        assert_eq!(4 * vote_cost(&config, VoteMode::Quadratic, &spread), 4);
+        assert_eq!(
+            effective_intensity(VoteMode::Quadratic, &concentrated),
+            4 * effective_intensity(VoteMode::Quadratic, &spread)
+        );
+    }
+
+    #[test]
+    fn test_max_intensity() {
+        assert_eq!(max_intensity(&credits(1), 1), 1);
+        assert_eq!(max_intensity(&credits(15), 1), 3);
+        assert_eq!(max_intensity(&credits(16), 1), 4);
+        assert_eq!(max_intensity(&credits(16), 2), 2);
+        assert_eq!(max_intensity(&credits(10_000), 1), 100);
+    }
+
+    #[test]
+    fn test_validate_vote_intensity() {
+        let config = credits(16);
+
+        assert!(validate_vote_intensity(VoteMode::Standard, None, 1, &vote(1.0, 0)).is_ok());
+        assert!(validate_vote_intensity(VoteMode::Standard, None, 1, &vote(1.0, 1)).is_ok());
+        assert!(validate_vote_intensity(VoteMode::Standard, None, 1, &vote(1.0, 2)).is_err());
+
+        assert!(validate_vote_intensity(VoteMode::Quadratic, Some(&config), 1, &vote(1.0, 4)).is_ok());
+        assert!(validate_vote_intensity(VoteMode::Quadratic, Some(&config), 1, &vote(1.0, 5)).is_err());
+        assert!(validate_vote_intensity(VoteMode::Quadratic, Some(&config), 2, &vote(-1.0, 3)).is_err());
+        assert!(validate_vote_intensity(VoteMode::Quadratic, None, 1, &vote(1.0, 1)).is_err());
+    }
+}
@@ -0,0 +1,370 @@
+/*!
+ * Per-principal rate limiting
+ *
+ * Sliding-window rate limiter keyed by (caller principal, collection), enforced at
+ * the very start of `assert_set_doc` so that spam is rejected before any
+ * `query_doc_by_key` call runs.
+ *
+ * # Budgets
+ * Each collection has a budget of `max_requests` writes per `window_seconds`.
+ * Collections without a budget are not limited. Controllers can change budgets
+ * at runtime with `set_rate_limit`; `DEFAULT_BUDGETS` applies until they do.
+ *
+ * # State
+ * The limiter lives in heap memory. Budgets set by controllers are also saved to the
+ * `settings` collection and restored after upgrades (see utils/settings.rs); recent
+ * writes are not, so every caller starts with a full budget after an upgrade.
+ * Timestamps older than their window are dropped on every check for the caller,
+ * and all callers are swept every `PRUNE_INTERVAL_NS` so idle principals don't
+ * accumulate.
+ *
+ * # Notes
+ * - Controllers and the satellite itself are never limited
+ * - A write rejected later in `assert_set_doc` traps the call, which also rolls
+ *   back its rate limit record, so only accepted writes use the budget
+ */
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+use candid::{CandidType, Principal};
+use junobuild_satellite::get_controllers;
+use junobuild_shared::controllers::is_controller;
+use serde::{Deserialize, Serialize};
+
+use crate::logger;
+use crate::utils::errors::KarmieError;
+use crate::utils::settings::{load_setting, save_setting};
+
+/// Name of the saved budgets in the settings collection
+const RATE_LIMITS_SETTING: &str = "rate_limits";
+
+/// Nanoseconds in one second (IC time is in nanoseconds)
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// How often all callers are swept for expired timestamps (10 minutes)
+const PRUNE_INTERVAL_NS: u64 = 10 * 60 * NANOS_PER_SECOND;
+
+/// Budgets applied until controllers change them: (collection, max_requests, window_seconds)
+const DEFAULT_BUDGETS: [(&str, u32, u32); 3] = [
+    ("users", 5, 3_600),  // 5 profile writes per hour
+    ("tags", 5, 3_600),   // 5 tag writes per hour
+    ("votes", 30, 60),    // 30 votes per minute
+];
+
+/// Write budget for a collection
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, CandidType)]
+pub struct RateLimitBudget {
+    /// Maximum number of writes allowed within the window
+    pub max_requests: u32,
+
+    /// Length of the sliding window in seconds
+    pub window_seconds: u32,
+}
+
+impl RateLimitBudget {
+    fn window_ns(&self) -> u64 {
+        self.window_seconds as u64 * NANOS_PER_SECOND
+    }
+}
+
+/// Sliding-window rate limiter state
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Budget per collection
+    budgets: HashMap<String, RateLimitBudget>,
+
+    /// Timestamps (ns) of recent writes per (caller, collection), oldest first
+    hits: HashMap<(Principal, String), VecDeque<u64>>,
+
+    /// When the last full sweep ran (ns)
+    last_prune: u64,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        let budgets = DEFAULT_BUDGETS
+            .iter()
+            .map(|(collection, max_requests, window_seconds)| {
+                (collection.to_string(), RateLimitBudget { max_requests: *max_requests, window_seconds: *window_seconds })
+            })
+            .collect();
+
+        Self {
+            budgets,
+            hits: HashMap::new(),
+            last_prune: 0,
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Checks a write against the collection's budget and records it if allowed
+    ///
+    /// # Arguments
+    /// * `caller` - The principal writing the document
+    /// * `collection` - The collection being written to
+    /// * `now` - Current time in nanoseconds
+    ///
+    /// # Returns
+    /// * `Result<(), u64>` - Ok if allowed, Err with the seconds to wait before the next write
+    pub fn check_and_record(&mut self, caller: Principal, collection: &str, now: u64) -> Result<(), u64> {
+        if now.saturating_sub(self.last_prune) >= PRUNE_INTERVAL_NS {
+            self.prune(now);
+        }
+
+        let budget = match self.budgets.get(collection) {
+            Some(budget) => *budget,
+            None => return Ok(()),
+        };
+
+        let window_ns = budget.window_ns();
+        let hits = self.hits.entry((caller, collection.to_string())).or_default();
+
+        // Drop writes that left the window
+        while let Some(oldest) = hits.front() {
+            if now.saturating_sub(*oldest) >= window_ns {
+                hits.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if hits.len() >= budget.max_requests as usize {
+            // The oldest write in the window is the next one to expire
+            let retry_after_ns = hits
+                .front()
+                .map(|oldest| window_ns - now.saturating_sub(*oldest))
+                .unwrap_or(window_ns);
+            return Err(retry_after_ns.div_ceil(NANOS_PER_SECOND));
+        }
+
+        hits.push_back(now);
+        Ok(())
+    }
+
+    /// Removes expired timestamps for all callers and drops empty entries
+    ///
+    /// # Arguments
+    /// * `now` - Current time in nanoseconds
+    pub fn prune(&mut self, now: u64) {
+        let budgets = &self.budgets;
+        self.hits.retain(|(_, collection), hits| {
+            match budgets.get(collection) {
+                Some(budget) => {
+                    let window_ns = budget.window_ns();
+                    hits.retain(|hit| now.saturating_sub(*hit) < window_ns);
+                    !hits.is_empty()
+                }
+                // Budget was removed, nothing left to track
+                None => false,
+            }
+        });
+        self.last_prune = now;
+    }
+
+    /// Sets or removes (None) the budget of a collection
+    pub fn set_budget(&mut self, collection: &str, budget: Option<RateLimitBudget>) {
+        match budget {
+            Some(budget) => {
+                self.budgets.insert(collection.to_string(), budget);
+            }
+            None => {
+                self.budgets.remove(collection);
+            }
+        }
+    }
+
+    /// Replaces all budgets
+    pub fn replace_budgets(&mut self, budgets: Vec<(String, RateLimitBudget)>) {
+        self.budgets = budgets.into_iter().collect();
+    }
+
+    /// Lists all configured budgets, sorted by collection
+    pub fn budgets(&self) -> Vec<(String, RateLimitBudget)> {
+        let mut budgets: Vec<(String, RateLimitBudget)> = self
+            .budgets
+            .iter()
+            .map(|(collection, budget)| (collection.clone(), *budget))
+            .collect();
+        budgets.sort_by(|a, b| a.0.cmp(&b.0));
+        budgets
+    }
+}
+
+thread_local! {
+    static RATE_LIMITER: RefCell<RateLimiter> = RefCell::new(RateLimiter::default());
+}
+
+/// Enforces the caller's write budget for a collection
+///
+/// Must run before any other validation in `assert_set_doc`.
+///
+/// # Arguments
+/// * `caller` - The principal writing the document
+/// * `collection` - The collection being written to
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if the write is allowed, `RateLimited` if the caller is over budget
+pub fn enforce_rate_limit(caller: Principal, collection: &str) -> Result<(), KarmieError> {
+    // Controllers and the satellite itself (e.g. reputation updates from on_set_doc) are exempt
+    if is_controller(caller, &get_controllers()) {
+        return Ok(());
+    }
+
+    let now = ic_cdk::api::time();
+    RATE_LIMITER.with(|limiter| limiter.borrow_mut().check_and_record(caller, collection, now))
+        .map_err(|retry_after_seconds| {
+            logger!("warn", "[enforce_rate_limit] Rate limit exceeded for collection '{}'. Try again in {} seconds. caller={}",
+                collection, retry_after_seconds, caller);
+            KarmieError::RateLimited {
+                collection: collection.to_string(),
+                retry_after_seconds,
+            }
+        })
+}
+
+/// Sets or removes the write budget of a collection (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal making the change
+/// * `collection` - The collection to configure
+/// * `budget` - The new budget, or None to stop limiting the collection
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if updated, Err if the caller is not a controller or the budget is invalid
+pub fn set_rate_limit_budget(
+    caller: Principal,
+    collection: &str,
+    budget: Option<RateLimitBudget>,
+) -> Result<(), KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        logger!("error", "[set_rate_limit_budget] Only controllers can configure rate limits caller={}", caller);
+        return Err(KarmieError::Unauthorized {
+            reason: "Only controllers can configure rate limits".to_string(),
+        });
+    }
+
+    if collection.trim().is_empty() {
+        return Err(KarmieError::invalid_field("collection", "cannot be empty"));
+    }
+
+    if let Some(budget) = &budget {
+        if budget.max_requests == 0 || budget.window_seconds == 0 {
+            return Err(KarmieError::invalid_field("budget", format!(
+                "max_requests and window_seconds must be greater than 0 (got: {}, {})",
+                budget.max_requests, budget.window_seconds
+            )));
+        }
+    }
+
+    // Saved first, so that a failed save leaves the running budgets unchanged
+    let mut budgets: Vec<(String, RateLimitBudget)> = get_rate_limit_budgets()
+        .into_iter()
+        .filter(|(configured, _)| configured != collection)
+        .collect();
+    if let Some(budget) = budget {
+        budgets.push((collection.to_string(), budget));
+    }
+    save_setting(RATE_LIMITS_SETTING, &budgets)?;
+
+    RATE_LIMITER.with(|limiter| limiter.borrow_mut().set_budget(collection, budget));
+    logger!("info", "[set_rate_limit_budget] Rate limit for collection={} set to {:?}", collection, budget);
+
+    Ok(())
+}
+
+/// Lists all configured write budgets
+pub fn get_rate_limit_budgets() -> Vec<(String, RateLimitBudget)> {
+    RATE_LIMITER.with(|limiter| limiter.borrow().budgets())
+}
+
+/// Restores the budgets saved by controllers, called after upgrades
+pub fn restore_rate_limits() {
+    match load_setting::<Vec<(String, RateLimitBudget)>>(RATE_LIMITS_SETTING) {
+        Ok(Some(budgets)) => {
+            logger!("info", "[restore_rate_limits] Restored {} rate limit budgets", budgets.len());
+            RATE_LIMITER.with(|limiter| limiter.borrow_mut().replace_budgets(budgets));
+        }
+        Ok(None) => {}
+        Err(e) => logger!("error", "[restore_rate_limits] Keeping the default budgets: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = 1_750_000_000_000_000_000;
+
+    fn limiter_with(max_requests: u32, window_seconds: u32) -> RateLimiter {
+        let mut limiter = RateLimiter::default();
+        limiter.set_budget("votes", Some(RateLimitBudget { max_requests, window_seconds }));
+        limiter.last_prune = START;
+        limiter
+    }
+
+    #[test]
+    fn test_allows_writes_within_budget() {
+        let mut limiter = limiter_with(3, 60);
+        let caller = Principal::anonymous();
+
+        for i in 0..3 {
+            assert!(limiter.check_and_record(caller, "votes", START + i).is_ok());
+        }
+        assert_eq!(limiter.check_and_record(caller, "votes", START + 3), Err(60));
+    }
+
+    #[test]
+    fn test_window_slides() {
+        let mut limiter = limiter_with(2, 60);
+        let caller = Principal::anonymous();
+
+        assert!(limiter.check_and_record(caller, "votes", START).is_ok());
+        assert!(limiter.check_and_record(caller, "votes", START + 30 * NANOS_PER_SECOND).is_ok());
+
+        // First write expires 60s after START, so 20s are left at START + 40s
+        assert_eq!(limiter.check_and_record(caller, "votes", START + 40 * NANOS_PER_SECOND), Err(20));
+        assert!(limiter.check_and_record(caller, "votes", START + 60 * NANOS_PER_SECOND).is_ok());
+    }
+
+    #[test]
+    fn test_budgets_are_per_caller_and_collection() {
+        let mut limiter = limiter_with(1, 60);
+        let alice = Principal::anonymous();
+        let bob = Principal::management_canister();
+
+        assert!(limiter.check_and_record(alice, "votes", START).is_ok());
+        assert!(limiter.check_and_record(alice, "votes", START).is_err());
+        assert!(limiter.check_and_record(bob, "votes", START).is_ok());
+
+        // Collections without a budget are not limited
+        limiter.set_budget("tags", None);
+        for _ in 0..100 {
+            assert!(limiter.check_and_record(alice, "tags", START).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_replace_budgets() {
+        let mut limiter = limiter_with(1, 60);
+        limiter.replace_budgets(vec![("tags".to_string(), RateLimitBudget { max_requests: 2, window_seconds: 10 })]);
+
+        // Collections missing from the saved budgets are no longer limited
+        assert_eq!(limiter.budgets(), vec![("tags".to_string(), RateLimitBudget { max_requests: 2, window_seconds: 10 })]);
+        assert!(limiter.check_and_record(Principal::anonymous(), "votes", START).is_ok());
+        assert!(limiter.check_and_record(Principal::anonymous(), "votes", START).is_ok());
+    }
+
+    #[test]
+    fn test_prune_removes_expired_entries() {
+        let mut limiter = limiter_with(5, 60);
+        let caller = Principal::anonymous();
+
+        assert!(limiter.check_and_record(caller, "votes", START).is_ok());
+        assert_eq!(limiter.hits.len(), 1);
+
+        limiter.prune(START + 61 * NANOS_PER_SECOND);
+        assert!(limiter.hits.is_empty());
+    }
+}
@@ -1,22 +1,61 @@
 use ic_cdk; // Import ic_cdk
 use junobuild_satellite::{set_doc_store, get_doc}; // Import junobuild_satellite functions
-use junobuild_satellite::SetDoc; // why is this not in the previous import?
+use junobuild_satellite::{Doc, SetDoc}; // why is this not in the previous import?
 use std::collections::HashMap; // Import std::collections::HashMap
 use junobuild_utils::{encode_doc_data, decode_doc_data}; // Import junobuild_utils functions
 use crate::logger; // Import our logger from the utils module 
 use crate::utils::time::calculate_months_between; // Import time calculations
-use crate::processors::document_keys::{create_reputation_key, format_reputation_key};
-use crate::processors::document_queries::query_doc_by_key;
+use crate::processors::document_keys::DocKey;
+use crate::processors::document_queries::{query_doc_by_key, DocQuery};
+use crate::processors::key_pattern::KeyPattern;
+use crate::utils::errors::KarmieError; // Import our typed error
+use crate::utils::metrics::record_store_error; // Count version conflicts
 
 // Import our data structures
 use crate::utils::structs::{
     Tag, VoteData, Reputation, ReputationData, VoteWeight,
-    AuthorInfo, TagData
+    AuthorInfo, TagData, VoteMode
 };
 
 // Import tag calculations
 use crate::core::get_active_users_count;
 
+// Import duplicate vote handling
+use crate::core::{apply_duplicate_vote_policy, VoteIdentity};
+
+// Import quadratic voting helpers
+use crate::core::quadratic_voting::{basis_contribution, effective_intensity};
+
+// Import delegation
+use crate::core::delegation::{get_author_info_with_delegations, get_outgoing_delegation};
+
+// Import reputation certification
+use crate::core::certified_reputations::certify_reputation_doc;
+
+// Import reputation snapshots
+use crate::core::reputation_snapshots::mark_snapshot_stale;
+
+// Import the event outbox
+use crate::core::events::{emit_reputation_events, observe_tag_phase};
+
+// Import voting ring detection
+use crate::core::ring_detection::{detect_voting_clusters, flagged_reciprocal_pairs, get_tag_votes, is_flagged_pair};
+
+/// Side effects of storing a reputation document
+///
+/// Must be called after every `set_doc_store` on the reputations collection, since
+/// documents written by the satellite don't trigger the hooks.
+///
+/// # Arguments
+/// * `key` - Key of the reputation document
+/// * `before` - The document before the write (None if it was created)
+/// * `after` - The stored document
+fn reputation_stored(key: &str, before: Option<&Doc>, after: &Doc) {
+    certify_reputation_doc(key, after);
+    mark_snapshot_stale(after);
+    emit_reputation_events(before, after);
+}
+
 /// Gets a slim version of user reputation data optimized for vote processing
 ///
 /// This retrieves only the essential reputation data needed for vote processing:
@@ -31,8 +70,8 @@ use crate::core::get_active_users_count;
 /// * `tag_key` - The tag's document key
 ///
 /// # Returns
-/// * `Result<Option<AuthorInfo>, String>` - Slim reputation data or None if not found
-pub async fn get_user_reputation_slim(user_key: &str, tag_key: &str) -> Result<Option<AuthorInfo>, String> {
+/// * `Result<Option<AuthorInfo>, KarmieError>` - Slim reputation data or None if not found
+pub async fn get_user_reputation_slim(user_key: &str, tag_key: &str) -> Result<Option<AuthorInfo>, KarmieError> {
     // Get active users count to check if we're in bootstrap phase
     let active_users = get_active_users_count(tag_key).await?;
     
@@ -41,12 +80,12 @@ pub async fn get_user_reputation_slim(user_key: &str, tag_key: &str) -> Result<O
     let in_bootstrap_phase = active_users < tag.data.min_users_for_threshold;
     
     // Create reputation key format: usr_{user_ulid}_tag_{tag_ulid}_
-    let reputation_key = match format_reputation_key(user_key, tag_key) {
-        Ok(key) => key,
+    let reputation_key = match DocKey::reputation(user_key, tag_key) {
+        Ok(key) => key.to_string(),
         Err(e) => {
             logger!("error", "[get_user_reputation_slim] Failed to format reputation key: user={}, tag={}, error={}", 
                 user_key, tag_key, e);
-            return Err(format!("Failed to format reputation key: {}", e));
+            return Err(e);
         }
     };
     
@@ -92,7 +131,7 @@ pub async fn get_user_reputation_slim(user_key: &str, tag_key: &str) -> Result<O
         Err(e) => {
             logger!("error", "[get_user_reputation_slim] Failed to decode reputation data: key={}, error={}", 
                 reputation_key, e);
-            Err(format!("Failed to decode reputation data: {}", e))
+            Err(KarmieError::invalid_data("reputations", e))
         }
     }
 }
@@ -163,7 +202,7 @@ pub async fn get_user_reputation_slim(user_key: &str, tag_key: &str) -> Result<O
 /// 
 /// For detailed explanation and examples, see: /docs/core/development/test-calculations.md
 ///
-pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> Result<f64, String> {
+pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> Result<f64, KarmieError> {
     // Overview: This function calculates a normalized vote weight for a user in a specific tag and 
     // stores it in their reputation document. The process involves:
     //
@@ -188,7 +227,7 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
     // Step 1: Get Tag Configuration for the specified tag
     // ----------------------------
     logger!("debug", "[calculate_and_store_vote_weight] Step 1: Getting tag configuration for tag={}", tag_key);
-    let _tag = get_tag_doc(tag_key).await?;
+    let tag = get_tag_doc(tag_key).await?;
     logger!("debug", "[calculate_and_store_vote_weight] Successfully retrieved tag: {}", tag_key);
 
     // Step 2: Get User's Votes
@@ -196,17 +235,23 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
     logger!("debug", "[calculate_and_store_vote_weight] Step 2: Querying votes by user={} in tag={}", user_key, tag_key);
     
     // Create the key pattern to find votes by this user in this tag
-    let vote_key_pattern = format!("usr_{}_tag_{}_", user_key, tag_key);
+    let vote_key_pattern = KeyPattern::new().anchor_start().usr(user_key).tag(tag_key).build();
     logger!("info", "[calculate_and_store_vote_weight] Using key pattern: {}", vote_key_pattern);
     
-    // Use our general-purpose query helper with the formatted key pattern
-    let user_votes_result = query_doc_by_key(
-        "votes",
-        &vote_key_pattern
-    )?;
+    // Read the user's votes; the duplicate vote policy compares votes on
+    // the same target, so they are collected before filtering
+    let user_votes = DocQuery::new("votes", &vote_key_pattern)
+        .iter()
+        .collect::<Result<Vec<_>, KarmieError>>()?;
     
-    // Use the results directly - no need for additional filtering
-    let user_votes_for_tag = user_votes_result.items;
+    // Only count the votes allowed by the tag's duplicate vote policy
+    // (e.g. with latest-wins, older votes on the same target are superseded)
+    let user_votes_for_tag = apply_duplicate_vote_policy(
+        user_votes,
+        tag.data.duplicate_vote_policy,
+        tag.data.duplicate_vote_cap,
+        |(_, doc)| VoteIdentity::from_doc(doc),
+    );
 
     // Get the count of votes
     let user_votes_count = user_votes_for_tag.len();
@@ -221,11 +266,20 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
     let mut total_weighted_votes = 0.0;
     for (_, doc) in &user_votes_for_tag {
         // Get time-based multiplier for this vote using the document's created_at timestamp
-        // We don't need to decode the document data since we only use the timestamp
         let time_multiplier = get_period_multiplier(doc.created_at, tag_key).await?;
-        
-        // Add to total: base value (1.0) * time multiplier
-        total_weighted_votes += 1.0 * time_multiplier;
+
+        // In quadratic voting tags, a vote counts `intensity` times in the target's reputation,
+        // so it also takes `intensity` shares of the author's weight to keep total influence at 100%.
+        // Other tags only need the timestamp, so the vote data isn't decoded.
+        let intensity = match tag.data.vote_mode {
+            VoteMode::Quadratic => decode_doc_data::<VoteData>(&doc.data)
+                .map(|vote| effective_intensity(VoteMode::Quadratic, &vote))
+                .unwrap_or(1),
+            VoteMode::Standard => 1,
+        };
+
+        // Add to total: intensity * time multiplier
+        total_weighted_votes += intensity as f64 * time_multiplier;
     }
     logger!("debug", "[calculate_and_store_vote_weight] Total weighted votes: {}", total_weighted_votes);
     // Add INFO level log for total weighted votes
@@ -240,7 +294,7 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
             Err(e) => {
                 logger!("error", "[calculate_and_store_vote_weight] Error creating vote weight: user={}, tag={}, error={}",
                     user_key, tag_key, e);
-                return Err(format!("Invalid vote weight calculated: {}", e));
+                return Err(KarmieError::invalid_field("vote_weight", e));
             }
         }
     } else {
@@ -253,7 +307,7 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
             Err(e) => {
                 logger!("error", "[calculate_and_store_vote_weight] Error creating initial vote weight: user={}, tag={}, error={}",
                     user_key, tag_key, e);
-                return Err(format!("Invalid vote weight calculated: {}", e));
+                return Err(KarmieError::invalid_field("vote_weight", e));
             }
         }
     };
@@ -266,12 +320,12 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
     logger!("debug", "[calculate_and_store_vote_weight] Step 5: Querying reputation document for user={}, tag={}", user_key, tag_key);
     
     // Create reputation key format: usr_{user_ulid}_tag_{tag_ulid}_
-    let reputation_key = match format_reputation_key(user_key, tag_key) {
-        Ok(key) => key,
+    let reputation_key = match DocKey::reputation(user_key, tag_key) {
+        Ok(key) => key.to_string(),
         Err(e) => {
             logger!("error", "[calculate_and_store_vote_weight] Failed to format reputation key: user={}, tag={}, error={}", 
                 user_key, tag_key, e);
-            return Err(format!("Failed to format reputation key: {}", e));
+            return Err(e);
         }
     };
     
@@ -287,7 +341,7 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
         Err(e) => {
             logger!("error", "[calculate_and_store_vote_weight] Error retrieving reputation document: key={}, error={}", 
                 reputation_key, e);
-            return Err(format!("Error retrieving reputation document: {}", e));
+            return Err(KarmieError::storage("reputations", e));
         }
     };
     
@@ -328,8 +382,8 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
         logger!("debug", "[calculate_and_store_vote_weight] No existing reputation document found for user={} for tag={}", 
             user_key, tag_key);
         
-        // Generate a new document key using our random key generator
-        let new_key = create_reputation_key(user_key, tag_key).await?;
+        // Reputation keys are derived from the user and tag
+        let new_key = DocKey::reputation(user_key, tag_key)?.to_string();
         logger!("debug", "[calculate_and_store_vote_weight] Generated key={} for new reputation document", new_key);
         
         (new_key, None, None)
@@ -407,12 +461,14 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
                     reputation.key.clone(),
                     doc,
                 ) {
-                    Ok(_) => {
+                    Ok(stored) => {
+                        reputation_stored(&reputation.key, stored.data.before.as_ref(), &stored.data.after);
                         logger!("info", "[calculate_and_store_vote_weight] SUCCESS: Updated reputation document with key={}, version={:?}, vote_weight={}",
                             reputation.key, version, vote_weight.value());
                         Ok(vote_weight.value())
                     },
                     Err(e) => {
+                        record_store_error("reputations", &e);
                         // Enhanced error logging for version conflicts
                         logger!("error", "[calculate_and_store_vote_weight] ERROR: Failed to store reputation document: key={}, attempted_version={:?}, error={}",
                             reputation.key, version, e);
@@ -426,7 +482,7 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
                                 reputation.key);
                         }
                         
-                        Err(format!("Failed to store reputation: {}", e))
+                        Err(KarmieError::storage("reputations", e))
                     }
                 }
             } else {
@@ -448,17 +504,19 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
                     reputation.key.clone(),
                     doc,
                 ) {
-                    Ok(_) => {
+                    Ok(stored) => {
+                        reputation_stored(&reputation.key, stored.data.before.as_ref(), &stored.data.after);
                         logger!("info", "[calculate_and_store_vote_weight] SUCCESS: Created reputation document with key={}, vote_weight={}",
                             reputation.key, vote_weight.value());
                         Ok(vote_weight.value())
                     },
                     Err(e) => {
+                        record_store_error("reputations", &e);
                         // Enhanced error logging for new document creation
                         logger!("error", "[calculate_and_store_vote_weight] ERROR: Failed to create reputation document: key={}, error={}",
                             reputation.key, e);
                         
-                        Err(format!("Failed to create reputation: {}", e))
+                        Err(KarmieError::storage("reputations", e))
                     }
                 }
             }
@@ -466,7 +524,7 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
         Err(e) => {
             logger!("error", "[calculate_and_store_vote_weight] Error serializing reputation data: user={}, tag={}, error={}",
                 user_key, tag_key, e);
-            Err(format!("Failed to serialize reputation data: {}", e))
+            Err(KarmieError::invalid_data("reputations", e))
         }
     }
 }
@@ -492,7 +550,10 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
 ///      - Skips row if the author's votes are not active
 ///      - Applies time-based multiplier from tag rules
 ///      - Multiplies by author's weight and reputation (from the index created in step 2)
+///      - Multiplies by the vote's intensity in quadratic voting tags (see quadratic_voting.rs)
 ///      - Adds to total (positive votes = +1, negative = -1)
+///    - Positive votes between flagged reciprocal voters are reduced by the tag's
+///      reciprocal_vote_dampening (see ring_detection.rs), if set
 ///    - Result stored as total_basis_reputation
 ///    - If no active authors are found, basis reputation is set to 0.0 but calculation continues
 /// 
@@ -528,7 +589,7 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
 /// * `tag_key` - The key of the tag to calculate reputation for
 /// 
 /// # Returns
-/// * `Result<ReputationData, String>` - The calculated reputation data or an error message
+/// * `Result<ReputationData, KarmieError>` - The calculated reputation data or the reason it failed
 /// 
 /// # Notes
 /// - Only considers votes from authors with non-zero effective reputation
@@ -537,7 +598,7 @@ pub async fn calculate_and_store_vote_weight(user_key: &str, tag_key: &str) -> R
 /// - All calculations are tag-specific and don't affect other tags
 /// - Trust status is stored to determine if user's votes are active
 /// - Voting rewards are always calculated even if user has received no votes
-pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<ReputationData, String> {
+pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<ReputationData, KarmieError> {
     // Add start log message
     logger!("info", "[calculate_user_reputation] START calculating reputation for user={}, tag={}", user_key, tag_key);
     
@@ -548,34 +609,23 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
     // ----------------------
     // Query votes where user is the target within the specific tag
     // Use key-based query: tag_{tag_key}_tar_{user_key}_
-    let vote_key_pattern = format!("tag_{}_tar_{}_", tag_key, user_key);
+    let vote_key_pattern = KeyPattern::new().tag(tag_key).tar(user_key).build();
     
     // Log the search pattern being used
     logger!("info", "[calculate_user_reputation] Searching for votes targeting user with key pattern: {}", vote_key_pattern);
     
-    // Execute the votes query using our key-based query helper
-    let vote_items_result = query_doc_by_key(
-        "votes",
-        &vote_key_pattern
-    )?;
-    
-    // Use the results directly - no need to filter
-    let vote_items = vote_items_result.items;
-
-    // Add vote count info log
-    logger!("info", "[calculate_user_reputation] VOTES TARGETING USER: Found {} votes where user={} is the target in tag={}",
-        vote_items.len(), user_key, tag_key);
-
-    // Convert raw vote documents into VoteData structs
+    // Execute the votes query and convert each raw vote document into a
+    // VoteData as it is read, so only the vote data and its timestamp are kept.
     // This step:
     // 1. Iterates through each vote document from Juno storage
     // 2. Uses decode_doc_data to convert binary data into VoteData
     // 3. Handles any binary decoding errors
-    let mut vote_data_list: Vec<VoteData> = Vec::new();
-    for (_, doc) in &vote_items {
+    let mut received_votes: Vec<(u64, VoteData)> = Vec::new();
+    for item in DocQuery::new("votes", &vote_key_pattern).iter() {
+        let (_, doc) = item?;
         match decode_doc_data::<VoteData>(&doc.data) {
             Ok(vote_data) => {
-                vote_data_list.push(vote_data);
+                received_votes.push((doc.created_at, vote_data));
             }
             Err(e) => {
                 logger!("warn", "Error decoding vote data: {}", e);
@@ -584,12 +634,24 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
         }
     }
 
+    // Only count the votes allowed by the tag's duplicate vote policy
+    let vote_items = apply_duplicate_vote_policy(
+        received_votes,
+        tag.data.duplicate_vote_policy,
+        tag.data.duplicate_vote_cap,
+        |(_, vote)| Some(VoteIdentity::from_vote(vote)),
+    );
+
+    // Add vote count info log
+    logger!("info", "[calculate_user_reputation] VOTES TARGETING USER: Found {} votes where user={} is the target in tag={}",
+        vote_items.len(), user_key, tag_key);
+
     // Add vote count info log
     logger!("info", "[calculate_user_reputation] Found {} votes targeting user={} in tag={}", vote_items.len(), user_key, tag_key);
 
     // Add detailed vote and reputation calculation log
     logger!("info", "[calculate_user_reputation] Found {} votes for user={} in tag={} (base reward per vote: {})",
-        vote_data_list.len(),
+        vote_items.len(),
         user_key,
         tag_key,
         tag.data.vote_reward
@@ -610,17 +672,23 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
     logger!("info", "[calculate_user_reputation] Checking {} unique authors who voted on user={}", vote_items.len(), user_key);
     
     // Process each vote to get author information
-    for (_, doc) in &vote_items {
-        let vote_data: VoteData = decode_doc_data(&doc.data)
-            .map_err(|e| format!("Failed to deserialize vote: {}", e))?;
+    for (_, vote_data) in &vote_items {
 
         // Skip if we already have this author's information
         if author_index.contains_key(&vote_data.owner_ulid) {
             continue;
         }
 
-        // Get author's reputation data
-        match get_user_reputation_slim(&vote_data.owner_ulid, tag_key).await {
+        // Skip authors who delegated their voting power in this tag, their power
+        // counts through their delegate's votes instead
+        if let Some(delegation) = get_outgoing_delegation(&vote_data.owner_ulid, tag_key)? {
+            logger!("info", "[calculate_user_reputation] Author={} delegated to {} in tag={}, own votes inactive",
+                vote_data.owner_ulid, delegation.delegate_ulid, tag_key);
+            continue;
+        }
+
+        // Get author's reputation data, including any power delegated to them
+        match get_author_info_with_delegations(&vote_data.owner_ulid, tag_key).await {
             Ok(Some(author_info)) => {
                 // Skip if author's votes are not active
                 if !author_info.votes_active {
@@ -670,11 +738,17 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
         // Then sum all vote contributions to get total_basis_reputation
         let mut basis_rep = 0.0;
 
-        // Iterate through all received votes
-        for (_, doc) in &vote_items {
-            let vote_data: VoteData = decode_doc_data(&doc.data)
-                .map_err(|e| format!("Failed to deserialize vote: {}", e))?;
+        // Find flagged reciprocal pairs if the tag dampens them
+        // This needs every vote in the tag, so it only runs when dampening is enabled
+        let flagged_pairs = if tag.data.reciprocal_vote_dampening > 0.0 {
+            let tag_votes = get_tag_votes(tag_key, &tag.data)?;
+            flagged_reciprocal_pairs(&detect_voting_clusters(&tag_votes))
+        } else {
+            Default::default()
+        };
 
+        // Iterate through all received votes
+        for (created_at, vote_data) in &vote_items {
             // Get author's information from our index
             let author_info = match author_index.get(&vote_data.owner_ulid) {
                 Some(info) => info,
@@ -686,20 +760,32 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
             };
 
             // Get time-based multiplier for this vote using the document's created_at timestamp
-            let time_multiplier = get_period_multiplier(doc.created_at, tag_key).await?;
+            let time_multiplier = get_period_multiplier(*created_at, tag_key).await?;
 
             // Calculate this vote's contribution:
             // 1. Base value: Use the vote's value directly
-            let base_value = vote_data.value;
-
-            // 2. Multiply by author's effective reputation
-            let with_reputation = base_value * author_info.effective_reputation;
-
-            // 3. Multiply by author's vote weight
-            let with_weight = with_reputation * author_info.vote_weight.value();
+            // 2. Multiply by the vote's intensity (always 1 outside quadratic voting tags)
+            // 3. Multiply by author's effective reputation
+            // 4. Multiply by author's vote weight
+            // 5. Apply time multiplier
+            let intensity = effective_intensity(tag.data.vote_mode, vote_data);
+            let with_time = basis_contribution(
+                vote_data.value,
+                intensity,
+                author_info.effective_reputation,
+                author_info.vote_weight.value(),
+                time_multiplier,
+            );
 
-            // 4. Finally apply time multiplier
-            let final_contribution = with_weight * time_multiplier;
+            // 6. Finally dampen positive votes from a flagged reciprocal voter
+            let final_contribution = if vote_data.value > 0.0 && is_flagged_pair(&flagged_pairs, &vote_data.owner_ulid, user_key) {
+                let dampened = with_time * (1.0 - tag.data.reciprocal_vote_dampening);
+                logger!("info", "[calculate_user_reputation] RING_DAMPENING: author={} and user={} are flagged reciprocal voters, contribution {} -> {}",
+                    vote_data.owner_ulid, user_key, with_time, dampened);
+                dampened
+            } else {
+                with_time
+            };
 
             // Add to total
             basis_rep += final_contribution;
@@ -720,6 +806,7 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
     // Get active users count for bootstrap phase check
     let active_users = get_active_users_count(tag_key).await?;
     let in_bootstrap_phase = active_users < tag.data.min_users_for_threshold;
+    observe_tag_phase(tag_key, in_bootstrap_phase);
     
     // Only set has_voting_power based on actual threshold achievement, not bootstrap phase
     // Bootstrap voting power is handled dynamically in get_user_reputation_slim()
@@ -748,20 +835,27 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
     // Sum all rewards to get total_voting_rewards_reputation
     // Query votes where this user is the author
     // Use key-based query pattern: usr_{user_key}_tag_{tag_key}_
-    let vote_key_pattern = format!("usr_{}_tag_{}_", user_key, tag_key);
+    let vote_key_pattern = KeyPattern::new().anchor_start().usr(user_key).tag(tag_key).build();
     
     // Log the query pattern
     logger!("info", "[calculate_user_reputation] Searching for votes cast by user with key pattern: {}", vote_key_pattern);
 
-    // Query the database for votes cast by the user using our helper
-    let user_votes_result = query_doc_by_key(
-        "votes",
-        &vote_key_pattern
-    )?;
+    // Query the database for votes cast by the user
+    let user_votes = DocQuery::new("votes", &vote_key_pattern)
+        .iter()
+        .collect::<Result<Vec<_>, KarmieError>>()?;
     
+    // Superseded or over-the-cap votes don't earn rewards
+    let user_votes = apply_duplicate_vote_policy(
+        user_votes,
+        tag.data.duplicate_vote_policy,
+        tag.data.duplicate_vote_cap,
+        |(_, doc)| VoteIdentity::from_doc(doc),
+    );
+
     // Log the results of the query
     logger!("info", "[calculate_user_reputation] USER VOTES: Found {} votes cast by user={} in tag={}",
-        user_votes_result.items.len(), user_key, tag_key);
+        user_votes.len(), user_key, tag_key);
 
     // Calculate rewards for each vote cast by the user
     let mut total_voting_rewards_reputation = 0.0;
@@ -773,7 +867,7 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
     let should_receive_rewards = in_bootstrap_phase || meets_threshold;
     
     if should_receive_rewards {
-        for (_, doc) in user_votes_result.items {
+        for (_, doc) in user_votes {
             // We only need the timestamp, not the vote data itself
             // Get time-based multiplier for this vote using the document's created_at timestamp
             let time_multiplier = get_period_multiplier(doc.created_at, tag_key).await?;
@@ -838,12 +932,12 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
     // Step 7.1: Query for existing document using key-based query
     // --------------------------------------------------------
     // Create the reputation key format: usr_{user_ulid}_tag_{tag_ulid}_
-    let reputation_key = match format_reputation_key(user_key, tag_key) {
-        Ok(key) => key,
+    let reputation_key = match DocKey::reputation(user_key, tag_key) {
+        Ok(key) => key.to_string(),
         Err(e) => {
             logger!("error", "[calculate_user_reputation] Failed to format reputation key: user={}, tag={}, error={}", 
                 user_key, tag_key, e);
-            return Err(format!("Failed to format reputation key: {}", e));
+            return Err(e);
         }
     };
     
@@ -883,13 +977,13 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
         // Case 3: No document found: create a new one
         logger!("info", "[calculate_user_reputation] No existing document found for user={} in tag={}, creating new",
             user_key, tag_key);
-        // Generate a new random document key
-        let new_key = match create_reputation_key(user_key, tag_key).await {
-            Ok(key) => key,
+        // Reputation keys are derived from the user and tag
+        let new_key = match DocKey::reputation(user_key, tag_key) {
+            Ok(key) => key.to_string(),
             Err(e) => {
                 logger!("error", "[calculate_user_reputation] Failed to create reputation key: user={}, tag={}, error={}", 
                     user_key, tag_key, e);
-                return Err(format!("Failed to create reputation key: {}", e));
+                return Err(e);
             }
         };
         
@@ -938,7 +1032,8 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
                     doc_key.clone(),
                     doc,
                 ) {
-                    Ok(_) => {
+                    Ok(stored) => {
+                        reputation_stored(&doc_key, stored.data.before.as_ref(), &stored.data.after);
                         logger!("info", "[calculate_user_reputation] SUCCESS: updated reputation for user={} in tag={}: basisR={}, voteR={}, totalR={}",
                             user_key, 
                             tag_key, 
@@ -949,9 +1044,10 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
                         Ok(reputation_data)
                     },
                     Err(e) => {
+                        record_store_error("reputations", &e);
                         logger!("error", "[calculate_user_reputation] Failed to update reputation document: key={}, error={}",
                             doc_key, e);
-                        Err(format!("Failed to update reputation: {}", e))
+                        Err(KarmieError::storage("reputations", e))
                     }
                 }
             } else {
@@ -980,7 +1076,8 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
                     doc_key.clone(),
                     doc,
                 ) {
-                    Ok(_) => {
+                    Ok(stored) => {
+                        reputation_stored(&doc_key, stored.data.before.as_ref(), &stored.data.after);
                         logger!("info", "[calculate_user_reputation] SUCCESS: created reputation for user={} in tag={}: basisR={}, voteR={}, totalR={}",
                             user_key, 
                             tag_key, 
@@ -991,9 +1088,10 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
                         Ok(reputation_data)
                     },
                     Err(e) => {
+                        record_store_error("reputations", &e);
                         logger!("error", "[calculate_user_reputation] Failed to create reputation document: key={}, error={}",
                             doc_key, e);
-                        Err(format!("Failed to create reputation: {}", e))
+                        Err(KarmieError::storage("reputations", e))
                     }
                 }
             }
@@ -1001,7 +1099,7 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
         Err(e) => {
             logger!("error", "[calculate_user_reputation] Failed to encode reputation data: {}",
                 e);
-            Err(format!("Failed to encode reputation data: {}", e))
+            Err(KarmieError::invalid_data("reputations", e))
         }
     }
 }
@@ -1033,7 +1131,7 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
 /// * `tag_key` - The tag key to get time period configuration from
 ///
 /// # Returns
-/// * `Result<f64, String>` - The calculated multiplier value or an error
+/// * `Result<f64, KarmieError>` - The calculated multiplier value or an error
 ///
 /// # Example
 /// If tag has periods:
@@ -1045,7 +1143,7 @@ pub async fn calculate_user_reputation(user_key: &str, tag_key: &str) -> Result<
 /// - It's not in Period 1 (exceeds 30 days)
 /// - It is in Period 2 (less than 60 days)
 /// - Returns multiplier of 1.2
-pub async fn get_period_multiplier(vote_timestamp_ns: u64, tag_key: &str) -> Result<f64, String> {
+pub async fn get_period_multiplier(vote_timestamp_ns: u64, tag_key: &str) -> Result<f64, KarmieError> {
     // Get tag settings to access configured time periods
     let tag = get_tag_doc(tag_key).await?;
     
@@ -1079,37 +1177,34 @@ pub async fn get_period_multiplier(vote_timestamp_ns: u64, tag_key: &str) -> Res
 /// * `tag_doc_ulid` - The ULID of the tag to retrieve
 /// 
 /// # Returns
-/// * `Result<Tag, String>` - The tag document or an error message
-async fn get_tag_doc(tag_doc_ulid: &str) -> Result<Tag, String> {
+/// * `Result<Tag, KarmieError>` - The tag document, or `NotFound` if the tag doesn't exist
+pub async fn get_tag_doc(tag_doc_ulid: &str) -> Result<Tag, KarmieError> {
     // Query for the tag using the tag ULID
     logger!("debug", "[get_tag_doc] Looking up tag with ULID: {}", tag_doc_ulid);
     
     // Use query_doc_by_key with tag pattern
-    let tag_key_pattern = format!("tag_{}_", tag_doc_ulid);
+    let tag_key_pattern = KeyPattern::new().tag(tag_doc_ulid).build();
     logger!("debug", "[get_tag_doc] Using key pattern: {}", tag_key_pattern);
     
     let tag_results = query_doc_by_key(
         "tags",
         &tag_key_pattern
-    )?;
-    
-    // Check if we found any matching tags
-    if tag_results.items.is_empty() {
-        let err_msg = format!("Tag not found: {}", tag_doc_ulid);
-        logger!("error", "[get_tag_doc] {}", err_msg);
-        return Err(err_msg);
-    }
+    )
+    .map_err(|e| KarmieError::storage("tags", e))?;
     
     // Get the first matching tag (there should only be one)
     let (doc_key, tag_doc) = tag_results.items.first()
-        .ok_or_else(|| format!("Tag not found: {}", tag_doc_ulid))?;
+        .ok_or_else(|| {
+            logger!("error", "[get_tag_doc] Tag not found: {}", tag_doc_ulid);
+            KarmieError::not_found("Tag", tag_doc_ulid)
+        })?;
 
     // Decode the tag data into TagData
     let tag_data: TagData = decode_doc_data(&tag_doc.data)
         .map_err(|e| {
             logger!("error", "[get_tag_doc] Failed to deserialize tag data: key={}, error={}",
                 tag_doc_ulid, e);
-            format!("Failed to deserialize tag: {}", e)
+            KarmieError::invalid_data("tags", e)
         })?;
         
     // Construct a full Tag with both metadata and data
@@ -1142,14 +1237,14 @@ async fn get_tag_doc(tag_doc_ulid: &str) -> Result<Tag, String> {
 /// * `vote_weight` - The weight of the vote (from author's voting power)
 /// 
 /// # Returns
-/// * `Result<(), String>` - Success or an error message
+/// * `Result<(), KarmieError>` - Success or an error message
 #[allow(dead_code)]
 pub async fn update_reputation_on_vote(
     target_key: &str,
     tag_key: &str,
     vote_value: f64,
     vote_weight: f64,
-) -> Result<(), String> {
+) -> Result<(), KarmieError> {
     // Add start log message
     logger!("info", "[update_reputation_on_vote] START updating reputation for target={}, tag={}, vote_value={}, vote_weight={}", target_key, tag_key, vote_value, vote_weight);
     
@@ -1157,12 +1252,12 @@ pub async fn update_reputation_on_vote(
     logger!("info", "[update_reputation_on_vote] START updating reputation for target={}, tag={}, vote_value={}, vote_weight={}", target_key, tag_key, vote_value, vote_weight);
     
     // Create reputation key format to query for the document: usr_{user_ulid}_tag_{tag_ulid}_
-    let reputation_key = match format_reputation_key(target_key, tag_key) {
-        Ok(key) => key,
+    let reputation_key = match DocKey::reputation(target_key, tag_key) {
+        Ok(key) => key.to_string(),
         Err(e) => {
             logger!("error", "[update_reputation_on_vote] Failed to format reputation key: user={}, tag={}, error={}", 
                 target_key, tag_key, e);
-            return Err(format!("Failed to format reputation key: {}", e));
+            return Err(e);
         }
     };
     
@@ -1183,9 +1278,8 @@ pub async fn update_reputation_on_vote(
         // Decode the existing reputation data
         let rep_data: ReputationData = decode_doc_data(&doc.data)
             .map_err(|e| {
-                let err_msg = format!("Failed to deserialize reputation data: {}", e);
-                logger!("error", "{}", err_msg);
-                err_msg
+                logger!("error", "[update_reputation_on_vote] Failed to deserialize reputation data: {}", e);
+                KarmieError::invalid_data("reputations", e)
             })?;
         
         // Return the key, data, and original version
@@ -1235,9 +1329,8 @@ pub async fn update_reputation_on_vote(
     // Step 5: Store updated reputation
     let doc = SetDoc {
         data: encode_doc_data(&reputation_data).map_err(|e| {
-            let err_msg = format!("Failed to serialize reputation data: {}", e);
-            logger!("error", "{}", err_msg);
-            err_msg
+            logger!("error", "[update_reputation_on_vote] Failed to serialize reputation data: {}", e);
+            KarmieError::invalid_data("reputations", e)
         })?,
         description: Some(description),
         version, // Use the original version, Juno will increment it automatically
@@ -1250,7 +1343,8 @@ pub async fn update_reputation_on_vote(
         rep_key.clone(),
         doc,
     ) {
-        Ok(_) => {
+        Ok(stored) => {
+            reputation_stored(&rep_key, stored.data.before.as_ref(), &stored.data.after);
             logger!("info", "[update_reputation_on_vote] RESULT: Successfully updated reputation for target={} in tag={}: new_total_basis={}",
                 target_key, 
                 tag_key, 
@@ -1259,9 +1353,9 @@ pub async fn update_reputation_on_vote(
             Ok(())
         },
         Err(e) => {
-            let err_msg = format!("Failed to store reputation: {}", e);
-            logger!("error", "{}", err_msg);
-            Err(err_msg)
+            record_store_error("reputations", &e);
+            logger!("error", "[update_reputation_on_vote] Failed to store reputation: {}", e);
+            Err(KarmieError::storage("reputations", e))
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,292 @@
+/*!
+ * Reputation threshold checks for integrators
+ *
+ * Third-party dApps (airdrop tools, marketplaces, ...) usually only need a yes/no
+ * answer: "does user X have at least R reputation and voting power in tag T?".
+ * This module answers it with a typed verdict, applying the same bootstrap rules as
+ * the reputation engine so integrators don't have to re-implement them:
+ * - A user has voting power if they reached the tag's `reputation_threshold`
+ * - While the tag has fewer than `min_users_for_threshold` active users (bootstrap
+ *   phase), every user has voting power
+ *
+ * Users can be identified by their user ULID or by their principal. Checks run
+ * against the stored reputation documents and never trigger a recalculation.
+ */
+
+use candid::{CandidType, Principal};
+use junobuild_satellite::get_doc;
+use junobuild_utils::decode_doc_data;
+use serde::{Deserialize, Serialize};
+
+use crate::core::reputation_calculations::get_tag_doc;
+use crate::core::tag_calculations::get_active_users_count;
+use crate::logger;
+use crate::processors::document_keys::DocKey;
+use crate::processors::document_queries::query_doc_by_key;
+use crate::processors::key_pattern::KeyPattern;
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::{ReputationData, UserData};
+
+/// Maximum number of users in a single batch check
+pub const MAX_REPUTATION_CHECK_BATCH: usize = 100;
+
+/// Identifies the user to check
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, CandidType)]
+pub enum UserIdentifier {
+    /// The user's ULID
+    Ulid(String),
+
+    /// The principal the user signed in with
+    Principal(Principal),
+}
+
+/// Outcome of a reputation check
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, CandidType)]
+pub enum ReputationCheckStatus {
+    /// The user meets every requirement
+    Passed,
+
+    /// The user's reputation is below `min_score`
+    BelowMinScore,
+
+    /// Voting power was required and the user doesn't have it
+    NoVotingPower,
+
+    /// No user is registered for the given principal
+    UserNotFound,
+}
+
+/// Verdict of a reputation check
+#[derive(Serialize, Deserialize, Clone, Debug, CandidType)]
+pub struct ReputationVerdict {
+    /// The user that was checked, as passed by the caller
+    pub user: UserIdentifier,
+
+    /// The user's ULID (None if the principal isn't registered)
+    pub user_ulid: Option<String>,
+
+    /// ULID of the tag
+    pub tag_ulid: String,
+
+    /// Whether every requirement is met (status == Passed)
+    pub passed: bool,
+
+    /// Detailed outcome
+    pub status: ReputationCheckStatus,
+
+    /// The user's effective reputation in the tag (0 if they have none)
+    pub reputation: f64,
+
+    /// Whether the user can vote right now (earned, or granted by the bootstrap phase)
+    pub has_voting_power: bool,
+
+    /// Whether the user reached the tag's reputation threshold
+    pub earned_voting_power: bool,
+
+    /// Whether the tag is in its bootstrap phase
+    pub in_bootstrap_phase: bool,
+
+    /// When the reputation was last calculated (None if the user has no reputation document)
+    pub last_calculation: Option<u64>,
+}
+
+/// Tag-wide inputs of a check, loaded once per batch
+#[derive(Debug, Clone, Copy)]
+struct TagContext {
+    in_bootstrap_phase: bool,
+}
+
+/// Decides the outcome of a check
+///
+/// # Arguments
+/// * `reputation` - The user's effective reputation
+/// * `has_voting_power` - Whether the user can vote (including bootstrap)
+/// * `min_score` - Minimum reputation required
+/// * `require_voting_power` - Whether voting power is required
+///
+/// # Returns
+/// * `ReputationCheckStatus` - Passed, or the first requirement that isn't met
+pub fn evaluate_reputation_check(
+    reputation: f64,
+    has_voting_power: bool,
+    min_score: f64,
+    require_voting_power: bool,
+) -> ReputationCheckStatus {
+    if reputation < min_score {
+        return ReputationCheckStatus::BelowMinScore;
+    }
+    if require_voting_power && !has_voting_power {
+        return ReputationCheckStatus::NoVotingPower;
+    }
+    ReputationCheckStatus::Passed
+}
+
+/// Resolves a user identifier to a user ULID
+///
+/// # Arguments
+/// * `user` - The user ULID or principal
+///
+/// # Returns
+/// * `Result<Option<String>, String>` - The ULID, or None if the principal isn't registered
+fn resolve_user_ulid(user: &UserIdentifier) -> Result<Option<String>, String> {
+    match user {
+        UserIdentifier::Ulid(ulid) => Ok(Some(ulid.clone())),
+        UserIdentifier::Principal(principal) => {
+            let results = query_doc_by_key("users", &KeyPattern::new().anchor_start().prn(&principal.to_text()).build())?;
+            match results.items.first() {
+                Some((_, doc)) => {
+                    let user_data: UserData = decode_doc_data(&doc.data)
+                        .map_err(|e| format!("Failed to decode user data: {}", e))?;
+                    Ok(user_data.user_ulid)
+                }
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+/// Checks a single user against a tag's context
+fn check_user(
+    user: &UserIdentifier,
+    tag_ulid: &str,
+    tag: TagContext,
+    min_score: f64,
+    require_voting_power: bool,
+) -> Result<ReputationVerdict, KarmieError> {
+    let user_ulid = match resolve_user_ulid(user).map_err(|e| KarmieError::storage("users", e))? {
+        Some(ulid) => ulid,
+        None => {
+            return Ok(ReputationVerdict {
+                user: user.clone(),
+                user_ulid: None,
+                tag_ulid: tag_ulid.to_string(),
+                passed: false,
+                status: ReputationCheckStatus::UserNotFound,
+                reputation: 0.0,
+                has_voting_power: false,
+                earned_voting_power: false,
+                in_bootstrap_phase: tag.in_bootstrap_phase,
+                last_calculation: None,
+            });
+        }
+    };
+
+    let reputation_key = DocKey::reputation(&user_ulid, tag_ulid)?.to_string();
+    let reputation_data = match get_doc(String::from("reputations"), reputation_key) {
+        Some(doc) => Some(decode_doc_data::<ReputationData>(&doc.data)
+            .map_err(|e| KarmieError::invalid_data("reputations", e))?),
+        None => None,
+    };
+
+    let reputation = reputation_data.as_ref().map(|data| data.reputation_total_effective).unwrap_or(0.0);
+    let earned_voting_power = reputation_data.as_ref().map(|data| data.has_voting_power).unwrap_or(false);
+    let has_voting_power = earned_voting_power || tag.in_bootstrap_phase;
+    let status = evaluate_reputation_check(reputation, has_voting_power, min_score, require_voting_power);
+
+    Ok(ReputationVerdict {
+        user: user.clone(),
+        user_ulid: Some(user_ulid),
+        tag_ulid: tag_ulid.to_string(),
+        passed: status == ReputationCheckStatus::Passed,
+        status,
+        reputation,
+        has_voting_power,
+        earned_voting_power,
+        in_bootstrap_phase: tag.in_bootstrap_phase,
+        last_calculation: reputation_data.map(|data| data.last_calculation),
+    })
+}
+
+/// Checks a batch of users against a reputation threshold in a tag
+///
+/// # Arguments
+/// * `users` - The users to check (at most `MAX_REPUTATION_CHECK_BATCH`)
+/// * `tag_ulid` - ULID of the tag
+/// * `min_score` - Minimum effective reputation required
+/// * `require_voting_power` - Whether the users must also have voting power
+///
+/// # Returns
+/// * `Result<Vec<ReputationVerdict>, KarmieError>` - One verdict per user, in order
+pub async fn check_reputation_batch(
+    users: &[UserIdentifier],
+    tag_ulid: &str,
+    min_score: f64,
+    require_voting_power: bool,
+) -> Result<Vec<ReputationVerdict>, KarmieError> {
+    if users.is_empty() {
+        return Ok(Vec::new());
+    }
+    if users.len() > MAX_REPUTATION_CHECK_BATCH {
+        return Err(KarmieError::LimitExceeded {
+            what: "users".to_string(),
+            max: MAX_REPUTATION_CHECK_BATCH as u64,
+            actual: users.len() as u64,
+        });
+    }
+    if !min_score.is_finite() {
+        return Err(KarmieError::invalid_field("min_score", format!("must be a finite number (got: {})", min_score)));
+    }
+
+    // Bootstrap status is the same for every user in the tag
+    let tag = get_tag_doc(tag_ulid).await?;
+    let active_users = get_active_users_count(tag_ulid).await?;
+    let tag_context = TagContext {
+        in_bootstrap_phase: active_users < tag.data.min_users_for_threshold,
+    };
+
+    let verdicts = users
+        .iter()
+        .map(|user| check_user(user, tag_ulid, tag_context, min_score, require_voting_power))
+        .collect::<Result<Vec<_>, KarmieError>>()?;
+
+    logger!("debug", "[check_reputation_batch] Checked {} users in tag={} (min_score={}, require_voting_power={}): {} passed",
+        verdicts.len(), tag_ulid, min_score, require_voting_power,
+        verdicts.iter().filter(|verdict| verdict.passed).count());
+
+    Ok(verdicts)
+}
+
+/// Checks a single user against a reputation threshold in a tag
+///
+/// # Arguments
+/// * `user` - The user ULID or principal
+/// * `tag_ulid` - ULID of the tag
+/// * `min_score` - Minimum effective reputation required
+/// * `require_voting_power` - Whether the user must also have voting power
+///
+/// # Returns
+/// * `Result<ReputationVerdict, KarmieError>` - The verdict or the reason the check failed
+pub async fn check_reputation(
+    user: UserIdentifier,
+    tag_ulid: &str,
+    min_score: f64,
+    require_voting_power: bool,
+) -> Result<ReputationVerdict, KarmieError> {
+    check_reputation_batch(&[user], tag_ulid, min_score, require_voting_power)
+        .await?
+        .pop()
+        .ok_or_else(|| KarmieError::from("[check_reputation] No verdict returned"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passes_when_all_requirements_met() {
+        assert_eq!(evaluate_reputation_check(50.0, true, 10.0, true), ReputationCheckStatus::Passed);
+        assert_eq!(evaluate_reputation_check(10.0, false, 10.0, false), ReputationCheckStatus::Passed);
+    }
+
+    #[test]
+    fn test_min_score_is_checked_first() {
+        assert_eq!(evaluate_reputation_check(9.9, false, 10.0, true), ReputationCheckStatus::BelowMinScore);
+        assert_eq!(evaluate_reputation_check(-1.0, true, 0.0, false), ReputationCheckStatus::BelowMinScore);
+    }
+
+    #[test]
+    fn test_voting_power_only_checked_when_required() {
+        assert_eq!(evaluate_reputation_check(50.0, false, 10.0, true), ReputationCheckStatus::NoVotingPower);
+        assert_eq!(evaluate_reputation_check(50.0, false, 10.0, false), ReputationCheckStatus::Passed);
+    }
+}
@@ -0,0 +1,440 @@
+/*!
+ * Static reputation snapshots
+ *
+ * Web2 integrators often can't (or don't want to) talk to the satellite through an
+ * agent library. For them, the satellite publishes per-tag reputation snapshots as
+ * plain JSON assets in the `snapshots` storage collection, served over HTTPS:
+ * ```text
+ * /snapshots/{tag}/leaderboard.json     -> every user of the tag, ranked by reputation
+ * /snapshots/{tag}/users/{user}.json    -> one user's reputation in the tag
+ * ```
+ * The collection also holds the root hash of the certified reputation tree, see
+ * core/certified_reputations.rs.
+ *
+ * # Refresh
+ * Every reputation write marks the (tag, user) pair as stale. Once a recalculation
+ * batch completes (a vote, a delegation change or a manual recalculation), the stale
+ * tags are re-rendered: the leaderboard is rewritten and so are the JSON files of the
+ * users whose reputation changed. User files carry no rank, since a single vote can move
+ * the rank of every user between the old and new position; ranks are only in the
+ * leaderboard. Snapshots are derived data, so failures are logged
+ * and never fail the write that triggered them; a controller can republish a tag with
+ * `publish_tag_snapshots`.
+ *
+ * Snapshots are only written by the satellite itself. `assert_upload_asset` and
+ * `assert_delete_asset` reject anyone else (see `assert_snapshot_write_allowed`).
+ *
+ * # State
+ * The stale set lives in heap memory. Anything pending during an upgrade is published
+ * with the tag's next batch, or by a controller.
+ */
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+use candid::Principal;
+use junobuild_satellite::{get_controllers, set_asset_handler, Doc};
+use junobuild_shared::controllers::is_controller;
+use junobuild_storage::http::types::HeaderField;
+use junobuild_storage::types::store::AssetKey;
+use junobuild_utils::decode_doc_data;
+use serde::Serialize;
+
+use crate::core::reputation_calculations::get_tag_doc;
+use crate::logger;
+use crate::processors::document_queries::query_doc_by_key;
+use crate::processors::key_pattern::KeyPattern;
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::ReputationData;
+
+/// Storage collection holding the snapshots
+pub const SNAPSHOTS_COLLECTION: &str = "snapshots";
+
+/// Maximum number of users listed in a leaderboard
+pub const MAX_LEADERBOARD_ENTRIES: usize = 1000;
+
+/// How long HTTP clients may cache a snapshot, in seconds
+const SNAPSHOT_MAX_AGE_SECONDS: u32 = 60;
+
+/// One ranked user in a leaderboard
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct LeaderboardEntry {
+    /// 1-based rank in the tag
+    pub rank: usize,
+
+    /// ULID of the user
+    pub user_ulid: String,
+
+    /// The user's effective reputation
+    pub reputation: f64,
+
+    /// Whether the user earned voting power in the tag
+    pub has_voting_power: bool,
+}
+
+/// Content of `/snapshots/{tag}/leaderboard.json`
+#[derive(Serialize, Clone, Debug)]
+pub struct LeaderboardSnapshot {
+    /// ULID of the tag
+    pub tag_ulid: String,
+
+    /// Display name of the tag
+    pub tag_handle: String,
+
+    /// When the snapshot was rendered (nanoseconds since epoch)
+    pub generated_at: u64,
+
+    /// Number of users with a reputation in the tag (may exceed `entries.len()`)
+    pub total_users: usize,
+
+    /// The top users, best first
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+/// Content of `/snapshots/{tag}/users/{user}.json`
+#[derive(Serialize, Clone, Debug)]
+pub struct UserSnapshot {
+    /// ULID of the user
+    pub user_ulid: String,
+
+    /// ULID of the tag
+    pub tag_ulid: String,
+
+    /// Reputation from received votes
+    pub reputation_basis: f64,
+
+    /// Reputation from casting votes
+    pub reputation_rewards: f64,
+
+    /// Effective reputation
+    pub reputation_total_effective: f64,
+
+    /// The user's vote weight (0.0 to 1.0)
+    pub vote_weight: f64,
+
+    /// Whether the user earned voting power in the tag
+    pub has_voting_power: bool,
+
+    /// When the reputation was last calculated
+    pub last_calculation: u64,
+
+    /// When the snapshot was rendered (nanoseconds since epoch)
+    pub generated_at: u64,
+}
+
+thread_local! {
+    /// Tag ULID -> users whose reputation changed since the last publication
+    static STALE_SNAPSHOTS: RefCell<BTreeMap<String, BTreeSet<String>>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Path of a tag's leaderboard
+pub fn leaderboard_path(tag_ulid: &str) -> String {
+    format!("/{}/{}/leaderboard.json", SNAPSHOTS_COLLECTION, tag_ulid)
+}
+
+/// Path of a user's snapshot in a tag
+pub fn user_snapshot_path(tag_ulid: &str, user_ulid: &str) -> String {
+    format!("/{}/{}/users/{}.json", SNAPSHOTS_COLLECTION, tag_ulid, user_ulid)
+}
+
+/// Sorts reputations best first
+///
+/// Ties are broken by user ULID so that ranks are stable between publications.
+pub fn rank_reputations(reputations: &mut [ReputationData]) {
+    reputations.sort_by(|a, b| {
+        b.reputation_total_effective
+            .total_cmp(&a.reputation_total_effective)
+            .then_with(|| a.owner_ulid.cmp(&b.owner_ulid))
+    });
+}
+
+/// Renders a tag's leaderboard
+///
+/// # Arguments
+/// * `tag_ulid` - ULID of the tag
+/// * `tag_handle` - Display name of the tag
+/// * `ranked` - The tag's reputations, sorted with `rank_reputations`
+/// * `generated_at` - Rendering time
+///
+/// # Returns
+/// * `LeaderboardSnapshot` - At most `MAX_LEADERBOARD_ENTRIES` entries
+pub fn build_leaderboard(
+    tag_ulid: &str,
+    tag_handle: &str,
+    ranked: &[ReputationData],
+    generated_at: u64,
+) -> LeaderboardSnapshot {
+    let entries = ranked
+        .iter()
+        .take(MAX_LEADERBOARD_ENTRIES)
+        .enumerate()
+        .map(|(index, reputation)| LeaderboardEntry {
+            rank: index + 1,
+            user_ulid: reputation.owner_ulid.clone(),
+            reputation: reputation.reputation_total_effective,
+            has_voting_power: reputation.has_voting_power,
+        })
+        .collect();
+
+    LeaderboardSnapshot {
+        tag_ulid: tag_ulid.to_string(),
+        tag_handle: tag_handle.to_string(),
+        generated_at,
+        total_users: ranked.len(),
+        entries,
+    }
+}
+
+/// Renders a user's snapshot
+///
+/// # Arguments
+/// * `reputation` - The user's reputation in the tag
+/// * `generated_at` - Rendering time
+pub fn build_user_snapshot(reputation: &ReputationData, generated_at: u64) -> UserSnapshot {
+    UserSnapshot {
+        user_ulid: reputation.owner_ulid.clone(),
+        tag_ulid: reputation.tag_ulid.clone(),
+        reputation_basis: reputation.reputation_basis,
+        reputation_rewards: reputation.reputation_rewards,
+        reputation_total_effective: reputation.reputation_total_effective,
+        vote_weight: reputation.vote_weight.value(),
+        has_voting_power: reputation.has_voting_power,
+        last_calculation: reputation.last_calculation,
+        generated_at,
+    }
+}
+
+/// Checks whether a caller may write or delete in a storage collection
+///
+/// Only the satellite and its controllers may touch the snapshots collection;
+/// other collections are left to their storage rules.
+///
+/// # Arguments
+/// * `caller` - The principal uploading or deleting
+/// * `collection` - The storage collection
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if allowed, `Unauthorized` otherwise
+pub fn assert_snapshot_write_allowed(caller: Principal, collection: &str) -> Result<(), KarmieError> {
+    if collection != SNAPSHOTS_COLLECTION {
+        return Ok(());
+    }
+
+    if caller == ic_cdk::id() || is_controller(caller, &get_controllers()) {
+        return Ok(());
+    }
+
+    Err(KarmieError::Unauthorized {
+        reason: format!(
+            "Reputation snapshots are published by the satellite, {} cannot write to the '{}' collection",
+            caller, SNAPSHOTS_COLLECTION
+        ),
+    })
+}
+
+/// Records that a stored reputation's snapshot is out of date
+///
+/// Called after every `set_doc_store` on the reputations collection. Nothing is
+/// published until `refresh_stale_snapshots` runs.
+///
+/// # Arguments
+/// * `doc` - The stored reputation document
+pub fn mark_snapshot_stale(doc: &Doc) {
+    match decode_doc_data::<ReputationData>(&doc.data) {
+        Ok(reputation) => STALE_SNAPSHOTS.with(|stale| {
+            stale
+                .borrow_mut()
+                .entry(reputation.tag_ulid)
+                .or_default()
+                .insert(reputation.owner_ulid);
+        }),
+        Err(e) => {
+            logger!("error", "[mark_snapshot_stale] Failed to decode reputation data: {}", e);
+        }
+    }
+}
+
+/// Uploads a JSON document into the snapshots collection
+///
+/// # Arguments
+/// * `full_path` - Path of the asset, inside the collection
+/// * `value` - The document to serialize
+/// * `max_age_seconds` - How long HTTP clients may cache the asset
+pub fn upload_json<T: Serialize>(full_path: String, value: &T, max_age_seconds: u32) -> Result<(), String> {
+    let content = serde_json::to_vec(value)
+        .map_err(|e| format!("Failed to serialize snapshot {}: {}", full_path, e))?;
+
+    let name = full_path.rsplit('/').next().unwrap_or_default().to_string();
+    let key = AssetKey {
+        name,
+        full_path: full_path.clone(),
+        token: None,
+        collection: SNAPSHOTS_COLLECTION.to_string(),
+        owner: ic_cdk::id(),
+        description: None,
+    };
+    let headers = vec![
+        HeaderField("Content-Type".to_string(), "application/json".to_string()),
+        HeaderField("Cache-Control".to_string(), format!("public, max-age={}", max_age_seconds)),
+        HeaderField("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+    ];
+
+    set_asset_handler(&key, &content, &headers)
+        .map_err(|e| format!("Failed to upload snapshot {}: {}", full_path, e))
+}
+
+/// Renders and uploads a tag's snapshots
+///
+/// # Arguments
+/// * `tag_ulid` - ULID of the tag
+/// * `users` - Users whose JSON file is rewritten (None rewrites every user's file)
+///
+/// # Returns
+/// * `Result<usize, String>` - Number of assets uploaded
+async fn publish_tag(tag_ulid: &str, users: Option<&BTreeSet<String>>) -> Result<usize, String> {
+    let tag = get_tag_doc(tag_ulid).await?;
+
+    let results = query_doc_by_key("reputations", &KeyPattern::new().tag(tag_ulid).anchor_end().build())?;
+    let mut reputations = Vec::with_capacity(results.items.len());
+    for (key, doc) in &results.items {
+        match decode_doc_data::<ReputationData>(&doc.data) {
+            Ok(reputation) => reputations.push(reputation),
+            Err(e) => {
+                logger!("warn", "[publish_tag] Skipping undecodable reputation: key={}, error={}", key, e);
+            }
+        }
+    }
+    rank_reputations(&mut reputations);
+
+    let generated_at = ic_cdk::api::time();
+    upload_json(
+        leaderboard_path(tag_ulid),
+        &build_leaderboard(tag_ulid, &tag.data.tag_handle, &reputations, generated_at),
+        SNAPSHOT_MAX_AGE_SECONDS,
+    )?;
+    let mut uploaded = 1;
+
+    for reputation in &reputations {
+        if users.is_some_and(|users| !users.contains(&reputation.owner_ulid)) {
+            continue;
+        }
+        upload_json(
+            user_snapshot_path(tag_ulid, &reputation.owner_ulid),
+            &build_user_snapshot(reputation, generated_at),
+            SNAPSHOT_MAX_AGE_SECONDS,
+        )?;
+        uploaded += 1;
+    }
+
+    logger!("info", "[publish_tag] Published {} snapshot assets for tag={} ({} users ranked)",
+        uploaded, tag_ulid, reputations.len());
+
+    Ok(uploaded)
+}
+
+/// Publishes the snapshots of every tag with stale reputations
+///
+/// Called at the end of each recalculation batch. Failures are logged rather than
+/// returned, and the failed tags stay stale so the next batch retries them.
+pub async fn refresh_stale_snapshots() {
+    let stale = STALE_SNAPSHOTS.with(|stale| std::mem::take(&mut *stale.borrow_mut()));
+
+    for (tag_ulid, users) in stale {
+        if let Err(e) = publish_tag(&tag_ulid, Some(&users)).await {
+            logger!("error", "[refresh_stale_snapshots] Failed to publish snapshots for tag={}: {}", tag_ulid, e);
+            STALE_SNAPSHOTS.with(|stale| {
+                stale.borrow_mut().entry(tag_ulid).or_default().extend(users);
+            });
+        }
+    }
+}
+
+/// Republishes every snapshot of a tag (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal requesting the publication
+/// * `tag_ulid` - ULID of the tag
+///
+/// # Returns
+/// * `Result<usize, KarmieError>` - Number of assets uploaded
+pub async fn publish_reputation_snapshots(caller: Principal, tag_ulid: &str) -> Result<usize, KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        return Err(KarmieError::Unauthorized {
+            reason: "Only controllers can publish reputation snapshots".to_string(),
+        });
+    }
+
+    let uploaded = publish_tag(tag_ulid, None).await
+        .map_err(|e| KarmieError::storage(SNAPSHOTS_COLLECTION, e))?;
+    STALE_SNAPSHOTS.with(|stale| stale.borrow_mut().remove(tag_ulid));
+
+    Ok(uploaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::structs::VoteWeight;
+
+    fn reputation(user: &str, total: f64) -> ReputationData {
+        ReputationData {
+            owner_ulid: user.to_string(),
+            tag_ulid: "T".to_string(),
+            reputation_basis: total,
+            reputation_rewards: 0.0,
+            reputation_total_effective: total,
+            last_calculation: 7,
+            vote_weight: VoteWeight::new(0.5).unwrap(),
+            has_voting_power: total >= 10.0,
+        }
+    }
+
+    #[test]
+    fn test_ranks_best_first_with_stable_ties() {
+        let mut reputations = vec![
+            reputation("C", 5.0),
+            reputation("B", 20.0),
+            reputation("A", 5.0),
+            reputation("D", -3.0),
+        ];
+        rank_reputations(&mut reputations);
+
+        let order: Vec<&str> = reputations.iter().map(|r| r.owner_ulid.as_str()).collect();
+        assert_eq!(order, vec!["B", "A", "C", "D"]);
+    }
+
+    #[test]
+    fn test_leaderboard_is_capped() {
+        let mut reputations: Vec<ReputationData> = (0..MAX_LEADERBOARD_ENTRIES + 5)
+            .map(|i| reputation(&format!("U{:05}", i), i as f64))
+            .collect();
+        rank_reputations(&mut reputations);
+
+        let leaderboard = build_leaderboard("T", "rust", &reputations, 42);
+        assert_eq!(leaderboard.total_users, MAX_LEADERBOARD_ENTRIES + 5);
+        assert_eq!(leaderboard.entries.len(), MAX_LEADERBOARD_ENTRIES);
+        assert_eq!(leaderboard.entries[0].rank, 1);
+        assert_eq!(leaderboard.entries[0].user_ulid, format!("U{:05}", MAX_LEADERBOARD_ENTRIES + 4));
+    }
+
+    #[test]
+    fn test_snapshot_json() {
+        let ranked = vec![reputation("B", 20.0)];
+        let leaderboard = serde_json::to_string(&build_leaderboard("T", "rust", &ranked, 42)).unwrap();
+        assert_eq!(
+            leaderboard,
+            r#"{"tag_ulid":"T","tag_handle":"rust","generated_at":42,"total_users":1,"entries":[{"rank":1,"user_ulid":"B","reputation":20.0,"has_voting_power":true}]}"#
+        );
+
+        let user = serde_json::to_string(&build_user_snapshot(&ranked[0], 42)).unwrap();
+        assert!(user.contains(r#""vote_weight":0.5"#));
+        // Ranks only live in the leaderboard, see the module docs
+        assert!(!user.contains(r#""rank""#));
+    }
+
+    #[test]
+    fn test_paths_are_inside_the_collection() {
+        assert_eq!(leaderboard_path("T"), "/snapshots/T/leaderboard.json");
+        assert_eq!(user_snapshot_path("T", "U"), "/snapshots/T/users/U.json");
+    }
+}
@@ -0,0 +1,213 @@
+/*!
+ * Reserved and blocked handles
+ *
+ * Controllers keep a list of handles that users and tags can't take, in a collection
+ * keyed by the handle's confusable skeleton (see utils/normalize.rs):
+ * ```text
+ * reserved_handles:  skl_{skeleton}_   data: { handle, reserved_for, reason }
+ * ```
+ * A lookup is a single `get_doc_store`, and look-alikes of a reserved handle share its
+ * key, so "аdmin" (Cyrillic "а") is as reserved as "admin".
+ *
+ * An entry either blocks the handle for everyone (`reserved_for: None`), or keeps it
+ * for one principal, e.g. the team account of a well-known user. Controllers are never
+ * blocked.
+ *
+ * `BUILT_IN_RESERVED_HANDLES` are blocked without an entry. A controller entry with the
+ * same skeleton takes precedence, e.g. to hand "karmie" to the team's principal.
+ */
+
+use candid::Principal;
+use junobuild_satellite::{delete_doc_store, get_controllers, get_doc_store, set_doc_store, DelDoc, SetDoc};
+use junobuild_shared::controllers::is_controller;
+use junobuild_utils::{decode_doc_data, encode_doc_data};
+
+use crate::logger;
+use crate::processors::document_queries::DocQuery;
+use crate::processors::key_pattern::KeySegment;
+use crate::utils::errors::KarmieError;
+use crate::utils::normalize::CanonicalHandle;
+use crate::utils::structs::ReservedHandleData;
+
+/// Collection holding the reserved handles
+pub const RESERVED_HANDLES_COLLECTION: &str = "reserved_handles";
+
+/// Handles blocked for everyone but controllers, without an entry in the collection
+pub const BUILT_IN_RESERVED_HANDLES: [&str; 16] = [
+    "admin", "administrator", "root", "system", "support", "help",
+    "moderator", "staff", "official", "security", "karmie", "juno",
+    "api", "null", "undefined", "anonymous",
+];
+
+/// Key of the reserved_handles document of a handle: `skl_{skeleton}_`
+fn reserved_handle_key(handle: &str) -> String {
+    format!("{}_{}_", KeySegment::Skl.name(), CanonicalHandle::new(handle).skeleton())
+}
+
+/// Fails with `Unauthorized` unless the caller is a controller
+fn assert_controller(caller: Principal, action: &str) -> Result<(), KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        logger!("error", "[reserved_handles] Only controllers can {} caller={}", action, caller);
+        return Err(KarmieError::Unauthorized {
+            reason: format!("Only controllers can {}", action),
+        });
+    }
+    Ok(())
+}
+
+/// Finds the reservation matching a handle or a look-alike of it
+///
+/// # Arguments
+/// * `handle` - The handle to look up
+///
+/// # Returns
+/// * `Result<Option<ReservedHandleData>, KarmieError>` - The reservation, or None if the handle is free
+pub fn find_reservation(handle: &str) -> Result<Option<ReservedHandleData>, KarmieError> {
+    let key = reserved_handle_key(handle);
+    if let Some(doc) = get_doc_store(ic_cdk::id(), RESERVED_HANDLES_COLLECTION.to_string(), key.clone())
+        .map_err(|e| KarmieError::storage(RESERVED_HANDLES_COLLECTION, e))?
+    {
+        let reservation: ReservedHandleData = decode_doc_data(&doc.data)
+            .map_err(|e| KarmieError::invalid_data(RESERVED_HANDLES_COLLECTION, format!("entry {}: {}", key, e)))?;
+        return Ok(Some(reservation));
+    }
+
+    Ok(built_in_reservation(handle))
+}
+
+/// The built-in reservation matching a handle, if any
+fn built_in_reservation(handle: &str) -> Option<ReservedHandleData> {
+    let skeleton = CanonicalHandle::new(handle).skeleton();
+    BUILT_IN_RESERVED_HANDLES
+        .iter()
+        .find(|reserved| CanonicalHandle::new(reserved).skeleton() == skeleton)
+        .map(|reserved| ReservedHandleData {
+            handle: reserved.to_string(),
+            reserved_for: None,
+            reason: "Built-in".to_string(),
+        })
+}
+
+/// Fails with `HandleReserved` if a handle is reserved for someone other than the caller
+///
+/// # Arguments
+/// * `handle` - The user or tag handle being claimed
+/// * `caller` - The principal claiming it
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if the handle isn't reserved, or is reserved for the caller
+pub fn assert_handle_not_reserved(handle: &str, caller: Principal) -> Result<(), KarmieError> {
+    let reservation = match find_reservation(handle)? {
+        Some(reservation) => reservation,
+        None => return Ok(()),
+    };
+
+    if reservation.reserved_for.as_deref() == Some(caller.to_text().as_str()) || is_controller(caller, &get_controllers()) {
+        return Ok(());
+    }
+
+    logger!("warn", "[assert_handle_not_reserved] Handle {} matches reserved handle {} caller={}",
+        handle, reservation.handle, caller);
+    Err(KarmieError::HandleReserved {
+        handle: handle.to_string(),
+    })
+}
+
+/// Reserves or blocks a handle and its look-alikes (controllers only)
+///
+/// Replaces the existing reservation of the same skeleton, if any.
+///
+/// # Arguments
+/// * `caller` - The principal making the change
+/// * `reservation` - The handle, who it is reserved for (None to block it) and why
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if stored, Err if the caller is not a controller or the reservation is invalid
+pub fn reserve_handle(caller: Principal, reservation: ReservedHandleData) -> Result<(), KarmieError> {
+    assert_controller(caller, "reserve handles")?;
+
+    if CanonicalHandle::new(&reservation.handle).skeleton().is_empty() {
+        return Err(KarmieError::invalid_field("handle", "must contain letters or digits"));
+    }
+    if let Some(principal) = &reservation.reserved_for {
+        Principal::from_text(principal)
+            .map_err(|e| KarmieError::invalid_field("reserved_for", format!("invalid principal: {}", e)))?;
+    }
+
+    let key = reserved_handle_key(&reservation.handle);
+    let version = get_doc_store(ic_cdk::id(), RESERVED_HANDLES_COLLECTION.to_string(), key.clone())
+        .map_err(|e| KarmieError::storage(RESERVED_HANDLES_COLLECTION, e))?
+        .and_then(|doc| doc.version);
+    let data = encode_doc_data(&reservation).map_err(|e| KarmieError::invalid_data(RESERVED_HANDLES_COLLECTION, e))?;
+    set_doc_store(
+        ic_cdk::id(),
+        RESERVED_HANDLES_COLLECTION.to_string(),
+        key.clone(),
+        SetDoc { data, description: None, version },
+    )
+    .map_err(|e| KarmieError::storage(RESERVED_HANDLES_COLLECTION, e))?;
+
+    logger!("info", "[reserve_handle] Reserved handle {} as {} for {:?}", reservation.handle, key, reservation.reserved_for);
+    Ok(())
+}
+
+/// Removes the reservation of a handle (controllers only)
+///
+/// Built-in reserved handles can't be removed, only overridden with `reserve_handle`.
+///
+/// # Arguments
+/// * `caller` - The principal making the change
+/// * `handle` - The reserved handle, or a look-alike of it
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if removed, `NotFound` if the handle wasn't reserved
+pub fn unreserve_handle(caller: Principal, handle: &str) -> Result<(), KarmieError> {
+    assert_controller(caller, "reserve handles")?;
+
+    let key = reserved_handle_key(handle);
+    let doc = get_doc_store(ic_cdk::id(), RESERVED_HANDLES_COLLECTION.to_string(), key.clone())
+        .map_err(|e| KarmieError::storage(RESERVED_HANDLES_COLLECTION, e))?
+        .ok_or_else(|| KarmieError::not_found("Reserved handle", handle))?;
+    delete_doc_store(ic_cdk::id(), RESERVED_HANDLES_COLLECTION.to_string(), key.clone(), DelDoc { version: doc.version })
+        .map_err(|e| KarmieError::storage(RESERVED_HANDLES_COLLECTION, e))?;
+
+    logger!("info", "[unreserve_handle] Removed reservation {} of handle {}", key, handle);
+    Ok(())
+}
+
+/// Lists the reservations stored by controllers (controllers only)
+///
+/// `BUILT_IN_RESERVED_HANDLES` are not included.
+///
+/// # Arguments
+/// * `caller` - The principal reading the list
+///
+/// # Returns
+/// * `Result<Vec<ReservedHandleData>, KarmieError>` - The reservations, in skeleton order
+pub fn list_reserved_handles(caller: Principal) -> Result<Vec<ReservedHandleData>, KarmieError> {
+    assert_controller(caller, "list reserved handles")?;
+
+    let mut reservations = Vec::new();
+    for item in DocQuery::new(RESERVED_HANDLES_COLLECTION, "").iter() {
+        let (key, doc) = item?;
+        let reservation: ReservedHandleData = decode_doc_data(&doc.data)
+            .map_err(|e| KarmieError::invalid_data(RESERVED_HANDLES_COLLECTION, format!("entry {}: {}", key, e)))?;
+        reservations.push(reservation);
+    }
+    Ok(reservations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_built_in_reservations_match_look_alikes() {
+        assert!(built_in_reservation("admin").is_some());
+        assert!(built_in_reservation("ADMIN").is_some());
+        assert!(built_in_reservation("аdmin").is_some()); // Cyrillic "а"
+        assert!(built_in_reservation("adrnin").is_some());
+        assert!(built_in_reservation("alice").is_none());
+        assert_eq!(reserved_handle_key("Аdmin"), reserved_handle_key("admin"));
+    }
+}
@@ -0,0 +1,393 @@
+/*!
+ * Reciprocal voting and voting ring detection
+ *
+ * Looks for groups of users in a tag that upvote each other, which is the typical
+ * pattern of sock puppets and vote-trading rings.
+ *
+ * # Detection
+ * 1. Two users form a reciprocal pair when both cast a positive vote on the other
+ * 2. Reciprocal pairs are joined into clusters (connected components)
+ * 3. Each cluster is scored on three signals (all between 0.0 and 1.0):
+ *    - reciprocity: share of the members' positive votes that are returned
+ *      (genuine users also vote outside their circle, rings mostly don't)
+ *    - density: reciprocal pairs / possible pairs among members (1.0 = full clique)
+ *    - new_account_clustering: largest share of members whose accounts were created
+ *      within `ACCOUNT_CREATION_WINDOW_MS` of each other (from their user ULID timestamp)
+ * 4. score = weighted sum of the signals; clusters with score >= `RING_FLAG_THRESHOLD` are flagged
+ *
+ * # Dampening
+ * Tags can set `reciprocal_vote_dampening` (0.0 to 1.0). When set, positive votes
+ * between the members of a flagged reciprocal pair contribute
+ * `(1 - reciprocal_vote_dampening)` of their normal value to the basis reputation.
+ */
+
+use std::collections::{HashMap, HashSet};
+
+use candid::{CandidType, Principal};
+use junobuild_satellite::get_controllers;
+use junobuild_shared::controllers::is_controller;
+use junobuild_utils::decode_doc_data;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{apply_duplicate_vote_policy, VoteIdentity};
+use crate::core::reputation_calculations::get_tag_doc;
+use crate::logger;
+use crate::processors::document_queries::DocQuery;
+use crate::processors::key_pattern::{KeyPattern, KeySegment};
+use crate::processors::ulid_timestamp_extract::extract_timestamp_ms;
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::{TagData, VoteData};
+
+/// Accounts created within this window of each other count as clustered (7 days)
+const ACCOUNT_CREATION_WINDOW_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+/// Weight of each signal in the cluster score
+const RECIPROCITY_WEIGHT: f64 = 0.4;
+const DENSITY_WEIGHT: f64 = 0.3;
+const NEW_ACCOUNT_WEIGHT: f64 = 0.3;
+
+/// Clusters scoring at or above this value are flagged
+const RING_FLAG_THRESHOLD: f64 = 0.7;
+
+/// A group of users connected by reciprocal positive votes
+#[derive(Serialize, Deserialize, Clone, Debug, CandidType)]
+pub struct VotingCluster {
+    /// User ULIDs in the cluster, sorted
+    pub members: Vec<String>,
+
+    /// Pairs of members that upvoted each other, each pair sorted
+    pub reciprocal_pairs: Vec<(String, String)>,
+
+    /// Share of the members' positive votes that are returned (0.0 to 1.0)
+    pub reciprocity: f64,
+
+    /// Reciprocal pairs / possible pairs among members (0.0 to 1.0)
+    pub density: f64,
+
+    /// Largest share of members created within the account creation window (0.0 to 1.0)
+    pub new_account_clustering: f64,
+
+    /// Weighted combination of the three signals (0.0 to 1.0)
+    pub score: f64,
+
+    /// Whether the score reached the flag threshold
+    pub flagged: bool,
+}
+
+/// Result of a voting ring analysis for a tag
+#[derive(Serialize, Deserialize, Clone, Debug, CandidType)]
+pub struct RingAnalysis {
+    pub tag_ulid: String,
+
+    /// Number of votes analyzed (after the tag's duplicate vote policy)
+    pub votes_analyzed: u32,
+
+    /// Number of clusters found, flagged or not
+    pub clusters_found: u32,
+
+    /// Flagged clusters, highest score first
+    pub flagged_clusters: Vec<VotingCluster>,
+}
+
+/// Finds and scores clusters of reciprocal voters
+///
+/// # Arguments
+/// * `votes` - The votes of a single tag
+///
+/// # Returns
+/// * `Vec<VotingCluster>` - All clusters, highest score first
+pub fn detect_voting_clusters(votes: &[VoteData]) -> Vec<VotingCluster> {
+    // Step 1: Collect positive votes per author (ignoring self-votes)
+    let mut upvoted: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for vote in votes {
+        if vote.value > 0.0 && vote.owner_ulid != vote.target_ulid {
+            upvoted
+                .entry(vote.owner_ulid.as_str())
+                .or_default()
+                .insert(vote.target_ulid.as_str());
+        }
+    }
+
+    let upvotes = |from: &str, to: &str| upvoted.get(from).is_some_and(|targets| targets.contains(to));
+
+    // Step 2: Find reciprocal pairs
+    let mut pairs: Vec<(&str, &str)> = Vec::new();
+    for (author, targets) in &upvoted {
+        for target in targets {
+            if author < target && upvotes(target, author) {
+                pairs.push((author, target));
+            }
+        }
+    }
+
+    // Step 3: Join pairs into clusters (union-find)
+    let mut parent: HashMap<&str, &str> = HashMap::new();
+    fn find<'a>(parent: &mut HashMap<&'a str, &'a str>, user: &'a str) -> &'a str {
+        let next = *parent.entry(user).or_insert(user);
+        if next == user {
+            return user;
+        }
+        let root = find(parent, next);
+        parent.insert(user, root);
+        root
+    }
+    for (a, b) in &pairs {
+        let root_a = find(&mut parent, a);
+        let root_b = find(&mut parent, b);
+        if root_a != root_b {
+            parent.insert(root_a, root_b);
+        }
+    }
+
+    // root -> (members, reciprocal pairs)
+    type Cluster<'a> = (Vec<&'a str>, Vec<(&'a str, &'a str)>);
+    let mut clusters: HashMap<&str, Cluster> = HashMap::new();
+    let users: Vec<&str> = parent.keys().copied().collect();
+    for user in users {
+        let root = find(&mut parent, user);
+        clusters.entry(root).or_default().0.push(user);
+    }
+    for (a, b) in &pairs {
+        let root = find(&mut parent, a);
+        clusters.entry(root).or_default().1.push((a, b));
+    }
+
+    // Step 4: Score each cluster
+    let mut scored: Vec<VotingCluster> = clusters
+        .into_values()
+        .map(|(mut members, mut cluster_pairs)| {
+            members.sort();
+            cluster_pairs.sort();
+
+            let member_count = members.len() as f64;
+            let possible_pairs = member_count * (member_count - 1.0) / 2.0;
+            let density = cluster_pairs.len() as f64 / possible_pairs;
+
+            // Each reciprocal pair accounts for two returned upvotes
+            let total_upvotes: usize = members.iter().map(|m| upvoted.get(m).map_or(0, |t| t.len())).sum();
+            let reciprocity = (cluster_pairs.len() * 2) as f64 / total_upvotes as f64;
+
+            let new_account_clustering = account_creation_clustering(&members);
+
+            let score = RECIPROCITY_WEIGHT * reciprocity
+                + DENSITY_WEIGHT * density
+                + NEW_ACCOUNT_WEIGHT * new_account_clustering;
+
+            VotingCluster {
+                members: members.iter().map(|m| m.to_string()).collect(),
+                reciprocal_pairs: cluster_pairs.iter().map(|(a, b)| (a.to_string(), b.to_string())).collect(),
+                reciprocity,
+                density,
+                new_account_clustering,
+                score,
+                flagged: score >= RING_FLAG_THRESHOLD,
+            }
+        })
+        .collect();
+
+    scored.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.members.cmp(&b.members)));
+    scored
+}
+
+/// Largest share of members whose accounts were created within the creation window
+///
+/// Account creation time is read from the user ULID. Members whose ULID can't
+/// be parsed never count as clustered.
+fn account_creation_clustering(members: &[&str]) -> f64 {
+    let mut created: Vec<u64> = members
+        .iter()
+        .filter_map(|member| extract_timestamp_ms(member).ok())
+        .collect();
+    created.sort_unstable();
+
+    // Sliding window over sorted creation times
+    let mut largest = 0;
+    let mut start = 0;
+    for end in 0..created.len() {
+        while created[end] - created[start] > ACCOUNT_CREATION_WINDOW_MS {
+            start += 1;
+        }
+        largest = largest.max(end - start + 1);
+    }
+
+    largest as f64 / members.len() as f64
+}
+
+/// Collects the reciprocal pairs of all flagged clusters
+///
+/// # Returns
+/// * `HashSet<(String, String)>` - Flagged pairs, each pair sorted (see `is_flagged_pair`)
+pub fn flagged_reciprocal_pairs(clusters: &[VotingCluster]) -> HashSet<(String, String)> {
+    clusters
+        .iter()
+        .filter(|cluster| cluster.flagged)
+        .flat_map(|cluster| cluster.reciprocal_pairs.iter().cloned())
+        .collect()
+}
+
+/// Checks whether a vote between two users belongs to a flagged reciprocal pair
+pub fn is_flagged_pair(flagged: &HashSet<(String, String)>, author_ulid: &str, target_ulid: &str) -> bool {
+    let pair = if author_ulid < target_ulid {
+        (author_ulid.to_string(), target_ulid.to_string())
+    } else {
+        (target_ulid.to_string(), author_ulid.to_string())
+    };
+    flagged.contains(&pair)
+}
+
+/// Loads all votes of a tag, filtered by the tag's duplicate vote policy
+///
+/// # Arguments
+/// * `tag_ulid` - ULID of the tag
+/// * `tag_data` - The tag's settings
+///
+/// # Returns
+/// * `Result<Vec<VoteData>, String>` - The tag's votes or an error message
+pub fn get_tag_votes(tag_ulid: &str, tag_data: &TagData) -> Result<Vec<VoteData>, String> {
+    // Only the decoded vote data is kept
+    let mut votes: Vec<VoteData> = Vec::new();
+    for item in DocQuery::new("votes", &KeyPattern::new().tag(tag_ulid).any(KeySegment::Tar).build()).iter() {
+        let (key, doc) = item?;
+        match decode_doc_data::<VoteData>(&doc.data) {
+            Ok(vote) => votes.push(vote),
+            Err(e) => logger!("warn", "[get_tag_votes] Skipping undecodable vote: key={}, error={}", key, e),
+        }
+    }
+
+    Ok(apply_duplicate_vote_policy(
+        votes,
+        tag_data.duplicate_vote_policy,
+        tag_data.duplicate_vote_cap,
+        |vote| Some(VoteIdentity::from_vote(vote)),
+    ))
+}
+
+/// Analyzes a tag for voting rings (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal requesting the analysis
+/// * `tag_ulid` - ULID of the tag to analyze
+///
+/// # Returns
+/// * `Result<RingAnalysis, KarmieError>` - The flagged clusters or the reason the analysis failed
+pub async fn analyze_voting_rings(caller: Principal, tag_ulid: &str) -> Result<RingAnalysis, KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        logger!("error", "[analyze_voting_rings] Only controllers can view voting ring analysis caller={}", caller);
+        return Err(KarmieError::Unauthorized {
+            reason: "Only controllers can view voting ring analysis".to_string(),
+        });
+    }
+
+    let tag = get_tag_doc(tag_ulid).await?;
+    let votes = get_tag_votes(tag_ulid, &tag.data).map_err(|e| KarmieError::storage("votes", e))?;
+    let clusters = detect_voting_clusters(&votes);
+
+    let clusters_found = clusters.len() as u32;
+    let flagged_clusters: Vec<VotingCluster> = clusters.into_iter().filter(|c| c.flagged).collect();
+
+    logger!("info", "[analyze_voting_rings] tag={}: {} votes, {} clusters, {} flagged",
+        tag_ulid, votes.len(), clusters_found, flagged_clusters.len());
+
+    Ok(RingAnalysis {
+        tag_ulid: tag_ulid.to_string(),
+        votes_analyzed: votes.len() as u32,
+        clusters_found,
+        flagged_clusters,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ULIDs with creation times far apart (2016, 2020, 2024) and close together (same day)
+    const OLD_2016: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAV";
+    const OLD_2020: &str = "01E0000000AAAAAAAAAAAAAAAA";
+    const OLD_2024: &str = "01HN000000AAAAAAAAAAAAAAAA";
+    const NEW_1: &str = "01J0000000AAAAAAAAAAAAAAA1";
+    const NEW_2: &str = "01J0000001AAAAAAAAAAAAAAA2";
+    const NEW_3: &str = "01J0000002AAAAAAAAAAAAAAA3";
+
+    fn vote(owner: &str, target: &str, value: f64) -> VoteData {
+        VoteData {
+            owner_ulid: owner.to_string(),
+            target_ulid: target.to_string(),
+            tag_ulid: "TAG".to_string(),
+            vote_ulid: format!("{}{}", owner, target),
+            value,
+            weight: 1.0,
+            intensity: 0,
+        }
+    }
+
+    #[test]
+    fn test_no_clusters_without_reciprocal_upvotes() {
+        let votes = vec![
+            vote(NEW_1, NEW_2, 1.0),
+            vote(NEW_2, NEW_3, 1.0),
+            // Reciprocal but negative
+            vote(NEW_3, NEW_1, -1.0),
+            vote(NEW_1, NEW_3, -1.0),
+        ];
+        assert!(detect_voting_clusters(&votes).is_empty());
+    }
+
+    #[test]
+    fn test_new_account_clique_is_flagged() {
+        let votes = vec![
+            vote(NEW_1, NEW_2, 1.0), vote(NEW_2, NEW_1, 1.0),
+            vote(NEW_2, NEW_3, 1.0), vote(NEW_3, NEW_2, 1.0),
+            vote(NEW_1, NEW_3, 1.0), vote(NEW_3, NEW_1, 1.0),
+        ];
+
+        let clusters = detect_voting_clusters(&votes);
+        assert_eq!(clusters.len(), 1);
+
+        let cluster = &clusters[0];
+        assert_eq!(cluster.members, vec![NEW_1, NEW_2, NEW_3]);
+        assert_eq!(cluster.reciprocal_pairs.len(), 3);
+        assert_eq!(cluster.density, 1.0);
+        assert_eq!(cluster.reciprocity, 1.0);
+        assert_eq!(cluster.new_account_clustering, 1.0);
+        assert!(cluster.flagged);
+
+        let flagged = flagged_reciprocal_pairs(&clusters);
+        assert!(is_flagged_pair(&flagged, NEW_3, NEW_1));
+        assert!(is_flagged_pair(&flagged, NEW_1, NEW_3));
+    }
+
+    #[test]
+    fn test_established_users_with_broad_voting_are_not_flagged() {
+        // Two old accounts upvote each other but also vote on many other users
+        let mut votes = vec![vote(OLD_2016, OLD_2020, 1.0), vote(OLD_2020, OLD_2016, 1.0)];
+        for other in [OLD_2024, NEW_1, NEW_2, NEW_3] {
+            votes.push(vote(OLD_2016, other, 1.0));
+            votes.push(vote(OLD_2020, other, 1.0));
+        }
+
+        let clusters = detect_voting_clusters(&votes);
+        assert_eq!(clusters.len(), 1);
+
+        let cluster = &clusters[0];
+        assert_eq!(cluster.density, 1.0);
+        assert_eq!(cluster.reciprocity, 2.0 / 10.0);
+        assert_eq!(cluster.new_account_clustering, 0.5);
+        assert!(!cluster.flagged);
+        assert!(flagged_reciprocal_pairs(&clusters).is_empty());
+    }
+
+    #[test]
+    fn test_chain_has_lower_density() {
+        // OLD_2016 <-> OLD_2020 <-> OLD_2024: 2 of 3 possible pairs
+        let votes = vec![
+            vote(OLD_2016, OLD_2020, 1.0), vote(OLD_2020, OLD_2016, 1.0),
+            vote(OLD_2020, OLD_2024, 1.0), vote(OLD_2024, OLD_2020, 1.0),
+        ];
+
+        let clusters = detect_voting_clusters(&votes);
+        assert_eq!(clusters.len(), 1);
+        assert!((clusters[0].density - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(clusters[0].reciprocity, 1.0);
+        assert!((clusters[0].new_account_clustering - 1.0 / 3.0).abs() < 1e-9);
+    }
+}
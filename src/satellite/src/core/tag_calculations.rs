@@ -37,7 +37,9 @@
  */
 
 use crate::logger;
-use crate::processors::document_queries::query_doc_by_key;
+use crate::processors::document_queries::{query_doc_by_key, DocQuery};
+use crate::processors::key_pattern::KeyPattern;
+use crate::utils::errors::KarmieError;
 use crate::utils::structs::{ReputationData, TagData};
 use junobuild_utils::decode_doc_data;
 
@@ -55,8 +57,8 @@ use junobuild_utils::decode_doc_data;
 /// * `tag_key` - The key of the tag to check
 ///
 /// # Returns
-/// * `Result<u32, String>` - The number of active users or an error message
-pub async fn get_active_users_count(tag_key: &str) -> Result<u32, String> {
+/// * `Result<u32, KarmieError>` - The number of active users or the reason they couldn't be counted
+pub async fn get_active_users_count(tag_key: &str) -> Result<u32, KarmieError> {
     // Step 1: Get tag configuration to find threshold
     logger!(
         "debug",
@@ -65,13 +67,13 @@ pub async fn get_active_users_count(tag_key: &str) -> Result<u32, String> {
     );
 
     // Get tag configuration using query_doc_by_key with tag pattern
-    let tag_results = query_doc_by_key("tags", &format!("tag_{}_", tag_key))?;
+    let tag_results = query_doc_by_key("tags", &KeyPattern::new().tag(tag_key).build())
+        .map_err(|e| KarmieError::storage("tags", e))?;
 
     // Check if we found any matching tags
     if tag_results.items.is_empty() {
-        let err_msg = format!("Tag not found: {}", tag_key);
-        logger!("error", "[get_active_users_count] {}", err_msg);
-        return Err(err_msg);
+        logger!("error", "[get_active_users_count] Tag not found: {}", tag_key);
+        return Err(KarmieError::not_found("Tag", tag_key));
     }
 
     // Get the first (and should be only) tag document
@@ -79,7 +81,8 @@ pub async fn get_active_users_count(tag_key: &str) -> Result<u32, String> {
     let (_, tag_doc) = &tag_results.items[0];
 
     // Decode to TagData
-    let tag_data: TagData = decode_doc_data(&tag_doc.data)?;
+    let tag_data: TagData = decode_doc_data(&tag_doc.data)
+        .map_err(|e| KarmieError::invalid_data("tags", e))?;
     let threshold = tag_data.reputation_threshold;
 
     // Log the threshold we're using
@@ -91,31 +94,20 @@ pub async fn get_active_users_count(tag_key: &str) -> Result<u32, String> {
     );
 
     // Step 2: Get all users who have reputations in this tag
-    // Method: use query_doc_by_key to find any document in 'reputations' collection that contains the tag key
+    // Method: a DocQuery over the 'reputations' collection for keys containing the tag key
     logger!(
         "debug",
         "[get_active_users_count] Querying active users for tag={}",
         tag_key
     );
 
-    // Use key-based query to find all users in this tag
-    // Method: use query_doc_by_key to find any document in 'reputations' collection that contains the tag key
-    let results = query_doc_by_key("reputations", &format!("tag_{}_", tag_key))?;
-
-    // Log how many reputation documents we found
-    logger!(
-        "info",
-        "[get_active_users_count] Found {} total reputation documents for tag={}",
-        results.items.len(),
-        tag_key
-    );
-
     // Step 3: Count users above threshold
     // Method: Check each reputation document's total_effective value against the threshold
     let mut active_users = 0; // Counter for users above threshold
     let mut inactive_users = 0; // Counter for users below threshold (for logging/monitoring)
 
-    for (doc_key, doc) in &results.items {
+    for item in DocQuery::new("reputations", &KeyPattern::new().tag(tag_key).anchor_end().build()).iter() {
+        let (doc_key, doc) = item?;
         match decode_doc_data::<ReputationData>(&doc.data) {
             Ok(rep_data) => {
                 if rep_data.reputation_total_effective >= threshold {
@@ -0,0 +1,614 @@
+/*!
+ * users_handles index collection
+ *
+ * Handles used to be part of the user key (`_prn_{principal}_usr_{ulid}_hdl_{handle}_`),
+ * which made them impossible to change. User keys are now `_prn_{principal}_usr_{ulid}_`
+ * and handles live in an index collection that maps each handle to the user holding it:
+ * ```text
+ * users:          _prn_{principal}_usr_{ulid}_
+ * users_handles:  hdl_{handle}_usr_{ulid}_      data: { user_ulid }   description: skl_{skeleton}_
+ * ```
+ * The description holds the confusable skeleton of the handle (see utils/normalize.rs),
+ * so that handles that look like another user's handle are found without a scan.
+ *
+ * The index is maintained by the satellite only:
+ * - `assert_doc_user` rejects user writes whose handle, or a handle that looks like it,
+ *   is held by another user, then claims the handle with `claim_user_handle`
+ * - `validate_users_handles_document` (assert_set_doc) rejects any index write that would
+ *   give a handle to two users, or two handles to a user
+ * - `sync_user_handle` runs in the users `on_set_doc` and delete hooks (single, batch and
+ *   filtered deletions), releases the handles of deleted users and repairs entries that don't match the user document
+ *
+ * The claim is written in the same call as the user document: if the user write fails,
+ * the claim is rolled back with it, and of two users claiming the same handle back to
+ * back, the second one finds the first one's entry and is rejected. Hooks run in a
+ * timer after the write, which is why they can't be the ones claiming.
+ *
+ * # Migration
+ * Users created before the index keep their legacy key until a controller runs
+ * `migrate_users_handles`, which moves them to the new key and indexes them in batches.
+ * Until then, handles in legacy keys are still taken into account by `handle_holders`, but
+ * not by `confusable_holders`. The migration also fills in the skeleton of index entries
+ * written before skeletons were introduced.
+ *
+ * # Renames
+ * Users rename by changing the handle in their document. Released handles are recorded,
+ * quarantined and resolvable by core/handle_renames.rs.
+ */
+
+use std::cell::RefCell;
+
+use candid::{CandidType, Principal};
+use junobuild_satellite::{delete_doc_store, get_controllers, set_doc_store, DelDoc, Doc, SetDoc};
+use junobuild_shared::controllers::is_controller;
+use junobuild_utils::{decode_doc_data, encode_doc_data};
+use serde::{Deserialize, Serialize};
+
+use crate::core::handle_renames::{assert_handle_not_quarantined, record_handle_release, record_rename};
+use crate::logger;
+use crate::processors::document_keys::DocKey;
+use crate::processors::document_queries::DocQuery;
+use crate::processors::key_pattern::{KeyPattern, KeySegment};
+use crate::utils::errors::KarmieError;
+use crate::utils::normalize::CanonicalHandle;
+use crate::utils::structs::{UserData, UserHandleData};
+
+/// Collection indexing the users by handle
+pub const USERS_HANDLES_COLLECTION: &str = "users_handles";
+
+/// Maximum number of legacy users moved by a `migrate_users_handles` call
+pub const MAX_HANDLES_MIGRATION_BATCH: usize = 50;
+
+thread_local! {
+    /// Legacy and new key of the user being migrated, whose writes skip the checks meant for users
+    static MIGRATING_USER: RefCell<Option<(String, String)>> = const { RefCell::new(None) };
+
+    /// ULID and handle of the user claiming a handle, whose document isn't written yet
+    static CLAIMING_HANDLE: RefCell<Option<(String, String)>> = const { RefCell::new(None) };
+}
+
+/// Outcome of a `migrate_users_handles` batch
+#[derive(Serialize, Deserialize, Clone, Debug, Default, CandidType)]
+pub struct HandlesMigrationReport {
+    /// Users moved to the new key and indexed, or whose index entry got its skeleton
+    pub migrated: u32,
+    /// Legacy keys that couldn't be migrated, with the reason
+    pub failed: Vec<String>,
+    /// Legacy keys and entries without skeleton left, including the failed ones
+    pub remaining: u32,
+}
+
+/// Whether a users key is the legacy or the new key of the user being migrated
+///
+/// # Arguments
+/// * `key` - Key of the users document being written or deleted
+///
+/// # Returns
+/// * `bool` - True while `migrate_users_handles` moves this user
+pub fn is_migrating_user(key: &str) -> bool {
+    MIGRATING_USER.with(|migrating| {
+        migrating
+            .borrow()
+            .as_ref()
+            .is_some_and(|(legacy_key, new_key)| key == legacy_key || key == new_key)
+    })
+}
+
+/// Finds the document of a user by ULID, whether it has a new or a legacy key
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+///
+/// # Returns
+/// * `Result<Option<(String, Doc)>, KarmieError>` - The key and document, or None if the user doesn't exist
+pub fn find_user_doc(user_ulid: &str) -> Result<Option<(String, Doc)>, KarmieError> {
+    let pattern = KeyPattern::new().anchor_start().any(KeySegment::Prn).usr(user_ulid).build();
+    DocQuery::new("users", &pattern).page_size(1).iter().next().transpose()
+}
+
+/// ULIDs of the users holding a handle
+///
+/// Looks in the index and, until they are migrated, in the legacy users keys.
+/// More than one holder means the handle was claimed twice (see the module documentation).
+///
+/// # Arguments
+/// * `handle` - The handle, canonicalized like in keys
+///
+/// # Returns
+/// * `Result<Vec<String>, KarmieError>` - ULIDs of the holders, without duplicates
+pub fn handle_holders(handle: &str) -> Result<Vec<String>, KarmieError> {
+    let handle = CanonicalHandle::new(handle).into_string();
+    let mut holders: Vec<String> = Vec::new();
+
+    let indexed = DocQuery::new(USERS_HANDLES_COLLECTION, &KeyPattern::new().anchor_start().hdl(&handle).build());
+    let legacy = DocQuery::new("users", &KeyPattern::new().hdl(&handle).anchor_end().build());
+
+    for item in indexed.iter().chain(legacy.iter()) {
+        let (key, _) = item?;
+        let user_ulid = match key.parse::<DocKey>() {
+            Ok(DocKey::UserHandle { user_ulid, .. }) | Ok(DocKey::LegacyUser { user_ulid, .. }) => user_ulid,
+            _ => {
+                logger!("warn", "[handle_holders] Skipping unexpected key {} for handle {}", key, handle);
+                continue;
+            }
+        };
+        if !holders.contains(&user_ulid) {
+            holders.push(user_ulid);
+        }
+    }
+
+    Ok(holders)
+}
+
+/// Description of the index and history documents of a handle: `skl_{skeleton}_`
+///
+/// # Arguments
+/// * `handle` - The handle
+///
+/// # Returns
+/// * `String` - The description, matched by `KeyPattern::skl`
+pub fn handle_description(handle: &str) -> String {
+    format!("{}_{}_", KeySegment::Skl.name(), CanonicalHandle::new(handle).skeleton())
+}
+
+/// Users holding a handle that looks like this one, but isn't it
+///
+/// # Arguments
+/// * `handle` - The handle
+///
+/// # Returns
+/// * `Result<Vec<(String, String)>, KarmieError>` - ULID and handle of each holder
+pub fn confusable_holders(handle: &str) -> Result<Vec<(String, String)>, KarmieError> {
+    let handle = CanonicalHandle::new(handle);
+    let skeleton = KeyPattern::new().anchor_start().skl(&handle.skeleton()).anchor_end().build();
+
+    let mut holders = Vec::new();
+    for item in DocQuery::new(USERS_HANDLES_COLLECTION, "").description(&skeleton).iter() {
+        let (key, _) = item?;
+        match key.parse::<DocKey>() {
+            Ok(DocKey::UserHandle { handle: held, user_ulid }) if held != handle.as_str() => holders.push((user_ulid, held)),
+            Ok(DocKey::UserHandle { .. }) => {}
+            _ => logger!("warn", "[confusable_holders] Skipping unexpected key {} for handle {}", key, handle),
+        }
+    }
+    Ok(holders)
+}
+
+/// Fails with `HandleTaken` if a handle is held by another user
+///
+/// This is the invariant of the index. Look-alike handles and quarantines are policies
+/// applied to user writes by `assert_handle_available`.
+///
+/// # Arguments
+/// * `handle` - The handle being claimed
+/// * `user_ulid` - ULID of the user claiming it
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if the handle is free or already held by this user
+pub fn assert_handle_unheld(handle: &str, user_ulid: &str) -> Result<(), KarmieError> {
+    if handle_holders(handle)?.iter().any(|holder| holder != user_ulid) {
+        return Err(KarmieError::HandleTaken {
+            handle: handle.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Fails with `HandleTaken` if a handle is held by another user, `HandleConfusable` if
+/// another user holds a handle that looks like it, or `HandleQuarantined` if another
+/// user released it too recently (see core/handle_renames.rs)
+///
+/// # Arguments
+/// * `handle` - The handle being claimed
+/// * `user_ulid` - ULID of the user claiming it
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if the handle is free or already held by this user
+pub fn assert_handle_available(handle: &str, user_ulid: &str) -> Result<(), KarmieError> {
+    assert_handle_unheld(handle, user_ulid)?;
+    if let Some((_, similar_to)) = confusable_holders(handle)?.into_iter().find(|(holder, _)| holder != user_ulid) {
+        return Err(KarmieError::HandleConfusable {
+            handle: handle.to_string(),
+            similar_to,
+        });
+    }
+    assert_handle_not_quarantined(handle, user_ulid)
+}
+
+/// An entry of the users_handles index
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandleEntry {
+    /// Key of the entry: `hdl_{handle}_usr_{ulid}_`
+    pub key: String,
+    /// Description of the entry: `skl_{skeleton}_`, None for entries written before skeletons
+    pub description: Option<String>,
+    /// Version of the entry
+    pub version: Option<u64>,
+}
+
+/// Storage of the users_handles index
+///
+/// The datastore in the satellite (`StoredIndex`), abstracted so that claims can be
+/// tested without a canister.
+pub trait HandleIndex {
+    /// ULIDs of the users holding a handle, see `handle_holders`
+    fn holders(&self, handle: &str) -> Result<Vec<String>, KarmieError>;
+
+    /// Entries of a user
+    fn user_entries(&self, user_ulid: &str) -> Result<Vec<HandleEntry>, KarmieError>;
+
+    /// Creates an entry, or replaces it if `version` is the version of the existing one
+    fn put(&mut self, key: &str, user_ulid: &str, description: String, version: Option<u64>) -> Result<(), KarmieError>;
+
+    /// Deletes an entry
+    fn delete(&mut self, key: &str, version: Option<u64>) -> Result<(), KarmieError>;
+}
+
+/// The users_handles collection
+struct StoredIndex;
+
+impl HandleIndex for StoredIndex {
+    fn holders(&self, handle: &str) -> Result<Vec<String>, KarmieError> {
+        handle_holders(handle)
+    }
+
+    fn user_entries(&self, user_ulid: &str) -> Result<Vec<HandleEntry>, KarmieError> {
+        let pattern = KeyPattern::new().usr(user_ulid).anchor_end().build();
+        DocQuery::new(USERS_HANDLES_COLLECTION, &pattern)
+            .iter()
+            .map(|item| item.map(|(key, doc)| HandleEntry { key, description: doc.description, version: doc.version }))
+            .collect()
+    }
+
+    fn put(&mut self, key: &str, user_ulid: &str, description: String, version: Option<u64>) -> Result<(), KarmieError> {
+        let data = encode_doc_data(&UserHandleData { user_ulid: user_ulid.to_string() })
+            .map_err(|e| KarmieError::invalid_data(USERS_HANDLES_COLLECTION, e))?;
+        set_doc_store(
+            ic_cdk::id(),
+            USERS_HANDLES_COLLECTION.to_string(),
+            key.to_string(),
+            SetDoc { data, description: Some(description), version },
+        )
+        .map_err(|e| KarmieError::storage(USERS_HANDLES_COLLECTION, e))?;
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &str, version: Option<u64>) -> Result<(), KarmieError> {
+        delete_doc_store(ic_cdk::id(), USERS_HANDLES_COLLECTION.to_string(), key.to_string(), DelDoc { version })
+            .map_err(|e| KarmieError::storage(USERS_HANDLES_COLLECTION, e))?;
+        Ok(())
+    }
+}
+
+/// Brings the index entries of a user in line with a handle
+///
+/// Entries for other handles are deleted, and the entry for the handle is created if
+/// missing or written again if it has no skeleton. Without a handle, every entry of the
+/// user is deleted. Running it again is a no-op.
+///
+/// # Arguments
+/// * `index` - The index
+/// * `user_ulid` - ULID of the user
+/// * `handle` - The handle the user has, None if the user was deleted
+///
+/// # Returns
+/// * `Result<Vec<String>, KarmieError>` - The handles the user released, `HandleTaken` if another user holds the handle
+fn index_user_handle(index: &mut impl HandleIndex, user_ulid: &str, handle: Option<&str>) -> Result<Vec<String>, KarmieError> {
+    let current_key = match handle {
+        Some(handle) => Some(DocKey::user_handle(handle, user_ulid)?.to_string()),
+        None => None,
+    };
+    let description = handle.map(handle_description);
+
+    // Checked before anything is deleted; the index assert checks it again on the write
+    if let Some(handle) = handle {
+        if index.holders(handle)?.iter().any(|holder| holder != user_ulid) {
+            return Err(KarmieError::HandleTaken {
+                handle: handle.to_string(),
+            });
+        }
+    }
+
+    let mut indexed = false;
+    let mut outdated_version = None;
+    let mut released = Vec::new();
+    for entry in index.user_entries(user_ulid)? {
+        if current_key.as_deref() == Some(entry.key.as_str()) {
+            // Entries written before skeletons were introduced are rewritten with one
+            if entry.description == description {
+                indexed = true;
+            } else {
+                outdated_version = entry.version;
+            }
+            continue;
+        }
+        index.delete(&entry.key, entry.version)?;
+        logger!("info", "[index_user_handle] Released handle entry {} of user {}", entry.key, user_ulid);
+
+        if let Ok(DocKey::UserHandle { handle, .. }) = entry.key.parse::<DocKey>() {
+            released.push(handle);
+        }
+    }
+
+    if let (Some(key), Some(description), false) = (current_key, description, indexed) {
+        index.put(&key, user_ulid, description, outdated_version)?;
+        logger!("info", "[index_user_handle] Indexed handle entry {} of user {}", key, user_ulid);
+    }
+
+    Ok(released)
+}
+
+/// Brings the index in line with a handle, and records the handles the user released
+/// in the handle history (and the rename in the rename log, if the user still has a handle)
+fn apply_user_handle(user_ulid: &str, handle: Option<&str>) -> Result<(), KarmieError> {
+    let released = index_user_handle(&mut StoredIndex, user_ulid, handle)?;
+    for old_handle in &released {
+        record_handle_release(user_ulid, old_handle, handle)?;
+    }
+
+    // A user going back to an old handle doesn't add a history entry, so renames are logged apart
+    if !released.is_empty() && handle.is_some() {
+        record_rename(user_ulid)?;
+    }
+    Ok(())
+}
+
+/// Whether a user is claiming a handle in `assert_doc_user`, before their document is written
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+///
+/// # Returns
+/// * `Option<String>` - The handle being claimed, None if the user isn't claiming one
+pub fn claimed_handle(user_ulid: &str) -> Option<String> {
+    CLAIMING_HANDLE.with(|claiming| {
+        claiming
+            .borrow()
+            .as_ref()
+            .filter(|(claimer, _)| claimer == user_ulid)
+            .map(|(_, handle)| handle.clone())
+    })
+}
+
+/// Gives a handle to a user in the index, as part of the write of their document
+///
+/// Called by `assert_doc_user` once every check has passed. The entry is written in the
+/// same call as the user document, so a failed user write rolls it back, and a second
+/// user claiming the same handle right after is rejected by the index.
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+/// * `handle` - The handle of the document being written
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok once the user holds the handle, `HandleTaken` if another user does
+pub fn claim_user_handle(user_ulid: &str, handle: &str) -> Result<(), KarmieError> {
+    CLAIMING_HANDLE.with(|claiming| *claiming.borrow_mut() = Some((user_ulid.to_string(), handle.to_string())));
+    let result = apply_user_handle(user_ulid, Some(handle));
+    CLAIMING_HANDLE.with(|claiming| *claiming.borrow_mut() = None);
+    result
+}
+
+/// Brings the index entries of a user in line with the handle of their document
+///
+/// User writes claim their handle themselves (see `claim_user_handle`); this runs in the
+/// users `on_set_doc` and delete hooks (including batch deletions) to release the handles
+/// of deleted users and repair entries, and after migrations.
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok once the index matches the user document
+pub fn sync_user_handle(user_ulid: &str) -> Result<(), KarmieError> {
+    let current_handle = match find_user_doc(user_ulid)? {
+        Some((key, doc)) => {
+            let user: UserData = decode_doc_data(&doc.data)
+                .map_err(|e| KarmieError::invalid_data("users", format!("user {}: {}", key, e)))?;
+            Some(user.user_handle)
+        }
+        None => None,
+    };
+    apply_user_handle(user_ulid, current_handle.as_deref())
+}
+
+/// Moves a legacy user to the new key and indexes their handle
+///
+/// The new document is written first, with the owner of the legacy document as caller so
+/// that ownership is kept, then the legacy document is deleted. Legacy keys whose handle
+/// isn't in canonical form are accepted, the handle is dropped from the key anyway.
+///
+/// # Arguments
+/// * `legacy_key` - Key of the legacy users document
+/// * `doc` - The legacy users document
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok once the user has the new key and is indexed
+pub fn migrate_legacy_user(legacy_key: &str, doc: &Doc) -> Result<(), KarmieError> {
+    let (principal, user_ulid) = match DocKey::parse_noncanonical(legacy_key)? {
+        DocKey::LegacyUser { principal, user_ulid, .. } => (principal, user_ulid),
+        other => return Err(other.kind_mismatch("legacy_user")),
+    };
+    let new_key = DocKey::user(&principal, &user_ulid)?.to_string();
+
+    MIGRATING_USER.with(|migrating| *migrating.borrow_mut() = Some((legacy_key.to_string(), new_key.clone())));
+    let moved = set_doc_store(
+        doc.owner,
+        "users".to_string(),
+        new_key.clone(),
+        SetDoc { data: doc.data.clone(), description: doc.description.clone(), version: None },
+    );
+    let deleted = moved.as_ref().ok().map(|_| {
+        delete_doc_store(doc.owner, "users".to_string(), legacy_key.to_string(), DelDoc { version: doc.version })
+    });
+    MIGRATING_USER.with(|migrating| *migrating.borrow_mut() = None);
+
+    match (moved, deleted) {
+        (Err(e), _) => return Err(KarmieError::storage("users", e)),
+        (Ok(_), Some(Err(e))) => {
+            // The user now has two documents, roll the whole batch back
+            ic_cdk::trap(&format!("[migrate_legacy_user] Failed to delete legacy user {}: {}", legacy_key, e));
+        }
+        _ => {}
+    }
+
+    logger!("info", "[migrate_legacy_user] Moved user {} from {} to {}", user_ulid, legacy_key, new_key);
+    sync_user_handle(&user_ulid)
+}
+
+/// ULIDs of the users whose index entry has no skeleton, or an outdated one
+fn users_without_skeleton() -> Result<Vec<String>, KarmieError> {
+    let mut users = Vec::new();
+    for item in DocQuery::new(USERS_HANDLES_COLLECTION, "").iter() {
+        let (key, doc) = item?;
+        if let Ok(DocKey::UserHandle { handle, user_ulid }) = key.parse::<DocKey>() {
+            if doc.description.as_deref() != Some(handle_description(&handle).as_str()) {
+                users.push(user_ulid);
+            }
+        }
+    }
+    Ok(users)
+}
+
+/// Moves a batch of legacy users to the new key format and indexes them (controllers only)
+///
+/// Index entries without skeleton are completed with what's left of each batch.
+/// Run it until `remaining` is 0. Users whose migration fails keep their legacy key and are
+/// listed in `failed`, they are retried by the next batch.
+///
+/// # Arguments
+/// * `caller` - The principal requesting the migration
+///
+/// # Returns
+/// * `Result<HandlesMigrationReport, KarmieError>` - The batch outcome, or Err if the caller is not a controller
+pub fn migrate_users_handles(caller: Principal) -> Result<HandlesMigrationReport, KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        return Err(KarmieError::Unauthorized {
+            reason: "Only controllers can migrate users handles".to_string(),
+        });
+    }
+
+    let legacy_pattern = KeyPattern::new().any(KeySegment::Hdl).anchor_end().build();
+    let batch = DocQuery::new("users", &legacy_pattern)
+        .page_size(MAX_HANDLES_MIGRATION_BATCH)
+        .iter()
+        .take(MAX_HANDLES_MIGRATION_BATCH)
+        .collect::<Result<Vec<_>, KarmieError>>()?;
+
+    let mut report = HandlesMigrationReport::default();
+    for (key, doc) in batch {
+        match migrate_legacy_user(&key, &doc) {
+            Ok(()) => report.migrated += 1,
+            Err(e) => {
+                logger!("error", "[migrate_users_handles] Failed to migrate {}: {}", key, e);
+                report.failed.push(format!("{}: {}", key, e));
+            }
+        }
+    }
+
+    // Then index entries written before skeletons were introduced, with what's left of the batch
+    let budget = MAX_HANDLES_MIGRATION_BATCH.saturating_sub(report.migrated as usize + report.failed.len());
+    for user_ulid in users_without_skeleton()?.into_iter().take(budget) {
+        match sync_user_handle(&user_ulid) {
+            Ok(()) => report.migrated += 1,
+            Err(e) => {
+                logger!("error", "[migrate_users_handles] Failed to add the skeleton of user {}: {}", user_ulid, e);
+                report.failed.push(format!("{}: {}", user_ulid, e));
+            }
+        }
+    }
+
+    let legacy_remaining = DocQuery::new("users", &legacy_pattern).iter().try_fold(0u32, |count, item| item.map(|_| count + 1))?;
+    report.remaining = legacy_remaining + users_without_skeleton()?.len() as u32;
+
+    logger!("info", "[migrate_users_handles] Migrated {} users, {} failed, {} legacy keys or entries without skeleton remaining",
+        report.migrated, report.failed.len(), report.remaining);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const ALICE: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAV";
+    const BOB: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAW";
+
+    /// Index kept in memory: key -> (user_ulid, description)
+    #[derive(Default)]
+    struct MemoryIndex(BTreeMap<String, (String, String)>);
+
+    impl HandleIndex for MemoryIndex {
+        fn holders(&self, handle: &str) -> Result<Vec<String>, KarmieError> {
+            let handle = CanonicalHandle::new(handle).into_string();
+            Ok(self.0.keys().filter_map(|key| match key.parse::<DocKey>() {
+                Ok(DocKey::UserHandle { handle: held, user_ulid }) if held == handle => Some(user_ulid),
+                _ => None,
+            }).collect())
+        }
+
+        fn user_entries(&self, user_ulid: &str) -> Result<Vec<HandleEntry>, KarmieError> {
+            Ok(self.0.iter()
+                .filter(|(_, (holder, _))| holder == user_ulid)
+                .map(|(key, (_, description))| HandleEntry { key: key.clone(), description: Some(description.clone()), version: None })
+                .collect())
+        }
+
+        fn put(&mut self, key: &str, user_ulid: &str, description: String, _version: Option<u64>) -> Result<(), KarmieError> {
+            self.0.insert(key.to_string(), (user_ulid.to_string(), description));
+            Ok(())
+        }
+
+        fn delete(&mut self, key: &str, _version: Option<u64>) -> Result<(), KarmieError> {
+            self.0.remove(key);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_back_to_back_claims() {
+        let mut index = MemoryIndex::default();
+
+        // The first claim is indexed right away, so the second one sees it
+        assert_eq!(index_user_handle(&mut index, ALICE, Some("alice")).unwrap(), Vec::<String>::new());
+        assert!(matches!(index_user_handle(&mut index, BOB, Some("alice")), Err(KarmieError::HandleTaken { .. })));
+        assert!(matches!(index_user_handle(&mut index, BOB, Some("Alice")), Err(KarmieError::HandleTaken { .. })));
+        assert_eq!(index.holders("alice").unwrap(), vec![ALICE.to_string()]);
+
+        // Claiming again is a no-op, renaming releases the old handle
+        assert!(index_user_handle(&mut index, ALICE, Some("alice")).unwrap().is_empty());
+        assert_eq!(index_user_handle(&mut index, ALICE, Some("alice2")).unwrap(), vec!["alice".to_string()]);
+        assert_eq!(index.0.len(), 1);
+
+        assert!(index_user_handle(&mut index, BOB, Some("alice")).is_ok());
+        assert_eq!(index.holders("alice").unwrap(), vec![BOB.to_string()]);
+
+        // Deleted users release everything
+        assert_eq!(index_user_handle(&mut index, BOB, None).unwrap(), vec!["alice".to_string()]);
+        assert!(index.holders("alice").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_claimed_handle() {
+        assert_eq!(claimed_handle(ALICE), None);
+        CLAIMING_HANDLE.with(|claiming| *claiming.borrow_mut() = Some((ALICE.to_string(), "alice".to_string())));
+        assert_eq!(claimed_handle(ALICE), Some("alice".to_string()));
+        assert_eq!(claimed_handle(BOB), None);
+        CLAIMING_HANDLE.with(|claiming| *claiming.borrow_mut() = None);
+    }
+
+    #[test]
+    fn test_is_migrating_user() {
+        let legacy_key = "_prn_2vxsx-fae_usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_hdl_johndoe_";
+        let new_key = "_prn_2vxsx-fae_usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_";
+        assert!(!is_migrating_user(new_key));
+
+        MIGRATING_USER.with(|migrating| *migrating.borrow_mut() = Some((legacy_key.to_string(), new_key.to_string())));
+        assert!(is_migrating_user(legacy_key));
+        assert!(is_migrating_user(new_key));
+        assert!(!is_migrating_user("_prn_2vxsx-fae_usr_01ARZ3NDEKTSV4RRFFQ69G5FAW_"));
+
+        MIGRATING_USER.with(|migrating| *migrating.borrow_mut() = None);
+        assert!(!is_migrating_user(legacy_key));
+    }
+}
@@ -0,0 +1,256 @@
+/*!
+ * Vote cooldown calculations
+ *
+ * Each tag can define a `vote_cooldown` (in minutes) that an author must wait
+ * before voting on the same target again in that tag.
+ * See docs/core/todo/vote-cooldowns.md for the specification.
+ *
+ * The cooldown is checked against the most recent vote between the same
+ * author/target pair, found with the vote key prefix:
+ * ```text
+ * usr_{author}_tag_{tag}_tar_{target}_
+ * ```
+ *
+ * Since the cooldown is read from the stored votes, a vote can't be deleted while
+ * its own cooldown is running (`assert_vote_delete_allowed`): deleting it and voting
+ * again would skip the wait. Controllers can still delete any vote.
+ */
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use junobuild_satellite::{get_controllers, Doc};
+use junobuild_shared::controllers::is_controller;
+use junobuild_utils::decode_doc_data;
+
+use crate::logger;
+use crate::core::duplicate_votes::query_author_votes_on_target;
+use crate::processors::document_queries::query_doc_by_key;
+use crate::processors::key_pattern::KeyPattern;
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::{TagData, VoteData};
+
+/// Nanoseconds in one minute (IC time is in nanoseconds)
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+
+/// Cooldown status between an author and a target in a tag
+#[derive(Serialize, Deserialize, Clone, Debug, CandidType)]
+pub struct VoteCooldownStatus {
+    /// The tag's configured cooldown in minutes (0 = no cooldown)
+    pub vote_cooldown: u32,
+
+    /// Minutes left before the author can vote on the target again (0 = can vote now)
+    pub remaining_minutes: u32,
+
+    /// Creation timestamp (nanoseconds) of the author's latest vote on the target, if any
+    pub last_vote_at: Option<u64>,
+}
+
+/// Gets the cooldown status between an author and a target for a known cooldown value
+///
+/// Use this when the tag data has already been loaded (e.g. during vote validation).
+///
+/// # Arguments
+/// * `author_ulid` - ULID of the user casting the vote
+/// * `target_ulid` - ULID of the user receiving the vote
+/// * `tag_ulid` - ULID of the tag
+/// * `vote_cooldown` - The tag's cooldown in minutes
+///
+/// # Returns
+/// * `Result<VoteCooldownStatus, String>` - The cooldown status or an error message
+pub fn get_vote_cooldown_status(
+    author_ulid: &str,
+    target_ulid: &str,
+    tag_ulid: &str,
+    vote_cooldown: u32,
+) -> Result<VoteCooldownStatus, String> {
+    // Skip the query entirely if the tag has no cooldown
+    if vote_cooldown == 0 {
+        return Ok(VoteCooldownStatus {
+            vote_cooldown,
+            remaining_minutes: 0,
+            last_vote_at: None,
+        });
+    }
+
+    // Find all previous votes between this author and target in this tag
+    let previous_votes = query_author_votes_on_target(author_ulid, target_ulid, tag_ulid)?;
+
+    let status = cooldown_status_from_votes(&previous_votes, vote_cooldown);
+
+    logger!("debug", "[get_vote_cooldown_status] author={}, target={}, tag={}, cooldown={}min, previous_votes={}, remaining={}min",
+        author_ulid, target_ulid, tag_ulid, vote_cooldown, previous_votes.len(), status.remaining_minutes);
+
+    Ok(status)
+}
+
+/// Computes the cooldown status from already loaded votes between an author and a target
+///
+/// Use this when the previous votes are also needed for other checks (e.g. the tag's
+/// duplicate vote policy) so they are only queried once.
+///
+/// # Arguments
+/// * `previous_votes` - The author's existing votes on the target in the tag
+/// * `vote_cooldown` - The tag's cooldown in minutes
+///
+/// # Returns
+/// * `VoteCooldownStatus` - The cooldown status
+pub fn cooldown_status_from_votes(previous_votes: &[(String, Doc)], vote_cooldown: u32) -> VoteCooldownStatus {
+    let last_vote_at = previous_votes
+        .iter()
+        .map(|(_, doc)| doc.created_at)
+        .max();
+
+    let remaining_minutes = match last_vote_at {
+        Some(last_vote) if vote_cooldown > 0 => remaining_minutes(last_vote, ic_cdk::api::time(), vote_cooldown),
+        _ => 0,
+    };
+
+    VoteCooldownStatus {
+        vote_cooldown,
+        remaining_minutes,
+        last_vote_at,
+    }
+}
+
+/// Gets the cooldown status between an author and a target, loading the tag's cooldown
+///
+/// # Arguments
+/// * `author_ulid` - ULID of the user casting the vote
+/// * `target_ulid` - ULID of the user receiving the vote
+/// * `tag_ulid` - ULID of the tag
+///
+/// # Returns
+/// * `Result<VoteCooldownStatus, KarmieError>` - The cooldown status or the reason it couldn't be computed
+pub fn get_vote_cooldown_status_for_tag(
+    author_ulid: &str,
+    target_ulid: &str,
+    tag_ulid: &str,
+) -> Result<VoteCooldownStatus, KarmieError> {
+    let tag_results = query_doc_by_key("tags", &KeyPattern::new().tag(tag_ulid).build())
+        .map_err(|e| KarmieError::storage("tags", e))?;
+
+    let (_, tag_doc) = tag_results.items.first().ok_or_else(|| {
+        logger!("error", "[get_vote_cooldown_status_for_tag] Tag not found: {}", tag_ulid);
+        KarmieError::not_found("Tag", tag_ulid)
+    })?;
+
+    let tag_data: TagData = decode_doc_data(&tag_doc.data)
+        .map_err(|e| KarmieError::invalid_data("tags", e))?;
+
+    get_vote_cooldown_status(author_ulid, target_ulid, tag_ulid, tag_data.vote_cooldown)
+        .map_err(|e| KarmieError::storage("votes", e))
+}
+
+/// Rejects deleting a vote whose cooldown is still running
+///
+/// Called by `assert_delete_doc` for the votes collection, including `delete_many_docs`.
+/// Votes in tags that no longer exist can be deleted.
+///
+/// # Arguments
+/// * `caller` - Principal deleting the vote
+/// * `vote_doc` - The stored vote document
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if the vote can be deleted, `VoteCooldown` otherwise
+pub fn assert_vote_delete_allowed(caller: Principal, vote_doc: &Doc) -> Result<(), KarmieError> {
+    if is_controller(caller, &get_controllers()) {
+        return Ok(());
+    }
+
+    let vote: VoteData = decode_doc_data(&vote_doc.data)
+        .map_err(|e| KarmieError::invalid_data("votes", e))?;
+
+    let tag_results = query_doc_by_key("tags", &KeyPattern::new().tag(&vote.tag_ulid).build())
+        .map_err(|e| KarmieError::storage("tags", e))?;
+    let (_, tag_doc) = match tag_results.items.first() {
+        Some(tag) => tag,
+        None => return Ok(()),
+    };
+    let tag_data: TagData = decode_doc_data(&tag_doc.data)
+        .map_err(|e| KarmieError::invalid_data("tags", e))?;
+
+    check_vote_delete(vote_doc.created_at, ic_cdk::api::time(), tag_data.vote_cooldown)
+        .inspect_err(|_| {
+            logger!("warn", "[assert_vote_delete_allowed] Vote still in cooldown: author={}, target={}, tag={}",
+                vote.owner_ulid, vote.target_ulid, vote.tag_ulid);
+        })
+}
+
+/// Checks that a vote's cooldown has passed, so deleting it doesn't shorten the wait
+///
+/// # Arguments
+/// * `created_at` - Creation timestamp of the vote in nanoseconds
+/// * `now_ns` - Current time in nanoseconds
+/// * `vote_cooldown` - The tag's cooldown in minutes
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok once the cooldown has passed, `VoteCooldown` otherwise
+fn check_vote_delete(created_at: u64, now_ns: u64, vote_cooldown: u32) -> Result<(), KarmieError> {
+    match remaining_minutes(created_at, now_ns, vote_cooldown) {
+        0 => Ok(()),
+        remaining_minutes => Err(KarmieError::VoteCooldown { remaining_minutes }),
+    }
+}
+
+/// Calculates the whole minutes left in a cooldown, rounded up
+///
+/// # Arguments
+/// * `last_vote_ns` - Creation timestamp of the last vote in nanoseconds
+/// * `now_ns` - Current time in nanoseconds
+/// * `vote_cooldown` - Cooldown in minutes
+///
+/// # Returns
+/// * `u32` - Minutes remaining (0 if the cooldown has passed)
+fn remaining_minutes(last_vote_ns: u64, now_ns: u64, vote_cooldown: u32) -> u32 {
+    let cooldown_ns = vote_cooldown as u64 * NANOS_PER_MINUTE;
+    let elapsed_ns = now_ns.saturating_sub(last_vote_ns);
+
+    if elapsed_ns >= cooldown_ns {
+        return 0;
+    }
+
+    let remaining_ns = cooldown_ns - elapsed_ns;
+    remaining_ns.div_ceil(NANOS_PER_MINUTE) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remaining_minutes() {
+        let last_vote = 1_750_000_000_000_000_000;
+
+        // Cooldown fully elapsed
+        assert_eq!(remaining_minutes(last_vote, last_vote + 60 * NANOS_PER_MINUTE, 60), 0);
+        assert_eq!(remaining_minutes(last_vote, last_vote + 90 * NANOS_PER_MINUTE, 60), 0);
+
+        // Just voted: full cooldown remains
+        assert_eq!(remaining_minutes(last_vote, last_vote, 60), 60);
+
+        // Partial minutes are rounded up
+        assert_eq!(remaining_minutes(last_vote, last_vote + 1, 60), 60);
+        assert_eq!(remaining_minutes(last_vote, last_vote + 59 * NANOS_PER_MINUTE + 1, 60), 1);
+
+        // Clock skew (vote in the future) is treated as zero elapsed time
+        assert_eq!(remaining_minutes(last_vote, last_vote - 1, 10), 10);
+
+        // Maximum cooldown (31 days) does not overflow
+        assert_eq!(remaining_minutes(last_vote, last_vote, 44_640), 44_640);
+    }
+
+    #[test]
+    fn test_vote_delete_blocked_during_cooldown() {
+        let created_at = 1_750_000_000_000_000_000;
+
+        // Deleting right after voting would let the author vote again at once
+        assert!(matches!(
+            check_vote_delete(created_at, created_at + 10 * NANOS_PER_MINUTE, 60),
+            Err(KarmieError::VoteCooldown { remaining_minutes: 50 })
+        ));
+
+        // Once the cooldown has passed, or without one, the vote can go
+        assert!(check_vote_delete(created_at, created_at + 60 * NANOS_PER_MINUTE, 60).is_ok());
+        assert!(check_vote_delete(created_at, created_at, 0).is_ok());
+    }
+}
@@ -0,0 +1,379 @@
+/*!
+ * Voting credits
+ *
+ * Tags can optionally give each user a budget of voting credits (`TagData.vote_credits`).
+ * Every vote spends credits, negative votes can cost more than positive ones, and
+ * credits regenerate over time up to the maximum.
+ *
+ * # Flow
+ * 1. `validate_vote_document` spends the cost of the vote (`spend_vote_credits`), or
+ *    rejects it if the author can't afford it
+ * 2. Clients read the current balance with the `get_vote_credits` query
+ *
+ * # Storage
+ * Balances live in the `vote_credits` collection, one document per user and tag:
+ * ```text
+ * usr_{user}_tag_{tag}_crd_
+ * ```
+ * Regeneration is applied lazily: the stored `balance` is valid as of `last_regen`,
+ * and credits earned since then are added whenever the balance is read.
+ * Users without a document have a full balance.
+ *
+ * # Notes
+ * - Editing a vote only costs the difference when it becomes more expensive
+ *   (e.g. flipping an upvote to a downvote or raising its intensity), never refunds
+ * - The balance is written in the same call as the vote, from its assert: a vote
+ *   rejected after the debit (or in a `set_many_docs` batch that fails) rolls it back,
+ *   and the next vote is checked against the debited balance
+ */
+
+use candid::CandidType;
+use junobuild_satellite::{get_doc, set_doc_store, SetDoc};
+use junobuild_utils::{decode_doc_data, encode_doc_data};
+use serde::{Deserialize, Serialize};
+
+use crate::core::quadratic_voting::intensity_cost_multiplier;
+use crate::core::reputation_calculations::get_tag_doc;
+use crate::logger;
+use crate::processors::document_keys::DocKey;
+use crate::utils::errors::KarmieError;
+use crate::utils::metrics::record_store_error;
+use crate::utils::structs::{VoteCreditsConfig, VoteCreditsData, VoteData, VoteMode};
+
+/// Collection storing voting credit balances
+const VOTE_CREDITS_COLLECTION: &str = "vote_credits";
+
+/// Nanoseconds in one minute (IC time is in nanoseconds)
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+
+/// Voting credits status of a user in a tag
+#[derive(Serialize, Deserialize, Clone, Debug, CandidType)]
+pub struct VoteCreditsStatus {
+    /// Whether the tag uses voting credits (all other fields are 0 if not)
+    pub enabled: bool,
+
+    /// Credits available right now
+    pub balance: u32,
+
+    /// Maximum balance
+    pub max_credits: u32,
+
+    /// Cost of a positive vote
+    pub positive_vote_cost: u32,
+
+    /// Cost of a negative vote
+    pub negative_vote_cost: u32,
+
+    /// Minutes until the next credit regenerates (0 = balance is full or never regenerates)
+    pub next_credit_in_minutes: u32,
+}
+
+/// Credits spent by a vote at intensity 1
+pub fn base_vote_cost(config: &VoteCreditsConfig, vote: &VoteData) -> u32 {
+    if vote.value < 0.0 {
+        config.negative_vote_cost
+    } else {
+        config.positive_vote_cost
+    }
+}
+
+/// Credits spent by a vote
+///
+/// The base cost is multiplied by intensity² in quadratic voting tags (see quadratic_voting.rs).
+pub fn vote_cost(config: &VoteCreditsConfig, mode: VoteMode, vote: &VoteData) -> u32 {
+    base_vote_cost(config, vote).saturating_mul(intensity_cost_multiplier(mode, vote))
+}
+
+/// Credits to charge for a vote write
+///
+/// New votes pay their full cost. Edits pay the difference if the vote became
+/// more expensive, and nothing otherwise.
+///
+/// # Arguments
+/// * `config` - The tag's credits configuration
+/// * `mode` - The tag's voting mode
+/// * `vote` - The vote being written
+/// * `previous` - The stored vote, for edits
+///
+/// # Returns
+/// * `u32` - The number of credits to charge
+pub fn vote_cost_delta(
+    config: &VoteCreditsConfig,
+    mode: VoteMode,
+    vote: &VoteData,
+    previous: Option<&VoteData>,
+) -> u32 {
+    let cost = vote_cost(config, mode, vote);
+    match previous {
+        Some(previous) => cost.saturating_sub(vote_cost(config, mode, previous)),
+        None => cost,
+    }
+}
+
+/// Applies regeneration to a stored balance
+///
+/// # Arguments
+/// * `balance` - Balance as of `last_regen`
+/// * `last_regen` - Time (ns) up to which regeneration was applied
+/// * `config` - The tag's credits configuration
+/// * `now` - Current time (ns)
+///
+/// # Returns
+/// * `(u32, u64)` - The current balance and the new `last_regen`
+///
+/// Partial progress towards the next credit is kept by only advancing `last_regen`
+/// by whole regeneration intervals. A full balance restarts the clock at `now`.
+fn regenerate(balance: u32, last_regen: u64, config: &VoteCreditsConfig, now: u64) -> (u32, u64) {
+    if balance >= config.max_credits {
+        return (config.max_credits, now);
+    }
+    if config.regen_minutes == 0 {
+        return (balance, last_regen);
+    }
+
+    let interval = config.regen_minutes as u64 * NANOS_PER_MINUTE;
+    let gained = now.saturating_sub(last_regen) / interval;
+    let regenerated = (balance as u64).saturating_add(gained);
+
+    if regenerated >= config.max_credits as u64 {
+        (config.max_credits, now)
+    } else {
+        (regenerated as u32, last_regen + gained * interval)
+    }
+}
+
+/// Minutes until the next credit, for a balance that already had regeneration applied
+fn next_credit_in_minutes(balance: u32, last_regen: u64, config: &VoteCreditsConfig, now: u64) -> u32 {
+    if balance >= config.max_credits || config.regen_minutes == 0 {
+        return 0;
+    }
+
+    let interval = config.regen_minutes as u64 * NANOS_PER_MINUTE;
+    let remaining = interval.saturating_sub(now.saturating_sub(last_regen));
+    remaining.div_ceil(NANOS_PER_MINUTE) as u32
+}
+
+/// Loads a user's balance with regeneration applied
+///
+/// # Returns
+/// * `Result<(VoteCreditsData, Option<u64>), String>` - The current balance and the stored
+///   document version (None if the user has no document yet)
+fn load_vote_credits(
+    user_ulid: &str,
+    tag_ulid: &str,
+    config: &VoteCreditsConfig,
+    now: u64,
+) -> Result<(VoteCreditsData, Option<u64>), String> {
+    let key = DocKey::vote_credits(user_ulid, tag_ulid)?.to_string();
+
+    let (stored, version) = match get_doc(VOTE_CREDITS_COLLECTION.to_string(), key.clone()) {
+        Some(doc) => {
+            let data: VoteCreditsData = decode_doc_data(&doc.data)
+                .map_err(|e| format!("[load_vote_credits] Failed to decode credits: key={}, error={}", key, e))?;
+            (data, doc.version)
+        }
+        None => (
+            VoteCreditsData {
+                owner_ulid: user_ulid.to_string(),
+                tag_ulid: tag_ulid.to_string(),
+                balance: config.max_credits,
+                last_regen: now,
+            },
+            None,
+        ),
+    };
+
+    let (balance, last_regen) = regenerate(stored.balance, stored.last_regen, config, now);
+
+    Ok((
+        VoteCreditsData {
+            balance,
+            last_regen,
+            ..stored
+        },
+        version,
+    ))
+}
+
+/// Gets a user's credits status in a tag for a known configuration
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+/// * `tag_ulid` - ULID of the tag
+/// * `config` - The tag's credits configuration (None = credits disabled)
+///
+/// # Returns
+/// * `Result<VoteCreditsStatus, String>` - The credits status or an error message
+pub fn get_vote_credits_status(
+    user_ulid: &str,
+    tag_ulid: &str,
+    config: Option<&VoteCreditsConfig>,
+) -> Result<VoteCreditsStatus, String> {
+    let config = match config {
+        Some(config) => config,
+        None => {
+            return Ok(VoteCreditsStatus {
+                enabled: false,
+                balance: 0,
+                max_credits: 0,
+                positive_vote_cost: 0,
+                negative_vote_cost: 0,
+                next_credit_in_minutes: 0,
+            })
+        }
+    };
+
+    let now = ic_cdk::api::time();
+    let (credits, _) = load_vote_credits(user_ulid, tag_ulid, config, now)?;
+
+    Ok(VoteCreditsStatus {
+        enabled: true,
+        balance: credits.balance,
+        max_credits: config.max_credits,
+        positive_vote_cost: config.positive_vote_cost,
+        negative_vote_cost: config.negative_vote_cost,
+        next_credit_in_minutes: next_credit_in_minutes(credits.balance, credits.last_regen, config, now),
+    })
+}
+
+/// Gets a user's credits status in a tag, loading the tag's configuration
+///
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+/// * `tag_ulid` - ULID of the tag
+///
+/// # Returns
+/// * `Result<VoteCreditsStatus, String>` - The credits status or an error message
+pub async fn get_vote_credits_status_for_tag(user_ulid: &str, tag_ulid: &str) -> Result<VoteCreditsStatus, String> {
+    let tag = get_tag_doc(tag_ulid).await?;
+    get_vote_credits_status(user_ulid, tag_ulid, tag.data.vote_credits.as_ref())
+}
+
+/// Spends the cost of a vote from the author's balance
+///
+/// Called by `validate_vote_document` once every other check has passed. The balance is
+/// written in the same call as the vote, so a failed vote write rolls the debit back.
+///
+/// # Arguments
+/// * `config` - The tag's credits configuration
+/// * `mode` - The tag's voting mode
+/// * `vote` - The vote being written
+/// * `previous` - The stored vote, for edits
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok once the cost is debited, `InsufficientCredits` if the
+///   author can't afford it
+pub fn spend_vote_credits(
+    config: &VoteCreditsConfig,
+    mode: VoteMode,
+    vote: &VoteData,
+    previous: Option<&VoteData>,
+) -> Result<(), KarmieError> {
+    let cost = vote_cost_delta(config, mode, vote, previous);
+    if cost == 0 {
+        return Ok(());
+    }
+
+    let now = ic_cdk::api::time();
+    let (mut credits, version) = load_vote_credits(&vote.owner_ulid, &vote.tag_ulid, config, now)
+        .map_err(|e| KarmieError::storage(VOTE_CREDITS_COLLECTION, e))?;
+
+    if credits.balance < cost {
+        return Err(KarmieError::InsufficientCredits {
+            balance: credits.balance,
+            cost,
+        });
+    }
+    let previous_balance = credits.balance;
+    credits.balance -= cost;
+
+    let key = DocKey::vote_credits(&vote.owner_ulid, &vote.tag_ulid)?.to_string();
+    let doc = SetDoc {
+        data: encode_doc_data(&credits)
+            .map_err(|e| KarmieError::storage(VOTE_CREDITS_COLLECTION, format!("failed to encode credits: {}", e)))?,
+        description: None,
+        version: version.or(Some(0)),
+    };
+
+    set_doc_store(ic_cdk::id(), VOTE_CREDITS_COLLECTION.to_string(), key.clone(), doc)
+        .map_err(|e| {
+            record_store_error(VOTE_CREDITS_COLLECTION, &e);
+            logger!("error", "[spend_vote_credits] Failed to store credits: key={}, error={}", key, e);
+            KarmieError::storage(VOTE_CREDITS_COLLECTION, e)
+        })?;
+
+    logger!("info", "[spend_vote_credits] user={} spent {} credits in tag={}: {} -> {}",
+        vote.owner_ulid, cost, vote.tag_ulid, previous_balance, credits.balance);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = 1_750_000_000_000_000_000;
+
+    fn config() -> VoteCreditsConfig {
+        VoteCreditsConfig {
+            max_credits: 10,
+            regen_minutes: 30,
+            positive_vote_cost: 1,
+            negative_vote_cost: 3,
+        }
+    }
+
+    fn vote(value: f64) -> VoteData {
+        VoteData {
+            owner_ulid: "A".to_string(),
+            target_ulid: "B".to_string(),
+            tag_ulid: "T".to_string(),
+            vote_ulid: "V".to_string(),
+            value,
+            weight: 1.0,
+            intensity: 0,
+        }
+    }
+
+    #[test]
+    fn test_vote_cost_delta() {
+        let config = config();
+        assert_eq!(vote_cost_delta(&config, VoteMode::Standard, &vote(1.0), None), 1);
+        assert_eq!(vote_cost_delta(&config, VoteMode::Standard, &vote(-1.0), None), 3);
+
+        // Upvote -> downvote pays the difference, downvote -> upvote is free
+        assert_eq!(vote_cost_delta(&config, VoteMode::Standard, &vote(-1.0), Some(&vote(1.0))), 2);
+        assert_eq!(vote_cost_delta(&config, VoteMode::Standard, &vote(1.0), Some(&vote(-1.0))), 0);
+    }
+
+    #[test]
+    fn test_regenerate_keeps_partial_progress() {
+        let config = config();
+        let interval = 30 * NANOS_PER_MINUTE;
+
+        // 2.5 intervals later: 2 credits gained, half an interval carried over
+        let (balance, last_regen) = regenerate(4, START, &config, START + interval * 5 / 2);
+        assert_eq!(balance, 6);
+        assert_eq!(last_regen, START + 2 * interval);
+        assert_eq!(next_credit_in_minutes(balance, last_regen, &config, START + interval * 5 / 2), 15);
+    }
+
+    #[test]
+    fn test_regenerate_caps_at_max() {
+        let config = config();
+        let now = START + 1_000 * 30 * NANOS_PER_MINUTE;
+
+        assert_eq!(regenerate(9, START, &config, now), (10, now));
+        assert_eq!(next_credit_in_minutes(10, now, &config, now), 0);
+
+        // Lowering max_credits caps existing balances
+        assert_eq!(regenerate(50, START, &config, START), (10, START));
+    }
+
+    #[test]
+    fn test_no_regeneration() {
+        let config = VoteCreditsConfig { regen_minutes: 0, ..config() };
+        assert_eq!(regenerate(3, START, &config, START + 1_000 * NANOS_PER_MINUTE), (3, START));
+        assert_eq!(next_credit_in_minutes(3, START, &config, START), 0);
+    }
+}
@@ -28,7 +28,8 @@
  *    - Runs AFTER successful document creation/update
  *    - Handles data processing and updates:
  *      - For votes: Recalculates reputation scores
- *      - For users/tags: No additional processing needed
+ *      - For users: Updates the users_handles index
+ *      - For tags: No additional processing needed
  *    - Updates related documents as needed
  *    - Maintains system consistency
  * 
@@ -72,6 +73,11 @@
  * usr_{ulid}_tag_{ulid}_
  * ```
  * 
+ * Delegations Collection:
+ * ```text
+ * usr_{delegatorUlid}_tag_{tagUlid}_del_{delegateUlid}_
+ * ```
+ * 
  * # Key-Based Query System
  * 
  * The system uses key-based queries for efficient data retrieval:
@@ -130,6 +136,7 @@ use junobuild_macros::{
     on_delete_filtered_docs,    // For handling filtered document deletion
     on_delete_many_assets,      // For handling batch asset deletion
     on_delete_many_docs,        // For handling batch document deletion
+    on_post_upgrade,            // For restoring heap state after an upgrade
     on_set_doc,                 // For handling document creation/update
     on_set_many_docs,           // For handling batch document creation/update
     on_upload_asset,            // For handling asset upload
@@ -203,14 +210,78 @@ use junobuild_utils::decode_doc_data;
 
 // Import our utility modules
 use crate::utils::{
-    structs::{VoteData, ReputationData}
+    structs::{VoteData, ReputationData, DelegationData, UserData},
+    errors::KarmieError,
 };
 
+// Import operational metrics
+use crate::utils::metrics::{
+    get_metrics_prometheus_text, get_metrics_snapshot, instruction_counter, record_hook_scheduled,
+    record_hook_success, record_operation, MetricsSnapshot,
+};
+
+// Import the runtime log levels
+use crate::utils::log_config::{get_log_level_config, restore_log_level_config, set_log_level_config, LogLevelConfig};
+
+// Import the persistent log store
+use crate::utils::log_store::{get_log_page, get_log_store_config, restore_log_store_config, set_log_store_config, start_correlation, start_log_pruning, LogFilter, LogPage, LogStoreConfig};
+
 // Import our core modules
+use crate::core::calculate_user_reputation;
+
+// Import budgeted recalculation jobs
+use crate::core::{get_job_list, requeue_jobs, run_steps_within_budget, JobData, JobStep};
+
+// Import vote cooldown status
+use crate::core::{assert_vote_delete_allowed, get_vote_cooldown_status_for_tag, VoteCooldownStatus};
+
+// Import voting ring detection
+use crate::core::{analyze_voting_rings, RingAnalysis};
+
+// Import reputation threshold checks
+use crate::core::{ReputationVerdict, UserIdentifier};
+
+// Import reputation certification
+use crate::core::{get_certified_reputation, rebuild_reputation_tree, restore_reputation_certification, CertifiedReputation};
+
+// Import the event outbox
+use crate::core::{emit_events, get_event_page, start_event_pruning, vote_event, EventPage, EventType};
+
+// Import reputation snapshots
+use crate::core::{assert_snapshot_write_allowed, publish_reputation_snapshots, refresh_stale_snapshots};
+
+// Import delegation
+use crate::core::{list_delegations, recalculate_delegation_targets, DelegationList};
+
+// Import voting credits
+use crate::core::{get_vote_credits_status_for_tag, VoteCreditsStatus};
+
+// Import the users_handles index
+use crate::core::{is_migrating_user, migrate_users_handles, sync_user_handle, HandlesMigrationReport};
+
+// Import handle renames
+use crate::core::{get_handle_history, get_handle_policy, resolve_handle, restore_handle_policy, set_handle_policy, HandlePolicy, HandleResolution};
+use crate::utils::structs::HandleHistoryData;
+
+// Import handle keys audit
+use crate::core::{audit_handle_keys, repair_handle_keys, HandleKeyMismatch, HandleKeysReport};
+
+// Import reserved handles
+use crate::core::{list_reserved_handles, reserve_handle, unreserve_handle};
+use crate::utils::structs::ReservedHandleData;
+
+// Import content moderation
 use crate::core::{
-    calculate_user_reputation,
-    calculate_and_store_vote_weight,
+    dismiss_flagged_content, list_moderation_queue, list_moderation_terms, remove_moderation_term,
+    set_moderation_term,
 };
+use crate::utils::structs::{ModerationQueueData, ModerationTermData};
+
+// Import rate limiting
+use crate::core::{enforce_rate_limit, get_rate_limit_budgets, restore_rate_limits, set_rate_limit_budget, RateLimitBudget};
+
+// Import typed document keys
+use crate::processors::document_keys::DocKey;
 
 // Import username availability checker
 use crate::processors::username_availability::check_username_availability_v2;
@@ -237,33 +308,90 @@ use assert_set_doc::{
     validate_vote_document,
     validate_tag_document,
     validate_reputation_document,
+    validate_delegation_document,
+    validate_users_handles_document,
 };
 
 // =============================================================================
 // Active Hooks and Assertions
 // =============================================================================
 
-/// Handles document updates for users, votes, and tags collections
+/// Restores the heap state that must survive upgrades
+/// 
+/// Juno calls this in a timer right after an upgrade. Settings changed by controllers
+/// are saved to the `settings` collection (see utils/settings.rs) and loaded back here.
+/// The certified reputation tree is rebuilt from the reputations collection.
+#[on_post_upgrade]
+fn on_post_upgrade() {
+    restore_log_level_config();
+    restore_log_store_config();
+    start_log_pruning();
+    logger!("info", "[on_post_upgrade] Restoring settings");
+    restore_rate_limits();
+    restore_handle_policy();
+    restore_reputation_certification();
+    start_event_pruning();
+}
+
+/// Handles document updates for users, votes, tags and delegations collections
 /// 
 /// This function is called by Juno AFTER a document is created or updated.
 /// It handles side effects that should occur after successful document creation:
 /// - For votes: Triggers reputation recalculation
-/// - For users/tags: No side effects needed
+/// - For delegations: Recalculates the targets of every vote the delegated power moved to
+/// - For users: Checks the users_handles index against the user's handle
+/// - For tags: No side effects needed
 /// 
 /// Note: This function does NOT handle validation. All validation is done in assert_set_doc
 /// which runs BEFORE document creation.
-#[on_set_doc(collections = ["users", "votes", "tags"])]
+#[on_set_doc(collections = ["users", "votes", "tags", "delegations"])]
 
 async fn on_set_doc(context: OnSetDocContext) -> Result<(), String> {
+    start_correlation();
+    let start = instruction_counter();
+
+    let result = handle_set_doc(&context).await;
+    if result.is_ok() {
+        // Counted as scheduled by assert_set_doc: a failed hook traps and would lose its own count
+        record_hook_success("on_set_doc", &context.data.collection, start, true);
+    }
+    result
+}
+
+/// Dispatches a written document to its collection's side effects
+async fn handle_set_doc(context: &OnSetDocContext) -> Result<(), String> {
     logger!("debug", "[on_set_doc] on_set_doc triggered");
     
     match context.data.collection.as_str() {
         "votes" => {
             logger!("debug", "[on_set_doc - Votes] Processing New Vote");
-            process_vote(&context).await
+            let start = instruction_counter();
+            let result = process_vote(context).await;
+            record_operation("process_vote", start, result.is_ok());
+            result
+        },
+        "delegations" => {
+            // The parties are part of the key, so an update can't change anything
+            if context.data.data.before.is_some() {
+                return Ok(());
+            }
+            logger!("debug", "[on_set_doc - Delegations] Processing New Delegation");
+            let delegation: DelegationData = decode_doc_data(&context.data.data.after.data)
+                .map_err(|e| e.to_string())?;
+            recalculate_delegation_targets(&delegation).await
+        },
+        "users" => {
+            // The handle was claimed by assert_doc_user, this only repairs the index if needed
+            logger!("debug", "[on_set_doc - Users] Checking users_handles index");
+            let user: UserData = decode_doc_data(&context.data.data.after.data)
+                .map_err(|e| e.to_string())?;
+            match user.user_ulid {
+                Some(user_ulid) => Ok(sync_user_handle(&user_ulid)?),
+                None => Ok(()),
+            }
         },
-        "users" | "tags" => {
-            // No side effects needed for users or tags
+        "tags" => {
+            // No side effects needed for tags
             logger!("debug", "No hooks defined for collection: {}", context.data.collection);
             Ok(())
         }
@@ -277,6 +405,17 @@ async fn on_set_doc(context: OnSetDocContext) -> Result<(), String> {
 }
 
 /// Process a vote document after it has been created or updated
+///
+/// For new votes, the author's vote weight and reputation and the target's reputation
+/// are recalculated in the vote's tag.
+///
+/// For edited votes, `before` and `after` are diffed:
+/// - If nothing that affects reputation changed (value, intensity, tag, target), recalculation is skipped
+/// - Otherwise, both the old and the new (tag, target) pairs are recalculated, and the
+///   author's vote weight and reputation are recalculated in every tag involved
+///
+/// Since reputation is always fully recalculated from the stored votes, recalculating the
+/// old target is what removes the previous effect of the vote.
 async fn process_vote(context: &OnSetDocContext) -> Result<(), String> {
     // Access the vote document metadata directly
     let vote_doc = &context.data.data.after;
@@ -287,9 +426,20 @@ async fn process_vote(context: &OnSetDocContext) -> Result<(), String> {
             logger!("error", "[process_vote] Failed to decode vote data: {}", e);
             e.to_string()
         })?;
+
+    // Decode the previous version of the vote if this is an update
+    let previous_vote: Option<VoteData> = match &context.data.data.before {
+        Some(before_doc) => Some(decode_doc_data(&before_doc.data)
+            .map_err(|e| {
+                logger!("error", "[process_vote] Failed to decode previous vote data: {}", e);
+                e.to_string()
+            })?),
+        None => None,
+    };
     
     // Log the vote details in a human-readable format
-    logger!("info", "[process_vote] Processing new vote: author={} voted {} on target={} in tag={}",
+    logger!("info", "[process_vote] Processing {} vote: author={} voted {} on target={} in tag={}",
+        if previous_vote.is_some() { "edited" } else { "new" },
         vote_data.owner_ulid,
         vote_data.value,
         vote_data.target_ulid,
@@ -302,63 +452,107 @@ async fn process_vote(context: &OnSetDocContext) -> Result<(), String> {
         logger!("error", "[process_vote] {}", err_msg);
         return Err(err_msg.to_string());
     }
+
+    // Collect every (tag, target) pair affected by this write
+    let mut affected: Vec<(String, String)> = vec![(vote_data.tag_ulid.clone(), vote_data.target_ulid.clone())];
+
+    if let Some(previous) = &previous_vote {
+        if previous.value == vote_data.value
+            && previous.intensity == vote_data.intensity
+            && previous.tag_ulid == vote_data.tag_ulid
+            && previous.target_ulid == vote_data.target_ulid
+        {
+            logger!("info", "[process_vote] Vote edited without changes to value, intensity, tag or target, skipping recalculation: author={}, target={}, tag={}",
+                vote_data.owner_ulid, vote_data.target_ulid, vote_data.tag_ulid);
+            return Ok(());
+        }
+
+        logger!("info", "[process_vote] Vote changed: value {} -> {}, intensity {} -> {}, target {} -> {}, tag {} -> {}",
+            previous.value, vote_data.value,
+            previous.intensity, vote_data.intensity,
+            previous.target_ulid, vote_data.target_ulid,
+            previous.tag_ulid, vote_data.tag_ulid
+        );
+
+        let previous_pair = (previous.tag_ulid.clone(), previous.target_ulid.clone());
+        if !previous.tag_ulid.is_empty() && !affected.contains(&previous_pair) {
+            affected.push(previous_pair);
+        }
+    }
+
+    // Record the vote in the event outbox before the reputation changes it causes
+    let event_type = if previous_vote.is_some() { EventType::VoteChanged } else { EventType::VoteCast };
+    emit_events(vec![vote_event(event_type, &vote_data, previous_vote.as_ref())]);
+
+    // The distinct tags involved (one, or two if the tag changed)
+    let mut affected_tags: Vec<String> = Vec::new();
+    for (tag_ulid, _) in &affected {
+        if !affected_tags.contains(tag_ulid) {
+            affected_tags.push(tag_ulid.clone());
+        }
+    }
     
-    // Step 1: Calculate and store the voting user's vote weight
-    logger!("info", "Step 1/3: Calculating vote weight for author: {}", vote_data.owner_ulid);
-    let vote_weight = calculate_and_store_vote_weight(&vote_data.owner_ulid, &vote_data.tag_ulid).await
-        .map_err(|e| {
-            logger!("error", "[process_vote] Failed to calculate vote weight: {}", e);
-            e.to_string()
-        })?;
-    logger!("info", "[process_vote] Step 1/3 COMPLETE: Vote weight for author={}: {}", vote_data.owner_ulid, vote_weight);
-    
-    // Step 2: Calculate reputation for the voting user (author)
-    logger!("info", "[process_vote] Step 2/3: Calculating reputation for author: {}", vote_data.owner_ulid);
-    let author_rep = calculate_user_reputation(&vote_data.owner_ulid, &vote_data.tag_ulid).await
-        .map_err(|e| {
-            logger!("error", "[process_vote] Failed to calculate author reputation: {}", e);
-            e.to_string()
-        })?;
-    logger!("info", "[process_vote] Step 2/3 COMPLETE: Author={}: basisR={}, voteR={}, totalR={}, voting_power={}",
-        vote_data.owner_ulid, 
-        author_rep.reputation_basis,
-        author_rep.reputation_rewards,
-        author_rep.reputation_total_effective,
-        author_rep.has_voting_power
-    );
-    
-    // Step 3: Calculate reputation for the target user
-    logger!("info", "[process_vote] Step 3/3: Calculating reputation for target: {}", vote_data.target_ulid);
-    let target_rep = calculate_user_reputation(&vote_data.target_ulid, &vote_data.tag_ulid).await
+    // Step 1: The voting user's vote weight in every affected tag
+    let mut steps: Vec<JobStep> = affected_tags
+        .iter()
+        .map(|tag_ulid| JobStep::VoteWeight { user_ulid: vote_data.owner_ulid.clone(), tag_ulid: tag_ulid.clone() })
+        .collect();
+
+    // Step 2: The voting user's (author's) reputation in every affected tag
+    steps.extend(affected_tags
+        .iter()
+        .map(|tag_ulid| JobStep::Reputation { user_ulid: vote_data.owner_ulid.clone(), tag_ulid: tag_ulid.clone() }));
+
+    // Step 3: The reputation of every affected target (old and new)
+    steps.extend(affected
+        .iter()
+        .map(|(tag_ulid, target_ulid)| JobStep::Reputation { user_ulid: target_ulid.clone(), tag_ulid: tag_ulid.clone() }));
+
+    // Steps that don't fit in this message's instruction budget continue in a job
+    let deferred = run_steps_within_budget("process_vote", steps).await
         .map_err(|e| {
-            logger!("error", "[process_vote] Failed to calculate target reputation: {}", e);
+            logger!("error", "[process_vote] Failed to recalculate: {}", e);
             e.to_string()
         })?;
-    logger!("info", "[process_vote] Step 3/3 COMPLETE: Target={}: basisR={}, voteR={}, totalR={}, voting_power={}",
-        vote_data.target_ulid, 
-        target_rep.reputation_basis,
-        target_rep.reputation_rewards,
-        target_rep.reputation_total_effective,
-        target_rep.has_voting_power
-    );
+    if deferred > 0 {
+        logger!("info", "[process_vote] Deferred {} recalculation steps to a job", deferred);
+    }
 
-    logger!("info", "[process_vote] Completed - author={}, target={}, tag={}, vote_value={}, vote_weight={}",
+    logger!("info", "[process_vote] Completed - author={}, target={}, tag={}, vote_value={}, recalculated_pairs={}",
         vote_data.owner_ulid, 
         vote_data.target_ulid, 
         vote_data.tag_ulid, 
         vote_data.value, 
-        vote_weight
+        affected.len()
     );
+
+    // Publish the static snapshots of the recalculated tags
+    refresh_stale_snapshots().await;
     
     Ok(())
 }
 
+/// Collections with an `on_set_doc` hook (must match the hook's attribute)
+const ON_SET_DOC_COLLECTIONS: [&str; 4] = ["users", "votes", "tags", "delegations"];
+
+/// Collections with an `on_delete_doc` hook (must match the hook's attribute)
+const ON_DELETE_DOC_COLLECTIONS: [&str; 3] = ["users", "votes", "delegations"];
+
 /// Configuration flag for playground mode
 pub const IS_PLAYGROUND: bool = false;  // Set to false for production
 
 
-#[assert_set_doc(collections = ["users", "votes", "tags", "reputations"])]
+#[assert_set_doc(collections = ["users", "votes", "tags", "reputations", "delegations", "users_handles"])]
 fn assert_set_doc(context: AssertSetDocContext) -> Result<(), String> {
+    start_correlation();
+    let start = instruction_counter();
+
+    // Reject callers over their write budget before running any (expensive) validation
+    enforce_rate_limit(context.caller, &context.data.collection)?;
+
+    // Juno rejects writes with a string, so errors are returned in their
+    // "KARMIE_<CODE>: <message>" form (see utils/errors.rs)
+
     let result = match context.data.collection.as_str() {
         "users" => {
             logger!("debug", "[assert_set_doc] Validating user document: key={}", context.data.key);
@@ -376,11 +570,19 @@ fn assert_set_doc(context: AssertSetDocContext) -> Result<(), String> {
             logger!("debug", "[assert_set_doc] Validating reputation document: key={}", context.data.key);
             validate_reputation_document(&context)
         },
+        "delegations" => {
+            logger!("debug", "[assert_set_doc] Validating delegation document: key={}", context.data.key);
+            validate_delegation_document(&context)
+        },
+        "users_handles" => {
+            logger!("debug", "[assert_set_doc] Validating users_handles document: key={}", context.data.key);
+            validate_users_handles_document(&context)
+        },
         _ => {
             // This should never happen because we're specifying collections in the decorator
             let err_msg = format!("Unexpected collection for validation: {}", context.data.collection);
             logger!("error", "[assert_set_doc] {}", err_msg);
-            Err(err_msg)
+            Err(KarmieError::from(err_msg))
         }
     };
     
@@ -389,8 +591,17 @@ fn assert_set_doc(context: AssertSetDocContext) -> Result<(), String> {
         Ok(_) => logger!("info", "[assert_set_doc] Validation passed for {}", context.data.key),
         Err(e) => logger!("error", "[assert_set_doc] Validation failed for {}: {}", context.data.key, e),
     }
+
+    // Rejected writes roll back every counter, so only accepted writes are measured
+    if result.is_ok() {
+        record_hook_success("assert_set_doc", &context.data.collection, start, false);
+        // Writes made by the satellite itself, like migrations, don't trigger hooks
+        if ON_SET_DOC_COLLECTIONS.contains(&context.data.collection.as_str()) && !is_migrating_user(&context.data.key) {
+            record_hook_scheduled("on_set_doc", &context.data.collection);
+        }
+    }
     
-    result
+    result.map_err(String::from)
 }   
 
 // =============================================================================
@@ -408,19 +619,129 @@ async fn on_set_many_docs(_context: OnSetManyDocsContext) -> Result<(), String>
     Ok(())
 }
 
-#[on_delete_doc]
-async fn on_delete_doc(_context: OnDeleteDocContext) -> Result<(), String> {
-    Ok(())
+/// Handles document deletions for the users, votes and delegations collections
+/// 
+/// - For users: The user's handle is released from the users_handles index
+/// - For votes: The vote's effect is removed by recalculating the author and the target in its tag
+/// - For delegations: Deleting a delegation revokes it, so the delegated power moves back
+///   to the delegator and every affected target is recalculated
+#[on_delete_doc(collections = ["users", "votes", "delegations"])]
+async fn on_delete_doc(context: OnDeleteDocContext) -> Result<(), String> {
+    start_correlation();
+    let start = instruction_counter();
+
+    let result = handle_delete_doc(&context).await;
+    if result.is_ok() {
+        // Counted as scheduled by assert_delete_doc
+        record_hook_success("on_delete_doc", &context.data.collection, start, true);
+    }
+    result
 }
 
-#[on_delete_many_docs]
-async fn on_delete_many_docs(_context: OnDeleteManyDocsContext) -> Result<(), String> {
-    Ok(())
+/// Dispatches a deleted document to its collection's side effects
+async fn handle_delete_doc(context: &OnDeleteDocContext) -> Result<(), String> {
+    let deleted_doc = match &context.data.data {
+        Some(doc) => doc,
+        None => return Ok(()),
+    };
+
+    match context.data.collection.as_str() {
+        "users" => {
+            let user: UserData = decode_doc_data(&deleted_doc.data)
+                .map_err(|e| {
+                    logger!("error", "[on_delete_doc - Users] Failed to decode removed user: {}", e);
+                    e.to_string()
+                })?;
+
+            logger!("info", "[on_delete_doc - Users] User removed: handle={}", user.user_handle);
+            match user.user_ulid {
+                Some(user_ulid) => Ok(sync_user_handle(&user_ulid)?),
+                None => Ok(()),
+            }
+        },
+        "votes" => {
+            let vote: VoteData = decode_doc_data(&deleted_doc.data)
+                .map_err(|e| {
+                    logger!("error", "[on_delete_doc - Votes] Failed to decode removed vote: {}", e);
+                    e.to_string()
+                })?;
+
+            logger!("info", "[on_delete_doc - Votes] Vote removed: author={} on target={} in tag={}",
+                vote.owner_ulid, vote.target_ulid, vote.tag_ulid);
+            emit_events(vec![vote_event(EventType::VoteRemoved, &vote, None)]);
+
+            // Reputation is always recalculated from the stored votes, so this drops the vote's effect
+            let steps = vec![
+                JobStep::VoteWeight { user_ulid: vote.owner_ulid.clone(), tag_ulid: vote.tag_ulid.clone() },
+                JobStep::Reputation { user_ulid: vote.owner_ulid.clone(), tag_ulid: vote.tag_ulid.clone() },
+                JobStep::Reputation { user_ulid: vote.target_ulid.clone(), tag_ulid: vote.tag_ulid.clone() },
+            ];
+            run_steps_within_budget("on_delete_doc", steps).await?;
+            refresh_stale_snapshots().await;
+            Ok(())
+        },
+        "delegations" => {
+            let delegation: DelegationData = decode_doc_data(&deleted_doc.data)
+                .map_err(|e| {
+                    logger!("error", "[on_delete_doc - Delegations] Failed to decode revoked delegation: {}", e);
+                    e.to_string()
+                })?;
+
+            logger!("info", "[on_delete_doc - Delegations] Delegation revoked: {} -> {} in tag={}",
+                delegation.delegator_ulid, delegation.delegate_ulid, delegation.tag_ulid);
+
+            recalculate_delegation_targets(&delegation).await
+        },
+        _ => {
+            // This should never happen because we're specifying collections in the decorator
+            let err_msg = format!("Unknown collection: {}", context.data.collection);
+            logger!("error", "[on_delete_doc] {}", err_msg);
+            Err(err_msg)
+        }
+    }
 }
 
-#[on_delete_filtered_docs]
-async fn on_delete_filtered_docs(_context: OnDeleteFilteredDocsContext) -> Result<(), String> {
-    Ok(())
+/// Handles batch deletions (`delete_many_docs`) like single ones, see `on_delete_doc`
+///
+/// Without it, deleting users in a batch would leave their handles held in the
+/// users_handles index, and deleted votes and delegations would keep counting.
+#[on_delete_many_docs(collections = ["users", "votes", "delegations"])]
+async fn on_delete_many_docs(context: OnDeleteManyDocsContext) -> Result<(), String> {
+    handle_delete_docs("on_delete_many_docs", context).await
+}
+
+/// Handles filtered deletions (`delete_filtered_docs`) like single ones, see `on_delete_doc`
+#[on_delete_filtered_docs(collections = ["users", "votes", "delegations"])]
+async fn on_delete_filtered_docs(context: OnDeleteFilteredDocsContext) -> Result<(), String> {
+    handle_delete_docs("on_delete_filtered_docs", context).await
+}
+
+/// Runs the `on_delete_doc` side effects of every document of a batch
+///
+/// A failing document doesn't stop the others, the first error is returned at the end.
+async fn handle_delete_docs(hook: &str, context: OnDeleteManyDocsContext) -> Result<(), String> {
+    start_correlation();
+    let caller = context.caller;
+    let mut first_error: Option<String> = None;
+
+    for doc in context.data {
+        let start = instruction_counter();
+        let doc_context = OnDeleteDocContext { caller, data: doc };
+        match handle_delete_doc(&doc_context).await {
+            // Counted as scheduled by assert_delete_doc, which runs for each document of a batch
+            Ok(()) => record_hook_success("on_delete_doc", &doc_context.data.collection, start, true),
+            Err(e) => {
+                logger!("error", "[{}] Failed to process deletion of {} in {}: {}",
+                    hook, doc_context.data.key, doc_context.data.collection, e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
 }
 
 // Asset Management Hooks
@@ -449,19 +770,47 @@ async fn on_delete_filtered_assets(_context: OnDeleteFilteredAssetsContext) -> R
 // Additional Assertions
 // -------------------
 
+/// Counts the `on_delete_doc` invocations scheduled by accepted deletions (see utils/metrics.rs)
 #[assert_delete_doc]
-fn assert_delete_doc(_context: AssertDeleteDocContext) -> Result<(), String> {
+fn assert_delete_doc(context: AssertDeleteDocContext) -> Result<(), String> {
+    // Deleting a vote in its cooldown would let the author vote on the target again right away
+    if context.data.collection == "votes" {
+        if let Some(vote_doc) = &context.data.data.current {
+            assert_vote_delete_allowed(context.caller, vote_doc)
+                .map_err(|e| {
+                    logger!("error", "[assert_delete_doc] Vote deletion rejected for {}: {}", context.data.key, e);
+                    String::from(e)
+                })?;
+        }
+    }
+
+    if ON_DELETE_DOC_COLLECTIONS.contains(&context.data.collection.as_str()) && !is_migrating_user(&context.data.key) {
+        record_hook_scheduled("on_delete_doc", &context.data.collection);
+    }
     Ok(())
 }
 
+/// Rejects uploads into the reputation snapshots collection
+///
+/// Snapshots are rendered and uploaded by the satellite itself (see core/reputation_snapshots.rs),
+/// users must not be able to forge them.
 #[assert_upload_asset]
-fn assert_upload_asset(_context: AssertUploadAssetContext) -> Result<(), String> {
-    Ok(())
+fn assert_upload_asset(context: AssertUploadAssetContext) -> Result<(), String> {
+    assert_snapshot_write_allowed(context.caller, &context.data.batch.key.collection)
+        .map_err(|e| {
+            logger!("error", "[assert_upload_asset] {}", e);
+            e.to_string()
+        })
 }
 
+/// Rejects deletions in the reputation snapshots collection
 #[assert_delete_asset]
-fn assert_delete_asset(_context: AssertDeleteAssetContext) -> Result<(), String> {
-    Ok(())
+fn assert_delete_asset(context: AssertDeleteAssetContext) -> Result<(), String> {
+    assert_snapshot_write_allowed(context.caller, &context.data.key.collection)
+        .map_err(|e| {
+            logger!("error", "[assert_delete_asset] {}", e);
+            e.to_string()
+        })
 }
 
 
@@ -484,7 +833,7 @@ fn assert_delete_asset(_context: AssertDeleteAssetContext) -> Result<(), String>
 /// * `tag_key` - The unique identifier of the tag
 /// 
 /// # Returns
-/// * `Result<f64, String>` - The user's last known reputation score or a detailed error message
+/// * `Result<f64, KarmieError>` - The user's last known reputation score or a detailed error message
 /// 
 /// # Errors
 /// - Returns error if user_key or tag_key is empty
@@ -492,33 +841,35 @@ fn assert_delete_asset(_context: AssertDeleteAssetContext) -> Result<(), String>
 /// - Returns error if user has no reputation in this tag
 
 #[query]
-async fn get_user_reputation(user_key: String, tag_key: String) -> Result<f64, String> {
+async fn get_user_reputation(user_key: String, tag_key: String) -> Result<f64, KarmieError> {
     logger!("debug", "[get_user_reputation] Fetching reputation for user={}, tag={}", user_key, tag_key);
     
     // Input validation
     if user_key.is_empty() {
-        let err_msg = "[get_user_reputation] User key cannot be empty";
-        logger!("error", "{}", err_msg);
-        return Err(err_msg.to_string());
+        logger!("error", "[get_user_reputation] User key cannot be empty");
+        return Err(KarmieError::invalid_field("user_key", "cannot be empty"));
     }
     if tag_key.is_empty() {
-        let err_msg = "[get_user_reputation] Tag key cannot be empty";
-        logger!("error", "{}", err_msg);
-        return Err(err_msg.to_string());
+        logger!("error", "[get_user_reputation] Tag key cannot be empty");
+        return Err(KarmieError::invalid_field("tag_key", "cannot be empty"));
     }
 
     // Check if user has reputation in this tag
-    let reputation_key = format!("{}_{}", user_key, tag_key);
+    let reputation_key = DocKey::reputation(&user_key, &tag_key)
+        .map_err(|e| {
+            logger!("error", "[get_user_reputation] Invalid reputation key: {}", e);
+            e
+        })?;
     let reputation_doc = junobuild_satellite::get_doc(
         String::from("reputations"),  // Collection name first
-        reputation_key,               // Document key second
+        reputation_key.to_string(),   // Document key second
     );
     
     // Match on the result instead of using map_err
     match reputation_doc {
         Some(doc) => {
             let reputation_data: ReputationData = decode_doc_data(&doc.data)
-                .map_err(|e| format!("Failed to decode reputation data: {}", e))?;
+                .map_err(|e| KarmieError::invalid_data("reputations", e))?;
     
             logger!("info", "[get_user_reputation] Successfully retrieved reputation: user={}, tag={}, value={}", 
                 user_key, tag_key, reputation_data.reputation_total_effective);
@@ -526,9 +877,8 @@ async fn get_user_reputation(user_key: String, tag_key: String) -> Result<f64, S
             Ok(reputation_data.reputation_total_effective)
         },
         None => {
-            let err_msg = format!("[get_user_reputation] User {} has no reputation in tag {}", user_key, tag_key);
-            logger!("error", "{}", err_msg);
-            Err(err_msg)
+            logger!("error", "[get_user_reputation] User {} has no reputation in tag {}", user_key, tag_key);
+            Err(KarmieError::not_found("Reputation", &reputation_key.to_string()))
         }
     }
 }
@@ -546,49 +896,50 @@ async fn get_user_reputation(user_key: String, tag_key: String) -> Result<f64, S
 /// * `tag_key` - The unique identifier of the tag
 /// 
 /// # Returns
-/// * `Result<ReputationData, String>` - The complete reputation data or a detailed error message
+/// * `Result<ReputationData, KarmieError>` - The complete reputation data or a detailed error message
 /// 
 /// # Errors
 /// - Returns error if user_key or tag_key is empty
 /// - Returns error if tag doesn't exist
 /// - Returns error if user has no reputation in this tag
 #[query]
-async fn get_user_reputation_full(user_key: String, tag_key: String) -> Result<ReputationData, String> {
+async fn get_user_reputation_full(user_key: String, tag_key: String) -> Result<ReputationData, KarmieError> {
     logger!("debug", "[get_user_reputation_full] Fetching complete reputation data for user={}, tag={}", user_key, tag_key);
     
     // Input validation
     if user_key.is_empty() {
-        let err_msg = "[get_user_reputation_full] User key cannot be empty";
-        logger!("error", "{}", err_msg);
-        return Err(err_msg.to_string());
+        logger!("error", "[get_user_reputation_full] User key cannot be empty");
+        return Err(KarmieError::invalid_field("user_key", "cannot be empty"));
     }
     if tag_key.is_empty() {
-        let err_msg = "[get_user_reputation_full] Tag key cannot be empty";
-        logger!("error", "{}", err_msg);
-        return Err(err_msg.to_string());
+        logger!("error", "[get_user_reputation_full] Tag key cannot be empty");
+        return Err(KarmieError::invalid_field("tag_key", "cannot be empty"));
     }
 
     // Check if user has reputation in this tag
-    let reputation_key = format!("{}_{}", user_key, tag_key);
+    let reputation_key = DocKey::reputation(&user_key, &tag_key)
+        .map_err(|e| {
+            logger!("error", "[get_user_reputation_full] Invalid reputation key: {}", e);
+            e
+        })?;
     let reputation_doc = junobuild_satellite::get_doc(
         String::from("reputations"),  // Collection name first
-        reputation_key,               // Document key second
+        reputation_key.to_string(),   // Document key second
     );
     
     // Match on the result instead of using map_err
     match reputation_doc {
         Some(doc) => {
             let reputation_data: ReputationData = decode_doc_data(&doc.data)
-                .map_err(|e| format!("Failed to decode reputation data: {}", e))?;
+                .map_err(|e| KarmieError::invalid_data("reputations", e))?;
     
             logger!("info", "[get_user_reputation_full] Successfully retrieved complete reputation data: user={}, tag={}", user_key, tag_key);
     
             Ok(reputation_data)
         },
         None => {
-            let err_msg = format!("[get_user_reputation_full] User {} has no reputation in tag {}", user_key, tag_key);
-            logger!("error", "{}", err_msg);
-            Err(err_msg)
+            logger!("error", "[get_user_reputation_full] User {} has no reputation in tag {}", user_key, tag_key);
+            Err(KarmieError::not_found("Reputation", &reputation_key.to_string()))
         }
     }
 }
@@ -615,7 +966,7 @@ async fn get_user_reputation_full(user_key: String, tag_key: String) -> Result<R
 /// * `tag_key` - The unique identifier of the tag
 ///
 /// # Returns
-/// * `Result<f64, String>` - The updated effective reputation score or a detailed error message
+/// * `Result<f64, KarmieError>` - The updated effective reputation score or a detailed error message
 /// 
 /// # Errors
 /// - Returns error if user_key or tag_key is empty
@@ -624,27 +975,27 @@ async fn get_user_reputation_full(user_key: String, tag_key: String) -> Result<R
 /// - Returns error with context about what specifically failed
 #[ic_cdk::update]
 #[candid::candid_method(update)]
-pub async fn recalculate_reputation(user_key: String, tag_key: String) -> Result<f64, String> {
+pub async fn recalculate_reputation(user_key: String, tag_key: String) -> Result<f64, KarmieError> {
     logger!("debug", "[recalculate_reputation] Starting recalculation for user={}, tag={}", user_key, tag_key);
     
     // Input validation
     if user_key.is_empty() {
-        let err_msg = "[recalculate_reputation] User key cannot be empty";
-        logger!("error", "{}", err_msg);
-        return Err(err_msg.to_string());
+        logger!("error", "[recalculate_reputation] User key cannot be empty");
+        return Err(KarmieError::invalid_field("user_key", "cannot be empty"));
     }
     if tag_key.is_empty() {
-        let err_msg = "[recalculate_reputation] Tag key cannot be empty";
-        logger!("error", "{}", err_msg);
-        return Err(err_msg.to_string());
+        logger!("error", "[recalculate_reputation] Tag key cannot be empty");
+        return Err(KarmieError::invalid_field("tag_key", "cannot be empty"));
     }
 
     // Attempt to calculate reputation
-    let reputation_data = calculate_user_reputation(&user_key, &tag_key).await
+    let start = instruction_counter();
+    let result = calculate_user_reputation(&user_key, &tag_key).await;
+    record_operation("recalculate_reputation", start, result.is_ok());
+    let reputation_data = result
         .map_err(|e| {
-            let err_msg = format!("[recalculate_reputation] Failed to calculate reputation: {}", e);
-            logger!("error", "{}", err_msg);
-            err_msg
+            logger!("error", "[recalculate_reputation] Failed to calculate reputation: {}", e);
+            e
         })?;
     
     logger!("info", "[recalculate_reputation] Successfully recalculated reputation: user={}, tag={}, value={}", 
@@ -652,17 +1003,17 @@ pub async fn recalculate_reputation(user_key: String, tag_key: String) -> Result
         tag_key, 
         reputation_data.reputation_total_effective
     );
+
+    refresh_stale_snapshots().await;
     
     Ok(reputation_data.reputation_total_effective)
 }
 
-/// Username availability check using full collection scan approach
+/// Checks whether a username is available
 /// 
-/// This endpoint demonstrates the new approach to username availability checking
-/// by filtering documents by their data.user_handle field instead of using key patterns.
-/// 
-/// **Performance Testing**: Use this endpoint to compare performance against the
-/// current handle-in-key approach in your frontend implementations.
+/// Looks the handle up in the users_handles index, and in the keys of the users that
+/// haven't been migrated to it yet. Handles quarantined after a rename, look-alikes of taken
+/// handles, and handles reserved for someone other than the caller are reported as taken.
 /// 
 /// **Note**: This is a query function for optimal performance. Logs from this function
 /// won't appear in Juno logs report since query functions run in a sandboxed environment.
@@ -671,10 +1022,199 @@ pub async fn recalculate_reputation(user_key: String, tag_key: String) -> Result
 /// * `username` - The username to check for availability
 /// 
 /// # Returns
-/// * `Result<bool, String>` - Returns true if available, false if taken
+/// * `Result<bool, KarmieError>` - Returns true if available, false if taken
+#[query]
+pub async fn check_username_availability(username: String) -> Result<bool, KarmieError> {
+    let start = instruction_counter();
+    let result = check_username_availability_v2(username).await;
+    record_operation("check_username_availability", start, result.is_ok());
+    Ok(result?)
+}
+
+/// Moves a batch of users created before the users_handles index to the new key format (controllers only)
+/// 
+/// Call it until the report's `remaining` is 0.
+/// 
+/// # Returns
+/// * `Result<HandlesMigrationReport, KarmieError>` - The batch outcome, or Err if the caller is not a controller
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn migrate_users_to_handles_index() -> Result<HandlesMigrationReport, KarmieError> {
+    migrate_users_handles(ic_cdk::caller())
+}
+
+/// Lists the documents whose key doesn't match the canonical form of their handle (controllers only)
+/// 
+/// Covers legacy users keys, the users_handles index and history, and tags.
+/// 
+/// # Returns
+/// * `Result<Vec<HandleKeyMismatch>, KarmieError>` - The mismatches, or Err if the caller is not a controller
+#[query]
+fn get_handle_key_mismatches() -> Result<Vec<HandleKeyMismatch>, KarmieError> {
+    audit_handle_keys(ic_cdk::caller())
+}
+
+/// Moves a batch of documents to the key matching the canonical form of their handle (controllers only)
+/// 
+/// Call it until the report's `remaining` is 0.
+/// 
+/// # Returns
+/// * `Result<HandleKeysReport, KarmieError>` - The batch outcome, or Err if the caller is not a controller
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn repair_handle_key_mismatches() -> Result<HandleKeysReport, KarmieError> {
+    repair_handle_keys(ic_cdk::caller())
+}
+
+/// Resolves a handle to a user, following renames
+/// 
+/// A handle nobody holds anymore resolves to the user who released it last, so that
+/// profile links keep working after a rename. Clients should redirect to `current_handle`
+/// when `redirected` is true.
+/// 
+/// # Arguments
+/// * `handle` - The handle to resolve, current or old
+/// 
+/// # Returns
+/// * `Result<HandleResolution, KarmieError>` - The user, or `NotFound` if no user ever had the handle
+#[query]
+fn resolve_user_handle(handle: String) -> Result<HandleResolution, KarmieError> {
+    resolve_handle(&handle)
+}
+
+/// Lists the handles a user released, most recent first
+/// 
+/// # Arguments
+/// * `user_ulid` - The ULID of the user
+/// 
+/// # Returns
+/// * `Result<Vec<HandleHistoryData>, KarmieError>` - The released handles, with when and what they were renamed to
+#[query]
+fn get_user_handle_history(user_ulid: String) -> Result<Vec<HandleHistoryData>, KarmieError> {
+    crate::processors::ulid_generator::validate_ulid(&user_ulid)?;
+    get_handle_history(&user_ulid)
+}
+
+/// Gets the handle rename rules: quarantine length and rename rate limit
+/// 
+/// # Returns
+/// * `HandlePolicy` - The current rules
+#[query]
+fn get_handle_rename_policy() -> HandlePolicy {
+    get_handle_policy()
+}
+
+/// Changes the handle rename rules (controllers only)
+/// 
+/// Rules are saved to the `settings` collection and survive upgrades.
+/// 
+/// # Arguments
+/// * `policy` - The quarantine length and the rename rate limit
+/// 
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if updated, Err if the caller is not a controller or the policy is invalid
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn set_handle_rename_policy(policy: HandlePolicy) -> Result<(), KarmieError> {
+    set_handle_policy(ic_cdk::caller(), policy)
+}
+
+/// Reserves or blocks a user and tag handle, with its look-alikes (controllers only)
+/// 
+/// A handle reserved for a principal can only be taken by that principal; a handle
+/// reserved for nobody is blocked. Replaces the reservation of a look-alike handle, if any.
+/// 
+/// # Arguments
+/// * `reservation` - The handle, who it is reserved for and why
+/// 
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if stored, Err if the caller is not a controller or the reservation is invalid
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn reserve_user_handle(reservation: ReservedHandleData) -> Result<(), KarmieError> {
+    reserve_handle(ic_cdk::caller(), reservation)
+}
+
+/// Removes the reservation of a handle (controllers only)
+/// 
+/// # Arguments
+/// * `handle` - The reserved handle, or a look-alike of it
+/// 
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if removed, `NotFound` if the handle wasn't reserved
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn unreserve_user_handle(handle: String) -> Result<(), KarmieError> {
+    unreserve_handle(ic_cdk::caller(), &handle)
+}
+
+/// Lists the handles reserved by controllers (controllers only)
+/// 
+/// Built-in reserved handles such as "admin" are not listed.
+/// 
+/// # Returns
+/// * `Result<Vec<ReservedHandleData>, KarmieError>` - The reservations, or Err if the caller is not a controller
+#[query]
+fn get_reserved_handles() -> Result<Vec<ReservedHandleData>, KarmieError> {
+    list_reserved_handles(ic_cdk::caller())
+}
+
+/// Adds or replaces a content moderation term (controllers only)
+/// 
+/// # Arguments
+/// * `term` - The term, whether it rejects or flags content, and whether it matches inside words
+/// 
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if stored, Err if the caller is not a controller or the term is empty
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn save_moderation_term(term: ModerationTermData) -> Result<(), KarmieError> {
+    set_moderation_term(ic_cdk::caller(), term)
+}
+
+/// Removes a content moderation term (controllers only)
+/// 
+/// # Arguments
+/// * `term` - The term, or any spelling of it with the same folded form
+/// 
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if removed, `NotFound` if there was no such term
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn delete_moderation_term(term: String) -> Result<(), KarmieError> {
+    remove_moderation_term(ic_cdk::caller(), &term)
+}
+
+/// Lists the content moderation terms (controllers only)
+/// 
+/// # Returns
+/// * `Result<Vec<ModerationTermData>, KarmieError>` - The terms, or Err if the caller is not a controller
 #[query]
-pub async fn check_username_availability_scan(username: String) -> Result<bool, String> {
-    check_username_availability_v2(username).await
+fn get_moderation_terms() -> Result<Vec<ModerationTermData>, KarmieError> {
+    list_moderation_terms(ic_cdk::caller())
+}
+
+/// Lists the user and tag documents flagged for review (controllers only)
+/// 
+/// # Returns
+/// * `Result<Vec<ModerationQueueData>, KarmieError>` - The flagged documents, oldest first
+#[query]
+fn get_moderation_queue() -> Result<Vec<ModerationQueueData>, KarmieError> {
+    list_moderation_queue(ic_cdk::caller())
+}
+
+/// Removes a reviewed document from the moderation queue (controllers only)
+/// 
+/// # Arguments
+/// * `collection` - Collection of the flagged document, "users" or "tags"
+/// * `doc_key` - Key of the flagged document
+/// 
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if removed, `NotFound` if the document wasn't queued
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn dismiss_moderation_entry(collection: String, doc_key: String) -> Result<(), KarmieError> {
+    dismiss_flagged_content(ic_cdk::caller(), &collection, &doc_key)
 }
 
 /// Creates a document key using the new ULID-based format
@@ -682,14 +1222,15 @@ pub async fn check_username_availability_scan(username: String) -> Result<bool,
 /// to generate properly formatted keys according to the schema
 ///
 /// # Arguments
-/// * `username` - The username to use in the key
+/// * `principal` - The principal of the user
 ///
 /// # Returns
-/// * `Result<String, String>` - The formatted key or an error
+/// * `Result<String, KarmieError>` - The formatted key or an error
 #[query]
-pub async fn create_document_key_for_user(principal: String, username: String) -> Result<String, String> {
+pub async fn create_document_key_for_user(principal: String) -> Result<String, KarmieError> {
     // Use our document_keys module to create a properly formatted key
-    crate::processors::document_keys::create_user_key(&principal, &username).await
+    crate::processors::document_keys::create_user_key(&principal).await
+        .map(|key| key.to_string())
 }
 
 /// Creates a document key for a tag using the new ULID-based format
@@ -699,11 +1240,12 @@ pub async fn create_document_key_for_user(principal: String, username: String) -
 /// * `tag_name` - The name of the tag
 ///
 /// # Returns
-/// * `Result<String, String>` - The formatted key or an error
+/// * `Result<String, KarmieError>` - The formatted key or an error
 #[query]
-pub async fn create_document_key_for_tag(user_ulid: String, tag_name: String) -> Result<String, String> {
+pub async fn create_document_key_for_tag(user_ulid: String, tag_name: String) -> Result<String, KarmieError> {
     // Use our document_keys module to create a properly formatted key
     crate::processors::document_keys::create_tag_key(&user_ulid, &tag_name).await
+        .map(|key| key.to_string())
 }
 
 /// Creates a document key for a reputation entry using the new ULID-based format
@@ -713,11 +1255,11 @@ pub async fn create_document_key_for_tag(user_ulid: String, tag_name: String) ->
 /// * `tag_ulid` - The ULID of the tag
 ///
 /// # Returns
-/// * `Result<String, String>` - The formatted key or an error
+/// * `Result<String, KarmieError>` - The formatted key or an error
 #[query]
-pub fn create_document_key_for_reputation(user_ulid: String, tag_ulid: String) -> Result<String, String> {
+pub fn create_document_key_for_reputation(user_ulid: String, tag_ulid: String) -> Result<String, KarmieError> {
     // Use our document_keys module to create a properly formatted key
-    crate::processors::document_keys::format_reputation_key(&user_ulid, &tag_ulid)
+    DocKey::reputation(&user_ulid, &tag_ulid).map(|key| key.to_string())
 }
 
 /// Creates a document key for a vote using the new ULID-based format
@@ -728,45 +1270,34 @@ pub fn create_document_key_for_reputation(user_ulid: String, tag_ulid: String) -
 /// * `target_ulid` - The ULID of the user receiving the vote
 ///
 /// # Returns
-/// * `Result<String, String>` - The formatted key or an error
+/// * `Result<String, KarmieError>` - The formatted key or an error
 #[query]
 pub async fn create_document_key_for_vote(
     user_ulid: String, 
     tag_ulid: String, 
     target_ulid: String
-) -> Result<String, String> {
+) -> Result<String, KarmieError> {
     // Use our document_keys module to create a properly formatted key
     crate::processors::document_keys::create_vote_key(&user_ulid, &tag_ulid, &target_ulid, None).await
+        .map(|key| key.to_string())
 }
 
 /// Validates that a document key is properly formatted
 ///
 /// # Arguments
 /// * `key` - The document key to validate
-/// * `doc_type` - The document type ("user", "tag", "reputation", or "vote")
+/// * `doc_type` - The document type ("user", "tag", "reputation", "vote" or "delegation")
 ///
 /// # Returns
-/// * `Result<bool, String>` - Ok(true) if valid, Err with message if invalid
+/// * `Result<bool, KarmieError>` - Ok(true) if valid, Err with message if invalid
 #[query]
-pub fn validate_document_key(key: String, doc_type: String) -> Result<bool, String> {
+pub fn validate_document_key(key: String, doc_type: String) -> Result<bool, KarmieError> {
     match doc_type.as_str() {
-        "user" => {
-            crate::processors::document_keys::validate_user_key(&key)?;
+        "user" | "tag" | "reputation" | "vote" | "delegation" => {
+            DocKey::parse_kind(&key, &doc_type)?;
             Ok(true)
         },
-        "tag" => {
-            crate::processors::document_keys::validate_tag_key(&key)?;
-            Ok(true)
-        },
-        "reputation" => {
-            crate::processors::document_keys::validate_reputation_key(&key)?;
-            Ok(true)
-        },
-        "vote" => {
-            crate::processors::document_keys::validate_vote_key(&key)?;
-            Ok(true)
-        },
-        _ => Err(format!("Unknown document type: {}", doc_type))
+        _ => Err(KarmieError::invalid_field("doc_type", format!("Unknown document type: {}", doc_type)))
     }
 }
 
@@ -782,10 +1313,347 @@ pub fn validate_document_key(key: String, doc_type: String) -> Result<bool, Stri
 /// * `query_type` - The query type: "tag", "user", or "all"
 /// 
 /// # Returns
-/// * `Result<GraphData, String>` - Graph data ready for Sigma.js visualization
+/// * `Result<GraphData, KarmieError>` - Graph data ready for Sigma.js visualization
+#[query]
+pub async fn get_graph_data(ulid: String, query_type: String) -> Result<GraphData, KarmieError> {
+    let start = instruction_counter();
+    let result = graph_processors::get_graph_data(ulid, query_type).await;
+    record_operation("get_graph_data", start, result.is_ok());
+    Ok(result?)
+}
+
+/// Gets the remaining vote cooldown between an author and a target in a tag
+/// 
+/// Clients use this to disable vote buttons and show the remaining time
+/// before the author can vote on the target again.
+/// 
+/// # Arguments
+/// * `author_ulid` - The ULID of the user who wants to vote
+/// * `target_ulid` - The ULID of the user to be voted on
+/// * `tag_ulid` - The ULID of the tag
+/// 
+/// # Returns
+/// * `Result<VoteCooldownStatus, KarmieError>` - The tag's cooldown, minutes remaining (0 = can vote) and last vote time
+#[query]
+pub fn get_vote_cooldown(author_ulid: String, target_ulid: String, tag_ulid: String) -> Result<VoteCooldownStatus, KarmieError> {
+    get_vote_cooldown_status_for_tag(&author_ulid, &target_ulid, &tag_ulid)
+}
+
+/// Lists the per-collection write budgets used by the rate limiter
+/// 
+/// Each budget allows `max_requests` writes per principal within a sliding
+/// window of `window_seconds`. Collections not listed are not limited.
+/// 
+/// # Returns
+/// * `Vec<(String, RateLimitBudget)>` - (collection, budget) pairs sorted by collection
+#[query]
+pub fn get_rate_limits() -> Vec<(String, RateLimitBudget)> {
+    get_rate_limit_budgets()
+}
+
+/// Sets or removes the write budget of a collection (controllers only)
+/// 
+/// Budgets are saved to the `settings` collection and survive upgrades.
+/// 
+/// # Arguments
+/// * `collection` - The collection to configure (e.g. "votes")
+/// * `budget` - The new budget, or None to stop limiting the collection
+/// 
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if updated, Err if the caller is not a controller or the budget is invalid
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+pub fn set_rate_limit(collection: String, budget: Option<RateLimitBudget>) -> Result<(), KarmieError> {
+    set_rate_limit_budget(ic_cdk::caller(), &collection, budget)
+}
+
+/// Analyzes a tag for reciprocal voting and voting rings (controllers only)
+/// 
+/// Finds clusters of users who upvote each other and scores them on reciprocity,
+/// clique density and account creation clustering. See core/ring_detection.rs.
+/// 
+/// # Arguments
+/// * `tag_ulid` - ULID of the tag to analyze
+/// 
+/// # Returns
+/// * `Result<RingAnalysis, KarmieError>` - The flagged clusters or an error message
+#[query]
+pub async fn get_voting_rings(tag_ulid: String) -> Result<RingAnalysis, KarmieError> {
+    analyze_voting_rings(ic_cdk::caller(), &tag_ulid).await
+}
+
+/// Gets a user's voting credits in a tag
+/// 
+/// Returns `enabled: false` if the tag doesn't use voting credits.
+/// 
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+/// * `tag_ulid` - ULID of the tag
+/// 
+/// # Returns
+/// * `Result<VoteCreditsStatus, KarmieError>` - Current balance, costs and regeneration status
+#[query]
+pub async fn get_vote_credits(user_ulid: String, tag_ulid: String) -> Result<VoteCreditsStatus, KarmieError> {
+    Ok(get_vote_credits_status_for_tag(&user_ulid, &tag_ulid).await?)
+}
+
+/// Lists the delegations a user has given and received
+/// 
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+/// * `tag_ulid` - Optional ULID of a tag to restrict the list to
+/// 
+/// # Returns
+/// * `Result<DelegationList, KarmieError>` - Incoming (direct only) and outgoing delegations
+#[query]
+pub fn get_delegations(user_ulid: String, tag_ulid: Option<String>) -> Result<DelegationList, KarmieError> {
+    list_delegations(&user_ulid, tag_ulid.as_deref())
+}
+
+/// Checks whether a user has at least `min_score` reputation in a tag
+/// 
+/// Intended for integrators (airdrop tools, marketplaces, ...) that only need a yes/no
+/// answer. The verdict applies the tag's bootstrap rules, so `has_voting_power` is
+/// true for every user while the tag is bootstrapping.
+/// 
+/// # Arguments
+/// * `user` - The user's ULID or principal
+/// * `tag_ulid` - ULID of the tag
+/// * `min_score` - Minimum effective reputation required
+/// * `require_voting_power` - Whether the user must also have voting power
+/// 
+/// # Returns
+/// * `Result<ReputationVerdict, KarmieError>` - The verdict, including the bootstrap status
+#[query]
+pub async fn check_reputation(
+    user: UserIdentifier,
+    tag_ulid: String,
+    min_score: f64,
+    require_voting_power: bool,
+) -> Result<ReputationVerdict, KarmieError> {
+    let start = instruction_counter();
+    let result = crate::core::check_reputation(user, &tag_ulid, min_score, require_voting_power).await;
+    record_operation("check_reputation", start, result.is_ok());
+    result
+}
+
+/// Checks a batch of users (up to 100) against a reputation threshold in a tag
+/// 
+/// # Arguments
+/// * `users` - The users' ULIDs or principals
+/// * `tag_ulid` - ULID of the tag
+/// * `min_score` - Minimum effective reputation required
+/// * `require_voting_power` - Whether the users must also have voting power
+/// 
+/// # Returns
+/// * `Result<Vec<ReputationVerdict>, KarmieError>` - One verdict per user, in order
 #[query]
-pub async fn get_graph_data(ulid: String, query_type: String) -> Result<GraphData, String> {
-    graph_processors::get_graph_data(ulid, query_type).await
+pub async fn check_reputation_batch(
+    users: Vec<UserIdentifier>,
+    tag_ulid: String,
+    min_score: f64,
+    require_voting_power: bool,
+) -> Result<Vec<ReputationVerdict>, KarmieError> {
+    let start = instruction_counter();
+    let result = crate::core::check_reputation_batch(&users, &tag_ulid, min_score, require_voting_power).await;
+    record_operation("check_reputation_batch", start, result.is_ok());
+    result
+}
+
+/// Inter-canister equivalent of `check_reputation_batch`
+/// 
+/// Runs as an update call, so the verdict goes through consensus and can be
+/// trusted by the calling canister without relying on a single replica.
+/// 
+/// # Arguments
+/// * `users` - The users' ULIDs or principals
+/// * `tag_ulid` - ULID of the tag
+/// * `min_score` - Minimum effective reputation required
+/// * `require_voting_power` - Whether the users must also have voting power
+/// 
+/// # Returns
+/// * `Result<Vec<ReputationVerdict>, KarmieError>` - One verdict per user, in order
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+pub async fn check_reputation_update(
+    users: Vec<UserIdentifier>,
+    tag_ulid: String,
+    min_score: f64,
+    require_voting_power: bool,
+) -> Result<Vec<ReputationVerdict>, KarmieError> {
+    let start = instruction_counter();
+    let result = crate::core::check_reputation_batch(&users, &tag_ulid, min_score, require_voting_power).await;
+    record_operation("check_reputation_update", start, result.is_ok());
+    result
+}
+
+/// Gets a user's reputation in a tag with a certificate and witness
+/// 
+/// Unlike `get_user_reputation_full`, the response can be verified by the client: the
+/// witness proves the reputation's hash under the root hash published in a certified
+/// asset, and the certificate and asset witness prove that asset under the satellite's
+/// certified data. See `verify_certified_reputation` in core/certified_reputations.rs.
+/// 
+/// Update calls have no certificate, so this must be called as a query.
+/// 
+/// # Arguments
+/// * `user_ulid` - ULID of the user
+/// * `tag_ulid` - ULID of the tag
+/// 
+/// # Returns
+/// * `Result<CertifiedReputation, KarmieError>` - The reputation (None if absent), its witness, the root asset, certificate and asset witness
+#[query]
+pub fn get_user_reputation_certified(user_ulid: String, tag_ulid: String) -> Result<CertifiedReputation, KarmieError> {
+    get_certified_reputation(&user_ulid, &tag_ulid)
+}
+
+/// Rebuilds the certified reputation tree from the reputations collection (controllers only)
+/// 
+/// The tree is kept in heap memory and rebuilt after each upgrade by `on_post_upgrade`.
+/// This rebuilds it and republishes the root asset, e.g. if publishing a root failed.
+/// 
+/// # Returns
+/// * `Result<u64, KarmieError>` - Number of reputations certified
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+pub fn rebuild_reputation_certification() -> Result<u64, KarmieError> {
+    rebuild_reputation_tree(ic_cdk::caller()).map(|count| count as u64)
+}
+
+/// Republishes every reputation snapshot of a tag (controllers only)
+///
+/// Snapshots are refreshed automatically after each recalculation; this rewrites the
+/// leaderboard and every user's JSON file, e.g. after an upgrade or a failed refresh.
+///
+/// # Arguments
+/// * `tag_ulid` - ULID of the tag
+///
+/// # Returns
+/// * `Result<u64, KarmieError>` - Number of assets uploaded
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+pub async fn publish_tag_snapshots(tag_ulid: String) -> Result<u64, KarmieError> {
+    publish_reputation_snapshots(ic_cdk::caller(), &tag_ulid).await.map(|count| count as u64)
+}
+
+/// Returns reputation events recorded after a cursor
+///
+/// Consumers store `next_cursor` and pass it to the next call. If `oldest_sequence`
+/// is greater than `cursor + 1`, events were pruned before the consumer read them.
+///
+/// # Arguments
+/// * `cursor` - The last sequence already processed (0 to start from the oldest event)
+/// * `limit` - Maximum number of events to return (at most 100)
+///
+/// # Returns
+/// * `Result<EventPage, KarmieError>` - The events, oldest first
+#[query]
+fn get_events_since(cursor: u64, limit: u32) -> Result<EventPage, KarmieError> {
+    get_event_page(cursor, limit)
+}
+
+/// Lists persisted log entries, newest first (controllers only)
+/// 
+/// # Arguments
+/// * `filter` - Minimum level, module path substring and time range to match
+/// * `cursor` - `next_cursor` of the previous page, None for the newest entries
+/// * `limit` - Maximum number of entries (at most 100)
+/// 
+/// # Returns
+/// * `Result<LogPage, KarmieError>` - The entries, or Err if the caller is not a controller or the filter is invalid
+#[query]
+fn get_logs(filter: LogFilter, cursor: Option<String>, limit: u32) -> Result<LogPage, KarmieError> {
+    get_log_page(ic_cdk::caller(), filter, cursor, limit)
+}
+
+/// Gets the log persistence settings (controllers only)
+/// 
+/// # Returns
+/// * `Result<LogStoreConfig, KarmieError>` - The settings, or Err if the caller is not a controller
+#[query]
+fn get_log_config() -> Result<LogStoreConfig, KarmieError> {
+    get_log_store_config(ic_cdk::caller())
+}
+
+/// Changes the log persistence settings (controllers only)
+/// 
+/// Settings are saved to the `settings` collection and survive upgrades.
+/// 
+/// # Arguments
+/// * `config` - Whether to persist, the minimum level, and the count and age limits
+/// 
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if updated, Err if the caller is not a controller or the config is invalid
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn set_log_config(config: LogStoreConfig) -> Result<(), KarmieError> {
+    set_log_store_config(ic_cdk::caller(), config)
+}
+
+/// Gets the minimum log levels applied to the satellite's logs
+/// 
+/// # Returns
+/// * `LogLevelConfig` - The global minimum level and the per-module overrides
+#[query]
+fn get_log_levels() -> LogLevelConfig {
+    get_log_level_config()
+}
+
+/// Changes the minimum log levels (controllers only)
+/// 
+/// Levels are saved to the `settings` collection and survive upgrades. Until a controller sets
+/// them, the defaults apply (Info, no overrides).
+/// 
+/// # Arguments
+/// * `config` - The global minimum level and the per-module overrides, e.g. ("core::events", Debug)
+/// 
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if updated, Err if the caller is not a controller or the config is invalid
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn set_log_levels(config: LogLevelConfig) -> Result<(), KarmieError> {
+    set_log_level_config(ic_cdk::caller(), config)
+}
+
+/// Gets the satellite's operational metrics (controllers only)
+/// 
+/// Hook invocations and failures per collection, calls and instructions per operation,
+/// documents scanned by `query_doc_by_key` and version conflicts. Counters are kept in
+/// heap memory and reset on upgrade (see utils/metrics.rs).
+/// 
+/// # Returns
+/// * `Result<MetricsSnapshot, KarmieError>` - The counters, or Err if the caller is not a controller
+#[query]
+fn get_metrics() -> Result<MetricsSnapshot, KarmieError> {
+    get_metrics_snapshot(ic_cdk::caller())
+}
+
+/// Gets the satellite's operational metrics in the Prometheus text format (controllers only)
+/// 
+/// # Returns
+/// * `Result<String, KarmieError>` - The exposition text, or Err if the caller is not a controller
+#[query]
+fn get_metrics_prometheus() -> Result<String, KarmieError> {
+    get_metrics_prometheus_text(ic_cdk::caller())
+}
+
+/// Lists the recalculation jobs checkpointed when a message ran low on instructions (controllers only)
+/// 
+/// # Returns
+/// * `Result<Vec<JobData>, KarmieError>` - Pending and failed jobs, oldest first, or Err if the caller is not a controller
+#[query]
+fn get_jobs() -> Result<Vec<JobData>, KarmieError> {
+    get_job_list(ic_cdk::caller())
+}
+
+/// Requeues failed recalculation jobs and schedules the job runner (controllers only)
+/// 
+/// # Returns
+/// * `Result<u32, KarmieError>` - Number of failed jobs requeued, or Err if the caller is not a controller
+#[ic_cdk::update]
+#[candid::candid_method(update)]
+fn run_jobs() -> Result<u32, KarmieError> {
+    requeue_jobs(ic_cdk::caller())
 }
 
-include_satellite!();
\ No newline at end of file
+include_satellite!();
@@ -1,239 +1,409 @@
 //! Document Key Management
 //! 
-//! This module provides utilities for generating, validating, and parsing
-//! document keys for various Juno collections. It follows the format specified
+//! This module provides the typed `DocKey` model used to generate, validate, and
+//! parse document keys for the Juno collections. It follows the format specified
 //! in the database schema documentation, utilizing ULIDs for unique identifiers.
 //!
 //! Each document type has specific key format requirements:
-//! - Users: `_prn_{principal}_usr_{ulid}_hdl_{username}_`
+//! - Users: `_prn_{principal}_usr_{ulid}_`
+//! - Users handles: `hdl_{username}_usr_{ulid}_`
 //! - Tags: `usr_{ulid}_tag_{ulid}_hdl_{tagName}_`
-//! - Reputations: `usr_{ulid}_tag_{ulid}`
+//! - Reputations: `usr_{ulid}_tag_{ulid}_`
 //! - Votes: `usr_{ulid}_tag_{ulid}_tar_{ulid}_key_{ulid}_`
+//! - Delegations: `usr_{ulid}_tag_{ulid}_del_{ulid}_`
+//! - Voting credits: `usr_{ulid}_tag_{ulid}_crd_`
+//!
+//! Users created before the `users_handles` index have legacy keys with their handle,
+//! `_prn_{principal}_usr_{ulid}_hdl_{username}_`, until they are migrated.
 //!
 //! This module ensures consistent key formatting across backend and frontend.
 
 use crate::processors::ulid_generator::{generate_ulid, validate_ulid};
-use std::collections::HashMap;
-use regex::Regex;
+use crate::utils::errors::KarmieError;
+use crate::utils::normalize::CanonicalHandle;
+use std::fmt;
+use std::str::FromStr;
 
 /// Type alias for ULID strings
 pub type ULID = String;
 
 // ===== Common Utility Functions =====
 
-/// Sanitizes a string for use in document keys
-/// 
-/// Converts to lowercase, removes spaces and special characters
-/// Keeps alphanumeric and hyphen characters
+/// Builds an `InvalidKey` error
+fn invalid_key(key: &str, reason: &str) -> KarmieError {
+    KarmieError::InvalidKey {
+        key: key.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Checks that a principal can be written in a key
+///
+/// Principals are textual (lowercase base32 groups separated by hyphens), so they
+/// never contain the underscore that separates key segments.
+fn validate_key_principal(principal: &str) -> Result<(), KarmieError> {
+    if principal.is_empty()
+        || !principal.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(KarmieError::invalid_field("principal", "must be a textual principal"));
+    }
+    Ok(())
+}
+
+/// Canonicalizes a handle and checks its length
 ///
 /// # Arguments
-/// * `input` - The string to sanitize
+/// * `field` - Name of the field, for the error
+/// * `label` - Label of the handle in the error message ("Handle", "Tag name")
+/// * `handle` - The handle to canonicalize
 ///
 /// # Returns
-/// * `String` - Sanitized string
-pub fn sanitize_for_key(input: &str) -> String {
-    // Remove spaces, special characters, and convert to lowercase
-    let alphanumeric_hyphen = input
-        .chars()
-        .filter(|c| c.is_alphanumeric() || *c == '-')
-        .collect::<String>()
-        .to_lowercase();
-    
-    alphanumeric_hyphen
+/// * `Result<String, KarmieError>` - The canonical handle or error
+fn key_handle(field: &str, label: &str, handle: &str) -> Result<String, KarmieError> {
+    let canonical = CanonicalHandle::new(handle).into_string();
+
+    if canonical.len() < 3 || canonical.len() > 30 {
+        return Err(KarmieError::invalid_field(field, format!("{} must be between 3 and 30 characters", label)));
+    }
+
+    Ok(canonical)
 }
 
-/// Extracts components from a document key
+// ===== Typed Document Keys =====
+
+/// A document key, one variant per collection
 ///
-/// # Arguments
-/// * `key` - The document key to parse
+/// Keys are only ever written with `Display` and read with `FromStr`, which are exact
+/// inverses: parsing a key and displaying it gives back the same string, and a string
+/// that isn't the display of a valid `DocKey` doesn't parse.
+/// Variants are built with the constructors below, which validate ULIDs and canonicalize
+/// handles, so a `DocKey` always displays to a well-formed key.
 ///
-/// # Returns
-/// * `Result<HashMap<String, String>, String>` - Map of components or error
-pub fn parse_key(key: &str) -> Result<HashMap<String, String>, String> {
-    let mut components = HashMap::new();
-    
-    // Split the key by underscore
+/// # Example
+/// ```rust
+/// let key = DocKey::reputation(&user_ulid, &tag_ulid)?;
+/// let doc = junobuild_satellite::get_doc("reputations".to_string(), key.to_string());
+///
+/// match context.data.key.parse::<DocKey>()? {
+///     DocKey::Vote { target_ulid, .. } => { /* ... */ }
+///     other => return Err(other.kind_mismatch("vote")),
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DocKey {
+    /// `_prn_{principal}_usr_{ulid}_`
+    User { principal: String, user_ulid: ULID },
+    /// `_prn_{principal}_usr_{ulid}_hdl_{handle}_`, users keys from before the handles index
+    LegacyUser { principal: String, user_ulid: ULID, handle: String },
+    /// `hdl_{handle}_usr_{ulid}_`
+    UserHandle { handle: String, user_ulid: ULID },
+    /// `usr_{ulid}_tag_{ulid}_hdl_{tagName}_`
+    Tag { user_ulid: ULID, tag_ulid: ULID, handle: String },
+    /// `usr_{ulid}_tag_{ulid}_`
+    Reputation { user_ulid: ULID, tag_ulid: ULID },
+    /// `usr_{ulid}_tag_{ulid}_tar_{ulid}_key_{ulid}_`
+    Vote { user_ulid: ULID, tag_ulid: ULID, target_ulid: ULID, vote_ulid: ULID },
+    /// `usr_{ulid}_tag_{ulid}_del_{ulid}_`
+    Delegation { delegator_ulid: ULID, tag_ulid: ULID, delegate_ulid: ULID },
+    /// `usr_{ulid}_tag_{ulid}_crd_`
+    VoteCredits { user_ulid: ULID, tag_ulid: ULID },
+}
+
+impl DocKey {
+    /// Builds a user key
+    ///
+    /// # Arguments
+    /// * `principal` - User's principal (Internet Identity string)
+    /// * `user_ulid` - ULID for the user, must be uppercase
+    ///
+    /// # Returns
+    /// * `Result<DocKey, KarmieError>` - The key or error
+    pub fn user(principal: &str, user_ulid: &str) -> Result<Self, KarmieError> {
+        validate_key_principal(principal)?;
+        validate_ulid(user_ulid)?;
+        Ok(DocKey::User {
+            principal: principal.to_string(),
+            user_ulid: user_ulid.to_string(),
+        })
+    }
+
+    /// Builds a legacy user key, with the handle
+    ///
+    /// Only existing documents have such keys, new users are created with `user`.
+    ///
+    /// # Arguments
+    /// * `principal` - User's principal (Internet Identity string)
+    /// * `user_ulid` - ULID for the user, must be uppercase
+    /// * `handle` - Username, will be canonicalized
+    ///
+    /// # Returns
+    /// * `Result<DocKey, KarmieError>` - The key or error
+    pub fn legacy_user(principal: &str, user_ulid: &str, handle: &str) -> Result<Self, KarmieError> {
+        validate_key_principal(principal)?;
+        validate_ulid(user_ulid)?;
+        Ok(DocKey::LegacyUser {
+            principal: principal.to_string(),
+            user_ulid: user_ulid.to_string(),
+            handle: key_handle("handle", "Handle", handle)?,
+        })
+    }
+
+    /// Builds a users handles index key
+    ///
+    /// # Arguments
+    /// * `handle` - Username, will be canonicalized
+    /// * `user_ulid` - ULID of the user holding the handle
+    ///
+    /// # Returns
+    /// * `Result<DocKey, KarmieError>` - The key or error
+    pub fn user_handle(handle: &str, user_ulid: &str) -> Result<Self, KarmieError> {
+        validate_ulid(user_ulid)?;
+        Ok(DocKey::UserHandle {
+            handle: key_handle("handle", "Handle", handle)?,
+            user_ulid: user_ulid.to_string(),
+        })
+    }
+
+    /// Builds a tag key
+    ///
+    /// # Arguments
+    /// * `user_ulid` - ULID of the user creating the tag
+    /// * `tag_ulid` - ULID for the tag
+    /// * `tag_name` - Name of the tag, will be canonicalized
+    ///
+    /// # Returns
+    /// * `Result<DocKey, KarmieError>` - The key or error
+    pub fn tag(user_ulid: &str, tag_ulid: &str, tag_name: &str) -> Result<Self, KarmieError> {
+        validate_ulid(user_ulid)?;
+        validate_ulid(tag_ulid)?;
+        Ok(DocKey::Tag {
+            user_ulid: user_ulid.to_string(),
+            tag_ulid: tag_ulid.to_string(),
+            handle: key_handle("tag_handle", "Tag name", tag_name)?,
+        })
+    }
+
+    /// Builds a reputation key
+    ///
+    /// # Arguments
+    /// * `user_ulid` - ULID of the user
+    /// * `tag_ulid` - ULID of the tag
+    ///
+    /// # Returns
+    /// * `Result<DocKey, KarmieError>` - The key or error
+    pub fn reputation(user_ulid: &str, tag_ulid: &str) -> Result<Self, KarmieError> {
+        validate_ulid(user_ulid)?;
+        validate_ulid(tag_ulid)?;
+        Ok(DocKey::Reputation {
+            user_ulid: user_ulid.to_string(),
+            tag_ulid: tag_ulid.to_string(),
+        })
+    }
+
+    /// Builds a vote key
+    ///
+    /// # Arguments
+    /// * `user_ulid` - ULID of the voter
+    /// * `tag_ulid` - ULID of the tag
+    /// * `target_ulid` - ULID of the target user
+    /// * `vote_ulid` - ULID of the vote
+    ///
+    /// # Returns
+    /// * `Result<DocKey, KarmieError>` - The key or error
+    pub fn vote(user_ulid: &str, tag_ulid: &str, target_ulid: &str, vote_ulid: &str) -> Result<Self, KarmieError> {
+        validate_ulid(user_ulid)?;
+        validate_ulid(tag_ulid)?;
+        validate_ulid(target_ulid)?;
+        validate_ulid(vote_ulid)?;
+        Ok(DocKey::Vote {
+            user_ulid: user_ulid.to_string(),
+            tag_ulid: tag_ulid.to_string(),
+            target_ulid: target_ulid.to_string(),
+            vote_ulid: vote_ulid.to_string(),
+        })
+    }
+
+    /// Builds a delegation key
+    ///
+    /// # Arguments
+    /// * `delegator_ulid` - ULID of the user delegating their voting power
+    /// * `tag_ulid` - ULID of the tag
+    /// * `delegate_ulid` - ULID of the user receiving the voting power
+    ///
+    /// # Returns
+    /// * `Result<DocKey, KarmieError>` - The key or error
+    pub fn delegation(delegator_ulid: &str, tag_ulid: &str, delegate_ulid: &str) -> Result<Self, KarmieError> {
+        validate_ulid(delegator_ulid)?;
+        validate_ulid(tag_ulid)?;
+        validate_ulid(delegate_ulid)?;
+        Ok(DocKey::Delegation {
+            delegator_ulid: delegator_ulid.to_string(),
+            tag_ulid: tag_ulid.to_string(),
+            delegate_ulid: delegate_ulid.to_string(),
+        })
+    }
+
+    /// Builds a voting credits key
+    ///
+    /// # Arguments
+    /// * `user_ulid` - ULID of the user
+    /// * `tag_ulid` - ULID of the tag
+    ///
+    /// # Returns
+    /// * `Result<DocKey, KarmieError>` - The key or error
+    pub fn vote_credits(user_ulid: &str, tag_ulid: &str) -> Result<Self, KarmieError> {
+        validate_ulid(user_ulid)?;
+        validate_ulid(tag_ulid)?;
+        Ok(DocKey::VoteCredits {
+            user_ulid: user_ulid.to_string(),
+            tag_ulid: tag_ulid.to_string(),
+        })
+    }
+
+    /// Kind of document the key belongs to: "user", "legacy_user", "user_handle", "tag",
+    /// "reputation", "vote", "delegation" or "vote_credits"
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DocKey::User { .. } => "user",
+            DocKey::LegacyUser { .. } => "legacy_user",
+            DocKey::UserHandle { .. } => "user_handle",
+            DocKey::Tag { .. } => "tag",
+            DocKey::Reputation { .. } => "reputation",
+            DocKey::Vote { .. } => "vote",
+            DocKey::Delegation { .. } => "delegation",
+            DocKey::VoteCredits { .. } => "vote_credits",
+        }
+    }
+
+    /// Error for a key of another kind than expected
+    pub fn kind_mismatch(&self, expected: &str) -> KarmieError {
+        invalid_key(&self.to_string(), &format!("expected a {} key, got a {} key", expected, self.kind()))
+    }
+
+    /// Parses a key that must be of the given kind
+    ///
+    /// # Arguments
+    /// * `key` - The key to parse
+    /// * `kind` - The expected kind, see `kind`
+    ///
+    /// # Returns
+    /// * `Result<DocKey, KarmieError>` - The key, or `InvalidKey` if it is malformed or of another kind
+    pub fn parse_kind(key: &str, kind: &str) -> Result<Self, KarmieError> {
+        let parsed: DocKey = key.parse()?;
+        if parsed.kind() != kind {
+            return Err(parsed.kind_mismatch(kind));
+        }
+        Ok(parsed)
+    }
+
+    /// Parses a key whose handle may not be in canonical form
+    ///
+    /// Keys written before handles were canonicalized the current way don't parse with
+    /// `FromStr`. This reads them anyway, with the handle canonicalized: the result displays
+    /// to the key the document should have (see core/handle_keys.rs).
+    ///
+    /// # Arguments
+    /// * `key` - The stored key
+    ///
+    /// # Returns
+    /// * `Result<DocKey, KarmieError>` - The key with a canonical handle, or `InvalidKey` if it is malformed
+    pub fn parse_noncanonical(key: &str) -> Result<Self, KarmieError> {
+        parse_segments(key)
+    }
+}
+
+/// Parses the segments of a key, canonicalizing its handle if it has one
+fn parse_segments(key: &str) -> Result<DocKey, KarmieError> {
+    // No segment value contains an underscore, so splitting on it is exact
     let parts: Vec<&str> = key.split('_').collect();
-    
-    // Basic validation
-    if parts.len() < 3 {
-        return Err("Key has insufficient components".to_string());
+
+    match parts.as_slice() {
+        ["", "prn", principal, "usr", user, ""] => DocKey::user(principal, user),
+        ["", "prn", principal, "usr", user, "hdl", handle, ""] => DocKey::legacy_user(principal, user, handle),
+        ["hdl", handle, "usr", user, ""] => DocKey::user_handle(handle, user),
+        ["usr", user, "tag", tag, "hdl", handle, ""] => DocKey::tag(user, tag, handle),
+        ["usr", user, "tag", tag, ""] => DocKey::reputation(user, tag),
+        ["usr", user, "tag", tag, "tar", target, "key", vote, ""] => DocKey::vote(user, tag, target, vote),
+        ["usr", delegator, "tag", tag, "del", delegate, ""] => DocKey::delegation(delegator, tag, delegate),
+        ["usr", user, "tag", tag, "crd", ""] => DocKey::vote_credits(user, tag),
+        _ => return Err(invalid_key(key, "Unknown key format")),
     }
-    
-    // Start extracting components
-    let mut i = 0;
-    
-    // Extract components based on pattern recognition
-    while i < parts.len() - 1 {
-        match parts[i] {
-            "usr" => {
-                if i + 1 < parts.len() && validate_ulid(parts[i+1]).is_ok() {
-                    components.insert("usr_key".to_string(), parts[i+1].to_string());
-                    i += 2;
-                } else {
-                    return Err("Invalid usr_key format".to_string());
-                }
-            },
-            "hdl" => {
-                // Extract handle (might span multiple parts due to underscores in handle)
-                let mut handle = parts[i+1].to_string();
-                i += 2;
-                // Keep collecting until we hit another known component
-                while i < parts.len() && !["usr", "tag", "tar", "key", "hdl"].contains(&parts[i]) {
-                    handle.push('_');
-                    handle.push_str(parts[i]);
-                    i += 1;
-                }
-                components.insert("handle".to_string(), handle);
-            },
-            "tag" => {
-                if i + 1 < parts.len() && validate_ulid(parts[i+1]).is_ok() {
-                    components.insert("tag_key".to_string(), parts[i+1].to_string());
-                    i += 2;
-                } else {
-                    return Err("Invalid tag_key format".to_string());
-                }
-            },
-            "tar" => {
-                if i + 1 < parts.len() && validate_ulid(parts[i+1]).is_ok() {
-                    components.insert("tar_key".to_string(), parts[i+1].to_string());
-                    i += 2;
-                } else {
-                    return Err("Invalid target_key format".to_string());
-                }
-            },
-            "key" => {
-                if i + 1 < parts.len() && validate_ulid(parts[i+1]).is_ok() {
-                    components.insert("vote_key".to_string(), parts[i+1].to_string());
-                    i += 2;
-                } else {
-                    return Err("Invalid vote_key format".to_string());
-                }
-            },
-            _ => {
-                // Skip unknown parts
-                i += 1;
+    .map_err(|e| invalid_key(key, &e.message()))
+}
+
+impl fmt::Display for DocKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocKey::User { principal, user_ulid } => write!(f, "_prn_{}_usr_{}_", principal, user_ulid),
+            DocKey::LegacyUser { principal, user_ulid, handle } => {
+                write!(f, "_prn_{}_usr_{}_hdl_{}_", principal, user_ulid, handle)
+            }
+            DocKey::UserHandle { handle, user_ulid } => write!(f, "hdl_{}_usr_{}_", handle, user_ulid),
+            DocKey::Tag { user_ulid, tag_ulid, handle } => {
+                write!(f, "usr_{}_tag_{}_hdl_{}_", user_ulid, tag_ulid, handle)
             }
+            DocKey::Reputation { user_ulid, tag_ulid } => write!(f, "usr_{}_tag_{}_", user_ulid, tag_ulid),
+            DocKey::Vote { user_ulid, tag_ulid, target_ulid, vote_ulid } => {
+                write!(f, "usr_{}_tag_{}_tar_{}_key_{}_", user_ulid, tag_ulid, target_ulid, vote_ulid)
+            }
+            DocKey::Delegation { delegator_ulid, tag_ulid, delegate_ulid } => {
+                write!(f, "usr_{}_tag_{}_del_{}_", delegator_ulid, tag_ulid, delegate_ulid)
+            }
+            DocKey::VoteCredits { user_ulid, tag_ulid } => write!(f, "usr_{}_tag_{}_crd_", user_ulid, tag_ulid),
         }
     }
-    
-    Ok(components)
 }
 
-// ===== Document-Specific Key Generation Functions =====
+impl FromStr for DocKey {
+    type Err = KarmieError;
+
+    /// Parses a key, failing with `InvalidKey` unless it displays back to itself
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        let parsed = parse_segments(key)?;
+
+        // Handles are canonicalized by the constructors, a key with another handle form doesn't display back
+        if parsed.to_string() != key {
+            return Err(invalid_key(key, "Handle is not in canonical form"));
+        }
 
-/// Create a new user document key with generated ULID
+        Ok(parsed)
+    }
+}
+
+// ===== Key Generation Functions =====
+
+/// Creates a new user key with a generated ULID
 /// 
 /// # Arguments
 /// * `principal` - User's principal (Internet Identity string)
-/// * `handle` - User's handle (username)
 /// 
 /// # Returns
-/// * `Result<String, String>` - Formatted key or error
+/// * `Result<DocKey, KarmieError>` - The key or error
 /// 
 /// # Example
 /// ```rust
-/// // Creates: "_prn_2vxsx-fae_usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_hdl_johndoe_"
-/// let key = create_user_key("2vxsx-fae", "johndoe").await?;
+/// // Creates: "_prn_2vxsx-fae_usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_"
+/// let key = create_user_key("2vxsx-fae").await?;
 /// ```
-pub async fn create_user_key(principal: &str, handle: &str) -> Result<String, String> {
+pub async fn create_user_key(principal: &str) -> Result<DocKey, KarmieError> {
     let user_ulid = generate_ulid().await;
-    format_user_key(principal, &user_ulid, handle)
-}
-
-/// Format a user key with existing ULID
-/// 
-/// # Arguments
-/// * `principal` - User's principal (Internet Identity string)
-/// * `ulid` - ULID for the user, must be uppercase
-/// * `handle` - Username, will be sanitized
-///
-/// # Returns
-/// * `Result<String, String>` - Formatted key or error
-pub fn format_user_key(principal: &str, ulid: &str, handle: &str) -> Result<String, String> {
-    validate_ulid(ulid)?;
-    let sanitized_handle = sanitize_for_key(handle);
-    
-    if sanitized_handle.len() < 3 || sanitized_handle.len() > 30 {
-        return Err("Handle must be between 3 and 30 characters".to_string());
-    }
-    
-    // Format: _prn_{principal}_usr_{ulid}_hdl_{username}_
-    Ok(format!("_prn_{}_usr_{}_hdl_{}_", principal, ulid, sanitized_handle))
+    DocKey::user(principal, &user_ulid)
 }
 
-/// Create a tag document key
+/// Creates a new tag key with a generated ULID
 /// 
 /// # Arguments
 /// * `user_ulid` - ULID of the user creating the tag
 /// * `tag_name` - Name of the tag
 /// 
 /// # Returns
-/// * `Result<String, String>` - Formatted key or error
-pub async fn create_tag_key(user_ulid: &str, tag_name: &str) -> Result<String, String> {
+/// * `Result<DocKey, KarmieError>` - The key or error
+pub async fn create_tag_key(user_ulid: &str, tag_name: &str) -> Result<DocKey, KarmieError> {
     let tag_ulid = generate_ulid().await;
-    format_tag_key(user_ulid, &tag_ulid, tag_name)
-}
-
-/// Format a tag key with existing ULIDs
-/// 
-/// # Arguments
-/// * `user_ulid` - ULID of the user creating the tag
-/// * `tag_ulid` - ULID for the tag
-/// * `tag_name` - Name of the tag
-/// 
-/// # Returns
-/// * `Result<String, String>` - Formatted key or error
-pub fn format_tag_key(user_ulid: &str, tag_ulid: &str, tag_name: &str) -> Result<String, String> {
-    validate_ulid(user_ulid)?;
-    validate_ulid(tag_ulid)?;
-    let sanitized_name = sanitize_for_key(tag_name);
-    
-    if sanitized_name.len() < 3 || sanitized_name.len() > 30 {
-        return Err("Tag name must be between 3 and 30 characters".to_string());
-    }
-    
-    // Format: usr_ULID_tag_ULID_hdl_name_
-    Ok(format!("usr_{}_tag_{}_hdl_{}_", user_ulid, tag_ulid, sanitized_name))
+    DocKey::tag(user_ulid, &tag_ulid, tag_name)
 }
 
-/// Creates a reputation document key with generated ULID for the reputation
-///
-/// # Arguments
-/// * `user_ulid` - ULID of the user
-/// * `tag_ulid` - ULID of the tag
-///
-/// # Returns
-/// * `Result<String, String>` - Formatted key or error message
-pub async fn create_reputation_key(user_ulid: &str, tag_ulid: &str) -> Result<String, String> {
-    format_reputation_key(user_ulid, tag_ulid)
-}
-
-/// Format a reputation key with existing ULIDs
-///
-/// Use this function for validation and key formatting that doesn't require 
-/// async operations or new ULIDs generation.
-///
-/// Format: usr_{userUlid}_tag_{tagUlid}_
-///
-/// # Arguments
-/// * `user_ulid` - ULID of the user
-/// * `tag_ulid` - ULID of the tag
-///
-/// # Returns
-/// * `Result<String, String>` - Formatted key or error message
-pub fn format_reputation_key(user_ulid: &str, tag_ulid: &str) -> Result<String, String> {
-    // Validate ULIDs
-    validate_ulid(user_ulid)?;
-    validate_ulid(tag_ulid)?;
-    
-    // Format key with trailing underscore
-    Ok(format!("usr_{}_tag_{}_", user_ulid, tag_ulid))
-}
-
-/// Creates a vote document key
-///
-/// Format: usr_{userUlid}_tag_{tagUlid}_tar_{targetUlid}_key_{voteUlid}_
+/// Creates a vote key
 ///
 /// # Arguments
 /// * `user_ulid` - ULID of the voter
@@ -242,209 +412,101 @@ pub fn format_reputation_key(user_ulid: &str, tag_ulid: &str) -> Result<String,
 /// * `vote_ulid` - Optional ULID for the vote, generated if None
 ///
 /// # Returns
-/// * `Result<String, String>` - Formatted key or error message
+/// * `Result<DocKey, KarmieError>` - The key or error
 pub async fn create_vote_key(
     user_ulid: &str, 
     tag_ulid: &str, 
     target_ulid: &str, 
     vote_ulid: Option<&str>
-) -> Result<String, String> {
-    // Validate ULIDs
-    validate_ulid(user_ulid)?;
-    validate_ulid(tag_ulid)?;
-    validate_ulid(target_ulid)?;
-    
-    // Validate or generate vote ULID
+) -> Result<DocKey, KarmieError> {
     let vote_id = match vote_ulid {
-        Some(id) => {
-            validate_ulid(id)?;
-            id.to_string()
-        },
+        Some(id) => id.to_string(),
         None => generate_ulid().await
     };
-    
-    // Format key
-    Ok(format!("usr_{}_tag_{}_tar_{}_key_{}_", 
-        user_ulid, tag_ulid, target_ulid, vote_id))
-}
 
-// ===== Document-Specific Key Validation Functions =====
+    DocKey::vote(user_ulid, tag_ulid, target_ulid, &vote_id)
+}
 
-/// Validates a user document key format
-///
-/// # Arguments
-/// * `key` - The key to validate
-///
-/// # Returns
-/// * `Result<(), String>` - Ok if valid, Err with message if invalid
-pub fn validate_user_key(key: &str) -> Result<(), String> {
-    let user_key_pattern = Regex::new(
-        r"^usr_[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}_hdl_[a-z0-9\-]+_$"
-    ).unwrap();
-    
-    if !user_key_pattern.is_match(key) {
-        return Err(format!("Invalid user key format: {}", key));
-    }
-    
-    // Parse key to extract components
-    let components = parse_key(key)?;
-    
-    // Ensure required components are present
-    if !components.contains_key("usr_key") || !components.contains_key("handle") {
-        return Err("User key missing required components".to_string());
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
     
-    // Username validation
-    if let Some(handle) = components.get("handle") {
-        if handle.len() < 3 || handle.len() > 30 {
-            return Err("Handle must be between 3 and 30 characters".to_string());
+    const USER: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAV";
+    const TAG: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAW";
+    const TARGET: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAX";
+    const VOTE: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAY";
+
+    #[test]
+    fn test_doc_key_round_trip() {
+        let keys = vec![
+            (DocKey::user("2vxsx-fae", USER).unwrap(), format!("_prn_2vxsx-fae_usr_{}_", USER)),
+            (DocKey::legacy_user("2vxsx-fae", USER, "John Doe").unwrap(), format!("_prn_2vxsx-fae_usr_{}_hdl_johndoe_", USER)),
+            (DocKey::user_handle("John Doe", USER).unwrap(), format!("hdl_johndoe_usr_{}_", USER)),
+            (DocKey::tag(USER, TAG, "Technical-Skills").unwrap(), format!("usr_{}_tag_{}_hdl_technical-skills_", USER, TAG)),
+            (DocKey::reputation(USER, TAG).unwrap(), format!("usr_{}_tag_{}_", USER, TAG)),
+            (DocKey::vote(USER, TAG, TARGET, VOTE).unwrap(), format!("usr_{}_tag_{}_tar_{}_key_{}_", USER, TAG, TARGET, VOTE)),
+            (DocKey::delegation(USER, TAG, TARGET).unwrap(), format!("usr_{}_tag_{}_del_{}_", USER, TAG, TARGET)),
+            (DocKey::vote_credits(USER, TAG).unwrap(), format!("usr_{}_tag_{}_crd_", USER, TAG)),
+        ];
+
+        for (key, text) in keys {
+            assert_eq!(key.to_string(), text);
+            assert_eq!(text.parse::<DocKey>().unwrap(), key);
+            assert_eq!(DocKey::parse_kind(&text, key.kind()).unwrap(), key);
         }
-    }
-    
-    Ok(())
-}
 
-/// Validates a tag document key format
-///
-/// # Arguments
-/// * `key` - The key to validate
-///
-/// # Returns
-/// * `Result<(), String>` - Ok if valid, Err with message if invalid
-pub fn validate_tag_key(key: &str) -> Result<(), String> {
-    let tag_key_pattern = Regex::new(
-        r"^usr_[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}_tag_[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}_hdl_[a-z0-9\-]+_$"
-    ).unwrap();
-    
-    if !tag_key_pattern.is_match(key) {
-        return Err(format!("Invalid tag key format: {}", key));
-    }
-    
-    // Parse key to extract components
-    let components = parse_key(key)?;
-    
-    // Ensure required components are present
-    if !components.contains_key("usr_key") || 
-       !components.contains_key("tag_key") || 
-       !components.contains_key("handle") {
-        return Err("Tag key missing required components".to_string());
-    }
-    
-    // Tag name validation
-    if let Some(tagname) = components.get("handle") {
-        if tagname.len() < 3 || tagname.len() > 30 {
-            return Err("Tag name must be between 3 and 30 characters".to_string());
+        match format!("usr_{}_tag_{}_tar_{}_key_{}_", USER, TAG, TARGET, VOTE).parse::<DocKey>().unwrap() {
+            DocKey::Vote { user_ulid, target_ulid, .. } => {
+                assert_eq!(user_ulid, USER);
+                assert_eq!(target_ulid, TARGET);
+            }
+            other => panic!("parsed as a {} key", other.kind()),
         }
     }
-    
-    Ok(())
-}
 
-/// Validates a reputation document key format
-///
-/// # Arguments
-/// * `key` - The key to validate
-///
-/// # Returns
-/// * `Result<(), String>` - Ok if valid, Err with message if invalid
-pub fn validate_reputation_key(key: &str) -> Result<(), String> {
-    let reputation_key_pattern = Regex::new(
-        r"^usr_[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}_tag_[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}_$"
-    ).unwrap();
-    
-    if !reputation_key_pattern.is_match(key) {
-        return Err(format!("Invalid reputation key format: {}", key));
-    }
-    
-    // Parse key to extract components
-    let components = parse_key(key)?;
-    
-    // Ensure required components are present
-    if !components.contains_key("usr_key") || !components.contains_key("tag_key") {
-        return Err("Reputation key missing required components".to_string());
-    }
-    
-    Ok(())
-}
+    #[test]
+    fn test_doc_key_rejects_malformed_keys() {
+        let malformed = vec![
+            "invalid_key_format".to_string(),
+            // Missing trailing underscore, or the old `{user}_{tag}` reputation key
+            format!("usr_{}_tag_{}", USER, TAG),
+            format!("{}_{}", USER, TAG),
+            // Invalid ULIDs
+            format!("usr_{}_tag_invalid_", USER),
+            format!("usr_{}_tag_{}_", USER.to_lowercase(), TAG),
+            // Non-canonical or too short handles
+            format!("_prn_2vxsx-fae_usr_{}_hdl_JohnDoe_", USER),
+            format!("usr_{}_tag_{}_hdl_py_", USER, TAG),
+            // Users keys carry the principal, handles keys end with an underscore
+            format!("usr_{}_hdl_johndoe_", USER),
+            format!("_prn__usr_{}_", USER),
+            format!("hdl_johndoe_usr_{}", USER),
+            // Extra segments
+            format!("usr_{}_tag_{}_del_{}_x_", USER, TAG, TARGET),
+        ];
 
-/// Validates a vote document key format
-///
-/// # Arguments
-/// * `key` - The key to validate
-///
-/// # Returns
-/// * `Result<(), String>` - Ok if valid, Err with message if invalid
-pub fn validate_vote_key(key: &str) -> Result<(), String> {
-    let vote_key_pattern = Regex::new(
-        r"^usr_[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}_tag_[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}_tar_[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}_key_[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}_$"
-    ).unwrap();
-    
-    if !vote_key_pattern.is_match(key) {
-        return Err(format!("Invalid vote key format: {}", key));
-    }
-    
-    // Parse key to extract components
-    let components = parse_key(key)?;
-    
-    // Ensure required components are present
-    if !components.contains_key("usr_key") || 
-       !components.contains_key("tag_key") || 
-       !components.contains_key("tar_key") ||
-       !components.contains_key("vote_key") {
-        return Err("Vote key missing required components".to_string());
-    }
-    
-    Ok(())
-}
+        for key in malformed {
+            assert!(
+                matches!(key.parse::<DocKey>(), Err(KarmieError::InvalidKey { .. })),
+                "{} should not parse",
+                key
+            );
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    
-    #[test]
-    fn test_parse_key() {
-        // Test parse user key
-        let user_key = "usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_hdl_johndoe_";
-        let components = parse_key(user_key).unwrap();
-        assert_eq!(components.get("usr_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAV");
-        assert_eq!(components.get("handle").unwrap(), "johndoe");
-        
-        // Test parse tag key
-        let tag_key = "usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_tag_01ARZ3NDEKTSV4RRFFQ69G5FAW_hdl_technical-skills_";
-        let components = parse_key(tag_key).unwrap();
-        assert_eq!(components.get("usr_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAV");
-        assert_eq!(components.get("tag_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAW");
-        assert_eq!(components.get("handle").unwrap(), "technical-skills");
-        
-        // Test parse reputation key
-        let rep_key = "usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_tag_01ARZ3NDEKTSV4RRFFQ69G5FAW_";
-        let components = parse_key(rep_key).unwrap();
-        assert_eq!(components.get("usr_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAV");
-        assert_eq!(components.get("tag_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAW");
-        
-        // Test parse vote key
-        let vote_key = "usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_tag_01ARZ3NDEKTSV4RRFFQ69G5FAW_tar_01ARZ3NDEKTSV4RRFFQ69G5FAX_key_01ARZ3NDEKTSV4RRFFQ69G5FAY_";
-        let components = parse_key(vote_key).unwrap();
-        assert_eq!(components.get("usr_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAV");
-        assert_eq!(components.get("tag_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAW");
-        assert_eq!(components.get("tar_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAX");
-        assert_eq!(components.get("vote_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAY");
-        
-        // Test invalid key
-        let invalid_key = "invalid_key_format";
-        assert!(parse_key(invalid_key).is_err());
+        let reputation = format!("usr_{}_tag_{}_", USER, TAG);
+        assert!(DocKey::parse_kind(&reputation, "vote").is_err());
+        assert!(DocKey::reputation(USER, "invalid").is_err());
+        assert!(DocKey::user_handle("ab", USER).is_err());
     }
     
     #[tokio::test]
     async fn test_create_user_key() {
-        // Test with handle
-        let key = create_user_key("2vxsx-fae", "johndoe").await.unwrap();
+        let key = create_user_key("2vxsx-fae").await.unwrap().to_string();
         assert!(key.starts_with("_prn_2vxsx-fae_usr_"));
-        assert!(key.ends_with("_hdl_johndoe_"));
-        assert!(validate_user_key(&key).is_ok());
+        assert!(DocKey::parse_kind(&key, "user").is_ok());
         
-        // Test with invalid handle (too short)
-        let result = create_user_key("2vxsx-fae", "ab").await;
+        // Test with invalid principal
+        let result = create_user_key("2vxsx_fae").await;
         assert!(result.is_err());
     }
     
@@ -454,21 +516,21 @@ mod tests {
         let key = create_tag_key(
             "01ARZ3NDEKTSV4RRFFQ69G5FAV",
             "Technical-Skills"
-        ).await.unwrap();
+        ).await.unwrap().to_string();
         
         assert!(key.starts_with("usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_tag_"));
         assert!(key.ends_with("_hdl_technical-skills_"));
-        assert!(validate_tag_key(&key).is_ok());
+        assert!(DocKey::parse_kind(&key, "tag").is_ok());
         
         // Test with another valid input
         let key = create_tag_key(
             "01ARZ3NDEKTSV4RRFFQ69G5FAV",
             "Programming"
-        ).await.unwrap();
+        ).await.unwrap().to_string();
         
         assert!(key.starts_with("usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_tag_"));
         assert!(key.ends_with("_hdl_programming_"));
-        assert!(validate_tag_key(&key).is_ok());
+        assert!(DocKey::parse_kind(&key, "tag").is_ok());
         
         // Test with invalid tag name (too short)
         let result = create_tag_key(
@@ -478,28 +540,6 @@ mod tests {
         assert!(result.is_err());
     }
     
-    #[test]
-    fn test_create_reputation_key() {
-        // Test with valid ULIDs
-        let key = create_reputation_key(
-            "01ARZ3NDEKTSV4RRFFQ69G5FAV",
-            "01ARZ3NDEKTSV4RRFFQ69G5FAW"
-        ).unwrap();
-        
-        assert_eq!(
-            key, 
-            "usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_tag_01ARZ3NDEKTSV4RRFFQ69G5FAW_"
-        );
-        assert!(validate_reputation_key(&key).is_ok());
-        
-        // Test with invalid ULID
-        let result = create_reputation_key(
-            "01ARZ3NDEKTSV4RRFFQ69G5FAV",
-            "invalid"
-        );
-        assert!(result.is_err());
-    }
-    
     #[tokio::test]
     async fn test_create_vote_key() {
         // Test with provided ULIDs
@@ -508,13 +548,13 @@ mod tests {
             "01ARZ3NDEKTSV4RRFFQ69G5FAW",
             "01ARZ3NDEKTSV4RRFFQ69G5FAX",
             Some("01ARZ3NDEKTSV4RRFFQ69G5FAY")
-        ).await.unwrap();
+        ).await.unwrap().to_string();
         
         assert_eq!(
             key, 
             "usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_tag_01ARZ3NDEKTSV4RRFFQ69G5FAW_tar_01ARZ3NDEKTSV4RRFFQ69G5FAX_key_01ARZ3NDEKTSV4RRFFQ69G5FAY_"
         );
-        assert!(validate_vote_key(&key).is_ok());
+        assert!(DocKey::parse_kind(&key, "vote").is_ok());
         
         // Test with auto-generated vote ULID
         let key = create_vote_key(
@@ -522,11 +562,11 @@ mod tests {
             "01ARZ3NDEKTSV4RRFFQ69G5FAW",
             "01ARZ3NDEKTSV4RRFFQ69G5FAX",
             None
-        ).await.unwrap();
+        ).await.unwrap().to_string();
         
         assert!(key.starts_with("usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_tag_01ARZ3NDEKTSV4RRFFQ69G5FAW_tar_01ARZ3NDEKTSV4RRFFQ69G5FAX_key_"));
         assert!(key.ends_with("_"));
-        assert!(validate_vote_key(&key).is_ok());
+        assert!(DocKey::parse_kind(&key, "vote").is_ok());
         
         // Test with invalid user ULID
         let result = create_vote_key(
@@ -539,46 +579,19 @@ mod tests {
     }
     
     #[test]
-    fn test_parse_key() {
-        // Test parse user key
-        let user_key = "usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_hdl_johndoe_";
-        let components = parse_key(user_key).unwrap();
-        assert_eq!(components.get("usr_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAV");
-        assert_eq!(components.get("handle").unwrap(), "johndoe");
-        
-        // Test parse tag key
-        let tag_key = "usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_tag_01ARZ3NDEKTSV4RRFFQ69G5FAW_hdl_technical-skills_";
-        let components = parse_key(tag_key).unwrap();
-        assert_eq!(components.get("usr_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAV");
-        assert_eq!(components.get("tag_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAW");
-        assert_eq!(components.get("handle").unwrap(), "technical-skills");
-        
-        // Test parse reputation key
-        let rep_key = "usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_tag_01ARZ3NDEKTSV4RRFFQ69G5FAW_";
-        let components = parse_key(rep_key).unwrap();
-        assert_eq!(components.get("usr_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAV");
-        assert_eq!(components.get("tag_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAW");
-        
-        // Test parse vote key
-        let vote_key = "usr_01ARZ3NDEKTSV4RRFFQ69G5FAV_tag_01ARZ3NDEKTSV4RRFFQ69G5FAW_tar_01ARZ3NDEKTSV4RRFFQ69G5FAX_key_01ARZ3NDEKTSV4RRFFQ69G5FAY_";
-        let components = parse_key(vote_key).unwrap();
-        assert_eq!(components.get("usr_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAV");
-        assert_eq!(components.get("tag_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAW");
-        assert_eq!(components.get("tar_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAX");
-        assert_eq!(components.get("vote_key").unwrap(), "01ARZ3NDEKTSV4RRFFQ69G5FAY");
-        
-        // Test invalid key
-        let invalid_key = "invalid_key_format";
-        assert!(parse_key(invalid_key).is_err());
-    }
-    
-    #[test]
-    fn test_sanitize_for_key() {
-        assert_eq!(sanitize_for_key("John Doe"), "johndoe");
-        assert_eq!(sanitize_for_key("Technical-Skills!"), "technical-skills");
-        assert_eq!(sanitize_for_key("Programming_123"), "programming123");
-        assert_eq!(sanitize_for_key("   spaces   "), "spaces");
-        assert_eq!(sanitize_for_key("UPPERCASE"), "uppercase");
-        assert_eq!(sanitize_for_key("special@#$%^&*chars"), "specialchars");
+    fn test_parse_noncanonical() {
+        // Keys written before handles were NFKC-normalized
+        let legacy = format!("usr_{}_tag_{}_hdl_ｒｕｓｔ_", USER, TAG);
+        assert!(legacy.parse::<DocKey>().is_err());
+        let parsed = DocKey::parse_noncanonical(&legacy).unwrap();
+        assert_eq!(parsed.to_string(), format!("usr_{}_tag_{}_hdl_rust_", USER, TAG));
+
+        // Canonical keys parse to themselves
+        let canonical = format!("hdl_johndoe_usr_{}_", USER);
+        assert_eq!(DocKey::parse_noncanonical(&canonical).unwrap().to_string(), canonical);
+
+        // Malformed keys are still rejected
+        assert!(DocKey::parse_noncanonical("invalid_key_format").is_err());
+        assert!(DocKey::parse_noncanonical(&format!("usr_{}_tag_{}_hdl_py_", USER, TAG)).is_err());
     }
-} 
\ No newline at end of file
+}
@@ -7,9 +7,19 @@
  * Key Features:
  * - Uses key-based queries instead of description field filtering
  * 
- * - Avoids loading entire collections into memory
  * - Provides type-safe query segments
  * - Standardized key patterns for consistent querying
+ * - `DocQuery` reads result sets with an optional hard cap and optional paging
+ * 
+ * Cost:
+ * - Every `list_docs_store` call decodes every document of the collection to match its
+ *   key, whatever the pattern or page size, so a query costs O(collection size)
+ * - `start_after` is resolved by a linear scan of the matches
+ * - Paging therefore multiplies the cost by the number of pages, and `DocQuery` reads all
+ *   matches in a single call unless a page size is set. Set one only where a caller needs
+ *   a bounded page (list endpoints, migration batches), not to save memory
+ * - Use `max_results` to fail early when a pattern matches more documents than a caller
+ *   can process in one message
  * 
  * Key Patterns:
  * - Users:  usr_{ulid}_hdl_{handle}_
@@ -21,15 +31,19 @@
  */
 
 use junobuild_satellite::list_docs_store;
-use junobuild_shared::types::list::{ListMatcher, ListParams, ListResults};
+use junobuild_shared::types::list::{ListMatcher, ListOrder, ListOrderField, ListPaginate, ListParams, ListResults};
 use junobuild_satellite::Doc;
 use crate::logger;
+use crate::utils::errors::KarmieError;
+use crate::utils::metrics::record_query;
 use ic_cdk;
 
 /// Query documents by exact key pattern
 /// 
 /// This general-purpose function allows searching for documents using any key pattern.
 /// It performs an efficient key-based query without loading the entire collection.
+/// Every matching document is returned at once, so prefer `DocQuery` for patterns that
+/// can match many documents (all votes of a tag, all reputations, ...).
 /// 
 /// # Arguments
 /// * `collection` - The collection to search in ("users", "tags", "votes", "reputations")
@@ -76,9 +90,239 @@ pub fn query_doc_by_key(
             }),
             ..Default::default()
         },
-    ).map_err(|e| {
+    ).inspect(|results| {
+        record_query(collection, results.items.len());
+    }).map_err(|e| {
         logger!("error", "[query_doc_by_key] Query failed: collection={}, pattern={}, error={}", 
             collection, key_pattern, e);
         e
     })
-} 
\ No newline at end of file
+} 
+/// Paginated query over a collection by key pattern
+/// 
+/// Built with `DocQuery::new` and configured with chained calls, then consumed with
+/// `iter()`. Without `page_size`, all matches are fetched by one `list_docs_store` call;
+/// with it, one page is fetched at a time as items are read (see the module doc for the cost).
+/// 
+/// # Examples
+/// ```rust
+/// // Count the reputations of a tag
+/// for item in DocQuery::new("reputations", &format!("tag_{}_", tag_key)).iter() {
+///     let (key, doc) = item?;
+///     // ...
+/// }
+/// 
+/// // Newest votes first, failing if more than 10,000 match
+/// let votes = DocQuery::new("votes", "")
+///     .order_by(ListOrderField::CreatedAt, true)
+///     .max_results(10_000)
+///     .iter()
+///     .collect::<Result<Vec<_>, KarmieError>>()?;
+/// ```
+#[derive(Clone)]
+pub struct DocQuery {
+    collection: String,
+    key_pattern: String,
+    description_pattern: Option<String>,
+    order: ListOrder,
+    page_size: Option<usize>,
+    start_after: Option<String>,
+    max_results: Option<usize>,
+}
+
+impl DocQuery {
+    /// Creates a query returning every document whose key matches the pattern, in key order
+    /// 
+    /// # Arguments
+    /// * `collection` - The collection to search in
+    /// * `key_pattern` - The key pattern to match (empty matches every document)
+    pub fn new(collection: &str, key_pattern: &str) -> Self {
+        DocQuery {
+            collection: collection.to_string(),
+            key_pattern: key_pattern.to_string(),
+            description_pattern: None,
+            order: ListOrder {
+                desc: false,
+                field: ListOrderField::Keys,
+            },
+            page_size: None,
+            start_after: None,
+            max_results: None,
+        }
+    }
+
+    /// Only returns the documents whose description also matches this pattern
+    /// 
+    /// Descriptions written by the satellite use the key segment format (see `KeyPattern`).
+    pub fn description(mut self, description_pattern: &str) -> Self {
+        self.description_pattern = Some(description_pattern.to_string());
+        self
+    }
+
+    /// Orders the documents by key, creation or update time
+    pub fn order_by(mut self, field: ListOrderField, desc: bool) -> Self {
+        self.order = ListOrder { desc, field };
+        self
+    }
+
+    /// Fetches the documents in pages of this size (at least 1) instead of all at once
+    /// 
+    /// Each page decodes the whole collection again, so only set this when the caller
+    /// reads a bounded number of documents.
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = Some(page_size.max(1));
+        self
+    }
+
+    /// Starts after the document with this key, in the query's order
+    /// 
+    /// If no matching document has this key, the query returns nothing.
+    pub fn start_after(mut self, key: &str) -> Self {
+        self.start_after = Some(key.to_string());
+        self
+    }
+
+    /// Fails with `KarmieError::LimitExceeded` when more documents than this match
+    /// 
+    /// The number of matches is known from the first page, so nothing is returned
+    /// before the error.
+    pub fn max_results(mut self, max_results: usize) -> Self {
+        self.max_results = Some(max_results);
+        self
+    }
+
+    /// Lazily iterates over the matching documents
+    pub fn iter(self) -> DocIter {
+        DocIter {
+            query: self,
+            page: Vec::new().into_iter(),
+            first_page: true,
+            finished: false,
+        }
+    }
+
+    /// Fetches the page after `start_after`
+    fn fetch_page(&self) -> Result<ListResults<Doc>, KarmieError> {
+        logger!("debug", "[DocQuery] Fetching page: collection={}, pattern={}, start_after={:?}, limit={:?}",
+            self.collection, self.key_pattern, self.start_after, self.page_size);
+
+        list_docs_store(
+            ic_cdk::id(),
+            self.collection.clone(),
+            &ListParams {
+                matcher: Some(ListMatcher {
+                    key: Some(self.key_pattern.clone()),
+                    description: self.description_pattern.clone(),
+                    ..Default::default()
+                }),
+                paginate: Some(ListPaginate {
+                    start_after: self.start_after.clone(),
+                    limit: self.page_size,
+                }),
+                order: Some(self.order.clone()),
+                owner: None,
+            },
+        )
+        .inspect(|results| record_query(&self.collection, results.items.len()))
+        .map_err(|e| {
+            logger!("error", "[DocQuery] Query failed: collection={}, pattern={}, error={}",
+                self.collection, self.key_pattern, e);
+            KarmieError::storage(&self.collection, e)
+        })
+    }
+}
+
+/// Iterator returned by `DocQuery::iter`
+/// 
+/// Yields `(key, doc)` pairs and stops after the first error.
+pub struct DocIter {
+    query: DocQuery,
+    page: std::vec::IntoIter<(String, Doc)>,
+    first_page: bool,
+    finished: bool,
+}
+
+impl Iterator for DocIter {
+    type Item = Result<(String, Doc), KarmieError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(item) = self.page.next() {
+            return Some(Ok(item));
+        }
+        if self.finished {
+            return None;
+        }
+
+        let results = match self.query.fetch_page() {
+            Ok(results) => results,
+            Err(e) => {
+                self.finished = true;
+                return Some(Err(e));
+            }
+        };
+
+        if self.first_page {
+            self.first_page = false;
+            if let Some(max) = exceeded_cap(results.matches_length, self.query.max_results) {
+                self.finished = true;
+                return Some(Err(KarmieError::LimitExceeded {
+                    what: format!("{} matching \"{}\"", self.query.collection, self.query.key_pattern),
+                    max: max as u64,
+                    actual: results.matches_length as u64,
+                }));
+            }
+        }
+
+        // A short page is the last one
+        self.finished = is_last_page(results.items.len(), self.query.page_size);
+        self.query.start_after = results.items.last().map(|(key, _)| key.clone());
+
+        self.page = results.items.into_iter();
+        self.page.next().map(Ok)
+    }
+}
+
+/// The cap a query's matches exceed, if any
+fn exceeded_cap(matches: usize, max_results: Option<usize>) -> Option<usize> {
+    max_results.filter(|max| matches > *max)
+}
+
+/// Whether a page with this many items ends the iteration
+/// 
+/// An unpaged query returns every match at once.
+fn is_last_page(items: usize, page_size: Option<usize>) -> bool {
+    page_size.is_none_or(|page_size| items < page_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let query = DocQuery::new("votes", "tag_T_")
+            .order_by(ListOrderField::CreatedAt, true)
+            .page_size(0)
+            .start_after("usr_A_")
+            .max_results(10);
+
+        assert_eq!(query.page_size, Some(1));
+        assert!(query.order.desc);
+        assert!(matches!(query.order.field, ListOrderField::CreatedAt));
+        assert_eq!(query.start_after.as_deref(), Some("usr_A_"));
+        assert_eq!(query.max_results, Some(10));
+        assert_eq!(DocQuery::new("votes", "").page_size, None);
+    }
+
+    #[test]
+    fn test_cap_and_last_page() {
+        assert_eq!(exceeded_cap(11, Some(10)), Some(10));
+        assert_eq!(exceeded_cap(10, Some(10)), None);
+        assert_eq!(exceeded_cap(1_000_000, None), None);
+
+        assert!(is_last_page(0, Some(100)));
+        assert!(is_last_page(99, Some(100)));
+        assert!(!is_last_page(100, Some(100)));
+        assert!(is_last_page(100_000, None));
+    }
+}
@@ -27,9 +27,15 @@ use serde::{Deserialize, Serialize};
 use candid::CandidType;
 
 use crate::utils::structs::{VoteData, UserData, ReputationData};
-use crate::processors::document_queries::query_doc_by_key;
+use crate::processors::document_queries::{query_doc_by_key, DocQuery};
+use crate::processors::key_pattern::{KeyPattern, KeySegment};
+use crate::core::{apply_duplicate_vote_policy, VoteIdentity};
+use crate::core::reputation_calculations::get_tag_doc;
 use crate::logger;
 
+/// Maximum number of votes a graph can be built from
+pub const MAX_GRAPH_VOTES: usize = 10_000;
+
 /// Graph data structure for Sigma.js visualization
 #[derive(Serialize, Deserialize, Clone, Debug, CandidType)]
 pub struct GraphData {
@@ -83,6 +89,10 @@ pub async fn get_graph_data(ulid: String, query_type: String) -> Result<GraphDat
     // Fetch votes based on query type
     let votes = fetch_votes_by_type(&ulid, &query_type).await?;
     logger!("info", "[get_graph_data] Found {} votes for type={}", votes.len(), query_type);
+
+    // Only keep votes that count under each tag's duplicate vote policy,
+    // so edge counts match what the reputation engine uses
+    let votes = apply_tag_vote_policies(votes).await;
     
     // Process votes into smart edges
     let edges = process_votes_into_smart_edges(votes)?;
@@ -104,13 +114,13 @@ async fn fetch_votes_by_type(ulid: &str, query_type: &str) -> Result<Vec<VoteDat
             if ulid.is_empty() {
                 return Err("Tag ULID cannot be empty for tag query".to_string());
             }
-            format!("tag_{}_", ulid)
+            KeyPattern::new().tag(ulid).build()
         },
         "user" => {
             if ulid.is_empty() {
                 return Err("User ULID cannot be empty for user query".to_string());
             }
-            format!("_{}_", ulid)
+            KeyPattern::new().one_of(&[KeySegment::Usr, KeySegment::Tar], ulid).build()
         },
         "all" => {
             String::new() // Empty pattern fetches all documents
@@ -122,17 +132,11 @@ async fn fetch_votes_by_type(ulid: &str, query_type: &str) -> Result<Vec<VoteDat
     
     logger!("debug", "[fetch_votes_by_type] Using key pattern: '{}'", key_pattern);
     
-    let votes_result = query_doc_by_key("votes", &key_pattern)
-        .map_err(|e| format!("Failed to fetch votes: {}", e))?;
-    
-    extract_vote_data(votes_result.items.into_iter().map(|(_, doc)| doc).collect())
-}
-
-/// Extracts vote data from Juno documents
-fn extract_vote_data(docs: Vec<junobuild_satellite::Doc>) -> Result<Vec<VoteData>, String> {
+    // Larger graphs fail explicitly instead of exhausting the query's memory
+    // and instructions
     let mut votes = Vec::new();
-    
-    for doc in docs {
+    for item in DocQuery::new("votes", &key_pattern).max_results(MAX_GRAPH_VOTES).iter() {
+        let (_, doc) = item.map_err(|e| format!("Failed to fetch votes: {}", e))?;
         let vote_data: VoteData = decode_doc_data(&doc.data)
             .map_err(|e| format!("Failed to decode vote data: {}", e))?;
         votes.push(vote_data);
@@ -141,6 +145,36 @@ fn extract_vote_data(docs: Vec<junobuild_satellite::Doc>) -> Result<Vec<VoteData
     Ok(votes)
 }
 
+/// Applies each tag's duplicate vote policy to a list of votes
+/// 
+/// Votes can span several tags ("user" and "all" queries), so they are split
+/// by tag and filtered with that tag's settings. If a tag can't be loaded,
+/// its votes are kept as-is rather than failing the whole graph.
+async fn apply_tag_vote_policies(votes: Vec<VoteData>) -> Vec<VoteData> {
+    let mut votes_by_tag: HashMap<String, Vec<VoteData>> = HashMap::new();
+    for vote in votes {
+        votes_by_tag.entry(vote.tag_ulid.clone()).or_default().push(vote);
+    }
+
+    let mut filtered = Vec::new();
+    for (tag_ulid, tag_votes) in votes_by_tag {
+        match get_tag_doc(&tag_ulid).await {
+            Ok(tag) => filtered.extend(apply_duplicate_vote_policy(
+                tag_votes,
+                tag.data.duplicate_vote_policy,
+                tag.data.duplicate_vote_cap,
+                |vote| Some(VoteIdentity::from_vote(vote)),
+            )),
+            Err(e) => {
+                logger!("warn", "[apply_tag_vote_policies] Could not load tag={}, keeping all its votes: {}", tag_ulid, e);
+                filtered.extend(tag_votes);
+            }
+        }
+    }
+
+    filtered
+}
+
 /// Processes votes with smart bidirectional grouping for cleaner visualization
 fn process_votes_into_smart_edges(votes: Vec<VoteData>) -> Result<Vec<GraphEdge>, String> {
     // Step 1: Group votes by user pairs (unordered) and direction within each pair
@@ -361,7 +395,7 @@ async fn fetch_user_node_data(
     tag_ulid: Option<&String>
 ) -> Result<GraphNode, String> {
     // Fetch user document
-    let user_result = query_doc_by_key("users", &format!("usr_{}_", user_ulid))
+    let user_result = query_doc_by_key("users", &KeyPattern::new().usr(user_ulid).build())
         .map_err(|e| format!("Failed to query user {}: {}", user_ulid, e))?;
     
     if user_result.items.is_empty() {
@@ -389,7 +423,7 @@ async fn fetch_user_node_data(
 
 /// Fetches user reputation for a specific tag
 async fn fetch_user_reputation(user_ulid: &str, tag_ulid: &str) -> Result<f64, String> {
-    let reputation_key = format!("usr_{}_tag_{}_", user_ulid, tag_ulid);
+    let reputation_key = KeyPattern::new().anchor_start().usr(user_ulid).tag(tag_ulid).anchor_end().build();
     let reputation_result = query_doc_by_key("reputations", &reputation_key)
         .map_err(|e| format!("Failed to query reputation: {}", e))?;
     
@@ -0,0 +1,247 @@
+/*!
+ * Typed key patterns for Juno's regex matchers
+ *
+ * `ListMatcher.key` is a regular expression, so a pattern written by hand with
+ * `format!` over-matches as soon as a value contains a regex metacharacter or appears
+ * in a segment other than the intended one.
+ * `KeyPattern` builds patterns from named segments instead:
+ * - Values are escaped, so they only ever match themselves
+ * - Every segment is matched whole: `tag_{ulid}_` starts at the beginning of the key or
+ *   after an underscore, and ends with the value's closing underscore
+ * - Segments added one after the other must be adjacent in the key, in that order
+ * - `anchor_start` and `anchor_end` anchor the pattern to the ends of the key
+ *
+ * # Examples
+ * ```rust
+ * // Votes cast by a user in a tag: ^_?usr_{user}_tag_{tag}_
+ * KeyPattern::new().anchor_start().usr(user_ulid).tag(tag_ulid).build();
+ *
+ * // A tag's delegations to a user: (?:^|_)tag_{tag}_del_{user}_$
+ * KeyPattern::new().tag(tag_ulid).del(user_ulid).anchor_end().build();
+ *
+ * // Votes cast or received by a user: (?:^|_)(?:usr|tar)_{user}_
+ * KeyPattern::new().one_of(&[KeySegment::Usr, KeySegment::Tar], user_ulid).build();
+ * ```
+ */
+
+use std::fmt;
+
+/// A named segment of a document key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeySegment {
+    /// `prn_{principal}_` (users)
+    Prn,
+    /// `usr_{ulid}_` (every collection)
+    Usr,
+    /// `tag_{ulid}_` (tags, votes, reputations, delegations)
+    Tag,
+    /// `tar_{ulid}_` (votes)
+    Tar,
+    /// `hdl_{handle}_` (users, tags)
+    Hdl,
+    /// `key_{ulid}_` (votes)
+    Key,
+    /// `del_{ulid}_` (delegations)
+    Del,
+    /// `skl_{skeleton}_` (descriptions of users_handles and users_handles_history, reserved_handles keys)
+    Skl,
+}
+
+impl KeySegment {
+    /// Name of the segment as written in keys
+    pub fn name(self) -> &'static str {
+        match self {
+            KeySegment::Prn => "prn",
+            KeySegment::Usr => "usr",
+            KeySegment::Tag => "tag",
+            KeySegment::Tar => "tar",
+            KeySegment::Hdl => "hdl",
+            KeySegment::Key => "key",
+            KeySegment::Del => "del",
+            KeySegment::Skl => "skl",
+        }
+    }
+}
+
+/// Builder of key regular expressions, see the module documentation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyPattern {
+    anchor_start: bool,
+    anchor_end: bool,
+    segments: Vec<String>,
+}
+
+impl KeyPattern {
+    /// An empty pattern, which matches every key
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The first segment must be the first segment of the key
+    ///
+    /// User keys start with an underscore (`_prn_`), which is allowed before it.
+    pub fn anchor_start(mut self) -> Self {
+        self.anchor_start = true;
+        self
+    }
+
+    /// The last segment must be the last segment of the key
+    pub fn anchor_end(mut self) -> Self {
+        self.anchor_end = true;
+        self
+    }
+
+    /// Adds a segment with an exact value
+    pub fn segment(mut self, segment: KeySegment, value: &str) -> Self {
+        self.segments.push(format!("{}_{}_", segment.name(), regex::escape(value)));
+        self
+    }
+
+    /// Adds a segment with any value
+    pub fn any(mut self, segment: KeySegment) -> Self {
+        self.segments.push(format!("{}_[^_]+_", segment.name()));
+        self
+    }
+
+    /// Adds a segment that can be any of several segments, with an exact value
+    pub fn one_of(mut self, segments: &[KeySegment], value: &str) -> Self {
+        let names: Vec<&str> = segments.iter().map(|segment| segment.name()).collect();
+        self.segments.push(format!("(?:{})_{}_", names.join("|"), regex::escape(value)));
+        self
+    }
+
+    /// Adds `prn_{principal}_`
+    pub fn prn(self, principal: &str) -> Self {
+        self.segment(KeySegment::Prn, principal)
+    }
+
+    /// Adds `usr_{ulid}_`
+    pub fn usr(self, user_ulid: &str) -> Self {
+        self.segment(KeySegment::Usr, user_ulid)
+    }
+
+    /// Adds `tag_{ulid}_`
+    pub fn tag(self, tag_ulid: &str) -> Self {
+        self.segment(KeySegment::Tag, tag_ulid)
+    }
+
+    /// Adds `tar_{ulid}_`
+    pub fn tar(self, target_ulid: &str) -> Self {
+        self.segment(KeySegment::Tar, target_ulid)
+    }
+
+    /// Adds `hdl_{handle}_`
+    pub fn hdl(self, handle: &str) -> Self {
+        self.segment(KeySegment::Hdl, handle)
+    }
+
+    /// Adds `del_{ulid}_`
+    pub fn del(self, delegate_ulid: &str) -> Self {
+        self.segment(KeySegment::Del, delegate_ulid)
+    }
+
+    /// Adds `skl_{skeleton}_`
+    pub fn skl(self, skeleton: &str) -> Self {
+        self.segment(KeySegment::Skl, skeleton)
+    }
+
+    /// Builds the regular expression to pass as `ListMatcher.key`
+    pub fn build(&self) -> String {
+        if self.segments.is_empty() {
+            return String::new();
+        }
+
+        let start = if self.anchor_start { "^_?" } else { "(?:^|_)" };
+        let end = if self.anchor_end { "$" } else { "" };
+        format!("{}{}{}", start, self.segments.concat(), end)
+    }
+}
+
+impl fmt::Display for KeyPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    const USER: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAV";
+    const OTHER: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAW";
+    const TAG: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAX";
+    const VOTE: &str = "01ARZ3NDEKTSV4RRFFQ69G5FAY";
+
+    fn matches(pattern: &KeyPattern, key: &str) -> bool {
+        Regex::new(&pattern.build()).unwrap().is_match(key)
+    }
+
+    fn vote_key(author: &str, target: &str) -> String {
+        format!("usr_{}_tag_{}_tar_{}_key_{}_", author, TAG, target, VOTE)
+    }
+
+    #[test]
+    fn test_build() {
+        assert_eq!(KeyPattern::new().build(), "");
+        assert_eq!(KeyPattern::new().tag("T").build(), "(?:^|_)tag_T_");
+        assert_eq!(KeyPattern::new().anchor_start().usr("U").tag("T").any(KeySegment::Tar).build(), "^_?usr_U_tag_T_tar_[^_]+_");
+        assert_eq!(KeyPattern::new().tag("T").anchor_end().to_string(), "(?:^|_)tag_T_$");
+    }
+
+    #[test]
+    fn test_segments_only_match_their_own_position() {
+        let by_author = KeyPattern::new().anchor_start().usr(USER).tag(TAG);
+        assert!(matches(&by_author, &vote_key(USER, OTHER)));
+        assert!(!matches(&by_author, &vote_key(OTHER, USER)));
+
+        let on_target = KeyPattern::new().tag(TAG).tar(USER);
+        assert!(matches(&on_target, &vote_key(OTHER, USER)));
+        assert!(!matches(&on_target, &vote_key(USER, OTHER)));
+
+        let by_or_on = KeyPattern::new().one_of(&[KeySegment::Usr, KeySegment::Tar], USER);
+        assert!(matches(&by_or_on, &vote_key(USER, OTHER)));
+        assert!(matches(&by_or_on, &vote_key(OTHER, USER)));
+        assert!(!matches(&by_or_on, &vote_key(OTHER, OTHER)));
+    }
+
+    #[test]
+    fn test_values_are_matched_whole() {
+        // A value is never a prefix or suffix match of a longer one
+        let short_tag = KeyPattern::new().tag("01ARZ");
+        assert!(!matches(&short_tag, &format!("usr_{}_tag_{}_", USER, TAG)));
+        assert!(!matches(&KeyPattern::new().tag("FAX"), &format!("usr_{}_tag_{}_", USER, TAG)));
+
+        // Segment names are matched whole too
+        assert!(!matches(&KeyPattern::new().tag(TAG), &format!("usr_{}_xtag_{}_", USER, TAG)));
+
+        // A handle must be the last segment to match, not a substring of another handle
+        let handle = KeyPattern::new().hdl("alice").anchor_end();
+        assert!(matches(&handle, &format!("_prn_aaaaa-aa_usr_{}_hdl_alice_", USER)));
+        assert!(!matches(&handle, &format!("_prn_aaaaa-aa_usr_{}_hdl_malice_", USER)));
+        assert!(!matches(&handle, &format!("_prn_aaaaa-aa_usr_{}_hdl_alice-x_", USER)));
+    }
+
+    #[test]
+    fn test_metacharacters_are_escaped() {
+        let pattern = KeyPattern::new().anchor_start().prn("a.b+c");
+        assert!(matches(&pattern, &format!("_prn_a.b+c_usr_{}_", USER)));
+        assert!(!matches(&pattern, &format!("_prn_axbbc_usr_{}_", USER)));
+
+        // A wildcard-looking value doesn't match everything
+        let everything = KeyPattern::new().hdl(".*");
+        assert!(!matches(&everything, &format!("_prn_aaaaa-aa_usr_{}_hdl_alice_", USER)));
+    }
+
+    #[test]
+    fn test_anchors() {
+        let reputation = KeyPattern::new().anchor_start().usr(USER).tag(TAG).anchor_end();
+        assert!(matches(&reputation, &format!("usr_{}_tag_{}_", USER, TAG)));
+        assert!(!matches(&reputation, &vote_key(USER, OTHER)));
+
+        // Users keys start with an underscore
+        let principal = KeyPattern::new().anchor_start().prn("aaaaa-aa");
+        assert!(matches(&principal, &format!("_prn_aaaaa-aa_usr_{}_hdl_alice_", USER)));
+        assert!(!matches(&KeyPattern::new().anchor_start().usr(USER), &format!("_prn_aaaaa-aa_usr_{}_", USER)));
+    }
+}
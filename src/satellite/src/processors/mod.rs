@@ -11,6 +11,7 @@ pub mod document_keys;
 pub mod ulid_timestamp_extract;
 pub mod ulid_type; 
 pub mod document_queries;
+pub mod key_pattern;
 pub mod username_availability;
 pub mod graph_processors;
 
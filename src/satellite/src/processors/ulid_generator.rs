@@ -17,6 +17,8 @@
 use ic_cdk;
 use ulid::Ulid;
 
+use crate::utils::errors::KarmieError;
+
 /// Generates a ULID (Universally Unique Lexicographically Sortable Identifier)
 /// 
 /// Uses IC's time for the timestamp and raw_rand function for randomness
@@ -63,28 +65,32 @@ pub async fn generate_ulid() -> String {
 /// * `ulid_str` - The ULID string to validate
 /// 
 /// # Returns
-/// * `Result<(), String>` - Ok if valid, Err with message if invalid
-pub fn validate_ulid(ulid_str: &str) -> Result<(), String> {
+/// * `Result<(), KarmieError>` - Ok if valid, `InvalidUlid` otherwise
+pub fn validate_ulid(ulid_str: &str) -> Result<(), KarmieError> {
+    let invalid = |reason: String| KarmieError::InvalidUlid {
+        ulid: ulid_str.to_string(),
+        reason,
+    };
+
     // Check length
     if ulid_str.len() != 26 {
-        return Err(format!("[validate_ulid] ULID must be exactly 26 characters, got {}", ulid_str.len()));
+        return Err(invalid(format!("ULID must be exactly 26 characters, got {}", ulid_str.len())));
     }
     
     // Check case
     if ulid_str != ulid_str.to_uppercase() {
-        return Err("[validate_ulid] ULID must be uppercase".to_string());
+        return Err(invalid("ULID must be uppercase".to_string()));
     }
     
     // Check character set (Crockford Base32)
     if !ulid_str.chars().all(|c| "0123456789ABCDEFGHJKMNPQRSTVWXYZ".contains(c)) {
-        return Err("[validate_ulid] ULID contains invalid characters".to_string());
+        return Err(invalid("ULID contains invalid characters".to_string()));
     }
     
     // Attempt to parse (validates format)
-    match Ulid::from_string(ulid_str) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!("[validate_ulid] Invalid ULID: {}", e))
-    }
+    Ulid::from_string(ulid_str)
+        .map(|_| ())
+        .map_err(|e| invalid(e.to_string()))
 }
 
 #[cfg(test)]
@@ -80,7 +80,7 @@ impl ULID {
         
         // Validate timestamp component (first 10 characters)
         if let Err(e) = validate_ulid_timestamp(&value, CheckULIDisNew::no()) {
-            return Err(e);
+            return Err(e.to_string());
         }
         
         Ok(ULID(value))
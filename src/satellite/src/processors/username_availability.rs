@@ -1,121 +1,83 @@
 /*!
- * Username Availability Checker using Full Collection Scan
- * 
- * This module provides functionality to check username availability using
- * the approach of filtering documents by their userDocument.data.user_handle field
- * 
- * **Performance Note**: This approach loads ALL user documents into memory and
- * filters them, which is less efficient than key-based queries but allows for
- * mutable usernames. Suitable for development and small user bases (<1000 users).
- * 
- * **Purpose**: This is a testing implementation to compare performance against the
- * current handle-in-key approach before implementing the full users_handles
- * index collection architecture.
+ * Username Availability Checker using the users_handles index
+ *
+ * This module provides functionality to check username availability by looking
+ * the handle up in the users_handles index collection (see core/users_handles.rs).
+ *
+ * **Performance Note**: The index is keyed by handle, so this is a key-based query
+ * whatever the number of users. Users that haven't been migrated to the index yet
+ * are found by the handle in their legacy key, also with a key-based query.
+ * Handles released recently are quarantined (see core/handle_renames.rs) and reported as taken.
+ * Look-alikes of a taken handle (same confusable skeleton, see utils/normalize.rs) and
+ * reserved handles (see core/reserved_handles.rs) are reported as taken too, with
+ * description-based and direct lookups.
  */
 
-use junobuild_satellite;
-use junobuild_utils::decode_doc_data;
-use crate::utils::structs::UserData;
-use crate::logger;
-use ic_cdk;
+use crate::core::{assert_handle_not_reserved, confusable_holders, handle_holders, is_handle_quarantined};
+use crate::utils::errors::KarmieError;
+use crate::utils::normalize::CanonicalHandle;
+use crate::validation::validate_handle;
 
-/// Checks if a username is available using full collection scan
-/// 
-/// This function demonstrates the new approach to username availability checking
-/// by filtering documents by their data.user_handle field instead of using key patterns.
-/// 
-/// **Performance Note**: This approach loads ALL user documents into memory and
-/// filters them, which is less efficient than key-based queries but allows for
-/// mutable usernames. Suitable for development and small user bases (<1000 users).
-/// 
-/// **Purpose**: This is a testing endpoint to compare performance against the
-/// current handle-in-key approach before implementing the full users_handles
-/// index collection architecture.
-/// 
+/// Checks if a username is available using the users_handles index
+///
+/// Usernames are validated and compared exactly like `assert_doc_user` does: same format
+/// rules (`validate_handle`) and same canonical form (`CanonicalHandle`), which is the
+/// form used in keys.
+///
 /// # Arguments
 /// * `username` - The username to check for availability
-/// 
+///
 /// # Returns
 /// * `Result<bool, String>` - Returns true if username is available (not taken),
 ///   false if username is already taken, or error message if operation fails
-/// 
+///
 /// # Process
-/// 1. Validates input username format and length
-/// 2. Normalizes username to lowercase for case-insensitive comparison
-/// 3. Uses list_docs_store to load ALL user documents
-/// 4. Filters documents to find any with matching user_handle
-/// 5. Returns availability status based on search results
-/// 
+/// 1. Validates the username format
+/// 2. Canonicalizes the username like in keys
+/// 3. Looks the handle up in the index and in legacy user keys
+/// 4. Looks up the holders of look-alike handles
+/// 5. Checks that the handle isn't reserved for someone other than the caller
+/// 6. Checks that the handle isn't quarantined after a recent release
+/// 7. Returns availability status based on search results
+///
 /// # Errors
-/// - Returns error if username is empty or invalid format
-/// - Returns error if list_docs_store fails
-/// - Returns error if document data decoding fails
+/// - Returns error if the username format is invalid
+/// - Returns error if the index query fails
 pub async fn check_username_availability_v2(username: String) -> Result<bool, String> {
-    // NOTE: Logs commented out since query functions don't persist logs to Juno console
-    // logger!("debug", "[check_username_availability_v2] Checking availability for username: {}", username);
-    
-    // Input validation
-    if username.is_empty() {
-        let err_msg = "[check_username_availability_v2] Username cannot be empty";
-        // logger!("error", "{}", err_msg);
-        return Err(err_msg.to_string());
+    // NOTE: No logs since query functions don't persist logs to Juno console
+
+    // Input validation, with the rules of the user document assert
+    validate_handle(&username)
+        .map_err(|e| format!("[check_username_availability_v2] {}", e.message()))?;
+
+    // Canonicalize username like in keys, which is what uniqueness is enforced on
+    let handle = CanonicalHandle::new(&username);
+
+    let holders = handle_holders(handle.as_str())
+        .map_err(|e| format!("[check_username_availability_v2] Failed to look up username: {}", e))?;
+
+    if !holders.is_empty() {
+        return Ok(false);
     }
-    
-    if username.len() < 3 {
-        let err_msg = "[check_username_availability_v2] Username must be at least 3 characters";
-        // logger!("error", "{}", err_msg);
-        return Err(err_msg.to_string());
+
+    // Look-alikes of a held handle can't be taken
+    let look_alikes = confusable_holders(handle.as_str())
+        .map_err(|e| format!("[check_username_availability_v2] Failed to look up similar usernames: {}", e))?;
+
+    if !look_alikes.is_empty() {
+        return Ok(false);
     }
-    
-    // Normalize username for case-insensitive comparison
-    let normalized_username = username.trim().to_lowercase();
-    // logger!("debug", "[check_username_availability_v2] Normalized username: {}", normalized_username);
-    
-    // Use list_docs_store to get ALL user documents (this loads everything into memory)
-    let list_docs_result = junobuild_satellite::list_docs_store(
-        ic_cdk::id(),  // Use canister's ID for admin/controller access
-        "users".to_string(),
-        &junobuild_shared::types::list::ListParams {
-            matcher: None,
-            paginate: None,
-            order: None,
-            owner: None,
-        }
-    );
-    
-    match list_docs_result {
-        Ok(docs_result) => {
-            // logger!("debug", "[check_username_availability_v2] Loaded {} user documents for filtering", docs_result.items.len());
-            
-            // Filter through all documents to find matching usernames
-            for (doc_key, doc) in &docs_result.items {
-                // Decode the document data to access the user_handle field
-                match decode_doc_data::<UserData>(&doc.data) {
-                    Ok(user_data) => {
-                        let existing_handle = user_data.user_handle.trim().to_lowercase();
-                        // logger!("debug", "[check_username_availability_v2] Checking document key={}, handle={}", doc_key, existing_handle);
-                        
-                        if existing_handle == normalized_username {
-                            // logger!("info", "[check_username_availability_v2] Username '{}' is TAKEN (found in document: {})", username, doc_key);
-                            return Ok(false); // Username is taken
-                        }
-                    },
-                    Err(_e) => {
-                        // logger!("error", "[check_username_availability_v2] Failed to decode user data for document {}: {}", doc_key, e);
-                        // Continue checking other documents instead of failing completely
-                        continue;
-                    }
-                }
-            }
-            
-            // If we get here, no matching username was found
-            // logger!("info", "[check_username_availability_v2] Username '{}' is AVAILABLE (checked {} documents)", username, docs_result.items.len());
-            Ok(true) // Username is available
-        },
-        Err(e) => {
-            let err_msg = format!("[check_username_availability_v2] Failed to list user documents: {}", e);
-            // logger!("error", "{}", err_msg);
-            Err(err_msg)
-        }
+
+    // Reserved handles are only available to the principal they are reserved for
+    match assert_handle_not_reserved(handle.as_str(), ic_cdk::caller()) {
+        Ok(()) => {}
+        Err(KarmieError::HandleReserved { .. }) => return Ok(false),
+        Err(e) => return Err(format!("[check_username_availability_v2] Failed to look up reserved handles: {}", e)),
     }
-} 
\ No newline at end of file
+
+    // Handles released by a rename or an account deletion are quarantined for a while
+    let quarantined = is_handle_quarantined(handle.as_str())
+        .map_err(|e| format!("[check_username_availability_v2] Failed to look up handle history: {}", e))?;
+
+    Ok(!quarantined)
+}
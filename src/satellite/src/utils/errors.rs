@@ -0,0 +1,315 @@
+/*!
+ * Typed errors
+ *
+ * `KarmieError` is the error type of validation, the reputation engine, the key
+ * utilities and the public endpoints. Each variant has a stable code that clients
+ * can match on instead of parsing English messages:
+ * - Endpoints return the enum itself through Candid (`Err : KarmieError`)
+ * - Juno assertions can only reject with a string, so they use the `Display` form
+ *   `KARMIE_<CODE>: <message>`, e.g. `KARMIE_SELF_VOTE: Users cannot vote on themselves`
+ *
+ * Codes are part of the public API: never rename or reuse one, only add new ones.
+ * Helpers that still return `String` (Juno APIs, internal queries) convert through
+ * `From`, which wraps the message as `Internal`.
+ */
+
+use std::fmt;
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+/// Errors returned by the satellite
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, CandidType)]
+pub enum KarmieError {
+    /// A document's data couldn't be decoded
+    InvalidData { collection: String, reason: String },
+
+    /// A field has a value outside of what's allowed
+    InvalidField { field: String, reason: String },
+
+    /// A ULID is malformed, or its timestamp is too old or in the future
+    InvalidUlid { ulid: String, reason: String },
+
+    /// A document key is malformed
+    InvalidKey { key: String, reason: String },
+
+    /// A document key doesn't match the document's data
+    KeyMismatch { expected: String, actual: String },
+
+    /// A field that's part of the document key was changed in an update
+    ImmutableField { field: String },
+
+    /// The username is already used by another user
+    HandleTaken { handle: String },
+
+    /// The username was released by another user too recently to be claimed
+    HandleQuarantined { handle: String, available_in_seconds: u64 },
+
+    /// The handle can be mistaken for another user's or tag's handle
+    HandleConfusable { handle: String, similar_to: String },
+
+    /// The handle is reserved or blocked
+    HandleReserved { handle: String },
+
+    /// The tag name is already used by another tag
+    TagNameTaken { name: String },
+
+    /// A field contains a term that the moderation term list rejects
+    ContentRejected { field: String },
+
+    /// The principal already owns a user document
+    AccountExists { principal: String },
+
+    /// A user tried to vote on themselves
+    SelfVote,
+
+    /// A user tried to delegate to themselves
+    SelfDelegation,
+
+    /// A referenced document doesn't exist
+    NotFound { entity: String, id: String },
+
+    /// The caller isn't the user the document claims to be written by
+    CallerMismatch { field: String, claimed: String, actual: String },
+
+    /// The caller isn't allowed to perform the operation
+    Unauthorized { reason: String },
+
+    /// The author voted on the same target too recently
+    VoteCooldown { remaining_minutes: u32 },
+
+    /// The tag's duplicate vote policy rejects another vote on the same target
+    DuplicateVote { reason: String },
+
+    /// The author can't afford the vote
+    InsufficientCredits { balance: u32, cost: u32 },
+
+    /// The delegation would create a cycle or an over-long chain, or conflicts with another one
+    DelegationRejected { reason: String },
+
+    /// The caller exceeded their write budget for a collection
+    RateLimited { collection: String, retry_after_seconds: u64 },
+
+    /// A request exceeds a size limit
+    LimitExceeded { what: String, max: u64, actual: u64 },
+
+    /// Reading or writing a collection failed
+    Storage { collection: String, reason: String },
+
+    /// Any other failure
+    Internal { message: String },
+}
+
+impl KarmieError {
+    /// Stable code of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            KarmieError::InvalidData { .. } => "KARMIE_INVALID_DATA",
+            KarmieError::InvalidField { .. } => "KARMIE_INVALID_FIELD",
+            KarmieError::InvalidUlid { .. } => "KARMIE_INVALID_ULID",
+            KarmieError::InvalidKey { .. } => "KARMIE_INVALID_KEY",
+            KarmieError::KeyMismatch { .. } => "KARMIE_KEY_MISMATCH",
+            KarmieError::ImmutableField { .. } => "KARMIE_IMMUTABLE_FIELD",
+            KarmieError::HandleTaken { .. } => "KARMIE_HANDLE_TAKEN",
+            KarmieError::HandleQuarantined { .. } => "KARMIE_HANDLE_QUARANTINED",
+            KarmieError::HandleConfusable { .. } => "KARMIE_HANDLE_CONFUSABLE",
+            KarmieError::HandleReserved { .. } => "KARMIE_HANDLE_RESERVED",
+            KarmieError::TagNameTaken { .. } => "KARMIE_TAG_NAME_TAKEN",
+            KarmieError::ContentRejected { .. } => "KARMIE_CONTENT_REJECTED",
+            KarmieError::AccountExists { .. } => "KARMIE_ACCOUNT_EXISTS",
+            KarmieError::SelfVote => "KARMIE_SELF_VOTE",
+            KarmieError::SelfDelegation => "KARMIE_SELF_DELEGATION",
+            KarmieError::NotFound { .. } => "KARMIE_NOT_FOUND",
+            KarmieError::CallerMismatch { .. } => "KARMIE_CALLER_MISMATCH",
+            KarmieError::Unauthorized { .. } => "KARMIE_UNAUTHORIZED",
+            KarmieError::VoteCooldown { .. } => "KARMIE_VOTE_COOLDOWN",
+            KarmieError::DuplicateVote { .. } => "KARMIE_DUPLICATE_VOTE",
+            KarmieError::InsufficientCredits { .. } => "KARMIE_INSUFFICIENT_CREDITS",
+            KarmieError::DelegationRejected { .. } => "KARMIE_DELEGATION_REJECTED",
+            KarmieError::RateLimited { .. } => "KARMIE_RATE_LIMITED",
+            KarmieError::LimitExceeded { .. } => "KARMIE_LIMIT_EXCEEDED",
+            KarmieError::Storage { .. } => "KARMIE_STORAGE",
+            KarmieError::Internal { .. } => "KARMIE_INTERNAL",
+        }
+    }
+
+    /// Human-readable message, without the code
+    pub fn message(&self) -> String {
+        match self {
+            KarmieError::InvalidData { collection, reason } => format!("Invalid {} data: {}", collection, reason),
+            KarmieError::InvalidField { field, reason } => format!("Invalid {}: {}", field, reason),
+            KarmieError::InvalidUlid { ulid, reason } => format!("Invalid ULID '{}': {}", ulid, reason),
+            KarmieError::InvalidKey { key, reason } => format!("Invalid key '{}': {}", key, reason),
+            KarmieError::KeyMismatch { expected, actual } => {
+                format!("Document key does not match its data (expected: {}, got: {})", expected, actual)
+            }
+            KarmieError::ImmutableField { field } => {
+                format!("Field '{}' cannot be changed on an existing document. Delete it and create a new one instead.", field)
+            }
+            KarmieError::HandleTaken { handle } => {
+                format!("Username '{}' is already taken. Please choose a different username.", handle)
+            }
+            KarmieError::HandleQuarantined { handle, available_in_seconds } => format!(
+                "Username '{}' was recently used by another user and can be claimed in {} seconds",
+                handle, available_in_seconds
+            ),
+            KarmieError::HandleConfusable { handle, similar_to } => format!(
+                "'{}' looks too similar to the existing '{}'. Please choose a different name.",
+                handle, similar_to
+            ),
+            KarmieError::HandleReserved { handle } => format!("'{}' is reserved and cannot be used", handle),
+            KarmieError::TagNameTaken { name } => format!("Tag name '{}' is already taken", name),
+            KarmieError::ContentRejected { field } => format!("The {} contains language that isn't allowed", field),
+            KarmieError::AccountExists { principal } => format!("Principal {} already has an account", principal),
+            KarmieError::SelfVote => "Users cannot vote on themselves".to_string(),
+            KarmieError::SelfDelegation => "Users cannot delegate to themselves".to_string(),
+            KarmieError::NotFound { entity, id } => format!("{} not found: {}", entity, id),
+            KarmieError::CallerMismatch { field, claimed, actual } => {
+                format!("{} mismatch: document claims '{}' but the caller is '{}'", field, claimed, actual)
+            }
+            KarmieError::Unauthorized { reason } => format!("Unauthorized: {}", reason),
+            KarmieError::VoteCooldown { remaining_minutes } => {
+                format!("Must wait {} more minutes before voting on this user again", remaining_minutes)
+            }
+            KarmieError::DuplicateVote { reason } => reason.clone(),
+            KarmieError::InsufficientCredits { balance, cost } => {
+                format!("Not enough voting credits (balance: {}, cost: {})", balance, cost)
+            }
+            KarmieError::DelegationRejected { reason } => reason.clone(),
+            KarmieError::RateLimited { collection, retry_after_seconds } => format!(
+                "Rate limit exceeded for '{}', retry in {} seconds",
+                collection, retry_after_seconds
+            ),
+            KarmieError::LimitExceeded { what, max, actual } => {
+                format!("Too many {} (max: {}, got: {})", what, max, actual)
+            }
+            KarmieError::Storage { collection, reason } => format!("Storage error in '{}': {}", collection, reason),
+            KarmieError::Internal { message } => message.clone(),
+        }
+    }
+
+    /// Shorthand for `NotFound`
+    pub fn not_found(entity: &str, id: &str) -> Self {
+        KarmieError::NotFound {
+            entity: entity.to_string(),
+            id: id.to_string(),
+        }
+    }
+
+    /// Shorthand for `InvalidField`
+    pub fn invalid_field(field: &str, reason: impl Into<String>) -> Self {
+        KarmieError::InvalidField {
+            field: field.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Shorthand for `InvalidData`
+    pub fn invalid_data(collection: &str, reason: impl fmt::Display) -> Self {
+        KarmieError::InvalidData {
+            collection: collection.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Shorthand for `Storage`
+    pub fn storage(collection: &str, reason: impl fmt::Display) -> Self {
+        KarmieError::Storage {
+            collection: collection.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for KarmieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl From<KarmieError> for String {
+    fn from(error: KarmieError) -> Self {
+        error.to_string()
+    }
+}
+
+impl From<String> for KarmieError {
+    /// Wraps a plain message as `Internal`
+    fn from(message: String) -> Self {
+        KarmieError::Internal { message }
+    }
+}
+
+impl From<&str> for KarmieError {
+    fn from(message: &str) -> Self {
+        KarmieError::Internal {
+            message: message.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_variants() -> Vec<KarmieError> {
+        vec![
+            KarmieError::invalid_data("votes", "eof"),
+            KarmieError::invalid_field("handle", "too short"),
+            KarmieError::InvalidUlid { ulid: "X".into(), reason: "too short".into() },
+            KarmieError::InvalidKey { key: "k".into(), reason: "bad".into() },
+            KarmieError::KeyMismatch { expected: "a".into(), actual: "b".into() },
+            KarmieError::ImmutableField { field: "tag_ulid".into() },
+            KarmieError::HandleTaken { handle: "alice".into() },
+            KarmieError::HandleQuarantined { handle: "alice".into(), available_in_seconds: 60 },
+            KarmieError::HandleConfusable { handle: "аlice".into(), similar_to: "alice".into() },
+            KarmieError::HandleReserved { handle: "admin".into() },
+            KarmieError::TagNameTaken { name: "rust".into() },
+            KarmieError::ContentRejected { field: "display_name".into() },
+            KarmieError::AccountExists { principal: "p".into() },
+            KarmieError::SelfVote,
+            KarmieError::SelfDelegation,
+            KarmieError::not_found("Tag", "T"),
+            KarmieError::CallerMismatch { field: "owner_ulid".into(), claimed: "A".into(), actual: "B".into() },
+            KarmieError::Unauthorized { reason: "controllers only".into() },
+            KarmieError::VoteCooldown { remaining_minutes: 5 },
+            KarmieError::DuplicateVote { reason: "one vote".into() },
+            KarmieError::InsufficientCredits { balance: 1, cost: 2 },
+            KarmieError::DelegationRejected { reason: "cycle".into() },
+            KarmieError::RateLimited { collection: "votes".into(), retry_after_seconds: 30 },
+            KarmieError::LimitExceeded { what: "users".into(), max: 100, actual: 101 },
+            KarmieError::storage("votes", "full"),
+            KarmieError::Internal { message: "oops".into() },
+        ]
+    }
+
+    #[test]
+    fn test_codes_are_unique_and_prefixed() {
+        let variants = all_variants();
+        let mut codes: Vec<&str> = variants.iter().map(|error| error.code()).collect();
+        assert!(codes.iter().all(|code| code.starts_with("KARMIE_")));
+
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), variants.len());
+    }
+
+    #[test]
+    fn test_string_form_starts_with_code() {
+        assert_eq!(KarmieError::SelfVote.to_string(), "KARMIE_SELF_VOTE: Users cannot vote on themselves");
+        assert_eq!(
+            String::from(KarmieError::VoteCooldown { remaining_minutes: 5 }),
+            "KARMIE_VOTE_COOLDOWN: Must wait 5 more minutes before voting on this user again"
+        );
+        for error in all_variants() {
+            assert!(error.to_string().starts_with(&format!("{}: ", error.code())));
+        }
+    }
+
+    #[test]
+    fn test_plain_messages_become_internal() {
+        let error: KarmieError = "Tag key cannot be empty".to_string().into();
+        assert_eq!(error.code(), "KARMIE_INTERNAL");
+        assert_eq!(error.to_string(), "KARMIE_INTERNAL: Tag key cannot be empty");
+    }
+}
@@ -0,0 +1,207 @@
+/*!
+ * Runtime log levels
+ *
+ * Decides which `logger!` calls are emitted. The macro asks `log_enabled` before
+ * formatting anything, so filtered messages cost a level comparison instead of a
+ * `format!` and a write to Juno's log.
+ *
+ * # Levels
+ * A message is emitted if its level is at or above the minimum level of its module:
+ * - The override whose module path matches the most segments, if any
+ * - The global `min_level` otherwise
+ *
+ * Overrides are module paths matched on whole `::` segments, with or without the crate
+ * name: "core::events" applies to `satellite::core::events` and its submodules, and
+ * "core" to every module under `core`.
+ *
+ * # Compile-time stripping
+ * With the `strip-debug-logs` feature, `logger!("debug", ...)` calls are removed at
+ * compile time whatever the runtime configuration says.
+ *
+ * # State
+ * The configuration lives in heap memory, since every `logger!` call reads it. Controllers
+ * change it with `set_log_levels`, which also saves it to the `settings` collection so that
+ * it is restored after upgrades (see utils/settings.rs).
+ */
+
+use std::cell::RefCell;
+
+use candid::{CandidType, Principal};
+use junobuild_satellite::get_controllers;
+use junobuild_shared::controllers::is_controller;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::errors::KarmieError;
+use crate::utils::log_store::{is_valid_module_path, LogLevel};
+use crate::utils::settings::{load_setting, save_setting};
+
+/// Name of the saved configuration in the settings collection
+const LOG_LEVELS_SETTING: &str = "log_levels";
+
+/// Maximum number of per-module overrides
+pub const MAX_MODULE_OVERRIDES: usize = 50;
+
+/// Minimum levels applied to `logger!` calls
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, CandidType)]
+pub struct LogLevelConfig {
+    /// Minimum level of modules without an override
+    pub min_level: LogLevel,
+
+    /// (module path, minimum level) overrides
+    pub module_overrides: Vec<(String, LogLevel)>,
+}
+
+impl Default for LogLevelConfig {
+    fn default() -> Self {
+        // Debug logs in the reputation loops are too expensive to emit by default
+        LogLevelConfig {
+            min_level: LogLevel::Info,
+            module_overrides: Vec::new(),
+        }
+    }
+}
+
+impl LogLevelConfig {
+    /// Minimum level of a module
+    ///
+    /// # Arguments
+    /// * `module` - `module_path!()` of the call site
+    ///
+    /// # Returns
+    /// * `LogLevel` - The level of the most specific matching override, or the global level
+    pub fn level_for(&self, module: &str) -> LogLevel {
+        if self.module_overrides.is_empty() {
+            return self.min_level;
+        }
+
+        // Surround with separators so that overrides only match whole segments
+        let module = format!("::{}::", module);
+        self.module_overrides
+            .iter()
+            .filter(|(prefix, _)| module.contains(&format!("::{}::", prefix)))
+            .max_by_key(|(prefix, _)| prefix.split("::").count())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.min_level)
+    }
+}
+
+thread_local! {
+    static LOG_LEVELS: RefCell<LogLevelConfig> = RefCell::new(LogLevelConfig::default());
+}
+
+/// Whether a `logger!` call should be emitted
+///
+/// Called by the `logger!` macro before the message is formatted.
+///
+/// # Arguments
+/// * `level` - The level passed to `logger!`
+/// * `module` - `module_path!()` of the call site
+pub fn log_enabled(level: &str, module: &str) -> bool {
+    let level = LogLevel::from_logger(level);
+    LOG_LEVELS.with(|config| level >= config.borrow().level_for(module))
+}
+
+/// Checks a configuration before it is applied
+pub fn validate_log_level_config(config: &LogLevelConfig) -> Result<(), KarmieError> {
+    if config.module_overrides.len() > MAX_MODULE_OVERRIDES {
+        return Err(KarmieError::LimitExceeded {
+            what: "module_overrides".to_string(),
+            max: MAX_MODULE_OVERRIDES as u64,
+            actual: config.module_overrides.len() as u64,
+        });
+    }
+
+    for (module, _) in &config.module_overrides {
+        if !is_valid_module_path(module) {
+            return Err(KarmieError::invalid_field(
+                "module_overrides",
+                format!("must contain module paths such as core::events (got: {})", module),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the log level configuration
+pub fn get_log_level_config() -> LogLevelConfig {
+    LOG_LEVELS.with(|config| config.borrow().clone())
+}
+
+/// Replaces the log level configuration (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal changing the configuration
+/// * `config` - The new configuration
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if updated, Err if the caller is not a controller or the config is invalid
+pub fn set_log_level_config(caller: Principal, config: LogLevelConfig) -> Result<(), KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        return Err(KarmieError::Unauthorized {
+            reason: "Only controllers can change the log levels".to_string(),
+        });
+    }
+
+    validate_log_level_config(&config)?;
+
+    save_setting(LOG_LEVELS_SETTING, &config)?;
+    LOG_LEVELS.with(|current| *current.borrow_mut() = config);
+    Ok(())
+}
+
+/// Restores the configuration saved by controllers, called after upgrades
+pub fn restore_log_level_config() {
+    match load_setting::<LogLevelConfig>(LOG_LEVELS_SETTING) {
+        Ok(Some(config)) => LOG_LEVELS.with(|current| *current.borrow_mut() = config),
+        Ok(None) => {}
+        Err(e) => {
+            ::junobuild_satellite::error(format!("[restore_log_level_config] Keeping the default levels: {}", e)).unwrap_or_default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(overrides: &[(&str, LogLevel)]) -> LogLevelConfig {
+        LogLevelConfig {
+            min_level: LogLevel::Warn,
+            module_overrides: overrides.iter().map(|(module, level)| (module.to_string(), *level)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_global_level_without_overrides() {
+        assert_eq!(config(&[]).level_for("satellite::core::events"), LogLevel::Warn);
+    }
+
+    #[test]
+    fn test_most_specific_override_wins() {
+        let config = config(&[("core", LogLevel::Error), ("core::events", LogLevel::Debug)]);
+        assert_eq!(config.level_for("satellite::core::events"), LogLevel::Debug);
+        assert_eq!(config.level_for("satellite::core::delegation"), LogLevel::Error);
+        assert_eq!(config.level_for("satellite::utils::log_store"), LogLevel::Warn);
+        assert_eq!(config.level_for("satellite"), LogLevel::Warn);
+    }
+
+    #[test]
+    fn test_overrides_match_whole_segments() {
+        let config = config(&[("core::vote", LogLevel::Debug), ("satellite::processors", LogLevel::Error)]);
+        assert_eq!(config.level_for("satellite::core::vote_credits"), LogLevel::Warn);
+        assert_eq!(config.level_for("satellite::processors::graph_processors"), LogLevel::Error);
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(validate_log_level_config(&config(&[("core::events", LogLevel::Debug)])).is_ok());
+        assert!(validate_log_level_config(&config(&[("core.*", LogLevel::Debug)])).is_err());
+
+        let too_many: Vec<(String, LogLevel)> = (0..=MAX_MODULE_OVERRIDES)
+            .map(|i| (format!("module_{}", i), LogLevel::Debug))
+            .collect();
+        let config = LogLevelConfig { min_level: LogLevel::Info, module_overrides: too_many };
+        assert!(validate_log_level_config(&config).is_err());
+    }
+}
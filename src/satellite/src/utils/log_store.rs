@@ -0,0 +1,645 @@
+/*!
+ * Persistent log store
+ *
+ * Juno's built-in log keeps the last 100 entries, which a single vote can fill on its
+ * own. Every `logger!` call is therefore also offered to this module, which writes the
+ * entries at or above a configurable level into the `logs` collection:
+ * ```text
+ * key:         log_{timestamp}_{sequence}_          (zero-padded to 20 and 10 digits)
+ * description: lvl_{level}_mod_{module}_
+ * ```
+ * Keys sort chronologically, and the description lets `get_logs` filter by level and
+ * module with a single `list_docs_store` call.
+ *
+ * # Correlation ids
+ * Every entry carries the id of the invocation that produced it. Hooks call
+ * `start_correlation` on entry so that all the lines of one vote share an id; other
+ * calls get a fresh id per message execution.
+ *
+ * # Retention
+ * Entries older than `retention_hours` and the oldest entries beyond `max_entries` are
+ * deleted by a timer every `LOG_PRUNE_INTERVAL_NS`, and right after every
+ * `LOG_PRUNE_EVERY_APPENDS` appends. Each message deletes `MAX_LOGS_PRUNED` entries at
+ * most, and a full batch schedules the next one until the store is caught up. The timer
+ * is started after upgrades and by the first append of a fresh satellite.
+ *
+ * # Notes
+ * - Logs are only persisted in replicated (update) calls; writes made by queries would be discarded
+ * - Like Juno's log, entries written by a call that is rejected or traps are rolled back
+ * - The configuration lives in heap memory; `set_log_store_config` also saves it to the
+ *   `settings` collection, and it is restored after upgrades (see utils/settings.rs)
+ * - This module must not use `logger!` itself: failures are reported to Juno's log directly
+ */
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+use candid::{CandidType, Principal};
+use junobuild_satellite::{delete_doc_store, get_controllers, list_docs_store, set_doc_store, DelDoc, SetDoc};
+use junobuild_shared::controllers::is_controller;
+use junobuild_shared::types::list::{
+    ListMatcher, ListOrder, ListOrderField, ListPaginate, ListParams, TimestampMatcher,
+};
+use junobuild_utils::{decode_doc_data, encode_doc_data};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::errors::KarmieError;
+use crate::utils::settings::{load_setting, save_setting};
+
+/// Collection holding the persisted logs
+pub const LOGS_COLLECTION: &str = "logs";
+
+/// Name of the saved configuration in the settings collection
+const LOG_STORE_SETTING: &str = "log_store";
+
+/// Maximum number of entries returned by `get_logs`
+pub const MAX_LOGS_PAGE: usize = 100;
+
+/// Minimum time between two pruning runs (10 minutes)
+pub const LOG_PRUNE_INTERVAL_NS: u64 = 10 * 60 * 1_000_000_000;
+
+/// Number of appends after which pruning is scheduled regardless of the interval
+pub const LOG_PRUNE_EVERY_APPENDS: u32 = 200;
+
+/// Maximum number of entries deleted by a single pruning message
+pub const MAX_LOGS_PRUNED: usize = 500;
+
+/// Nanoseconds in one hour
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+
+/// Upper bound of `max_entries`
+const MAX_LOG_ENTRIES_LIMIT: u32 = 1_000_000;
+
+/// Severity of a log entry, from least to most severe
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, CandidType)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// Parses the level passed to `logger!`
+    ///
+    /// "log" is Juno's plain level and maps to Info. Unknown levels map to Error,
+    /// like the macro itself does.
+    pub fn from_logger(level: &str) -> Self {
+        match level {
+            "debug" => LogLevel::Debug,
+            "log" | "info" => LogLevel::Info,
+            "warn" => LogLevel::Warn,
+            _ => LogLevel::Error,
+        }
+    }
+
+    /// Name of the level in keys and descriptions
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+
+    /// The levels at or above this one
+    fn and_above(&self) -> Vec<LogLevel> {
+        [LogLevel::Debug, LogLevel::Info, LogLevel::Warn, LogLevel::Error]
+            .into_iter()
+            .filter(|level| level >= self)
+            .collect()
+    }
+}
+
+/// Persistence settings, changed by controllers with `set_log_store_config`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, CandidType)]
+pub struct LogStoreConfig {
+    /// Whether entries are written to the `logs` collection at all
+    pub enabled: bool,
+
+    /// Entries below this level are only sent to Juno's log
+    pub min_level: LogLevel,
+
+    /// Maximum number of entries kept
+    pub max_entries: u32,
+
+    /// How long entries are kept, in hours
+    pub retention_hours: u32,
+}
+
+impl Default for LogStoreConfig {
+    fn default() -> Self {
+        // Info would persist dozens of entries per vote, so only problems are kept by default
+        LogStoreConfig {
+            enabled: true,
+            min_level: LogLevel::Warn,
+            max_entries: 10_000,
+            retention_hours: 7 * 24,
+        }
+    }
+}
+
+/// An entry stored in the `logs` collection
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, CandidType)]
+pub struct LogEntry {
+    /// Severity
+    pub level: LogLevel,
+
+    /// Module path of the call site (e.g. "satellite::core::events")
+    pub module: String,
+
+    /// Formatted message, including the [file:line] prefix
+    pub message: String,
+
+    /// Id shared by every entry of the same invocation
+    pub correlation_id: String,
+
+    /// When the entry was written (nanoseconds since epoch)
+    pub created_at: u64,
+}
+
+/// Filter of a `get_logs` query; every field is optional
+#[derive(Serialize, Deserialize, Clone, Debug, Default, CandidType)]
+pub struct LogFilter {
+    /// Only return entries at or above this level
+    pub min_level: Option<LogLevel>,
+
+    /// Only return entries whose module path contains this text (e.g. "core::events")
+    pub module: Option<String>,
+
+    /// Only return entries written at or after this time (nanoseconds)
+    pub from: Option<u64>,
+
+    /// Only return entries written at or before this time (nanoseconds)
+    pub to: Option<u64>,
+}
+
+/// A page of log entries, newest first
+#[derive(Serialize, Deserialize, Clone, Debug, CandidType)]
+pub struct LogPage {
+    /// The matching entries, newest first
+    pub entries: Vec<LogEntry>,
+
+    /// Cursor to pass to get the next (older) page, None if this is the last page
+    pub next_cursor: Option<String>,
+
+    /// Number of entries matching the filter, across all pages
+    pub total_matches: u64,
+}
+
+/// Heap state of the log store
+#[derive(Default)]
+struct LogStore {
+    config: LogStoreConfig,
+
+    /// Sequence of the next entry, to keep keys unique within a round
+    next_sequence: u64,
+
+    /// Current correlation id and the time it was started at
+    correlation: Option<(u64, String)>,
+
+    /// Whether the pruning timer is running (timers don't survive upgrades)
+    pruning_started: bool,
+
+    /// Whether a pruning message is already scheduled
+    prune_scheduled: bool,
+
+    /// Entries appended since pruning was last scheduled
+    appends_since_prune: u32,
+}
+
+thread_local! {
+    static LOG_STORE: RefCell<LogStore> = RefCell::new(LogStore::default());
+}
+
+/// Formats the key of a log entry
+///
+/// Timestamps and sequences are zero-padded so that key order is chronological order.
+pub fn format_log_key(created_at: u64, sequence: u64) -> String {
+    format!("log_{:020}_{:010}_", created_at, sequence % 10_000_000_000)
+}
+
+/// Formats the description of a log entry, used to filter by level and module
+pub fn format_log_description(level: LogLevel, module: &str) -> String {
+    format!("lvl_{}_mod_{}_", level.as_str(), module)
+}
+
+/// Whether a string is a (partial) module path, e.g. "core::events"
+pub fn is_valid_module_path(module: &str) -> bool {
+    !module.is_empty() && module.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Builds the description regex of a filter
+///
+/// # Arguments
+/// * `min_level` - Minimum level, if any
+/// * `module` - Text the module path must contain, if any
+///
+/// # Returns
+/// * `Result<Option<String>, KarmieError>` - The regex (None if nothing is filtered), Err if the module is invalid
+pub fn build_description_matcher(min_level: Option<LogLevel>, module: Option<&str>) -> Result<Option<String>, KarmieError> {
+    if let Some(module) = module {
+        // Module paths contain no character that is special in a regex
+        if !is_valid_module_path(module) {
+            return Err(KarmieError::invalid_field(
+                "module",
+                format!("must be a non-empty module path (got: {})", module),
+            ));
+        }
+    }
+
+    let levels = match min_level {
+        Some(level) if level > LogLevel::Debug => Some(
+            level.and_above().iter().map(|level| level.as_str()).collect::<Vec<_>>().join("|"),
+        ),
+        _ => None,
+    };
+
+    Ok(match (levels, module) {
+        (None, None) => None,
+        (Some(levels), None) => Some(format!("^lvl_({})_mod_", levels)),
+        (None, Some(module)) => Some(format!("_mod_.*{}", module)),
+        (Some(levels), Some(module)) => Some(format!("^lvl_({})_mod_.*{}", levels, module)),
+    })
+}
+
+/// Builds the creation time matcher of a filter
+pub fn build_time_matcher(from: Option<u64>, to: Option<u64>) -> Option<TimestampMatcher> {
+    match (from, to) {
+        (Some(from), Some(to)) => Some(TimestampMatcher::Between(from, to)),
+        (Some(from), None) => Some(TimestampMatcher::GreaterThan(from.saturating_sub(1))),
+        (None, Some(to)) => Some(TimestampMatcher::LessThan(to.saturating_add(1))),
+        (None, None) => None,
+    }
+}
+
+/// Number of the oldest entries a pruning run deletes
+///
+/// # Arguments
+/// * `created_at` - Creation times of the oldest entries, oldest first
+/// * `total` - Number of entries in the collection
+/// * `cutoff` - Entries created before this time are expired
+/// * `max_entries` - Maximum number of entries kept
+pub fn count_logs_to_prune(created_at: &[u64], total: usize, cutoff: u64, max_entries: usize) -> usize {
+    created_at
+        .iter()
+        .enumerate()
+        .take_while(|(deleted, created_at)| **created_at < cutoff || total - deleted > max_entries)
+        .count()
+}
+
+/// Starts a new correlation id for the current invocation
+///
+/// Called by the hooks on entry so that every entry they log shares the id.
+pub fn start_correlation() {
+    let now = ic_cdk::api::time();
+    LOG_STORE.with(|store| {
+        let mut store = store.borrow_mut();
+        let id = format!("{:x}-{}", now, store.next_sequence);
+        store.next_sequence += 1;
+        store.correlation = Some((now, id));
+    });
+}
+
+/// Returns the current correlation id, starting a new one if it belongs to an earlier execution
+fn current_correlation_id(now: u64) -> String {
+    LOG_STORE.with(|store| {
+        let mut store = store.borrow_mut();
+        match &store.correlation {
+            Some((started_at, id)) if *started_at == now => id.clone(),
+            _ => {
+                let id = format!("{:x}-{}", now, store.next_sequence);
+                store.next_sequence += 1;
+                store.correlation = Some((now, id.clone()));
+                id
+            }
+        }
+    })
+}
+
+/// Starts pruning entries on a timer, unless it is already running
+///
+/// Called after upgrades and when entries are appended.
+pub fn start_log_pruning() {
+    let started = LOG_STORE.with(|store| std::mem::replace(&mut store.borrow_mut().pruning_started, true));
+    if started {
+        return;
+    }
+
+    ic_cdk_timers::set_timer_interval(Duration::from_nanos(LOG_PRUNE_INTERVAL_NS), prune_logs);
+    // Catch up with anything that expired while the timer wasn't running
+    schedule_log_pruning();
+}
+
+/// Schedules a pruning message, unless one is already pending
+fn schedule_log_pruning() {
+    let scheduled = LOG_STORE.with(|store| {
+        let mut store = store.borrow_mut();
+        store.appends_since_prune = 0;
+        std::mem::replace(&mut store.prune_scheduled, true)
+    });
+    if !scheduled {
+        ic_cdk_timers::set_timer(Duration::ZERO, prune_logs);
+    }
+}
+
+/// Deletes a batch of entries, and schedules the next batch if there may be more
+fn prune_logs() {
+    let config = LOG_STORE.with(|store| {
+        let mut store = store.borrow_mut();
+        store.prune_scheduled = false;
+        store.config
+    });
+
+    let (selected, pruned) = prune_logs_batch(ic_cdk::api::time(), &config);
+
+    // A batch with failed deletions stops here, the next interval retries it
+    if selected == MAX_LOGS_PRUNED && pruned == selected {
+        schedule_log_pruning();
+    }
+}
+
+/// Deletes up to `MAX_LOGS_PRUNED` expired entries and oldest entries beyond `max_entries`
+///
+/// # Returns
+/// * `(usize, usize)` - Number of entries selected for deletion and number deleted
+fn prune_logs_batch(now: u64, config: &LogStoreConfig) -> (usize, usize) {
+    let oldest = list_docs_store(
+        ic_cdk::id(),
+        LOGS_COLLECTION.to_string(),
+        &ListParams {
+            matcher: Some(ListMatcher {
+                key: Some("^log_".to_string()),
+                ..Default::default()
+            }),
+            paginate: Some(ListPaginate {
+                start_after: None,
+                limit: Some(MAX_LOGS_PRUNED),
+            }),
+            order: Some(ListOrder {
+                desc: false,
+                field: ListOrderField::Keys,
+            }),
+            owner: None,
+        },
+    );
+
+    let oldest = match oldest {
+        Ok(results) => results,
+        Err(e) => {
+            ::junobuild_satellite::error(format!("[prune_logs_batch] Failed to list logs: {}", e)).unwrap_or_default();
+            return (0, 0);
+        }
+    };
+
+    let cutoff = now.saturating_sub(config.retention_hours as u64 * NANOS_PER_HOUR);
+    let created_at: Vec<u64> = oldest.items.iter().map(|(_, doc)| doc.created_at).collect();
+    let to_prune = count_logs_to_prune(&created_at, oldest.matches_length, cutoff, config.max_entries as usize);
+
+    let mut pruned = 0;
+    for (key, doc) in oldest.items.into_iter().take(to_prune) {
+        match delete_doc_store(ic_cdk::id(), LOGS_COLLECTION.to_string(), key.clone(), DelDoc { version: doc.version }) {
+            Ok(_) => pruned += 1,
+            Err(e) => {
+                ::junobuild_satellite::error(format!("[prune_logs_batch] Failed to delete log {}: {}", key, e)).unwrap_or_default();
+            }
+        }
+    }
+
+    (to_prune, pruned)
+}
+
+/// Writes an entry to the `logs` collection
+fn append_log(level: LogLevel, module: &str, message: &str) -> Result<(), String> {
+    let now = ic_cdk::api::time();
+    let correlation_id = current_correlation_id(now);
+    let sequence = LOG_STORE.with(|store| {
+        let mut store = store.borrow_mut();
+        let sequence = store.next_sequence;
+        store.next_sequence += 1;
+        sequence
+    });
+
+    let entry = LogEntry {
+        level,
+        module: module.to_string(),
+        message: message.to_string(),
+        correlation_id,
+        created_at: now,
+    };
+
+    set_doc_store(
+        ic_cdk::id(),
+        LOGS_COLLECTION.to_string(),
+        format_log_key(now, sequence),
+        SetDoc {
+            data: encode_doc_data(&entry)?,
+            description: Some(format_log_description(level, module)),
+            version: None,
+        },
+    )?;
+
+    start_log_pruning();
+    let appends_since_prune = LOG_STORE.with(|store| {
+        let mut store = store.borrow_mut();
+        store.appends_since_prune += 1;
+        store.appends_since_prune
+    });
+    if appends_since_prune >= LOG_PRUNE_EVERY_APPENDS {
+        schedule_log_pruning();
+    }
+
+    Ok(())
+}
+
+/// Persists a `logger!` entry if its level is enabled
+///
+/// Called by the `logger!` macro for every message. Failures are reported to Juno's
+/// log and never propagated to the caller.
+///
+/// # Arguments
+/// * `level` - The level passed to `logger!`
+/// * `module` - `module_path!()` of the call site
+/// * `message` - The formatted message
+pub fn persist_log(level: &str, module: &str, message: &str) {
+    let level = LogLevel::from_logger(level);
+    let config = LOG_STORE.with(|store| store.borrow().config);
+    if !config.enabled || level < config.min_level {
+        return;
+    }
+
+    // Queries can't keep their writes
+    if !ic_cdk::api::in_replicated_execution() {
+        return;
+    }
+
+    if let Err(e) = append_log(level, module, message) {
+        ::junobuild_satellite::error(format!("[persist_log] Failed to persist log: {}", e)).unwrap_or_default();
+    }
+}
+
+/// Rejects callers that are not controllers
+fn assert_controller(caller: Principal, action: &str) -> Result<(), KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        return Err(KarmieError::Unauthorized {
+            reason: format!("Only controllers can {}", action),
+        });
+    }
+    Ok(())
+}
+
+/// Returns the log store configuration (controllers only)
+pub fn get_log_store_config(caller: Principal) -> Result<LogStoreConfig, KarmieError> {
+    assert_controller(caller, "view the log configuration")?;
+    Ok(LOG_STORE.with(|store| store.borrow().config))
+}
+
+/// Replaces the log store configuration (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal changing the configuration
+/// * `config` - The new configuration
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if updated, Err if the caller is not a controller or the config is invalid
+pub fn set_log_store_config(caller: Principal, config: LogStoreConfig) -> Result<(), KarmieError> {
+    assert_controller(caller, "change the log configuration")?;
+
+    if config.max_entries == 0 || config.max_entries > MAX_LOG_ENTRIES_LIMIT {
+        return Err(KarmieError::invalid_field(
+            "max_entries",
+            format!("must be between 1 and {} (got: {})", MAX_LOG_ENTRIES_LIMIT, config.max_entries),
+        ));
+    }
+    if config.retention_hours == 0 {
+        return Err(KarmieError::invalid_field("retention_hours", "must be greater than 0"));
+    }
+
+    save_setting(LOG_STORE_SETTING, &config)?;
+    LOG_STORE.with(|store| store.borrow_mut().config = config);
+    Ok(())
+}
+
+/// Restores the configuration saved by controllers, called after upgrades
+pub fn restore_log_store_config() {
+    match load_setting::<LogStoreConfig>(LOG_STORE_SETTING) {
+        Ok(Some(config)) => LOG_STORE.with(|store| store.borrow_mut().config = config),
+        Ok(None) => {}
+        Err(e) => {
+            ::junobuild_satellite::error(format!("[restore_log_store_config] Keeping the default configuration: {}", e)).unwrap_or_default();
+        }
+    }
+}
+
+/// Lists persisted log entries, newest first (controllers only)
+///
+/// # Arguments
+/// * `caller` - The principal reading the logs
+/// * `filter` - Level, module and time range to match
+/// * `cursor` - `next_cursor` of the previous page, None for the newest entries
+/// * `limit` - Maximum number of entries (capped at `MAX_LOGS_PAGE`)
+///
+/// # Returns
+/// * `Result<LogPage, KarmieError>` - The entries or the reason they couldn't be listed
+pub fn get_log_page(caller: Principal, filter: LogFilter, cursor: Option<String>, limit: u32) -> Result<LogPage, KarmieError> {
+    assert_controller(caller, "read the logs")?;
+
+    if let (Some(from), Some(to)) = (filter.from, filter.to) {
+        if from > to {
+            return Err(KarmieError::invalid_field("from", format!("must not be after to ({} > {})", from, to)));
+        }
+    }
+
+    let limit = (limit as usize).clamp(1, MAX_LOGS_PAGE);
+    let results = list_docs_store(
+        ic_cdk::id(),
+        LOGS_COLLECTION.to_string(),
+        &ListParams {
+            matcher: Some(ListMatcher {
+                key: Some("^log_".to_string()),
+                description: build_description_matcher(filter.min_level, filter.module.as_deref())?,
+                created_at: build_time_matcher(filter.from, filter.to),
+                ..Default::default()
+            }),
+            paginate: Some(ListPaginate {
+                start_after: cursor,
+                // One more than requested tells whether another page exists
+                limit: Some(limit + 1),
+            }),
+            order: Some(ListOrder {
+                desc: true,
+                field: ListOrderField::Keys,
+            }),
+            owner: None,
+        },
+    )
+    .map_err(|e| KarmieError::storage(LOGS_COLLECTION, e))?;
+
+    let has_more = results.items.len() > limit;
+    let entries = results
+        .items
+        .iter()
+        .take(limit)
+        .map(|(_, doc)| decode_doc_data::<LogEntry>(&doc.data).map_err(|e| KarmieError::invalid_data(LOGS_COLLECTION, e)))
+        .collect::<Result<Vec<_>, KarmieError>>()?;
+    let next_cursor = if has_more {
+        results.items.get(limit - 1).map(|(key, _)| key.clone())
+    } else {
+        None
+    };
+
+    Ok(LogPage {
+        entries,
+        next_cursor,
+        total_matches: results.matches_length as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_keys_sort_chronologically() {
+        let earlier = format_log_key(999, 5);
+        let later = format_log_key(1_000, 0);
+        assert!(earlier < later);
+        assert!(format_log_key(1_000, 1) < format_log_key(1_000, 2));
+        assert_eq!(format_log_key(1, 2), "log_00000000000000000001_0000000002_");
+    }
+
+    #[test]
+    fn test_level_parsing_and_order() {
+        assert_eq!(LogLevel::from_logger("log"), LogLevel::Info);
+        assert_eq!(LogLevel::from_logger("warn"), LogLevel::Warn);
+        assert_eq!(LogLevel::from_logger("typo"), LogLevel::Error);
+        assert!(LogLevel::Debug < LogLevel::Info && LogLevel::Warn < LogLevel::Error);
+    }
+
+    #[test]
+    fn test_description_matcher() {
+        assert_eq!(build_description_matcher(None, None).unwrap(), None);
+        assert_eq!(build_description_matcher(Some(LogLevel::Debug), None).unwrap(), None);
+        assert_eq!(
+            build_description_matcher(Some(LogLevel::Warn), None).unwrap(),
+            Some("^lvl_(warn|error)_mod_".to_string())
+        );
+        assert_eq!(
+            build_description_matcher(Some(LogLevel::Error), Some("core::events")).unwrap(),
+            Some("^lvl_(error)_mod_.*core::events".to_string())
+        );
+        assert!(build_description_matcher(None, Some("core.*")).is_err());
+        assert!(build_description_matcher(None, Some("")).is_err());
+    }
+
+    #[test]
+    fn test_prune_count_by_age_and_count() {
+        // Only the expired entry goes when under the count limit
+        assert_eq!(count_logs_to_prune(&[5, 20, 30], 3, 10, 100), 1);
+        // The oldest entries beyond max_entries go even if they are recent
+        assert_eq!(count_logs_to_prune(&[20, 30, 40, 50], 4, 10, 2), 2);
+        // Nothing to delete
+        assert_eq!(count_logs_to_prune(&[20, 30], 2, 10, 2), 0);
+    }
+}
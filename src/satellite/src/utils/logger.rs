@@ -33,12 +33,10 @@
  * - Maximum of 100 entries retained in Juno's system
  * - Oldest entries are discarded when limit is reached
  * - Logs are only saved if the containing function succeeds
- * 
- * TODO: Implement custom log storage in Juno datastore
- * - Create 'logs' collection with proper schema (see @database.md)
- * - Use set_doc_store to persist logs beyond Juno's 100 entry limit
- * - Add timestamp-based cleanup for log rotation
- * - Add query interface for log analysis
+ * - Messages below the runtime minimum level of their module are skipped
+ *   before being formatted (see utils/log_config.rs)
+ * - Entries at or above the configured level are also persisted in the 'logs'
+ *   collection with their module and correlation id (see utils/log_store.rs)
  */
 
 
@@ -63,10 +61,14 @@
 /// - Bad:  logger!("info", "Starting validation")
 /// 
 /// At compile time, this expands to code that:
-/// 1. Formats any message variables using format!()
-/// 2. Adds the [file:line] prefix using file!() and line!()
-/// 3. Directly matches the log level string to the appropriate Juno function
-/// 4. Falls back to error() if an invalid level is provided
+/// 1. Returns early if the level is filtered for the calling module (see utils/log_config.rs),
+///    or if it is "debug" and the `strip-debug-logs` feature is enabled
+///    - Outside of a canister (native unit tests), nothing is logged, since Juno's log needs the IC
+/// 2. Formats any message variables using format!()
+/// 3. Adds the [file:line] prefix using file!() and line!()
+/// 4. Offers the entry to the log store, which persists it if its level is enabled
+/// 5. Directly matches the log level string to the appropriate Juno function
+/// 6. Falls back to error() if an invalid level is provided
 /// 
 /// Note: If an invalid log type is provided, we fall back to Error level
 /// because an invalid log type indicates a programming error that should
@@ -79,27 +81,41 @@
 macro_rules! logger {
     // Pattern 1: Handle printf-style formatting with variables
     ($type:expr, $($arg:tt)*) => {{
-        let message = format!("[{}:{}] {}", file!(), line!(), format!($($arg)*));
-        match $type {
-            "log" => ::junobuild_satellite::log(message).unwrap_or_default(),
-            "debug" => ::junobuild_satellite::debug(message).unwrap_or_default(),
-            "info" => ::junobuild_satellite::info(message).unwrap_or_default(),
-            "warn" => ::junobuild_satellite::warn(message).unwrap_or_default(),
-            "error" => ::junobuild_satellite::error(message).unwrap_or_default(),
-            _ => ::junobuild_satellite::error(message).unwrap_or_default()
+        // Filtered messages are never formatted
+        if cfg!(target_arch = "wasm32")
+            && !(cfg!(feature = "strip-debug-logs") && $type == "debug")
+            && $crate::utils::log_config::log_enabled($type, module_path!())
+        {
+            let message = format!("[{}:{}] {}", file!(), line!(), format!($($arg)*));
+            $crate::utils::log_store::persist_log($type, module_path!(), &message);
+            match $type {
+                "log" => ::junobuild_satellite::log(message).unwrap_or_default(),
+                "debug" => ::junobuild_satellite::debug(message).unwrap_or_default(),
+                "info" => ::junobuild_satellite::info(message).unwrap_or_default(),
+                "warn" => ::junobuild_satellite::warn(message).unwrap_or_default(),
+                "error" => ::junobuild_satellite::error(message).unwrap_or_default(),
+                _ => ::junobuild_satellite::error(message).unwrap_or_default()
+            }
         }
     }};
 
     // Pattern 2: Handle messages with additional structured data
     ($type:expr, $message:expr, $data:expr) => {{
-        let message = format!("[{}:{}] {}", file!(), line!(), $message);
-        match $type {
-            "log" => ::junobuild_satellite::log_with_data(message, $data).unwrap_or_default(),
-            "debug" => ::junobuild_satellite::debug_with_data(message, $data).unwrap_or_default(),
-            "info" => ::junobuild_satellite::info_with_data(message, $data).unwrap_or_default(),
-            "warn" => ::junobuild_satellite::warn_with_data(message, $data).unwrap_or_default(),
-            "error" => ::junobuild_satellite::error_with_data(message, $data).unwrap_or_default(),
-            _ => ::junobuild_satellite::error_with_data(message, $data).unwrap_or_default()
+        // Filtered messages are never formatted
+        if cfg!(target_arch = "wasm32")
+            && !(cfg!(feature = "strip-debug-logs") && $type == "debug")
+            && $crate::utils::log_config::log_enabled($type, module_path!())
+        {
+            let message = format!("[{}:{}] {}", file!(), line!(), $message);
+            $crate::utils::log_store::persist_log($type, module_path!(), &message);
+            match $type {
+                "log" => ::junobuild_satellite::log_with_data(message, $data).unwrap_or_default(),
+                "debug" => ::junobuild_satellite::debug_with_data(message, $data).unwrap_or_default(),
+                "info" => ::junobuild_satellite::info_with_data(message, $data).unwrap_or_default(),
+                "warn" => ::junobuild_satellite::warn_with_data(message, $data).unwrap_or_default(),
+                "error" => ::junobuild_satellite::error_with_data(message, $data).unwrap_or_default(),
+                _ => ::junobuild_satellite::error_with_data(message, $data).unwrap_or_default()
+            }
         }
     }};
 }
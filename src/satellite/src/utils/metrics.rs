@@ -0,0 +1,468 @@
+/*!
+ * Operational metrics
+ *
+ * In-memory registry of counters that tell how busy and how expensive the satellite is:
+ * - Hook invocations, successes and instructions per hook and collection
+ * - Calls, failures and instructions per operation (`process_vote`, `get_graph_data`, ...)
+ * - `query_doc_by_key` calls and documents scanned per collection
+ * - Version conflicts of the satellite's own `set_doc_store` writes per collection
+ *
+ * Instructions are read with `performance_counter(1)`, which counts across the awaits of
+ * a call, so async operations are measured as a whole.
+ *
+ * Controllers read the registry with `get_metrics`, or with `get_metrics_prometheus` in
+ * the Prometheus text exposition format for a local scraper.
+ *
+ * # Rollbacks
+ * Counters live in heap memory and follow the IC's rules:
+ * - A call that traps rolls back the counters it updated. Hooks trap when they fail, so
+ *   `on_set_doc` and `on_delete_doc` invocations are counted by the assertion that
+ *   schedules them, and failures are the scheduled invocations that never succeeded
+ * - Queries can't keep state: operations behind a query are only counted when the query
+ *   is called in replicated mode (as an update)
+ * - Everything is reset on upgrade; `collected_since` tells when counting started
+ */
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use candid::{CandidType, Principal};
+use junobuild_satellite::get_controllers;
+use junobuild_shared::controllers::is_controller;
+use junobuild_shared::msg::{JUNO_ERROR_NO_VERSION, JUNO_ERROR_VERSION_OUTDATED_OR_FUTURE};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::errors::KarmieError;
+
+/// Instruction totals of a hook or operation
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, CandidType)]
+pub struct InstructionStats {
+    /// Instructions used by all measured runs
+    pub total: u64,
+
+    /// Most instructions used by a single run
+    pub max: u64,
+
+    /// Instructions used by the last run
+    pub last: u64,
+}
+
+impl InstructionStats {
+    fn record(&mut self, instructions: u64) {
+        self.total = self.total.saturating_add(instructions);
+        self.max = self.max.max(instructions);
+        self.last = instructions;
+    }
+}
+
+/// Counters of a hook in a collection
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, CandidType)]
+pub struct HookMetrics {
+    /// Hook name ("assert_set_doc", "on_set_doc", "on_delete_doc")
+    pub hook: String,
+
+    /// Collection the hook ran for
+    pub collection: String,
+
+    /// Invocations (for `on_*` hooks: invocations scheduled by an accepted write)
+    pub invocations: u64,
+
+    /// Invocations that completed successfully
+    pub succeeded: u64,
+
+    /// Invocations that failed or haven't run yet
+    pub failures: u64,
+
+    /// Instructions of the successful invocations
+    pub instructions: InstructionStats,
+}
+
+/// Counters of an operation
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, CandidType)]
+pub struct OperationMetrics {
+    /// Operation name (e.g. "process_vote")
+    pub operation: String,
+
+    /// Completed calls, successful or not
+    pub calls: u64,
+
+    /// Calls that returned an error
+    pub failures: u64,
+
+    /// Instructions of the completed calls
+    pub instructions: InstructionStats,
+}
+
+/// Counters of `query_doc_by_key` in a collection
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, CandidType)]
+pub struct QueryMetrics {
+    /// The queried collection
+    pub collection: String,
+
+    /// Number of queries
+    pub queries: u64,
+
+    /// Documents returned by the queries, which callers then iterate
+    pub docs_scanned: u64,
+}
+
+/// Everything the registry collected
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, CandidType)]
+pub struct MetricsSnapshot {
+    /// When counting started (nanoseconds since epoch, 0 if nothing was recorded yet)
+    pub collected_since: u64,
+
+    /// Per hook and collection, sorted
+    pub hooks: Vec<HookMetrics>,
+
+    /// Per operation, sorted
+    pub operations: Vec<OperationMetrics>,
+
+    /// Per collection, sorted
+    pub queries: Vec<QueryMetrics>,
+
+    /// Version conflicts per collection, sorted
+    pub version_conflicts: Vec<(String, u64)>,
+}
+
+#[derive(Default, Debug)]
+struct HookCounters {
+    scheduled: u64,
+    succeeded: u64,
+    instructions: InstructionStats,
+}
+
+#[derive(Default, Debug)]
+struct OperationCounters {
+    calls: u64,
+    failures: u64,
+    instructions: InstructionStats,
+}
+
+/// The metrics registry
+#[derive(Default, Debug)]
+pub struct MetricsRegistry {
+    collected_since: u64,
+    hooks: BTreeMap<(String, String), HookCounters>,
+    operations: BTreeMap<String, OperationCounters>,
+    queries: BTreeMap<String, (u64, u64)>,
+    version_conflicts: BTreeMap<String, u64>,
+}
+
+impl MetricsRegistry {
+    fn touch(&mut self, now: u64) {
+        if self.collected_since == 0 {
+            self.collected_since = now;
+        }
+    }
+
+    /// Counts a hook invocation that will run later
+    pub fn hook_scheduled(&mut self, hook: &str, collection: &str, now: u64) {
+        self.touch(now);
+        self.hooks.entry((hook.to_string(), collection.to_string())).or_default().scheduled += 1;
+    }
+
+    /// Records a successful hook invocation
+    ///
+    /// # Arguments
+    /// * `scheduled` - Whether the invocation was already counted by `hook_scheduled`
+    pub fn hook_succeeded(&mut self, hook: &str, collection: &str, instructions: u64, scheduled: bool, now: u64) {
+        self.touch(now);
+        let counters = self.hooks.entry((hook.to_string(), collection.to_string())).or_default();
+        if !scheduled {
+            counters.scheduled += 1;
+        }
+        counters.succeeded += 1;
+        counters.instructions.record(instructions);
+    }
+
+    /// Records a completed operation
+    pub fn operation_completed(&mut self, operation: &str, instructions: u64, succeeded: bool, now: u64) {
+        self.touch(now);
+        let counters = self.operations.entry(operation.to_string()).or_default();
+        counters.calls += 1;
+        if !succeeded {
+            counters.failures += 1;
+        }
+        counters.instructions.record(instructions);
+    }
+
+    /// Records a `query_doc_by_key` call
+    pub fn query_completed(&mut self, collection: &str, docs_scanned: usize, now: u64) {
+        self.touch(now);
+        let (queries, docs) = self.queries.entry(collection.to_string()).or_default();
+        *queries += 1;
+        *docs += docs_scanned as u64;
+    }
+
+    /// Records a version conflict
+    pub fn version_conflict(&mut self, collection: &str, now: u64) {
+        self.touch(now);
+        *self.version_conflicts.entry(collection.to_string()).or_default() += 1;
+    }
+
+    /// Copies the counters out of the registry
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            collected_since: self.collected_since,
+            hooks: self
+                .hooks
+                .iter()
+                .map(|((hook, collection), counters)| HookMetrics {
+                    hook: hook.clone(),
+                    collection: collection.clone(),
+                    invocations: counters.scheduled,
+                    succeeded: counters.succeeded,
+                    failures: counters.scheduled.saturating_sub(counters.succeeded),
+                    instructions: counters.instructions.clone(),
+                })
+                .collect(),
+            operations: self
+                .operations
+                .iter()
+                .map(|(operation, counters)| OperationMetrics {
+                    operation: operation.clone(),
+                    calls: counters.calls,
+                    failures: counters.failures,
+                    instructions: counters.instructions.clone(),
+                })
+                .collect(),
+            queries: self
+                .queries
+                .iter()
+                .map(|(collection, (queries, docs_scanned))| QueryMetrics {
+                    collection: collection.clone(),
+                    queries: *queries,
+                    docs_scanned: *docs_scanned,
+                })
+                .collect(),
+            version_conflicts: self.version_conflicts.iter().map(|(collection, count)| (collection.clone(), *count)).collect(),
+        }
+    }
+}
+
+thread_local! {
+    static METRICS: RefCell<MetricsRegistry> = RefCell::new(MetricsRegistry::default());
+}
+
+/// Instructions executed so far in the current call, across awaits
+///
+/// Pass the value to `record_operation` or `record_hook_success` once the work is done.
+pub fn instruction_counter() -> u64 {
+    ic_cdk::api::performance_counter(1)
+}
+
+/// Counts a hook invocation scheduled by the current (accepted) write
+///
+/// # Arguments
+/// * `hook` - "on_set_doc" or "on_delete_doc"
+/// * `collection` - The collection written to
+pub fn record_hook_scheduled(hook: &str, collection: &str) {
+    let now = ic_cdk::api::time();
+    METRICS.with(|metrics| metrics.borrow_mut().hook_scheduled(hook, collection, now));
+}
+
+/// Records a successful hook invocation
+///
+/// # Arguments
+/// * `hook` - The hook's name
+/// * `collection` - The collection it ran for
+/// * `start` - `instruction_counter()` when the hook started
+/// * `scheduled` - Whether the invocation was counted by `record_hook_scheduled`
+pub fn record_hook_success(hook: &str, collection: &str, start: u64, scheduled: bool) {
+    let instructions = instruction_counter().saturating_sub(start);
+    let now = ic_cdk::api::time();
+    METRICS.with(|metrics| metrics.borrow_mut().hook_succeeded(hook, collection, instructions, scheduled, now));
+}
+
+/// Records a completed operation
+///
+/// # Arguments
+/// * `operation` - The operation's name
+/// * `start` - `instruction_counter()` when the operation started
+/// * `succeeded` - Whether it returned Ok
+pub fn record_operation(operation: &str, start: u64, succeeded: bool) {
+    let instructions = instruction_counter().saturating_sub(start);
+    let now = ic_cdk::api::time();
+    METRICS.with(|metrics| metrics.borrow_mut().operation_completed(operation, instructions, succeeded, now));
+}
+
+/// Records a `query_doc_by_key` call
+pub fn record_query(collection: &str, docs_scanned: usize) {
+    let now = ic_cdk::api::time();
+    METRICS.with(|metrics| metrics.borrow_mut().query_completed(collection, docs_scanned, now));
+}
+
+/// Whether a `set_doc_store` error is a version conflict
+pub fn is_version_conflict(error: &str) -> bool {
+    error.contains(JUNO_ERROR_VERSION_OUTDATED_OR_FUTURE) || error.contains(JUNO_ERROR_NO_VERSION)
+}
+
+/// Counts a failed `set_doc_store` write if it was a version conflict
+///
+/// # Arguments
+/// * `collection` - The collection written to
+/// * `error` - The error returned by `set_doc_store`
+pub fn record_store_error(collection: &str, error: &str) {
+    if is_version_conflict(error) {
+        let now = ic_cdk::api::time();
+        METRICS.with(|metrics| metrics.borrow_mut().version_conflict(collection, now));
+    }
+}
+
+/// Escapes a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Appends one metric family
+fn write_family(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, u64)]) {
+    if samples.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+/// Renders a snapshot in the Prometheus text exposition format
+///
+/// # Arguments
+/// * `snapshot` - The counters to render
+///
+/// # Returns
+/// * `String` - One family per counter, labelled by hook, collection or operation
+pub fn render_prometheus(snapshot: &MetricsSnapshot) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# HELP karmie_metrics_collected_since_seconds When counting started (Unix time)");
+    let _ = writeln!(out, "# TYPE karmie_metrics_collected_since_seconds gauge");
+    let _ = writeln!(out, "karmie_metrics_collected_since_seconds {}", snapshot.collected_since / 1_000_000_000);
+
+    let hook_labels = |hook: &HookMetrics| {
+        format!("hook=\"{}\",collection=\"{}\"", escape_label(&hook.hook), escape_label(&hook.collection))
+    };
+    let hook_family = |value: fn(&HookMetrics) -> u64| {
+        snapshot.hooks.iter().map(|hook| (hook_labels(hook), value(hook))).collect::<Vec<_>>()
+    };
+    write_family(&mut out, "karmie_hook_invocations_total", "counter", "Hook invocations", &hook_family(|h| h.invocations));
+    write_family(&mut out, "karmie_hook_failures_total", "counter", "Hook invocations that failed or haven't run yet", &hook_family(|h| h.failures));
+    write_family(&mut out, "karmie_hook_instructions_total", "counter", "Instructions used by successful hook invocations", &hook_family(|h| h.instructions.total));
+    write_family(&mut out, "karmie_hook_instructions_max", "gauge", "Most instructions used by a hook invocation", &hook_family(|h| h.instructions.max));
+
+    let operation_family = |value: fn(&OperationMetrics) -> u64| {
+        snapshot
+            .operations
+            .iter()
+            .map(|operation| (format!("operation=\"{}\"", escape_label(&operation.operation)), value(operation)))
+            .collect::<Vec<_>>()
+    };
+    write_family(&mut out, "karmie_operation_calls_total", "counter", "Completed operation calls", &operation_family(|o| o.calls));
+    write_family(&mut out, "karmie_operation_failures_total", "counter", "Operation calls that returned an error", &operation_family(|o| o.failures));
+    write_family(&mut out, "karmie_operation_instructions_total", "counter", "Instructions used by operation calls", &operation_family(|o| o.instructions.total));
+    write_family(&mut out, "karmie_operation_instructions_max", "gauge", "Most instructions used by an operation call", &operation_family(|o| o.instructions.max));
+    write_family(&mut out, "karmie_operation_instructions_last", "gauge", "Instructions used by the last operation call", &operation_family(|o| o.instructions.last));
+
+    let query_family = |value: fn(&QueryMetrics) -> u64| {
+        snapshot
+            .queries
+            .iter()
+            .map(|query| (format!("collection=\"{}\"", escape_label(&query.collection)), value(query)))
+            .collect::<Vec<_>>()
+    };
+    write_family(&mut out, "karmie_query_doc_by_key_calls_total", "counter", "query_doc_by_key calls", &query_family(|q| q.queries));
+    write_family(&mut out, "karmie_query_doc_by_key_docs_scanned_total", "counter", "Documents returned by query_doc_by_key", &query_family(|q| q.docs_scanned));
+
+    let conflicts: Vec<(String, u64)> = snapshot
+        .version_conflicts
+        .iter()
+        .map(|(collection, count)| (format!("collection=\"{}\"", escape_label(collection)), *count))
+        .collect();
+    write_family(&mut out, "karmie_version_conflicts_total", "counter", "Version conflicts of the satellite's own writes", &conflicts);
+
+    out
+}
+
+/// Rejects callers that are not controllers
+fn assert_controller(caller: Principal) -> Result<(), KarmieError> {
+    if !is_controller(caller, &get_controllers()) {
+        return Err(KarmieError::Unauthorized {
+            reason: "Only controllers can read the metrics".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Returns the collected metrics (controllers only)
+pub fn get_metrics_snapshot(caller: Principal) -> Result<MetricsSnapshot, KarmieError> {
+    assert_controller(caller)?;
+    Ok(METRICS.with(|metrics| metrics.borrow().snapshot()))
+}
+
+/// Returns the collected metrics in the Prometheus text format (controllers only)
+pub fn get_metrics_prometheus_text(caller: Principal) -> Result<String, KarmieError> {
+    assert_controller(caller)?;
+    Ok(METRICS.with(|metrics| render_prometheus(&metrics.borrow().snapshot())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_failures_are_unsucceeded_scheduled_invocations() {
+        let mut registry = MetricsRegistry::default();
+        registry.hook_scheduled("on_set_doc", "votes", 10);
+        registry.hook_scheduled("on_set_doc", "votes", 20);
+        registry.hook_succeeded("on_set_doc", "votes", 1_000, true, 30);
+        registry.hook_succeeded("assert_set_doc", "votes", 500, false, 40);
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.collected_since, 10);
+        let assert_hook = &snapshot.hooks[0];
+        assert_eq!((assert_hook.invocations, assert_hook.succeeded, assert_hook.failures), (1, 1, 0));
+        let on_set = &snapshot.hooks[1];
+        assert_eq!((on_set.invocations, on_set.succeeded, on_set.failures), (2, 1, 1));
+        assert_eq!(on_set.instructions.total, 1_000);
+    }
+
+    #[test]
+    fn test_operation_instruction_stats() {
+        let mut registry = MetricsRegistry::default();
+        registry.operation_completed("process_vote", 300, true, 1);
+        registry.operation_completed("process_vote", 700, false, 2);
+        registry.operation_completed("process_vote", 100, true, 3);
+
+        let operation = &registry.snapshot().operations[0];
+        assert_eq!((operation.calls, operation.failures), (3, 1));
+        assert_eq!(operation.instructions, InstructionStats { total: 1_100, max: 700, last: 100 });
+    }
+
+    #[test]
+    fn test_version_conflict_detection() {
+        assert!(is_version_conflict("juno.error.version_outdated_or_future (1 - 2)"));
+        assert!(is_version_conflict(JUNO_ERROR_NO_VERSION));
+        assert!(!is_version_conflict("Cannot write."));
+    }
+
+    #[test]
+    fn test_prometheus_rendering() {
+        let mut registry = MetricsRegistry::default();
+        registry.hook_scheduled("on_set_doc", "votes", 2_000_000_000);
+        registry.query_completed("votes", 42, 3_000_000_000);
+        registry.version_conflict("reputations", 3_000_000_000);
+
+        let text = render_prometheus(&registry.snapshot());
+        assert!(text.contains("karmie_metrics_collected_since_seconds 2\n"));
+        assert!(text.contains("# TYPE karmie_hook_invocations_total counter\n"));
+        assert!(text.contains("karmie_hook_failures_total{hook=\"on_set_doc\",collection=\"votes\"} 1\n"));
+        assert!(text.contains("karmie_query_doc_by_key_docs_scanned_total{collection=\"votes\"} 42\n"));
+        assert!(text.contains("karmie_version_conflicts_total{collection=\"reputations\"} 1\n"));
+        // Families without samples are left out
+        assert!(!text.contains("karmie_operation_calls_total"));
+        assert_eq!(escape_label("a\"b\\c"), "a\\\"b\\\\c");
+    }
+}
@@ -6,7 +6,12 @@
  */
 
 // Export utility modules
+pub mod errors;
+pub mod log_config;
+pub mod log_store;
 pub mod logger;
+pub mod metrics;
 pub mod normalize;
+pub mod settings;
 pub mod structs;
 pub mod time;
\ No newline at end of file
@@ -1,58 +1,186 @@
 /*! 
- * Username normalization utilities
+ * Handle normalization utilities
  * 
- * This module provides functions for normalizing usernames to ensure consistent
- * comparison and storage. Normalization includes:
- * - Converting to lowercase
- * - Removing leading/trailing whitespace
- * - Handling special characters
+ * User and tag handles have one canonical form, `CanonicalHandle`, used everywhere two
+ * handles are compared: in keys (see processors/document_keys.rs), in the uniqueness
+ * asserts, and in the availability check. Canonicalization:
+ * - Removes leading/trailing whitespace
+ * - Applies Unicode NFKC normalization (fullwidth letters, ligatures, etc. become plain letters)
+ * - Converts to lowercase
+ * - Keeps only alphanumeric characters and hyphens (underscores separate key segments)
+ * 
+ * The frontend mirrors it in src/lib/keys/canonical_handle.ts. Documents whose key was
+ * written with an earlier normalization are found and repaired by core/handle_keys.rs.
+ * 
+ * # Skeletons
+ * Handles accept any Unicode letter, so "раypal" (Cyrillic "ра") and "paypal" are
+ * different handles that look the same. `CanonicalHandle::skeleton` maps a handle to its
+ * confusable skeleton (Unicode Technical Standard #39): handles that look alike have
+ * the same skeleton, and uniqueness of user and tag handles is checked on it.
+ * Skeletons are only compared, never displayed: "m" and "rn" share one, for example.
  */
 
-/// Normalizes a username for consistent comparison and storage.
-/// 
-/// This function performs the following transformations:
-/// - Converts the username to lowercase
-/// - Removes leading and trailing whitespace
-/// - Ensures the username only contains allowed characters
-/// 
-/// # Arguments
-/// * `username` - The username to normalize
-/// 
-/// # Returns
-/// A normalized version of the username as a String
-/// 
-/// # Example
-/// ```
-/// let normalized = normalize_handle("  UserName123  ");
-/// assert_eq!(normalized, "username123");
-/// ```
-pub fn normalize_handle(username: &str) -> String {
-    // First trim any whitespace and convert to lowercase
-    let normalized = username.trim().to_lowercase();
-    
-    // Filter out any characters that aren't alphanumeric, underscore, or hyphen
-    normalized
-        .chars()
-        .filter(|c| c.is_alphanumeric() || *c == '_' || *c == '-')
-        .collect()
+use std::fmt;
+
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::skeleton;
+
+/// A user or tag handle in canonical form
+///
+/// Two handles collide if and only if their canonical forms are equal. Built with
+/// `CanonicalHandle::new`, which accepts any input: format rules (length, dashes) are
+/// checked separately by `validate_handle`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CanonicalHandle(String);
+
+impl CanonicalHandle {
+    /// Canonicalizes a handle
+    /// 
+    /// # Arguments
+    /// * `handle` - The handle as entered or stored
+    /// 
+    /// # Returns
+    /// The canonical handle
+    /// 
+    /// # Example
+    /// ```
+    /// let handle = CanonicalHandle::new("  ＵｓｅｒName_123  ");
+    /// assert_eq!(handle.as_str(), "username123");
+    /// ```
+    pub fn new(handle: &str) -> Self {
+        // First trim any whitespace, fold compatibility characters and convert to lowercase
+        let folded = handle.trim().nfkc().collect::<String>().to_lowercase();
+
+        // Filter out any characters that aren't alphanumeric or hyphen
+        CanonicalHandle(
+            folded
+                .chars()
+                .filter(|c| c.is_alphanumeric() || *c == '-')
+                .collect(),
+        )
+    }
+
+    /// Whether a stored handle is already in canonical form
+    ///
+    /// # Arguments
+    /// * `handle` - A handle read from a key or a document
+    ///
+    /// # Returns
+    /// * `bool` - True if canonicalizing it gives it back unchanged
+    pub fn is_canonical(handle: &str) -> bool {
+        CanonicalHandle::new(handle).as_str() == handle
+    }
+
+    /// The canonical handle as a string slice
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The canonical handle as an owned string
+    pub fn into_string(self) -> String {
+        self.0
+    }
+
+    /// Maps the handle to its confusable skeleton
+    /// 
+    /// Two handles that can be mistaken for one another, like "paypal" and "раypal" or
+    /// "admin" and "adrnin", have the same skeleton. The skeleton only contains alphanumeric
+    /// characters and hyphens, so it can be used in keys and descriptions.
+    /// 
+    /// # Returns
+    /// The skeleton of the canonical handle
+    /// 
+    /// # Example
+    /// ```
+    /// assert_eq!(CanonicalHandle::new("раypal").skeleton(), CanonicalHandle::new("PayPal").skeleton());
+    /// ```
+    pub fn skeleton(&self) -> String {
+        // The skeleton can map lowercase letters to uppercase prototypes (e.g. "0" -> "O")
+        skeleton(&self.0)
+            .collect::<String>()
+            .to_lowercase()
+            .chars()
+            .filter(|c| c.is_alphanumeric() || *c == '-')
+            .collect()
+    }
+}
+
+impl fmt::Display for CanonicalHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for CanonicalHandle {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn canonical(handle: &str) -> String {
+        CanonicalHandle::new(handle).into_string()
+    }
+
+    fn skeleton_of(handle: &str) -> String {
+        CanonicalHandle::new(handle).skeleton()
+    }
+
     #[test]
-    fn test_normalize_username() {
+    fn test_canonical_handle() {
         // Test basic normalization
-        assert_eq!(normalize_handle("UserName"), "username");
+        assert_eq!(canonical("UserName"), "username");
+        assert_eq!(canonical("UPPERCASE"), "uppercase");
         
         // Test whitespace handling
-        assert_eq!(normalize_handle("  user  name  "), "username");
+        assert_eq!(canonical("  user  name  "), "username");
+        assert_eq!(canonical("John Doe"), "johndoe");
         
         // Test special characters
-        assert_eq!(normalize_handle("user@name!123"), "username123");
+        assert_eq!(canonical("user@name!123"), "username123");
+        assert_eq!(canonical("special@#$%^&*chars"), "specialchars");
         
-        // Test allowed special characters
-        assert_eq!(normalize_handle("user_name-123"), "user_name-123");
+        // Hyphens are kept, underscores separate key segments and are removed
+        assert_eq!(canonical("Technical-Skills!"), "technical-skills");
+        assert_eq!(canonical("user_name-123"), "username-123");
+        assert_eq!(canonical("Programming_123"), "programming123");
+
+        // Test Unicode normalization (fullwidth letters, ligatures)
+        assert_eq!(canonical("ＵｓｅｒＮａｍｅ"), "username");
+        assert_eq!(canonical("ﬁle"), "file");
+    }
+
+    #[test]
+    fn test_is_canonical() {
+        assert!(CanonicalHandle::is_canonical("john-doe"));
+        assert!(CanonicalHandle::is_canonical("jöhn"));
+        assert!(!CanonicalHandle::is_canonical("John"));
+        assert!(!CanonicalHandle::is_canonical("ｊohn"));
+        assert!(!CanonicalHandle::is_canonical("john_doe"));
+
+        // Canonicalizing is idempotent
+        for handle in ["ＵｓｅｒＮａｍｅ", "ﬁle", "Jöhn-Doe", "İstanbul"] {
+            assert!(CanonicalHandle::is_canonical(&canonical(handle)), "{}", handle);
+        }
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_handle_skeleton() {
+        // Cyrillic "р" and "а" look like Latin "p" and "a"
+        assert_eq!(skeleton_of("раypal"), skeleton_of("paypal"));
+        assert_ne!("раypal", "paypal");
+
+        // Case, compatibility forms and look-alike digits
+        assert_eq!(skeleton_of("PayPal"), skeleton_of("paypal"));
+        assert_eq!(skeleton_of("ｐａｙｐａｌ"), skeleton_of("paypal"));
+        assert_eq!(skeleton_of("paypa1"), skeleton_of("paypal"));
+        assert_eq!(skeleton_of("adrnin"), skeleton_of("admin"));
+
+        // Different handles keep different skeletons
+        assert_ne!(skeleton_of("paypal"), skeleton_of("payday"));
+        assert!(!skeleton_of("some_user").contains('_'));
+    }
+}
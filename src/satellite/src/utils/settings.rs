@@ -0,0 +1,76 @@
+/*!
+ * Persisted controller settings
+ *
+ * Configuration that controllers change at runtime (rate limits, log levels, ...) is read
+ * on hot paths, so each module keeps it in heap memory. Heap memory is wiped on upgrade,
+ * so every change is also saved to the `settings` collection, one document per module:
+ * ```text
+ * settings:  {name}_   data: the module's configuration
+ * ```
+ * The `on_post_upgrade` hook (see lib.rs) loads the saved documents back into the heap.
+ *
+ * # Notes
+ * - A module without a saved document keeps its defaults
+ * - Juno runs `on_post_upgrade` in a timer right after the upgrade, so calls that are
+ *   already queued may still see the defaults
+ * - The collection is written by the satellite only, see juno.dev.config.ts
+ */
+
+use junobuild_satellite::{get_doc_store, set_doc_store, SetDoc};
+use junobuild_utils::{decode_doc_data, encode_doc_data};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::utils::errors::KarmieError;
+
+/// Collection holding the controller settings
+pub const SETTINGS_COLLECTION: &str = "settings";
+
+/// Key of the document holding a setting
+fn setting_key(name: &str) -> String {
+    format!("{}_", name)
+}
+
+/// Saves a setting, replacing the previous value
+///
+/// # Arguments
+/// * `name` - Name of the setting, e.g. "rate_limits"
+/// * `value` - The configuration to save
+///
+/// # Returns
+/// * `Result<(), KarmieError>` - Ok if saved, Err if the document couldn't be written
+pub fn save_setting<T: Serialize>(name: &str, value: &T) -> Result<(), KarmieError> {
+    let key = setting_key(name);
+    let version = get_doc_store(ic_cdk::id(), SETTINGS_COLLECTION.to_string(), key.clone())
+        .map_err(|e| KarmieError::storage(SETTINGS_COLLECTION, e))?
+        .and_then(|doc| doc.version);
+    let data = encode_doc_data(value).map_err(|e| KarmieError::invalid_data(SETTINGS_COLLECTION, e))?;
+
+    set_doc_store(
+        ic_cdk::id(),
+        SETTINGS_COLLECTION.to_string(),
+        key,
+        SetDoc { data, description: None, version },
+    )
+    .map_err(|e| KarmieError::storage(SETTINGS_COLLECTION, e))?;
+    Ok(())
+}
+
+/// Loads a saved setting
+///
+/// # Arguments
+/// * `name` - Name of the setting, e.g. "rate_limits"
+///
+/// # Returns
+/// * `Result<Option<T>, KarmieError>` - The saved configuration, None if it was never saved
+pub fn load_setting<T: DeserializeOwned>(name: &str) -> Result<Option<T>, KarmieError> {
+    let doc = get_doc_store(ic_cdk::id(), SETTINGS_COLLECTION.to_string(), setting_key(name))
+        .map_err(|e| KarmieError::storage(SETTINGS_COLLECTION, e))?;
+
+    match doc {
+        Some(doc) => decode_doc_data(&doc.data)
+            .map(Some)
+            .map_err(|e| KarmieError::invalid_data(SETTINGS_COLLECTION, format!("setting {}: {}", name, e))),
+        None => Ok(None),
+    }
+}
@@ -10,7 +10,7 @@ use candid::{Principal, CandidType};
 pub struct User {
 
     /// Unique identifier for the user document created using src/satellite/src/processors/document_keys.rs
-    /// Users: `_prn_{principal}_usr_{ulid}_hdl_{username}_`
+    /// Users: `_prn_{principal}_usr_{ulid}_` (legacy users: `_prn_{principal}_usr_{ulid}_hdl_{username}_`)
     /// This is NOT the Principal ID - it's just a unique document identifier
     pub key: String,
 
@@ -54,6 +54,128 @@ pub struct UserData {
     pub avatar_url: String,
 }
 
+/// Document of the users_handles index collection
+///
+/// Maps a handle to the user holding it, so that handles can be looked up and kept
+/// unique without being part of the user key. Maintained by the satellite only.
+/// Key Format: `hdl_{userHandle}_usr_{userULID}_`, Description: `skl_{skeleton}_`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserHandleData {
+    /// ULID of the user holding the handle
+    pub user_ulid: String,
+}
+
+/// Document of the users_handles_history collection
+///
+/// Records a handle a user released by renaming or deleting their account. Released
+/// handles are quarantined for the other users, and profile lookups by the old handle
+/// resolve to the user who released it.
+/// Key Format: `hdl_{userHandle}_usr_{userULID}_`, Description: `skl_{skeleton}_`
+#[derive(Debug, Serialize, Deserialize, Clone, CandidType)]
+pub struct HandleHistoryData {
+    /// ULID of the user who released the handle
+    pub user_ulid: String,
+
+    /// The released handle, canonicalized like in keys
+    pub handle: String,
+
+    /// When the handle was released (nanoseconds)
+    pub released_at: u64,
+
+    /// The handle the user renamed to, None if the user was deleted
+    pub renamed_to: Option<String>,
+}
+
+/// Document of the users_handles_renames collection
+///
+/// When a user renamed, used to rate limit renames. Kept apart from the handle history,
+/// which has one entry per released handle and can't count renames back to a handle.
+/// Key Format: `usr_{userULID}_`
+#[derive(Debug, Serialize, Deserialize, Clone, CandidType)]
+pub struct HandleRenamesData {
+    /// ULID of the user
+    pub user_ulid: String,
+
+    /// When the user renamed (nanoseconds), oldest first, at most the largest `max_renames` allowed
+    pub renamed_at: Vec<u64>,
+}
+
+/// Document of the reserved_handles collection
+///
+/// A handle that users and tags can't take, managed by controllers. Matching is done on
+/// the confusable skeleton, so look-alikes of a reserved handle are reserved too.
+/// Key Format: `skl_{skeleton}_`
+#[derive(Debug, Serialize, Deserialize, Clone, CandidType)]
+pub struct ReservedHandleData {
+    /// The reserved handle, as entered by the controller
+    pub handle: String,
+
+    /// Principal allowed to use the handle anyway, None if it is blocked for everyone
+    pub reserved_for: Option<String>,
+
+    /// Why the handle is reserved (shown to controllers only)
+    pub reason: String,
+}
+
+/// What happens to content matching a moderation term
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, CandidType)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationSeverity {
+    /// The write is accepted and the document is added to the review queue
+    Flag,
+
+    /// The write is rejected with `ContentRejected`
+    Reject,
+}
+
+/// Document of the moderation_terms collection
+///
+/// A term that user and tag content is checked against, managed by controllers.
+/// Terms and content are compared in a folded form that undoes leetspeak and look-alike
+/// characters, so "b4d", "bаd" (Cyrillic "а") and "BAD" all match the term "bad".
+/// Key Format: `trm_{folded term}_`
+#[derive(Debug, Serialize, Deserialize, Clone, CandidType)]
+pub struct ModerationTermData {
+    /// The term, as entered by the controller
+    pub term: String,
+
+    /// Whether matching content is rejected or flagged for review
+    pub severity: ModerationSeverity,
+
+    /// Whether the term also matches inside longer words, not only as whole words
+    pub match_inside_words: bool,
+}
+
+/// A term found in a field of a flagged document
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, CandidType)]
+pub struct FlaggedTerm {
+    /// The field the term was found in, e.g. "display_name"
+    pub field: String,
+
+    /// The moderation term that matched
+    pub term: String,
+}
+
+/// Document of the moderation_queue collection
+///
+/// A user or tag document whose content matched terms flagged for review. Written by the
+/// satellite when the document is validated; controllers dismiss entries once reviewed.
+/// Key Format: `{collection}_{documentKey}`
+#[derive(Debug, Serialize, Deserialize, Clone, CandidType)]
+pub struct ModerationQueueData {
+    /// Collection of the flagged document
+    pub collection: String,
+
+    /// Key of the flagged document
+    pub doc_key: String,
+
+    /// The terms found, by field
+    pub matches: Vec<FlaggedTerm>,
+
+    /// When the document was flagged (nanoseconds)
+    pub flagged_at: u64,
+}
+
 /// Represents a tag that can be used for categorizing votes and reputation
 /// See format standards in docs/core/architecture/database.md
 #[derive(Debug, Serialize, Deserialize)]
@@ -115,6 +237,86 @@ pub struct TagData {
     /// Minimum number of users that need to reach threshold before vote rewards are restricted
     pub min_users_for_threshold: u32, 
 
+    /// Minutes an author must wait before voting on the same target again in this tag
+    /// (0 = no cooldown, maximum 44640 = 31 days)
+    /// Defaults to 0 for tags created before cooldowns existed
+    #[serde(default)]
+    pub vote_cooldown: u32,
+
+    /// How repeated votes by the same author on the same target are counted
+    /// Defaults to cumulative (every vote counts) for tags created before this setting existed
+    #[serde(default)]
+    pub duplicate_vote_policy: DuplicateVotePolicy,
+
+    /// Maximum number of votes an author can cast on the same target in this tag
+    /// Only used with the cumulative policy (0 = no cap)
+    #[serde(default)]
+    pub duplicate_vote_cap: u32,
+
+    /// How much to reduce positive votes between flagged reciprocal voters (0.0 to 1.0)
+    /// 0.0 disables dampening, 1.0 ignores those votes entirely (see core/ring_detection.rs)
+    #[serde(default)]
+    pub reciprocal_vote_dampening: f64,
+
+    /// Optional voting credits budget (None = votes are free)
+    /// See core/vote_credits.rs
+    #[serde(default)]
+    pub vote_credits: Option<VoteCreditsConfig>,
+
+    /// How votes are weighted and paid for
+    /// Quadratic mode requires `vote_credits` (see core/quadratic_voting.rs)
+    #[serde(default)]
+    pub vote_mode: VoteMode,
+
+}
+
+/// Policy for repeated votes between the same author and target within a tag
+/// 
+/// Vote keys include a per-vote ULID (`key_{ulid}`), so nothing in the key itself
+/// prevents an author from voting on the same target many times.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, CandidType)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateVotePolicy {
+    /// Every vote counts, up to `duplicate_vote_cap` votes if the cap is set
+    #[default]
+    Cumulative,
+
+    /// Only the author's most recent vote on a target counts, older ones are superseded
+    LatestWins,
+
+    /// Only one vote per target is allowed; it can be edited but not repeated
+    OneVote,
+}
+
+/// Voting mode of a tag
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, CandidType)]
+#[serde(rename_all = "snake_case")]
+pub enum VoteMode {
+    /// Every vote has intensity 1
+    #[default]
+    Standard,
+
+    /// Votes have an intensity: they count `intensity` times and cost `intensity²` times the credits
+    Quadratic,
+}
+
+/// Voting credits configuration for a tag
+/// 
+/// Each user starts with `max_credits` and regenerates one credit every
+/// `regen_minutes`, up to `max_credits`. Casting a vote spends credits.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, CandidType)]
+pub struct VoteCreditsConfig {
+    /// Maximum (and starting) balance
+    pub max_credits: u32,
+
+    /// Minutes to regenerate one credit (0 = credits never regenerate)
+    pub regen_minutes: u32,
+
+    /// Credits spent by a positive vote
+    pub positive_vote_cost: u32,
+
+    /// Credits spent by a negative vote (can be higher to make downvotes deliberate)
+    pub negative_vote_cost: u32,
 }
 
 /// Represents a vote cast by one user on another
@@ -173,6 +375,11 @@ pub struct VoteData {
     /// Vote weight (default: 1.0)
     pub weight: f64,
 
+    /// Vote intensity for quadratic voting tags (0 or 1 = a regular vote)
+    /// See core/quadratic_voting.rs
+    #[serde(default)]
+    pub intensity: u32,
+
 }
 
 /// Represents a user's reputation in a specific tag
@@ -237,6 +444,41 @@ pub struct ReputationData {
     pub has_voting_power: bool,
 }
 
+/// Voting credits balance of a user in a tag
+/// Stored in the `vote_credits` collection with key `usr_{ulid}_tag_{ulid}_crd_`
+#[derive(Debug, Serialize, Deserialize, Clone, CandidType)]
+pub struct VoteCreditsData {
+
+    /// ULID of the user this balance belongs to
+    pub owner_ulid: String,
+
+    /// ULID of the tag this balance is for
+    pub tag_ulid: String,
+
+    /// Balance as of `last_regen`
+    pub balance: u32,
+
+    /// Time (nanoseconds) up to which regeneration has been applied to `balance`
+    pub last_regen: u64,
+}
+
+/// Delegation of a user's voting power to another user within a tag
+/// Stored in the `delegations` collection with key `usr_{delegator}_tag_{tag}_del_{delegate}_`
+///
+/// Deleting the document revokes the delegation.
+#[derive(Debug, Serialize, Deserialize, Clone, CandidType)]
+pub struct DelegationData {
+
+    /// ULID of the user giving away their voting power
+    pub delegator_ulid: String,
+
+    /// ULID of the user receiving the voting power
+    pub delegate_ulid: String,
+
+    /// ULID of the tag the delegation applies to
+    pub tag_ulid: String,
+}
+
 /// Represents a vote weight with constraints (0.0 to 1.0)
 #[derive(Debug, Clone, CandidType)]
 pub struct VoteWeight(f64);
@@ -1,4 +1,5 @@
 use crate::logger;
+use crate::utils::errors::KarmieError;
 
 /// Validates a description string against expected format
 /// 
@@ -6,17 +7,17 @@ use crate::logger;
 /// - Length between 0 and 1024 characters
 /// - No leading or trailing whitespace
 /// - No consecutive whitespace characters
-pub fn validate_description(description: &str) -> Result<(), String> {
+pub fn validate_description(description: &str) -> Result<(), KarmieError> {
     if description.len() > 1024 {
         let err_msg = format!("Description must be 1024 characters or less (current length: {})", description.len());
         logger!("error", "[validate_description] {}", err_msg);
-        return Err(err_msg);
+        return Err(KarmieError::invalid_field("description", err_msg));
     }
 
     if description.trim() != description {
         let err_msg = "Description must not contain leading or trailing whitespace.".to_string();
         logger!("error", "[validate_description] {}", err_msg);
-        return Err(err_msg);
+        return Err(KarmieError::invalid_field("description", err_msg));
     }
 
     let mut prev_char = ' ';
@@ -24,7 +25,7 @@ pub fn validate_description(description: &str) -> Result<(), String> {
         if c.is_whitespace() && prev_char.is_whitespace() {
             let err_msg = "Description must not contain consecutive whitespace characters.".to_string();
             logger!("error", "[validate_description] {}", err_msg);
-            return Err(err_msg);
+            return Err(KarmieError::invalid_field("description", err_msg));
         }
         prev_char = c;
     }
@@ -1,4 +1,5 @@
 use crate::logger;
+use crate::utils::errors::KarmieError;
 
 /// Validates a display name string against expected format
 /// 
@@ -6,24 +7,24 @@ use crate::logger;
 /// - Length between 1 and 50 characters
 /// - No leading/trailing whitespace
 /// - No consecutive whitespace
-pub fn validate_display_name(display_name: &str) -> Result<(), String> {
+pub fn validate_display_name(display_name: &str) -> Result<(), KarmieError> {
     if display_name.trim().is_empty() {
         logger!("error", "[validate_display_name] Display name is empty");
-        return Err("Display name cannot be empty.".to_string());
+        return Err(KarmieError::invalid_field("display_name", "Display name cannot be empty."));
     }
     if display_name.len() > 50 {
         logger!("error", "[validate_display_name] Display name too long: {}", display_name);
-        return Err("Display name must be 50 characters or less.".to_string());
+        return Err(KarmieError::invalid_field("display_name", "Display name must be 50 characters or less."));
     }
 
     if display_name.trim() != display_name {
-        return Err("Display name must not contain leading or trailing whitespace.".to_string());
+        return Err(KarmieError::invalid_field("display_name", "Display name must not contain leading or trailing whitespace."));
     }
 
     let mut prev_char = ' ';
     for c in display_name.chars() {
         if c.is_whitespace() && prev_char.is_whitespace() {
-            return Err("Display name must not contain consecutive whitespace.".to_string());
+            return Err(KarmieError::invalid_field("display_name", "Display name must not contain consecutive whitespace."));
         }
         prev_char = c;
     }
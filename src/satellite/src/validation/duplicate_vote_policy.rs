@@ -0,0 +1,21 @@
+use crate::logger;
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::DuplicateVotePolicy;
+
+/// Validates a tag's duplicate vote settings
+/// 
+/// Requirements:
+/// - `duplicate_vote_cap` can only be set with the cumulative policy
+///   (latest-wins and one-vote already count a single vote per target)
+pub fn validate_duplicate_vote_policy(policy: DuplicateVotePolicy, cap: u32) -> Result<(), KarmieError> {
+    if policy != DuplicateVotePolicy::Cumulative && cap > 0 {
+        let err_msg = format!(
+            "Duplicate vote cap can only be used with the cumulative policy (policy: {:?}, cap: {})",
+            policy, cap
+        );
+        logger!("error", "[validate_duplicate_vote_policy] {}", err_msg);
+        return Err(KarmieError::invalid_field("duplicate_vote_cap", err_msg));
+    }
+
+    Ok(())
+}
@@ -7,22 +7,34 @@
  * - Tag name validation
  * - Time period validation
  * - Description format validation
+ * - Vote cooldown validation
+ * - Duplicate vote policy validation
+ * - Reciprocal vote dampening validation
+ * - Voting credits configuration and voting mode validation
  * 
  * These validation functions are used throughout the codebase to ensure
  * data integrity and consistency.
  */
 
 mod description;
+mod duplicate_vote_policy;
 mod display_name;
 mod validate_handle;
+mod reciprocal_vote_dampening;
 pub mod ulid_timestamp_validate;
 mod validate_tag_date;
+mod vote_cooldown;
+mod vote_credits;
 
 pub use description::validate_description;
+pub use duplicate_vote_policy::validate_duplicate_vote_policy;
 pub use display_name::validate_display_name;
 pub use validate_handle::validate_handle;
+pub use reciprocal_vote_dampening::validate_reciprocal_vote_dampening;
 pub use validate_tag_date::validate_tag_date_struct;
 pub use ulid_timestamp_validate::{validate_ulid_timestamp, CheckULIDisNew};
+pub use vote_cooldown::validate_vote_cooldown;
+pub use vote_credits::{validate_vote_credits, validate_vote_mode};
 
 // TODO: As validation functions are moved from utils to this module,
 // add the following structure:
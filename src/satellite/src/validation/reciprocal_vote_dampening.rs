@@ -0,0 +1,19 @@
+use crate::logger;
+use crate::utils::errors::KarmieError;
+
+/// Validates a tag's reciprocal vote dampening setting
+/// 
+/// Requirements:
+/// - Between 0.0 (no dampening) and 1.0 (flagged reciprocal votes are ignored)
+pub fn validate_reciprocal_vote_dampening(dampening: f64) -> Result<(), KarmieError> {
+    if !(0.0..=1.0).contains(&dampening) {
+        let err_msg = format!(
+            "Reciprocal vote dampening must be between 0.0 and 1.0 (got: {})",
+            dampening
+        );
+        logger!("error", "[validate_reciprocal_vote_dampening] {}", err_msg);
+        return Err(KarmieError::invalid_field("reciprocal_vote_dampening", err_msg));
+    }
+
+    Ok(())
+}
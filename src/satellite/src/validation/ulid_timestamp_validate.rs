@@ -18,6 +18,7 @@
 //! - Used for validating new documents
 
 use crate::processors::ulid_timestamp_extract::extract_timestamp_ms;
+use crate::utils::errors::KarmieError;
 use ic_cdk;
 
 /// Configuration for checking if a ULID timestamp is new
@@ -48,7 +49,7 @@ impl CheckULIDisNew {
 /// * `check_new` - Whether to also check if the timestamp is not too old
 /// 
 /// # Returns
-/// * `Result<(), String>` - Ok if valid, Err with descriptive message if invalid
+/// * `Result<(), KarmieError>` - Ok if valid, `InvalidUlid` describing the problem if invalid
 /// 
 /// # Example
 /// ```
@@ -60,10 +61,14 @@ impl CheckULIDisNew {
 /// // Basic validation + freshness check
 /// validate_ulid_timestamp(ulid_str, CheckULIDisNew::yes())?;
 /// ```
-pub fn validate_ulid_timestamp(ulid_str: &str, check_new: CheckULIDisNew) -> Result<(), String> {
+pub fn validate_ulid_timestamp(ulid_str: &str, check_new: CheckULIDisNew) -> Result<(), KarmieError> {
+    let invalid = |reason: String| KarmieError::InvalidUlid {
+        ulid: ulid_str.to_string(),
+        reason,
+    };
 
     // Extract timestamp from the ULID
-    let timestamp_ms = extract_timestamp_ms(ulid_str)?;
+    let timestamp_ms = extract_timestamp_ms(ulid_str).map_err(invalid)?;
     
     // Get current time from IC in milliseconds
     // (IC time is in nanoseconds, divide by 1_000_000 to get milliseconds)
@@ -72,19 +77,19 @@ pub fn validate_ulid_timestamp(ulid_str: &str, check_new: CheckULIDisNew) -> Res
     // General validation (always done)
     let year_2025_ms: u64 = 1735689600000; // Jan 1, 2025
     if timestamp_ms < year_2025_ms {
-        return Err(format!("ULID timestamp is too old (before 2025): {} ms since epoch", timestamp_ms));
+        return Err(invalid(format!("ULID timestamp is too old (before 2025): {} ms since epoch", timestamp_ms)));
     }
     
     if timestamp_ms > now_ms + 120_000 { // 2 minutes
-        return Err(format!("ULID timestamp is too far in the future (>2min): {} ms (now: {} ms)", 
-            timestamp_ms, now_ms));
+        return Err(invalid(format!("ULID timestamp is too far in the future (>2min): {} ms (now: {} ms)", 
+            timestamp_ms, now_ms)));
     }
     
     // Optional new document check
     if matches!(check_new, CheckULIDisNew::Yes) {
         if timestamp_ms + 120_000 < now_ms { // 2 minutes
-            return Err(format!("ULID timestamp is too old (>2min): {} ms (now: {} ms)", 
-                timestamp_ms, now_ms));
+            return Err(invalid(format!("ULID timestamp is too old (>2min): {} ms (now: {} ms)", 
+                timestamp_ms, now_ms)));
         }
     }
     
@@ -107,7 +112,7 @@ mod tests {
         let result = validate_ulid_timestamp(too_old_ulid, CheckULIDisNew::no());
         assert!(result.is_err());
         if let Err(err) = result {
-            assert!(err.contains("too old"), "Error should mention timestamp being too old: {}", err);
+            assert!(err.to_string().contains("too old"), "Error should mention timestamp being too old: {}", err);
         }
     }
     
@@ -117,7 +122,7 @@ mod tests {
         let result = validate_ulid_timestamp(future_ulid, CheckULIDisNew::no());
         assert!(result.is_err());
         if let Err(err) = result {
-            assert!(err.contains("future"), "Error should mention timestamp being in the future: {}", err);
+            assert!(err.to_string().contains("future"), "Error should mention timestamp being in the future: {}", err);
         }
     }
     
@@ -127,7 +132,7 @@ mod tests {
         let result = validate_ulid_timestamp(old_ulid, CheckULIDisNew::yes());
         assert!(result.is_err());
         if let Err(err) = result {
-            assert!(err.contains("too old"), "Error should mention timestamp being too old: {}", err);
+            assert!(err.to_string().contains("too old"), "Error should mention timestamp being too old: {}", err);
         }
     }
 } 
\ No newline at end of file
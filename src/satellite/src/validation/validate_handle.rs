@@ -1,3 +1,5 @@
+use crate::utils::errors::KarmieError;
+
 /// Validates a handle (username or tag) string against expected format
 /// 
 /// Requirements:
@@ -5,28 +7,28 @@
 /// - Only alphanumeric characters and dashes
 /// - Cannot start or end with a dash
 /// - No consecutive dashes
-pub fn validate_handle(username: &str) -> Result<(), String> {
+pub fn validate_handle(username: &str) -> Result<(), KarmieError> {
     if username.len() < 3 || username.len() > 20 {
-        return Err("[validate_handle] Handle must be between 3 and 20 characters.".to_string());
+        return Err(KarmieError::invalid_field("handle", "Handle must be between 3 and 20 characters."));
     }
 
     // Check if starts or ends with dash
     if username.starts_with('-') {
-        return Err("[validate_handle] Handle cannot start with a dash.".to_string());
+        return Err(KarmieError::invalid_field("handle", "Handle cannot start with a dash."));
     }
     
     if username.ends_with('-') {
-        return Err("[validate_handle] Handle cannot end with a dash.".to_string());
+        return Err(KarmieError::invalid_field("handle", "Handle cannot end with a dash."));
     }
 
     // Check for valid characters and consecutive dashes
     let mut prev_char = ' '; // Use space as initial value since it's not a dash
     for c in username.chars() {
         if !c.is_alphanumeric() && c != '-' {
-            return Err("[validate_handle] Handle can only contain alphanumeric characters and dashes.".to_string());
+            return Err(KarmieError::invalid_field("handle", "Handle can only contain alphanumeric characters and dashes."));
         }
         if c == '-' && prev_char == '-' {
-            return Err("[validate_handle] Handle cannot contain consecutive dashes.".to_string());
+            return Err(KarmieError::invalid_field("handle", "Handle cannot contain consecutive dashes."));
         }
         prev_char = c;
     }
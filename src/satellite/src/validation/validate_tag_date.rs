@@ -7,6 +7,7 @@
 
 use crate::utils::structs::TimePeriod;
 use crate::logger;
+use crate::utils::errors::KarmieError;
 
 /// Validates a vector of TimePeriod structs
 /// Each TimePeriod defines a duration in calendar months and a multiplier for votes that fall within that period
@@ -15,7 +16,7 @@ use crate::logger;
 /// * `time_periods` - A reference to a vector of TimePeriod structs
 ///
 /// # Returns
-/// * `Result<(), String>` - Ok if valid, Err with error message if invalid
+/// * `Result<(), KarmieError>` - Ok if valid, `InvalidField` on `time_periods` if invalid
 ///
 /// # Example
 /// ```
@@ -27,7 +28,7 @@ use crate::logger;
 ///   { months: 12, multiplier: 0.75 } // Next 12 months: 75% weight (month 13 through 24)
 /// ]
 /// ```
-pub fn validate_tag_date_struct(time_periods: &Vec<TimePeriod>) -> Result<(), String> {
+pub fn validate_tag_date_struct(time_periods: &Vec<TimePeriod>) -> Result<(), KarmieError> {
     if time_periods.is_empty() {
         return Ok(());
     }
@@ -38,14 +39,14 @@ pub fn validate_tag_date_struct(time_periods: &Vec<TimePeriod>) -> Result<(), St
         if period.months < 1 {
             logger!("error", "[validate_tag_date_struct] Invalid months value in period {}: {} (must be ≥ 1)", 
                 i + 1, period.months);
-            return Err(format!("Duration must be at least 1 month for period {}", i + 1));
+            return Err(KarmieError::invalid_field("time_periods", format!("Duration must be at least 1 month for period {}", i + 1)));
         }
 
         // Validate multiplier range (0.05-10)
         if period.multiplier < 0.05 || period.multiplier > 10.0 {
             logger!("error", "[validate_tag_date_struct] Invalid multiplier in period {}: {} (must be between 0.05 and 10)", 
                 i + 1, period.multiplier);
-            return Err(format!("Multiplier must be between 0.05 and 10 for period {}", i + 1));
+            return Err(KarmieError::invalid_field("time_periods", format!("Multiplier must be between 0.05 and 10 for period {}", i + 1)));
         }
 
         // Validate multiplier is a multiple of 0.05
@@ -54,7 +55,7 @@ pub fn validate_tag_date_struct(time_periods: &Vec<TimePeriod>) -> Result<(), St
         if (period.multiplier - normalized).abs() > f64::EPSILON {
             logger!("error", "[validate_tag_date_struct] Invalid multiplier in period {}: {} (must be a multiple of 0.05)", 
                 i + 1, period.multiplier);
-            return Err(format!("Multiplier must be a multiple of 0.05 for period {} (e.g., 0.05, 0.10, 0.15, etc.)", i + 1));
+            return Err(KarmieError::invalid_field("time_periods", format!("Multiplier must be a multiple of 0.05 for period {} (e.g., 0.05, 0.10, 0.15, etc.)", i + 1)));
         }
     }
 
@@ -102,7 +103,7 @@ mod tests {
         ];
         let result = validate_tag_date_struct(&periods);
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Duration must be at least 1 month"));
+        assert!(result.unwrap_err().message().contains("Duration must be at least 1 month"));
     }
 
     #[test]
@@ -112,7 +113,7 @@ mod tests {
         ];
         let result = validate_tag_date_struct(&periods);
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Multiplier must be between 0.05 and 10"));
+        assert!(result.unwrap_err().message().contains("Multiplier must be between 0.05 and 10"));
     }
 
     #[test]
@@ -122,7 +123,7 @@ mod tests {
         ];
         let result = validate_tag_date_struct(&periods);
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Multiplier must be between 0.05 and 10"));
+        assert!(result.unwrap_err().message().contains("Multiplier must be between 0.05 and 10"));
     }
 
     #[test]
@@ -132,6 +133,6 @@ mod tests {
         ];
         let result = validate_tag_date_struct(&periods);
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("must be a multiple of 0.05"));
+        assert!(result.unwrap_err().message().contains("must be a multiple of 0.05"));
     }
 } 
\ No newline at end of file
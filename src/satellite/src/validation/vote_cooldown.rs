@@ -0,0 +1,24 @@
+use crate::logger;
+use crate::utils::errors::KarmieError;
+
+/// Maximum cooldown a tag can define: 31 days in minutes
+pub const MAX_VOTE_COOLDOWN_MINUTES: u32 = 44_640;
+
+/// Validates a tag's vote cooldown setting
+/// 
+/// Requirements:
+/// - Whole number of minutes (enforced by the u32 type)
+/// - Between 0 (no cooldown) and 44640 (31 days)
+pub fn validate_vote_cooldown(vote_cooldown: u32) -> Result<(), KarmieError> {
+    if vote_cooldown > MAX_VOTE_COOLDOWN_MINUTES {
+        let err_msg = format!(
+            "Vote cooldown must be between 0 and {} minutes (got: {})",
+            MAX_VOTE_COOLDOWN_MINUTES, vote_cooldown
+        );
+        logger!("error", "[validate_vote_cooldown] {}", err_msg);
+        return Err(KarmieError::invalid_field("vote_cooldown", err_msg));
+    }
+
+    Ok(())
+}
+
@@ -0,0 +1,64 @@
+use crate::logger;
+use crate::utils::errors::KarmieError;
+use crate::utils::structs::{VoteCreditsConfig, VoteMode};
+
+/// Highest balance a tag can configure
+const MAX_VOTE_CREDITS: u32 = 10_000;
+
+/// Validates a tag's voting credits configuration
+/// 
+/// Requirements (only checked when credits are enabled):
+/// - `max_credits` between 1 and 10000
+/// - Both vote costs between 1 and `max_credits` (every vote must be affordable from a full balance)
+/// - `regen_minutes` up to 44640 (31 days), 0 disables regeneration
+pub fn validate_vote_credits(config: Option<&VoteCreditsConfig>) -> Result<(), KarmieError> {
+    let config = match config {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+
+    let err_msg = if config.max_credits == 0 || config.max_credits > MAX_VOTE_CREDITS {
+        Some(format!(
+            "Vote credits max_credits must be between 1 and {} (got: {})",
+            MAX_VOTE_CREDITS, config.max_credits
+        ))
+    } else if config.positive_vote_cost == 0 || config.positive_vote_cost > config.max_credits {
+        Some(format!(
+            "Vote credits positive_vote_cost must be between 1 and max_credits ({}) (got: {})",
+            config.max_credits, config.positive_vote_cost
+        ))
+    } else if config.negative_vote_cost == 0 || config.negative_vote_cost > config.max_credits {
+        Some(format!(
+            "Vote credits negative_vote_cost must be between 1 and max_credits ({}) (got: {})",
+            config.max_credits, config.negative_vote_cost
+        ))
+    } else if config.regen_minutes > 44_640 {
+        Some(format!(
+            "Vote credits regen_minutes must be between 0 and 44640 (got: {})",
+            config.regen_minutes
+        ))
+    } else {
+        None
+    };
+
+    if let Some(err_msg) = err_msg {
+        logger!("error", "[validate_vote_credits] {}", err_msg);
+        return Err(KarmieError::invalid_field("vote_credits", err_msg));
+    }
+
+    Ok(())
+}
+
+/// Validates a tag's voting mode
+/// 
+/// Requirements:
+/// - Quadratic voting requires voting credits, since intensity is paid for with credits
+pub fn validate_vote_mode(mode: VoteMode, credits: Option<&VoteCreditsConfig>) -> Result<(), KarmieError> {
+    if mode == VoteMode::Quadratic && credits.is_none() {
+        let err_msg = "Quadratic voting requires vote_credits to be configured".to_string();
+        logger!("error", "[validate_vote_mode] {}", err_msg);
+        return Err(KarmieError::invalid_field("vote_mode", err_msg));
+    }
+
+    Ok(())
+}